@@ -26,8 +26,30 @@ enum Commands {
         port: u16,
         #[arg(long)]
         username: Option<String>,
+        /// Game password. Experimental: how it is hashed is unconfirmed against
+        /// the game, so a real server may reject it
+        #[arg(long)]
+        password: Option<String>,
+        #[arg(long)]
+        reconnect: bool,
     },
     Disconnect,
+    /// Listen for LAN server broadcasts
+    Discover {
+        #[arg(long, default_value_t = 3000)]
+        window_ms: u64,
+        #[arg(long, default_value_t = factorio_client::protocol::LAN_BROADCAST_PORT)]
+        port: u16,
+    },
+    /// Query server info without joining
+    Probe {
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+        #[arg(long, default_value = "34197")]
+        port: u16,
+        #[arg(long, default_value_t = 5000)]
+        timeout_ms: u64,
+    },
     Status {
         #[arg(long)]
         watch: bool,
@@ -41,6 +63,8 @@ enum Commands {
         id: Option<u64>,
     },
     Position,
+    /// Peers in the game, with player index and username
+    Players,
     State {
         #[arg(long)]
         radius: Option<f64>,
@@ -780,13 +804,18 @@ enum Commands {
     Chat {
         message: String,
     },
+    /// Chat messages the daemon has seen, oldest first
+    ChatLog {
+        #[arg(long)]
+        since: Option<u32>,
+    },
 }
 
 fn main() {
     let cli = Cli::parse();
 
     let result = match cli.command {
-        Commands::Connect { host, port, username } => {
+        Commands::Connect { host, port, username, password, reconnect } => {
             let username = username
                 .and_then(|name| {
                     let trimmed = name.trim();
@@ -797,9 +826,11 @@ fn main() {
                     }
                 })
                 .unwrap_or_else(random_username);
-            start_daemon(&host, port, &username)
+            start_daemon(&host, port, &username, password.as_deref(), reconnect)
         }
         Commands::Disconnect => stop_daemon(),
+        Commands::Discover { window_ms, port } => discover_servers(window_ms, port),
+        Commands::Probe { host, port, timeout_ms } => probe_server(&host, port, timeout_ms),
         cmd @ Commands::MoveTo { blocking, timeout_ms, .. } => match send_command(cmd) {
             Ok(response) => {
                 if blocking && response.success {
@@ -832,6 +863,7 @@ fn main() {
                                     success,
                                     result: Some(result),
                                     error,
+                                    code: response.code,
                                 })
                             }
                             Err(e) => Err(e),
@@ -905,7 +937,13 @@ fn random_username() -> String {
     format!("FactorioBot-{:04x}-{}", pid & 0xffff, suffix)
 }
 
-fn start_daemon(host: &str, port: u16, username: &str) -> Result<Response, Box<dyn std::error::Error>> {
+fn start_daemon(
+    host: &str,
+    port: u16,
+    username: &str,
+    password: Option<&str>,
+    reconnect: bool,
+) -> Result<Response, Box<dyn std::error::Error>> {
     let socket_path = daemon::socket_path();
     let log_path = socket_path
         .parent()
@@ -921,6 +959,7 @@ fn start_daemon(host: &str, port: u16, username: &str) -> Result<Response, Box<d
                         success: true,
                         result: Some(json!({"message": "Already connected"})),
                         error: None,
+                        code: None,
                     });
                 }
             }
@@ -943,11 +982,21 @@ fn start_daemon(host: &str, port: u16, username: &str) -> Result<Response, Box<d
         .open(&log_path)?;
     let log_file_err = log_file.try_clone()?;
 
-    let mut child = Command::new(&exe)
+    let mut command = Command::new(&exe);
+    command
         .args(["--host", host, "--port", &port.to_string(), "--username", username])
         .stdout(Stdio::from(log_file))
-        .stderr(Stdio::from(log_file_err))
-        .spawn()?;
+        .stderr(Stdio::from(log_file_err));
+    // Pass the password through the environment so it doesn't show up in `ps`.
+    if let Some(password) = password {
+        command.env("FACTORIO_PASSWORD", password);
+    }
+    if reconnect {
+        command.arg("--reconnect");
+    }
+    // Whatever a previous daemon left behind is not about this attempt
+    let _ = std::fs::remove_file(daemon::error_path());
+    let mut child = command.spawn()?;
 
     for _ in 0..200 {
         std::thread::sleep(std::time::Duration::from_millis(100));
@@ -958,10 +1007,15 @@ fn start_daemon(host: &str, port: u16, username: &str) -> Result<Response, Box<d
                     success: true,
                     result: Some(json!({"message": "Connected"})),
                     error: None,
+                    code: None,
                 });
             }
         }
         if let Some(status) = child.try_wait()? {
+            if let Some(response) = daemon::read_join_error() {
+                let _ = std::fs::remove_file(daemon::error_path());
+                return Ok(response);
+            }
             let tail = std::fs::read_to_string(&log_path)
                 .ok()
                 .map(|content| {
@@ -995,6 +1049,72 @@ fn start_daemon(host: &str, port: u16, username: &str) -> Result<Response, Box<d
     ).into())
 }
 
+fn discover_servers(window_ms: u64, port: u16) -> Result<Response, Box<dyn std::error::Error>> {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let bind = std::net::SocketAddr::from(([0, 0, 0, 0], port));
+    let servers = runtime.block_on(factorio_client::protocol::discovery::discover_on(
+        bind,
+        Duration::from_millis(window_ms),
+    ))?;
+    let servers: Vec<_> = servers
+        .iter()
+        .map(|s| {
+            json!({
+                "name": s.name,
+                "address": s.addr.to_string(),
+                "version": format!("{}.{}.{}", s.version.major, s.version.minor, s.version.patch),
+                "build": s.version.build,
+                "players": s.player_count,
+            })
+        })
+        .collect();
+    Ok(Response {
+        id: "discover".into(),
+        success: true,
+        result: Some(json!({ "servers": servers })),
+        error: None,
+        code: None,
+    })
+}
+
+fn probe_server(host: &str, port: u16, timeout_ms: u64) -> Result<Response, Box<dyn std::error::Error>> {
+    use std::net::ToSocketAddrs;
+
+    let addr = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| format!("could not resolve {}", host))?;
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let info = runtime.block_on(factorio_client::protocol::probe_with_timeout(
+        addr,
+        Duration::from_millis(timeout_ms),
+    ))?;
+    let mods: Vec<_> = info
+        .mods
+        .iter()
+        .map(|m| json!({"name": m.name, "version": m.version.to_string(), "crc": format!("{:08x}", m.crc)}))
+        .collect();
+    Ok(Response {
+        id: "probe".into(),
+        success: true,
+        result: Some(json!({
+            "address": addr.to_string(),
+            "name": info.name,
+            "description": info.description,
+            "version": format!("{}.{}.{}", info.version.major, info.version.minor, info.version.patch),
+            "build": info.version.build,
+            "max_players": info.max_players,
+            "has_password": info.has_password,
+            "players": info.players,
+            "tick": info.tick,
+            "mods": mods,
+            "partial": info.partial,
+        })),
+        error: None,
+        code: None,
+    })
+}
+
 fn probe_daemon_status(socket_path: &std::path::Path) -> Result<Option<serde_json::Value>, Box<dyn std::error::Error>> {
     let mut stream = UnixStream::connect(socket_path)?;
     stream.set_read_timeout(Some(std::time::Duration::from_millis(300)))?;
@@ -1024,6 +1144,11 @@ fn stop_daemon() -> Result<Response, Box<dyn std::error::Error>> {
         unsafe {
             libc::kill(pid, libc::SIGTERM);
         }
+        // The daemon leaves the game on SIGTERM; give it time to get the server's ack.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline && unsafe { libc::kill(pid, 0) } == 0 {
+            std::thread::sleep(Duration::from_millis(50));
+        }
         let _ = std::fs::remove_file(&pid_path);
     }
 
@@ -1035,6 +1160,7 @@ fn stop_daemon() -> Result<Response, Box<dyn std::error::Error>> {
         success: true,
         result: Some(json!({"message": "Disconnected"})),
         error: None,
+        code: None,
     })
 }
 
@@ -1225,10 +1351,10 @@ fn build_map_progress_line(status: &serde_json::Value) -> Option<String> {
 
 fn build_request(cmd: Commands) -> Result<(String, serde_json::Value), Box<dyn std::error::Error>> {
     match cmd {
-        Commands::Position | Commands::Status { .. } | Commands::ActionStatus { .. } | Commands::State { .. } |
+        Commands::Position | Commands::Players | Commands::Status { .. } | Commands::ActionStatus { .. } | Commands::State { .. } |
         Commands::Recipes | Commands::Techs | Commands::Alerts { .. } | Commands::GetTrain { .. } |
         Commands::GetSignals { .. } | Commands::PowerStatus { .. } | Commands::LogisticsStatus { .. } |
-        Commands::Pollution { .. } | Commands::Chat { .. } => build_status_request(cmd),
+        Commands::Pollution { .. } | Commands::Chat { .. } | Commands::ChatLog { .. } => build_status_request(cmd),
         Commands::Walk { .. } | Commands::Stop | Commands::MoveTo { .. } |
         Commands::FindPath { .. } | Commands::Mine { .. } | Commands::StopMining => {
             build_movement_request(cmd)
@@ -1267,6 +1393,7 @@ fn build_request(cmd: Commands) -> Result<(String, serde_json::Value), Box<dyn s
 fn build_status_request(cmd: Commands) -> Result<(String, serde_json::Value), Box<dyn std::error::Error>> {
     match cmd {
         Commands::Position => Ok(("position".into(), json!({}))),
+        Commands::Players => Ok(("players".into(), json!({}))),
         Commands::Status { .. } => Ok(("status".into(), json!({}))),
         Commands::ActionStatus { id } => {
             let mut args = json!({});
@@ -1333,6 +1460,13 @@ fn build_status_request(cmd: Commands) -> Result<(String, serde_json::Value), Bo
             Ok(("pollution".into(), args))
         }
         Commands::Chat { message } => Ok(("chat".into(), json!({"message": message}))),
+        Commands::ChatLog { since } => {
+            let mut args = json!({});
+            if let Some(tick) = since {
+                args["since"] = json!(tick);
+            }
+            Ok(("chat-log".into(), args))
+        }
         _ => Err("Invalid command".into()),
     }
 }
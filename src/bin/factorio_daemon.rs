@@ -1,5 +1,7 @@
 use clap::Parser;
 use factorio_client::daemon::{self, Daemon};
+use factorio_client::Credentials;
+use factorio_client::protocol::ReconnectPolicy;
 use std::fs::OpenOptions;
 use std::os::unix::io::AsRawFd;
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -17,6 +19,25 @@ struct Args {
     #[arg(long)]
     username: Option<String>,
 
+    /// Game password (falls back to FACTORIO_PASSWORD so it stays out of the process list).
+    /// Experimental: how it is hashed is unconfirmed against the game
+    #[arg(long)]
+    password: Option<String>,
+
+    #[arg(long)]
+    server_key: Option<String>,
+
+    #[arg(long)]
+    server_key_timestamp: Option<String>,
+
+    /// Reconnect with exponential backoff if the connection drops
+    #[arg(long)]
+    reconnect: bool,
+
+    /// Give up after this many reconnect attempts (default: retry forever)
+    #[arg(long)]
+    reconnect_max_attempts: Option<u32>,
+
     #[arg(long)]
     foreground: bool,
 }
@@ -66,16 +87,35 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         })
         .unwrap_or_else(random_username);
 
-    let daemon = match Daemon::connect(&args.host, args.port, &username).await {
+    let credentials = Credentials {
+        password: args
+            .password
+            .or_else(|| std::env::var("FACTORIO_PASSWORD").ok())
+            .filter(|p| !p.is_empty()),
+        server_key: args.server_key,
+        server_key_timestamp: args.server_key_timestamp,
+    };
+
+    let mut daemon = match Daemon::connect_with_credentials(&args.host, args.port, &username, credentials).await {
         Ok(d) => d,
         Err(e) => {
             eprintln!("Daemon connect failed: {}", e);
+            if let Err(write_err) = daemon::write_join_error(&e) {
+                eprintln!("Could not record the connect error: {}", write_err);
+            }
             return Err(e.into());
         }
     };
 
     eprintln!("Connected! Player ID: {:?}", daemon.player_id());
 
+    if args.reconnect {
+        daemon.connection.set_reconnect_policy(Some(ReconnectPolicy {
+            max_attempts: args.reconnect_max_attempts,
+            ..ReconnectPolicy::default()
+        }));
+    }
+
     std::fs::write(&pid_path, std::process::id().to_string())?;
 
     eprintln!("Daemon running on {:?}", socket_path);
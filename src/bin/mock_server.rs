@@ -0,0 +1,44 @@
+use clap::Parser;
+use factorio_client::protocol::{MockServer, MockServerConfig};
+use tokio::net::UdpSocket;
+
+#[derive(Parser)]
+#[command(name = "mock-server")]
+#[command(about = "Stand-in Factorio server for integration tests (no game logic)")]
+struct Args {
+    #[arg(long, default_value = "0.0.0.0:34197")]
+    bind: String,
+
+    /// Save file served as the map download
+    #[arg(long)]
+    map: Option<String>,
+
+    #[arg(long)]
+    password: Option<String>,
+
+    #[arg(long, default_value = "mock server")]
+    name: String,
+
+    #[arg(long, default_value_t = 216_000)]
+    start_tick: u32,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let mut config = MockServerConfig {
+        name: args.name,
+        password: args.password,
+        start_tick: args.start_tick,
+        ..MockServerConfig::default()
+    };
+    if let Some(path) = &args.map {
+        config = config.with_map_file(path)?;
+    }
+
+    let socket = UdpSocket::bind(&args.bind).await?;
+    println!("mock server listening on {} ({} byte map)", socket.local_addr()?, config.map.len());
+    MockServer::new(config).serve_udp(socket).await?;
+    Ok(())
+}
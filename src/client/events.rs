@@ -1,4 +1,7 @@
-use crate::codec::{InputAction, MapPosition, Direction};
+use std::time::Duration;
+
+use crate::codec::{InputAction, MapPosition, Direction, SynchronizerAction};
+use crate::protocol::MapDownloadProgress;
 use crate::state::{PlayerId, EntityId};
 
 /// Game events that can be received
@@ -40,9 +43,12 @@ pub enum GameEvent {
         entity_id: EntityId,
     },
 
-    /// Chat message received
+    /// Chat message from a tick closure. `player_id` is `None` for messages
+    /// typed into the server console.
     ChatMessage {
+        tick: u32,
         player_id: Option<PlayerId>,
+        username: Option<String>,
         message: String,
     },
 
@@ -62,10 +68,21 @@ pub enum GameEvent {
         action: InputAction,
     },
 
-    /// Map download progress
+    /// Map download progress, emitted a few times per second while downloading
     MapDownloadProgress {
+        /// Bytes received
         received: usize,
+        /// Bytes in the whole save
         total: usize,
+        blocks_received: u32,
+        blocks_total: u32,
+        /// Requests currently allowed in flight
+        window: u32,
+        retransmits: u64,
+        /// Fraction of requests that had to be repeated
+        loss_rate: f64,
+        /// Time since this attempt started (a resumed download restarts the clock)
+        elapsed: Duration,
     },
 
     /// Map download completed
@@ -81,6 +98,19 @@ pub enum GameEvent {
         reason: DisconnectReason,
     },
 
+    /// Reconnected after a drop (handshake and map download redone)
+    Reconnected {
+        player_index: Option<PlayerId>,
+        attempts: u32,
+    },
+
+    /// Synchronizer action from a server heartbeat: peers joining or leaving,
+    /// pauses, saves, desyncs, progress updates and the like
+    SyncAction {
+        peer_id: u16,
+        action: SynchronizerAction,
+    },
+
     /// Desync detected
     Desync {
         tick: u32,
@@ -103,6 +133,21 @@ pub enum DisconnectReason {
     Other(String),
 }
 
+impl From<MapDownloadProgress> for GameEvent {
+    fn from(progress: MapDownloadProgress) -> Self {
+        GameEvent::MapDownloadProgress {
+            received: progress.bytes_received as usize,
+            total: progress.bytes_total as usize,
+            blocks_received: progress.blocks_received,
+            blocks_total: progress.blocks_total,
+            window: progress.window,
+            retransmits: progress.retransmits,
+            loss_rate: progress.loss_rate,
+            elapsed: progress.elapsed,
+        }
+    }
+}
+
 impl DisconnectReason {
     pub fn from_code(code: u8) -> Self {
         match code {
@@ -1,7 +1,9 @@
 pub mod session;
 pub mod events;
 pub mod commands;
+pub mod pool;
 
 pub use session::{Session, ClientBuilder, ClientConfig};
 pub use events::{GameEvent, DisconnectReason, EventHandler, EventCollector};
 pub use commands::ActionBuilder;
+pub use pool::ClientPool;
@@ -0,0 +1,240 @@
+//! Several bot connections to one server, sharing the map
+//!
+//! Every connection of a `ClientPool` keeps its own player, action queue and
+//! heartbeat loop, but the save is downloaded and parsed once: the connections
+//! share a `SharedMaps`, so whichever is offered the save first fetches it and
+//! the rest take the same save bytes, `Arc<MapData>` and starting world.
+//! Prototypes are already process-wide (`Prototypes::global()`) and are loaded
+//! once however many connections run.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tokio::task::JoinSet;
+
+use crate::codec::MapData;
+use crate::error::{Error, Result};
+use crate::protocol::{Connection, ReceivedPacket, SharedMaps};
+use super::session::ClientConfig;
+
+/// Connections to the same server sharing one map download
+pub struct ClientPool {
+    connections: Vec<Connection>,
+    maps: SharedMaps,
+}
+
+impl ClientPool {
+    /// Open `count` connections, named `{username}_{n}`.
+    pub async fn connect(config: &ClientConfig, count: usize) -> Result<Self> {
+        let mut connections = Vec::with_capacity(count);
+        for n in 0..count {
+            let mut conn = Connection::new_with_credentials(
+                config.server_addr,
+                format!("{}_{}", config.username, n),
+                config.credentials.clone(),
+            ).await?;
+            conn.set_handshake_config(config.handshake.clone());
+            conn.set_reconnect_policy(config.reconnect.clone());
+            connections.push(conn);
+        }
+        Ok(Self::from_connections(connections))
+    }
+
+    /// Pool connections that are set up but not yet connected.
+    pub fn from_connections(mut connections: Vec<Connection>) -> Self {
+        let maps = SharedMaps::new();
+        for conn in &mut connections {
+            conn.set_shared_maps(Some(maps.clone()));
+        }
+        Self { connections, maps }
+    }
+
+    /// Connect every connection and download the map, all at once so none
+    /// sits idle while the others join. Returns the first error. A connection
+    /// whose task panicked is lost with it and leaves the pool; the others
+    /// stay, joined or not.
+    pub async fn join(&mut self, parse_map: bool) -> Result<()> {
+        let mut tasks = JoinSet::new();
+        let mut indices = HashMap::new();
+        for (i, mut conn) in self.connections.drain(..).enumerate() {
+            let handle = tasks.spawn(async move {
+                let result = match conn.connect().await {
+                    Ok(()) => conn.download_map_with_parse(parse_map).await.map(|_| ()),
+                    Err(e) => Err(e),
+                };
+                (i, conn, result)
+            });
+            indices.insert(handle.id(), i);
+        }
+
+        let mut joined = Vec::with_capacity(tasks.len());
+        let mut first_error = None;
+        while let Some(task) = tasks.join_next().await {
+            match task {
+                Ok((i, conn, result)) => {
+                    if let Err(e) = result {
+                        first_error.get_or_insert((i, e));
+                    }
+                    joined.push((i, conn));
+                }
+                Err(e) => {
+                    first_error.get_or_insert((indices[&e.id()], Error::TaskFailed(e.to_string())));
+                }
+            }
+        }
+        joined.sort_by_key(|(i, _)| *i);
+        self.connections = joined.into_iter().map(|(_, conn)| conn).collect();
+
+        match first_error {
+            Some((_, e)) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Poll each connection once.
+    pub async fn poll_all(&mut self) -> Result<Vec<Option<ReceivedPacket>>> {
+        let mut packets = Vec::with_capacity(self.connections.len());
+        for conn in &mut self.connections {
+            packets.push(conn.poll().await?);
+        }
+        Ok(packets)
+    }
+
+    pub fn connections(&self) -> &[Connection] {
+        &self.connections
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Connection> {
+        self.connections.get_mut(index)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Connection> {
+        self.connections.iter_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    pub fn shared_maps(&self) -> &SharedMaps {
+        &self.maps
+    }
+
+    /// The parsed map, once any connection has joined with parsing on
+    pub fn map(&self) -> Option<Arc<MapData>> {
+        self.connections.iter().find_map(Connection::shared_map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::protocol::message::Credentials;
+    use crate::protocol::mock_server::{mock_session, MockServerConfig, MOCK_SERVER_ADDR};
+    use crate::protocol::transport::{DatagramSocket, Transport};
+    use crate::protocol::ConnectionState;
+
+    #[tokio::test]
+    async fn test_pool_downloads_map_once() {
+        let map: Vec<u8> = (0..4000u32).map(|i| (i * 7) as u8).collect();
+        let mut servers = Vec::new();
+        let mut connections = Vec::new();
+        for n in 0..3 {
+            let (client, server) = mock_session(MockServerConfig { map: map.clone(), ..MockServerConfig::default() });
+            servers.push(server);
+            connections.push(Connection::with_transport(MOCK_SERVER_ADDR, Transport::with_socket(client), format!("bot_{}", n), Credentials::default()));
+        }
+
+        let mut pool = ClientPool::from_connections(connections);
+        pool.join(false).await.unwrap();
+        assert_eq!(pool.len(), 3);
+        assert_eq!(pool.shared_maps().len(), 1);
+        for conn in pool.connections() {
+            assert_eq!(conn.state(), ConnectionState::InGame);
+            assert_eq!(conn.map_data(), &map[..]);
+        }
+        pool.poll_all().await.unwrap();
+
+        drop(pool);
+        for server in servers {
+            server.abort();
+        }
+    }
+
+    struct PanickingSocket;
+
+    #[async_trait::async_trait]
+    impl DatagramSocket for PanickingSocket {
+        async fn send(&mut self, _data: &[u8]) -> Result<()> {
+            panic!("socket exploded");
+        }
+
+        async fn recv(&mut self) -> Result<Vec<u8>> {
+            std::future::pending().await
+        }
+
+        fn try_recv(&mut self) -> Result<Option<Vec<u8>>> {
+            Ok(None)
+        }
+
+        fn local_addr(&self) -> Result<SocketAddr> {
+            Ok("127.0.0.1:40009".parse().unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pool_join_survives_a_panicking_connection() {
+        let (client, server) = mock_session(MockServerConfig { map: vec![1; 1000], ..MockServerConfig::default() });
+        let connections = vec![
+            Connection::with_transport(MOCK_SERVER_ADDR, Transport::with_socket(client), "bot_0".into(), Credentials::default()),
+            Connection::with_transport(MOCK_SERVER_ADDR, Transport::with_socket(PanickingSocket), "bot_1".into(), Credentials::default()),
+        ];
+
+        let mut pool = ClientPool::from_connections(connections);
+        let err = pool.join(false).await.unwrap_err();
+        assert_eq!(err.code(), "task_failed");
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.connections()[0].state(), ConnectionState::InGame);
+
+        drop(pool);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_pool_shares_parsed_map() {
+        let Ok(map) = std::fs::read("server_map.zip") else {
+            return;
+        };
+        // Resource scanning is most of a debug-build parse and beside the point here
+        std::env::set_var("FACTORIO_SKIP_RESOURCE_PARSE", "1");
+        let mut servers = Vec::new();
+        let mut connections = Vec::new();
+        for n in 0..3 {
+            let (client, server) = mock_session(MockServerConfig { map: map.clone(), ..MockServerConfig::default() });
+            servers.push(server);
+            connections.push(Connection::with_transport(MOCK_SERVER_ADDR, Transport::with_socket(client), format!("bot_{}", n), Credentials::default()));
+        }
+
+        let mut pool = ClientPool::from_connections(connections);
+        pool.join(true).await.unwrap();
+        let parsed = pool.map().expect("map parsed");
+        assert!(!parsed.entities.is_empty());
+        let first = &pool.connections()[0];
+        for conn in pool.connections() {
+            assert!(Arc::ptr_eq(&conn.shared_map().unwrap(), &parsed));
+            assert!(std::ptr::eq(conn.map_data(), first.map_data()));
+            assert!(std::ptr::eq(conn.entities(), &parsed.entities[..]));
+        }
+
+        drop(pool);
+        for server in servers {
+            server.abort();
+        }
+    }
+}
@@ -2,16 +2,19 @@ use std::net::SocketAddr;
 use std::time::Duration;
 
 use crate::error::Result;
-use crate::protocol::{Connection, ConnectionState};
+use crate::protocol::{Connection, ConnectionState, Credentials, HandshakeConfig, ReconnectPolicy};
 use crate::state::{GameWorld, PlayerId};
 use crate::state::entity::entity_type_from_name;
-use crate::client::events::{GameEvent, EventCollector};
+use crate::client::events::{GameEvent, EventCollector, EventHandler};
 
 /// Client configuration
 #[derive(Debug, Clone)]
 pub struct ClientConfig {
     pub server_addr: SocketAddr,
     pub username: String,
+    pub credentials: Credentials,
+    pub handshake: HandshakeConfig,
+    pub reconnect: Option<ReconnectPolicy>,
     pub receive_timeout: Duration,
 }
 
@@ -20,6 +23,9 @@ impl ClientConfig {
         Self {
             server_addr,
             username: username.into(),
+            credentials: Credentials::default(),
+            handshake: HandshakeConfig::from_env(),
+            reconnect: None,
             receive_timeout: Duration::from_millis(100),
         }
     }
@@ -42,6 +48,31 @@ impl ClientBuilder {
         self
     }
 
+    /// Game password for password-protected servers.
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.config.credentials.password = Some(password.into());
+        self
+    }
+
+    /// Override the version, mod list or checksums sent during the handshake.
+    pub fn handshake(mut self, handshake: HandshakeConfig) -> Self {
+        self.config.handshake = handshake;
+        self
+    }
+
+    /// Reconnect automatically after a drop (see `GameEvent::Reconnected`).
+    pub fn reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.config.reconnect = Some(policy);
+        self
+    }
+
+    /// User verification token pair (`server_key`, `timestamp`) from the auth server.
+    pub fn server_key(mut self, key: impl Into<String>, timestamp: impl Into<String>) -> Self {
+        self.config.credentials.server_key = Some(key.into());
+        self.config.credentials.server_key_timestamp = Some(timestamp.into());
+        self
+    }
+
     pub async fn connect(self) -> Result<Session> {
         Session::connect(self.config).await
     }
@@ -57,10 +88,13 @@ pub struct Session {
 
 impl Session {
     async fn connect(config: ClientConfig) -> Result<Self> {
-        let mut connection = Connection::new(
+        let mut connection = Connection::new_with_credentials(
             config.server_addr,
             config.username,
+            config.credentials,
         ).await?;
+        connection.set_handshake_config(config.handshake);
+        connection.set_reconnect_policy(config.reconnect);
 
         connection.connect().await?;
 
@@ -134,8 +168,23 @@ impl Session {
         self.connection.server_name()
     }
 
+    /// Poll the connection once, collecting any events it produced
+    pub async fn poll(&mut self) -> Result<()> {
+        self.connection.poll().await?;
+        for event in self.connection.drain_events() {
+            if let GameEvent::Reconnected { player_index, .. } = &event {
+                self.local_player_id = *player_index;
+            }
+            self.events.on_event(event);
+        }
+        Ok(())
+    }
+
     /// Drain pending events
     pub fn drain_events(&mut self) -> Vec<GameEvent> {
+        for event in self.connection.drain_events() {
+            self.events.on_event(event);
+        }
         self.events.drain()
     }
 
@@ -1,4 +1,4 @@
-use crate::codec::{BinaryReader, BinaryWriter, MapPosition, Direction};
+use crate::codec::{ActionNumbering, BinaryReader, BinaryWriter, MapPosition, Direction};
 use crate::error::{Error, Result};
 
 /// Input action type IDs (from Factorio 2.0 binary reverse engineering)
@@ -920,13 +920,18 @@ impl InputAction {
 
     /// Write the action type as var u16 (1 byte if < 256, else 0xFF + u16)
     pub fn write_type(&self, writer: &mut BinaryWriter) {
-        let action_type = self.action_type() as u16;
-        if action_type < 256 {
-            writer.write_u8(action_type as u8);
-        } else {
-            writer.write_u8(0xFF);
-            writer.write_u16_le(action_type);
-        }
+        write_type_id(writer, self.action_type() as u16);
+    }
+
+    /// Write the action type under another build's numbering. Fails if that
+    /// build has no such action.
+    pub fn write_type_numbered(&self, writer: &mut BinaryWriter, numbering: &ActionNumbering) -> Result<()> {
+        let action_type = self.action_type();
+        let id = numbering.input_action_id(action_type).ok_or_else(|| {
+            Error::InvalidPacket(format!("input action {:?} does not exist in this game version", action_type))
+        })?;
+        write_type_id(writer, id);
+        Ok(())
     }
 
     /// Write just the action data (without the type)
@@ -1292,24 +1297,56 @@ impl InputAction {
         self.write_data(writer);
     }
 
+    /// `write_protocol_order` with the action id taken from `numbering`
+    pub fn write_protocol_order_numbered(
+        &self,
+        writer: &mut BinaryWriter,
+        player_index_delta: u16,
+        numbering: &ActionNumbering,
+    ) -> Result<()> {
+        self.write_type_numbered(writer, numbering)?;
+        writer.write_opt_u16(player_index_delta);
+        self.write_data(writer);
+        Ok(())
+    }
+
     pub fn read(reader: &mut BinaryReader) -> Result<Self> {
-        Self::read_inner(reader, false)
+        Self::read_inner(reader, false, &ActionNumbering::CANONICAL)
     }
 
     pub fn read_known(reader: &mut BinaryReader) -> Result<Self> {
-        Self::read_inner(reader, true)
+        Self::read_inner(reader, true, &ActionNumbering::CANONICAL)
+    }
+
+    /// Read an action whose type id follows another build's numbering
+    pub fn read_numbered(reader: &mut BinaryReader, numbering: &ActionNumbering) -> Result<Self> {
+        Self::read_inner(reader, false, numbering)
+    }
+
+    /// Read the data of an action whose type id was already read, as in tick
+    /// closures, where it is an opt_u16 of its own
+    pub fn read_data_numbered(action_type: u16, reader: &mut BinaryReader, numbering: &ActionNumbering) -> Result<Self> {
+        Self::read_data_inner(action_type, reader, false, numbering)
     }
 
-    fn read_inner(reader: &mut BinaryReader, strict: bool) -> Result<Self> {
+    fn read_inner(reader: &mut BinaryReader, strict: bool, numbering: &ActionNumbering) -> Result<Self> {
         // Action type is varint-encoded: u8 if < 255, else 0xFF + u16
         let action_type = match reader.read_u8()? {
             0xFF => reader.read_u16_le()?,
             v => v as u16,
         };
+        Self::read_data_inner(action_type, reader, strict, numbering)
+    }
 
+    fn read_data_inner(
+        action_type: u16,
+        reader: &mut BinaryReader,
+        strict: bool,
+        numbering: &ActionNumbering,
+    ) -> Result<Self> {
         // For action types > 255, treat as unknown (will become Raw)
-        match if action_type <= 255 { 
-            InputActionType::from_u8(action_type as u8) 
+        match if action_type <= 255 || !numbering.is_canonical() {
+            numbering.input_action_type(action_type)
         } else { 
             None 
         } {
@@ -1565,6 +1602,15 @@ impl InputAction {
     }
 }
 
+fn write_type_id(writer: &mut BinaryWriter, action_type: u16) {
+    if action_type < 256 {
+        writer.write_u8(action_type as u8);
+    } else {
+        writer.write_u8(0xFF);
+        writer.write_u16_le(action_type);
+    }
+}
+
 fn write_relative_item_stack_location(
     writer: &mut BinaryWriter,
     location: RelativeItemStackLocation,
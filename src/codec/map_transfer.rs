@@ -1,5 +1,5 @@
 use std::io::{Read, Cursor};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
 use serde::{Deserialize, Serialize};
@@ -242,12 +242,21 @@ pub fn parse_map_data(data: &[u8]) -> Result<MapData> {
 pub fn parse_map_data_with_progress(
     data: &[u8],
     progress: Option<Arc<ParseProgress>>,
+) -> Result<MapData> {
+    parse_map_data_with_format(data, MapFormat::FACTORIO_2_0, progress)
+}
+
+/// Parse a save written in `format`, refusing maps from other builds
+pub fn parse_map_data_with_format(
+    data: &[u8],
+    format: MapFormat,
+    progress: Option<Arc<ParseProgress>>,
 ) -> Result<MapData> {
     if let Some(p) = progress.as_ref() {
         p.set_stage(ParseStage::Init);
     }
     let result = if data.len() >= 4 && &data[0..4] == b"PK\x03\x04" {
-        parse_zip_map_with_progress(data, progress.clone())
+        parse_zip_map_with_progress(data, format, progress.clone())
     } else {
         let decompressed = decompress_if_needed(data)?;
         MapData::parse(&decompressed)
@@ -2447,31 +2456,54 @@ fn skip_localised_string(reader: &mut BinaryReader) -> Result<()> {
     Ok(())
 }
 
+/// Run one section of a level.dat stream, tagging a failure with the section,
+/// the offset it started at and the offset it failed at
+fn section<'a, T>(
+    reader: &mut BinaryReader<'a>,
+    name: &'static str,
+    parse: impl FnOnce(&mut BinaryReader<'a>) -> Result<T>,
+) -> Result<T> {
+    let start = reader.position();
+    parse(reader).map_err(|e| e.in_section(name, start, reader.position()))
+}
+
+fn read_map_header_ticks(reader: &mut BinaryReader) -> Result<(u64, u64, u64)> {
+    Ok((reader.read_u64_le()?, reader.read_u64_le()?, reader.read_u64_le()?))
+}
+
+/// Fields between the RNG state and the prototype mappings (doc lines 1409-1411)
+fn skip_unknown_map_fields(reader: &mut BinaryReader) -> Result<()> {
+    let _unknown_bool = reader.read_bool()?;
+    let _unknown_u32 = reader.read_u32_le()?;
+    let _unknown_u16 = reader.read_u16_le()?;
+    Ok(())
+}
+
 impl LevelDatStream {
-    fn parse(data: &[u8]) -> Result<Self> {
+    fn parse(data: &[u8], format: MapFormat) -> Result<Self> {
         let mut reader = BinaryReader::new(data);
         let debug = std::env::var("FACTORIO_DEBUG").is_ok();
 
         // Parse shared header (doc lines 1269-1316)
-        let version = skip_shared_header(&mut reader)?;
+        let version = section(&mut reader, "shared header", skip_shared_header)?;
+        format.check(&version)?;
 
         // Now we're at MapSerialiser data (doc lines 1391-1444)
         // Note: MapSerialiser may or may not write its own MapVersion depending on flags
 
         // Check if next 9 bytes look like another MapVersion
         let peek_pos = reader.position();
-        let peek_major = u16::from_le_bytes([data[peek_pos], data[peek_pos + 1]]);
-        if peek_major == version.major {
+        let peek_major = data.get(peek_pos..peek_pos + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+        if peek_major == Some(version.major) {
             // Skip the redundant MapVersion
-            let _ = MapVersion::read(&mut reader)?;
+            section(&mut reader, "map version", MapVersion::read)?;
             #[cfg(test)]
             eprintln!("DEBUG: Skipped redundant MapVersion, pos={}", reader.position());
         }
 
         // 2) MapHeader (doc lines 1400-1403)
-        let update_tick = reader.read_u64_le()?;
-        let entity_tick = reader.read_u64_le()?;
-        let ticks_played = reader.read_u64_le()?;
+        let header_start = reader.position();
+        let (update_tick, entity_tick, ticks_played) = section(&mut reader, "map header", read_map_header_ticks)?;
         if debug {
             eprintln!(
                 "[DEBUG] MapHeader ticks: update={} entity={} played={} pos={}",
@@ -2489,10 +2521,9 @@ impl LevelDatStream {
                 if debug {
                     eprintln!("[DEBUG] MapHeader invalid, falling back to parse_from_map_header at offset={}", offset);
                 }
-                reader = BinaryReader::new(&data[offset..]);
-                let update_tick = reader.read_u64_le()?;
-                let entity_tick = reader.read_u64_le()?;
-                let ticks_played = reader.read_u64_le()?;
+                reader.set_position(offset);
+                let (update_tick, entity_tick, ticks_played) =
+                    section(&mut reader, "map header", read_map_header_ticks)?;
                 if debug {
                     eprintln!(
                         "[DEBUG] MapHeader fallback ticks: update={} entity={} played={} pos={}",
@@ -2502,16 +2533,20 @@ impl LevelDatStream {
                         reader.position()
                     );
                 }
-                return Self::parse_from_map_header(data, offset, version, update_tick, entity_tick, ticks_played);
+                return Self::parse_from_map_header(data, offset, version, format, update_tick, entity_tick, ticks_played);
             }
-            return Err(Error::InvalidPacket("MapHeader tick values invalid".into()));
+            return Err(Error::InvalidPacket("MapHeader tick values invalid".into()).in_section(
+                "map header",
+                header_start,
+                header_start,
+            ));
         }
 
         // 3) MapGenSettings (doc line 1404)
         if debug {
             eprintln!("[DEBUG] MapGenSettings start pos={}", reader.position());
         }
-        let map_gen_settings = MapGenSettings::read(&mut reader)?;
+        let map_gen_settings = section(&mut reader, "map gen settings", MapGenSettings::read)?;
         if debug {
             eprintln!("[DEBUG] MapGenSettings end pos={}", reader.position());
             eprintln!("[DEBUG] property_expression_names:");
@@ -2528,21 +2563,18 @@ impl LevelDatStream {
         if debug {
             eprintln!("[DEBUG] MapSettings start pos={}", reader.position());
         }
-        skip_map_settings(&mut reader)?;
+        section(&mut reader, "map settings", skip_map_settings)?;
         if debug {
             eprintln!("[DEBUG] MapSettings end pos={}", reader.position());
         }
 
         // 5) Random generators - Space Age 2.0 observed as 86 bytes total.
-        let rng_len = if version.major >= 2 { 86 } else { 5 * 12 };
-        reader.skip(rng_len)?;
+        section(&mut reader, "random generators", |r| r.skip(format.rng_state_len))?;
         #[cfg(test)]
         eprintln!("DEBUG: RNGs skipped, pos={}", reader.position());
 
         // 6) Unknown map fields (doc lines 1409-1411)
-        let _unknown_bool = reader.read_bool()?;
-        let _unknown_u32 = reader.read_u32_le()?;
-        let _unknown_u16 = reader.read_u16_le()?;
+        section(&mut reader, "map fields", skip_unknown_map_fields)?;
         #[cfg(test)]
         eprintln!("DEBUG: Unknown fields done, pos={}", reader.position());
 
@@ -2550,71 +2582,72 @@ impl LevelDatStream {
         if debug {
             eprintln!("[DEBUG] Prototype mappings start pos={}", reader.position());
         }
-        let prototype_mappings = parse_all_prototype_mappings(&mut reader, &version)?;
+        let prototype_mappings =
+            section(&mut reader, "prototype mappings", |r| parse_all_prototype_mappings(r, &version))?;
         if debug {
             eprintln!("[DEBUG] Prototype mappings end pos={}", reader.position());
         }
 
         // 8) Prototype migration list (doc line 1413)
-        skip_prototype_migration_list(&mut reader)?;
+        section(&mut reader, "prototype migrations", skip_prototype_migration_list)?;
         if debug {
             eprintln!("[DEBUG] after migration list pos={}", reader.position());
         }
 
         // 9) Map version-gated block + MapModSettings (doc lines 1660+)
-        skip_map_version_gated_block(&mut reader, &version)?;
-        skip_map_mod_settings(&mut reader)?;
+        section(&mut reader, "version gated block", |r| skip_map_version_gated_block(r, &version))?;
+        section(&mut reader, "mod settings", skip_map_mod_settings)?;
         if debug {
             eprintln!("[DEBUG] after MapModSettings pos={}", reader.position());
         }
 
         // 10) Planets (Space Age)
-        skip_planets(&mut reader, &version)?;
+        section(&mut reader, "planets", |r| skip_planets(r, &version))?;
         if debug {
             eprintln!("[DEBUG] after Planets pos={}", reader.position());
         }
 
         // 11) Train manager
-        skip_train_manager(&mut reader)?;
+        section(&mut reader, "train manager", skip_train_manager)?;
         if debug {
             eprintln!("[DEBUG] after TrainManager pos={}", reader.position());
         }
 
         // 12) Planned entity updates
-        skip_planned_entity_updates(&mut reader)?;
+        section(&mut reader, "planned entity updates", skip_planned_entity_updates)?;
         if debug {
             eprintln!("[DEBUG] after PlannedEntityUpdates pos={}", reader.position());
         }
 
         // 13) Force manager + linked inventories
-        let force_count = skip_force_manager(&mut reader)?;
-        skip_force_linked_inventories(&mut reader, force_count)?;
+        let force_count = section(&mut reader, "force manager", skip_force_manager)?;
+        section(&mut reader, "linked inventories", |r| skip_force_linked_inventories(r, force_count))?;
         if debug {
             eprintln!("[DEBUG] after ForceManager/LinkedInventories pos={}", reader.position());
         }
 
         // 14) Control behavior manager / circuit network / spoil queue
-        skip_control_behavior_manager(&mut reader)?;
-        skip_circuit_network_manager(&mut reader)?;
-        skip_item_spoil_queue(&mut reader)?;
+        section(&mut reader, "control behavior manager", skip_control_behavior_manager)?;
+        section(&mut reader, "circuit network manager", skip_circuit_network_manager)?;
+        section(&mut reader, "item spoil queue", skip_item_spoil_queue)?;
         if debug {
             eprintln!("[DEBUG] after Control/Circuit/Spoil pos={}", reader.position());
         }
 
         // 15) Script areas/positions + destroyed hooks + rendering
-        skip_script_areas_positions(&mut reader)?;
-        skip_object_destroyed_hooks(&mut reader)?;
-        skip_script_rendering(&mut reader)?;
+        section(&mut reader, "script areas", skip_script_areas_positions)?;
+        section(&mut reader, "object destroyed hooks", skip_object_destroyed_hooks)?;
+        section(&mut reader, "script rendering", skip_script_rendering)?;
         if debug {
             eprintln!("[DEBUG] after Script blocks pos={}", reader.position());
         }
 
         // 16) Electric/Fluid/Heat/ExtraScript + runtime counters
-        skip_electric_network_manager(&mut reader)?;
-        skip_fluid_segment_manager(&mut reader)?;
-        skip_heat_buffer_manager(&mut reader)?;
-        skip_extra_script_data_inventories(&mut reader)?;
-        skip_map_runtime_counters(&mut reader)?;
+        section(&mut reader, "electric networks", skip_electric_network_manager)?;
+        section(&mut reader, "fluid segments", skip_fluid_segment_manager)?;
+        section(&mut reader, "heat buffers", skip_heat_buffer_manager)?;
+        section(&mut reader, "extra script inventories", skip_extra_script_data_inventories)?;
+        section(&mut reader, "runtime counters", skip_map_runtime_counters)?;
         if debug {
             eprintln!("[DEBUG] after Networks/Fluids/Heat/ExtraScript pos={}", reader.position());
         }
@@ -2638,37 +2671,37 @@ impl LevelDatStream {
         data: &[u8],
         header_offset: usize,
         version: MapVersion,
+        format: MapFormat,
         update_tick: u64,
         entity_tick: u64,
         ticks_played: u64,
     ) -> Result<Self> {
-        let mut reader = BinaryReader::new(&data[header_offset + 24..]); // Skip past the 3 u64 ticks
+        let mut reader = BinaryReader::new(data);
+        reader.set_position(header_offset + 24); // Skip past the 3 u64 ticks
         let debug = std::env::var("FACTORIO_DEBUG").is_ok();
 
         // 3) MapGenSettings (doc line 1404)
-        let map_gen_settings = MapGenSettings::read(&mut reader)?;
+        let map_gen_settings = section(&mut reader, "map gen settings", MapGenSettings::read)?;
 
         // 4) MapSettings (Space Age 2.0 flat format)
-        skip_map_settings(&mut reader)?;
+        section(&mut reader, "map settings", skip_map_settings)?;
 
         // 5) Random generators - Space Age 2.0 observed as 86 bytes total.
-        let rng_len = if version.major >= 2 { 86 } else { 5 * 12 };
-        reader.skip(rng_len)?;
+        section(&mut reader, "random generators", |r| r.skip(format.rng_state_len))?;
 
         // 6) Unknown map fields
-        let _unknown_bool = reader.read_bool()?;
-        let _unknown_u32 = reader.read_u32_le()?;
-        let _unknown_u16 = reader.read_u16_le()?;
+        section(&mut reader, "map fields", skip_unknown_map_fields)?;
 
         // 7) Prototype ID mappings (doc line 1443)
         #[cfg(test)]
         eprintln!("DEBUG: Starting prototype mappings at pos={}", reader.position());
-        let prototype_mappings = parse_all_prototype_mappings(&mut reader, &version)?;
+        let prototype_mappings =
+            section(&mut reader, "prototype mappings", |r| parse_all_prototype_mappings(r, &version))?;
         #[cfg(test)]
         eprintln!("DEBUG: Prototype mappings done, pos={}", reader.position());
 
         // 8) Prototype migration list (doc line 1413)
-        skip_prototype_migration_list(&mut reader)?;
+        section(&mut reader, "prototype migrations", skip_prototype_migration_list)?;
         if debug {
             eprintln!(
                 "[DEBUG] after migration list (fallback) pos={}",
@@ -2677,8 +2710,8 @@ impl LevelDatStream {
         }
 
         // 9) Map version-gated block + MapModSettings (doc lines 1660+)
-        skip_map_version_gated_block(&mut reader, &version)?;
-        skip_map_mod_settings(&mut reader)?;
+        section(&mut reader, "version gated block", |r| skip_map_version_gated_block(r, &version))?;
+        section(&mut reader, "mod settings", skip_map_mod_settings)?;
         if debug {
             eprintln!(
                 "[DEBUG] after MapModSettings (fallback) pos={}",
@@ -2687,7 +2720,7 @@ impl LevelDatStream {
         }
 
         // 10) Planets (Space Age)
-        skip_planets(&mut reader, &version)?;
+        section(&mut reader, "planets", |r| skip_planets(r, &version))?;
         if debug {
             eprintln!(
                 "[DEBUG] after Planets (fallback) pos={}",
@@ -2696,7 +2729,7 @@ impl LevelDatStream {
         }
 
         // 11) Train manager
-        skip_train_manager(&mut reader)?;
+        section(&mut reader, "train manager", skip_train_manager)?;
         if debug {
             eprintln!(
                 "[DEBUG] after TrainManager (fallback) pos={}",
@@ -2705,7 +2738,7 @@ impl LevelDatStream {
         }
 
         // 12) Planned entity updates
-        skip_planned_entity_updates(&mut reader)?;
+        section(&mut reader, "planned entity updates", skip_planned_entity_updates)?;
         if debug {
             eprintln!(
                 "[DEBUG] after PlannedEntityUpdates (fallback) pos={}",
@@ -2714,7 +2747,7 @@ impl LevelDatStream {
         }
 
         if std::env::var("FACTORIO_PARSE_FORCE").is_ok() {
-            skip_force_manager(&mut reader)?;
+            section(&mut reader, "force manager", skip_force_manager)?;
             if debug {
                 eprintln!(
                     "[DEBUG] after ForceManager (fallback) pos={}",
@@ -2724,9 +2757,9 @@ impl LevelDatStream {
         }
 
         if std::env::var("FACTORIO_PARSE_CONTROL").is_ok() {
-            skip_control_behavior_manager(&mut reader)?;
-            skip_circuit_network_manager(&mut reader)?;
-            skip_item_spoil_queue(&mut reader)?;
+            section(&mut reader, "control behavior manager", skip_control_behavior_manager)?;
+            section(&mut reader, "circuit network manager", skip_circuit_network_manager)?;
+            section(&mut reader, "item spoil queue", skip_item_spoil_queue)?;
             if debug {
                 eprintln!(
                     "[DEBUG] after Control/Circuit/Spoil (fallback) pos={}",
@@ -2736,9 +2769,9 @@ impl LevelDatStream {
         }
 
         if std::env::var("FACTORIO_PARSE_SCRIPT").is_ok() {
-            skip_script_areas_positions(&mut reader)?;
-            skip_object_destroyed_hooks(&mut reader)?;
-            skip_script_rendering(&mut reader)?;
+            section(&mut reader, "script areas", skip_script_areas_positions)?;
+            section(&mut reader, "object destroyed hooks", skip_object_destroyed_hooks)?;
+            section(&mut reader, "script rendering", skip_script_rendering)?;
             if debug {
                 eprintln!(
                     "[DEBUG] after Script blocks (fallback) pos={}",
@@ -2748,7 +2781,7 @@ impl LevelDatStream {
         }
 
         if std::env::var("FACTORIO_PARSE_NETWORKS").is_ok() {
-            skip_electric_network_manager(&mut reader)?;
+            section(&mut reader, "electric networks", skip_electric_network_manager)?;
             if debug {
                 eprintln!(
                     "[DEBUG] after ElectricNetworkManager (fallback) pos={}",
@@ -2758,7 +2791,7 @@ impl LevelDatStream {
         }
 
         if std::env::var("FACTORIO_PARSE_FLUIDS").is_ok() {
-            skip_fluid_segment_manager(&mut reader)?;
+            section(&mut reader, "fluid segments", skip_fluid_segment_manager)?;
             if debug {
                 eprintln!(
                     "[DEBUG] after FluidSegmentManager (fallback) pos={}",
@@ -2768,7 +2801,7 @@ impl LevelDatStream {
         }
 
         if std::env::var("FACTORIO_PARSE_HEAT").is_ok() {
-            skip_heat_buffer_manager(&mut reader)?;
+            section(&mut reader, "heat buffers", skip_heat_buffer_manager)?;
             if debug {
                 eprintln!(
                     "[DEBUG] after HeatBufferManager (fallback) pos={}",
@@ -2778,8 +2811,8 @@ impl LevelDatStream {
         }
 
         if std::env::var("FACTORIO_PARSE_EXTRA_SCRIPT").is_ok() {
-            skip_extra_script_data_inventories(&mut reader)?;
-            skip_map_runtime_counters(&mut reader)?;
+            section(&mut reader, "extra script inventories", skip_extra_script_data_inventories)?;
+            section(&mut reader, "runtime counters", skip_map_runtime_counters)?;
             if debug {
                 eprintln!(
                     "[DEBUG] after ExtraScriptData/runtime counters (fallback) pos={}",
@@ -2788,7 +2821,7 @@ impl LevelDatStream {
             }
         }
 
-        let end_position = header_offset + 24 + reader.position();
+        let end_position = reader.position();
         Ok(Self {
             version,
             update_tick,
@@ -3733,6 +3766,44 @@ fn collect_tile_chunk_indices(data: &[u8]) -> Vec<u32> {
     indices
 }
 
+/// Names of the save's players, in player index order.
+///
+/// A player record holds its color and chat color (RGBA f32 each) right before
+/// the length-prefixed name. Chat colors are opaque and player colors are not
+/// transparent, which tells the records apart from other colored data. In
+/// server_map.zip the six players (npace, MapDownloader7522, ..., MapDownloader2030)
+/// come out in the order the server numbered them.
+fn scan_for_player_names(data: &[u8]) -> Vec<String> {
+    const COLORS: usize = 32;
+    const MAX_NAME: usize = 60;
+    let mut names = Vec::new();
+    let mut i = 0;
+    while i + COLORS + 1 < data.len() {
+        let color = |n: usize| {
+            let at = i + n * 4;
+            f32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+        };
+        let len = data[i + COLORS] as usize;
+        let name_start = i + COLORS + 1;
+        let plausible = len > 0
+            && len <= MAX_NAME
+            && name_start + len <= data.len()
+            && (0..8).all(|n| (0.0..=1.0).contains(&color(n)))
+            && color(3) >= 0.1
+            && color(7) == 1.0
+            && data[name_start..name_start + len]
+                .iter()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'));
+        if plausible {
+            names.push(String::from_utf8_lossy(&data[name_start..name_start + len]).into_owned());
+            i = name_start + len;
+        } else {
+            i += 1;
+        }
+    }
+    names
+}
+
 fn derive_chunk_grid_from_indices(indices: &[u32]) -> Option<(u32, u32)> {
     if indices.is_empty() {
         return None;
@@ -3774,13 +3845,30 @@ fn derive_chunk_grid_from_indices(indices: &[u32]) -> Option<(u32, u32)> {
 // ZIP parsing
 // ============================================================================
 fn parse_zip_map(data: &[u8]) -> Result<MapData> {
-    parse_zip_map_with_progress(data, None)
+    parse_zip_map_with_progress(data, MapFormat::FACTORIO_2_0, None)
 }
 
 fn parse_zip_map_with_progress(
     data: &[u8],
+    format: MapFormat,
     progress: Option<Arc<ParseProgress>>,
 ) -> Result<MapData> {
+    let contents = read_save_archive(data)?;
+    parse_save_contents(contents, None, format, progress)
+}
+
+/// The parts of a save archive the parser uses
+#[derive(Debug, Default, PartialEq)]
+struct SaveContents {
+    file_names: Vec<String>,
+    mapgen_json: Option<String>,
+    /// level.dat0, level.dat1, ... decompressed and concatenated
+    level_dat: Vec<u8>,
+    /// level-init.dat (contains chunk data in newer saves)
+    level_init: Option<Vec<u8>>,
+}
+
+fn read_save_archive(data: &[u8]) -> Result<SaveContents> {
     let cursor = Cursor::new(data);
     let mut archive = zip::ZipArchive::new(cursor)
         .map_err(|e| Error::InvalidPacket(format!("ZIP error: {}", e)))?;
@@ -3838,12 +3926,35 @@ fn parse_zip_map_with_progress(
         }
     }
 
+    Ok(SaveContents {
+        file_names,
+        mapgen_json,
+        level_dat: full_stream,
+        level_init: level_init_data,
+    })
+}
+
+/// Build `MapData` from an unpacked save. `stream` is the level.dat header when
+/// it was already parsed (by `MapStreamParser`).
+fn parse_save_contents(
+    contents: SaveContents,
+    stream: Option<LevelDatStream>,
+    format: MapFormat,
+    progress: Option<Arc<ParseProgress>>,
+) -> Result<MapData> {
+    let SaveContents {
+        file_names,
+        mapgen_json,
+        level_dat: full_stream,
+        level_init: mut level_init_data,
+    } = contents;
+
     if let Some(p) = progress.as_ref() {
         p.set_stage(ParseStage::Prototypes);
     }
-    let mut stream = match LevelDatStream::parse(&full_stream) {
-        Ok(s) => s,
-        Err(e) => return Err(e),
+    let mut stream = match stream {
+        Some(s) => s,
+        None => LevelDatStream::parse(&full_stream, format)?,
     };
     let debug = std::env::var("FACTORIO_DEBUG").is_ok();
     if debug {
@@ -3858,8 +3969,10 @@ fn parse_zip_map_with_progress(
         );
     }
 
-    let mut surface_reader = BinaryReader::new(&full_stream[stream.end_position..]);
-    let mut surface_preludes = parse_surface_preludes(&mut surface_reader, &stream.version)?;
+    let mut surface_reader = BinaryReader::new(&full_stream);
+    surface_reader.set_position(stream.end_position);
+    let mut surface_preludes =
+        section(&mut surface_reader, "surface preludes", |r| parse_surface_preludes(r, &stream.version))?;
     if !surface_preludes.is_empty()
         && !is_surface_prelude_plausible(&surface_preludes, stream.map_width, stream.map_height)
     {
@@ -3961,6 +4074,7 @@ fn parse_zip_map_with_progress(
         full_chunk_indices
     };
 
+    let players = scan_for_player_names(&full_stream);
     let full_stream_arc = Arc::new(full_stream);
     let tile_stream_arc = if use_init_tiles {
         Arc::new(level_init_data.take().unwrap_or_default())
@@ -4168,6 +4282,7 @@ fn parse_zip_map_with_progress(
         entities,
         tiles,
         player_spawn: (0.0, 0.0),
+        players,
         raw_files: file_names,
         item_prototypes,
         recipe_prototypes,
@@ -4214,6 +4329,414 @@ fn decompress_if_needed(data: &[u8]) -> Result<Vec<u8>> {
     Ok(data.to_vec())
 }
 
+// ============================================================================
+// Streaming ZIP parsing
+// ============================================================================
+
+const ZIP_LOCAL_HEADER_LEN: usize = 30;
+const ZIP_DESCRIPTOR_SIGNATURE: &[u8; 4] = b"PK\x07\x08";
+/// Signature, CRC and both sizes as u64 (Factorio writes these even for small saves)
+const ZIP_DESCRIPTOR_MAX_LEN: usize = 24;
+/// level.dat parts past this are ignored, as in `read_save_archive`
+const MAX_LEVEL_DAT_PARTS: usize = 20;
+/// An early level.dat header parse only counts with this much data behind it,
+/// so none of the parser's lookahead ran into the end of a partial stream.
+const LEVEL_DAT_PARSE_MARGIN: usize = 64 * 1024;
+
+/// Save entries the parser reads
+enum SaveEntry {
+    MapGenSettings,
+    LevelInit,
+    LevelDat(usize),
+}
+
+impl SaveEntry {
+    fn from_name(name: &str) -> Option<Self> {
+        let file = name.rsplit('/').next().unwrap_or(name);
+        match file {
+            "map-gen-settings.json" => Some(Self::MapGenSettings),
+            "level-init.dat" => Some(Self::LevelInit),
+            _ => file
+                .strip_prefix("level.dat")?
+                .parse()
+                .ok()
+                .filter(|&part| part < MAX_LEVEL_DAT_PARTS)
+                .map(Self::LevelDat),
+        }
+    }
+}
+
+/// The zip entry currently arriving
+struct ZipEntry {
+    name: String,
+    kind: Option<SaveEntry>,
+    body: EntryBody,
+}
+
+enum EntryBody {
+    /// Exactly `remaining` more bytes, kept as they are
+    Sized { remaining: usize, data: Vec<u8> },
+    /// Deflate stream, inflated as it arrives. Without a size it runs until the
+    /// stream ends and a data descriptor follows.
+    Deflate { remaining: Option<usize>, inflater: flate2::Decompress, data: Vec<u8> },
+    /// Stored with its size only in the trailing data descriptor (as Factorio
+    /// writes level.datN): ends at the first descriptor giving its own offset as
+    /// the size. The first `scanned` bytes are known not to start one.
+    StoredUntilDescriptor { scanned: usize },
+}
+
+impl EntryBody {
+    /// Read from `rest`, the bytes following what was already consumed. Returns the
+    /// bytes used and, once the entry is complete, its contents (empty unless `keep`).
+    fn read(&mut self, rest: &[u8], keep: bool) -> Result<(usize, Option<Vec<u8>>)> {
+        match self {
+            EntryBody::Sized { remaining, data } => {
+                let used = (*remaining).min(rest.len());
+                if keep {
+                    data.extend_from_slice(&rest[..used]);
+                }
+                *remaining -= used;
+                Ok((used, (*remaining == 0).then(|| std::mem::take(data))))
+            }
+            EntryBody::Deflate { remaining, inflater, data } => {
+                let available = remaining.map_or(rest.len(), |r| r.min(rest.len()));
+                let (used, stream_end) = inflate(inflater, &rest[..available], data)?;
+                if !keep {
+                    data.clear();
+                }
+                if let Some(r) = remaining.as_mut() {
+                    *r -= used;
+                }
+                let complete = stream_end || *remaining == Some(0);
+                Ok((used, complete.then(|| std::mem::take(data))))
+            }
+            EntryBody::StoredUntilDescriptor { scanned } => {
+                while let Some(found) = rest[*scanned..]
+                    .windows(ZIP_DESCRIPTOR_SIGNATURE.len())
+                    .position(|w| w == ZIP_DESCRIPTOR_SIGNATURE)
+                {
+                    let end = *scanned + found;
+                    if rest.len() < end + ZIP_DESCRIPTOR_MAX_LEN {
+                        *scanned = end;
+                        return Ok((0, None));
+                    }
+                    if let Some(len) = descriptor_len(&rest[end..], end as u64, end as u64) {
+                        let data = if keep { rest[..end].to_vec() } else { Vec::new() };
+                        return Ok((end + len, Some(data)));
+                    }
+                    *scanned = end + 1;
+                }
+                *scanned = rest.len().saturating_sub(ZIP_DESCRIPTOR_SIGNATURE.len() - 1).max(*scanned);
+                Ok((0, None))
+            }
+        }
+    }
+
+    /// Compressed and uncompressed size of a completed entry whose data
+    /// descriptor is still to come
+    fn descriptor_sizes(&self) -> Option<(u64, u64)> {
+        match self {
+            EntryBody::Deflate { remaining: None, inflater, .. } => Some((inflater.total_in(), inflater.total_out())),
+            _ => None,
+        }
+    }
+}
+
+/// Length of the data descriptor starting `rest` if it is one for an entry of
+/// these sizes. Needs `ZIP_DESCRIPTOR_MAX_LEN` bytes, which the central
+/// directory after the last entry guarantees.
+fn descriptor_len(rest: &[u8], compressed: u64, uncompressed: u64) -> Option<usize> {
+    let sizes = if rest.starts_with(ZIP_DESCRIPTOR_SIGNATURE) { 8 } else { 4 };
+    let le = |at: usize, len: usize| {
+        rest.get(at..at + len)
+            .map(|b| b.iter().rev().fold(0u64, |v, &byte| v << 8 | byte as u64))
+    };
+    if le(sizes, 8)? == compressed && le(sizes + 8, 8)? == uncompressed {
+        return Some(sizes + 16);
+    }
+    if le(sizes, 4)? == compressed && le(sizes + 4, 4)? == uncompressed {
+        return Some(sizes + 8);
+    }
+    None
+}
+
+/// Inflate as much of `input` as possible into `out`. Returns the input used and
+/// whether the deflate stream ended.
+fn inflate(inflater: &mut flate2::Decompress, input: &[u8], out: &mut Vec<u8>) -> Result<(usize, bool)> {
+    let start = inflater.total_in();
+    loop {
+        if out.capacity() - out.len() < 32 * 1024 {
+            out.reserve(64 * 1024);
+        }
+        let used = (inflater.total_in() - start) as usize;
+        let status = inflater
+            .decompress_vec(&input[used..], out, flate2::FlushDecompress::None)
+            .map_err(|e| zip_stream_error(format!("deflate: {}", e)))?;
+        let used = (inflater.total_in() - start) as usize;
+        if status == flate2::Status::StreamEnd {
+            return Ok((used, true));
+        }
+        // Out of input with room left in the output: wait for more
+        if used == input.len() && out.len() < out.capacity() {
+            return Ok((used, false));
+        }
+    }
+}
+
+/// Parses a save while it is still being downloaded.
+///
+/// Feed the transfer in order. Each zip entry is inflated as it arrives and the
+/// compressed bytes are dropped; the level.dat header, with the prototype
+/// mappings, is parsed once enough of level.dat is in. `finish` then only has
+/// the entity and tile passes left.
+///
+/// Archives that cannot be read front to back (encryption, zip64, compression
+/// other than deflate) make `finish` fail; parse the assembled save with
+/// `parse_map_data` instead.
+pub struct MapStreamParser {
+    progress: Option<Arc<ParseProgress>>,
+    format: MapFormat,
+    /// Received bytes not yet consumed
+    pending: Vec<u8>,
+    entry: Option<ZipEntry>,
+    /// A data descriptor for an entry of these sizes is next
+    descriptor: Option<(u64, u64)>,
+    contents: SaveContents,
+    /// level.dat parts waiting for an earlier part
+    parts: BTreeMap<usize, Vec<u8>>,
+    next_part: usize,
+    stream: Option<LevelDatStream>,
+    /// Reached the central directory
+    done: bool,
+    error: Option<Error>,
+}
+
+impl MapStreamParser {
+    pub fn new(progress: Option<Arc<ParseProgress>>) -> Self {
+        Self::with_format(MapFormat::FACTORIO_2_0, progress)
+    }
+
+    /// Parser for saves in `format`; a map from another build stops it as
+    /// soon as the version at the start of level.dat is in.
+    pub fn with_format(format: MapFormat, progress: Option<Arc<ParseProgress>>) -> Self {
+        if let Some(p) = progress.as_ref() {
+            p.set_stage(ParseStage::Init);
+        }
+        Self {
+            progress,
+            format,
+            pending: Vec::new(),
+            entry: None,
+            descriptor: None,
+            contents: SaveContents::default(),
+            parts: BTreeMap::new(),
+            next_part: 0,
+            stream: None,
+            done: false,
+            error: None,
+        }
+    }
+
+    /// Append the next bytes of the save.
+    pub fn feed(&mut self, data: &[u8]) {
+        if self.is_done() {
+            return;
+        }
+        let mut pending = std::mem::take(&mut self.pending);
+        pending.extend_from_slice(data);
+        let mut pos = 0;
+        match self.consume(&pending, &mut pos) {
+            Ok(()) => {
+                pending.drain(..pos);
+                self.pending = pending;
+            }
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    /// Whether the level.dat header has been parsed already
+    pub fn has_header(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Whether everything up to the central directory has arrived, or the
+    /// archive turned out not to be streamable
+    pub fn is_done(&self) -> bool {
+        self.done || self.error.is_some()
+    }
+
+    /// Whether the map was written by a build outside the parser's format
+    pub fn refused_version(&self) -> bool {
+        matches!(self.error, Some(Error::UnsupportedVersion(_)))
+    }
+
+    pub fn finish(self) -> Result<MapData> {
+        let progress = self.progress.clone();
+        let format = self.format;
+        let result = self
+            .into_contents()
+            .and_then(|(contents, stream)| parse_save_contents(contents, stream, format, progress.clone()));
+        if let Some(p) = progress.as_ref() {
+            p.set_stage(if result.is_ok() { ParseStage::Done } else { ParseStage::Error });
+        }
+        result
+    }
+
+    fn into_contents(mut self) -> Result<(SaveContents, Option<LevelDatStream>)> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        if !self.done {
+            return Err(zip_stream_error("archive ends before its central directory".into()));
+        }
+        // Parts after a missing one are appended anyway, like read_save_archive does
+        for part in std::mem::take(&mut self.parts).into_values() {
+            self.contents.level_dat.extend(part);
+        }
+        Ok((self.contents, self.stream))
+    }
+
+    /// Consume what can be consumed of `data[*pos..]`, leaving `*pos` at the
+    /// first byte still needed.
+    fn consume(&mut self, data: &[u8], pos: &mut usize) -> Result<()> {
+        loop {
+            let rest = &data[*pos..];
+            if let Some((compressed, uncompressed)) = self.descriptor {
+                if rest.len() < ZIP_DESCRIPTOR_MAX_LEN {
+                    return Ok(());
+                }
+                *pos += descriptor_len(rest, compressed, uncompressed)
+                    .ok_or_else(|| zip_stream_error("data descriptor does not match its entry".into()))?;
+                self.descriptor = None;
+                continue;
+            }
+            let Some(mut entry) = self.entry.take() else {
+                match self.local_header(rest)? {
+                    Some((entry, header_len)) => {
+                        self.entry = Some(entry);
+                        *pos += header_len;
+                        continue;
+                    }
+                    None => return Ok(()),
+                }
+            };
+            let (used, contents) = entry.body.read(rest, entry.kind.is_some())?;
+            *pos += used;
+            let Some(contents) = contents else {
+                self.entry = Some(entry);
+                return Ok(());
+            };
+            self.descriptor = entry.body.descriptor_sizes();
+            self.contents.file_names.push(entry.name);
+            if let Some(kind) = entry.kind {
+                self.add_entry(kind, contents);
+            }
+        }
+    }
+
+    /// Start the next entry. `None` when the header is not complete yet or the
+    /// central directory was reached.
+    fn local_header(&mut self, rest: &[u8]) -> Result<Option<(ZipEntry, usize)>> {
+        if rest.len() < 4 {
+            return Ok(None);
+        }
+        match &rest[..4] {
+            b"PK\x03\x04" => {}
+            b"PK\x01\x02" | b"PK\x05\x06" => {
+                self.done = true;
+                return Ok(None);
+            }
+            other => return Err(zip_stream_error(format!("unexpected signature {:02x?}", other))),
+        }
+        if rest.len() < ZIP_LOCAL_HEADER_LEN {
+            return Ok(None);
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([rest[i], rest[i + 1]]);
+        let flags = u16_at(6);
+        let method = u16_at(8);
+        let compressed_size = u32::from_le_bytes([rest[18], rest[19], rest[20], rest[21]]);
+        let name_len = u16_at(26) as usize;
+        let header_len = ZIP_LOCAL_HEADER_LEN + name_len + u16_at(28) as usize;
+        if rest.len() < header_len {
+            return Ok(None);
+        }
+        let name = String::from_utf8_lossy(&rest[ZIP_LOCAL_HEADER_LEN..ZIP_LOCAL_HEADER_LEN + name_len]).into_owned();
+        if flags & 0x0001 != 0 {
+            return Err(zip_stream_error(format!("{}: encrypted", name)));
+        }
+        let has_descriptor = flags & 0x0008 != 0;
+        if !has_descriptor && compressed_size == u32::MAX {
+            return Err(zip_stream_error(format!("{}: zip64", name)));
+        }
+        let kind = SaveEntry::from_name(&name);
+        let remaining = compressed_size as usize;
+        let body = match (method, has_descriptor) {
+            (0, true) => EntryBody::StoredUntilDescriptor { scanned: 0 },
+            (8, true) => EntryBody::Deflate { remaining: None, inflater: flate2::Decompress::new(false), data: Vec::new() },
+            (_, false) if kind.is_none() || method == 0 => EntryBody::Sized { remaining, data: Vec::new() },
+            (8, false) => EntryBody::Deflate {
+                remaining: Some(remaining),
+                inflater: flate2::Decompress::new(false),
+                data: Vec::new(),
+            },
+            (other, _) => return Err(zip_stream_error(format!("{}: compression method {}", name, other))),
+        };
+        Ok(Some((ZipEntry { name, kind, body }, header_len)))
+    }
+
+    fn add_entry(&mut self, entry: SaveEntry, data: Vec<u8>) {
+        match entry {
+            SaveEntry::MapGenSettings => {
+                if self.contents.mapgen_json.is_none() {
+                    self.contents.mapgen_json = String::from_utf8(data).ok();
+                }
+            }
+            SaveEntry::LevelInit => {
+                self.contents.level_init.get_or_insert(data);
+            }
+            SaveEntry::LevelDat(part) => {
+                // An undecodable part is left out, as in read_save_archive
+                let decompressed = decompress_if_needed(&data).unwrap_or_default();
+                self.parts.entry(part).or_insert(decompressed);
+                let mut appended = false;
+                while let Some(next) = self.parts.remove(&self.next_part) {
+                    self.contents.level_dat.extend(next);
+                    self.next_part += 1;
+                    appended = true;
+                }
+                if appended && self.stream.is_none() {
+                    self.try_parse_header();
+                }
+            }
+        }
+    }
+
+    fn try_parse_header(&mut self) {
+        let level_dat = &self.contents.level_dat;
+        if let Ok(version) = MapVersion::read(&mut BinaryReader::new(level_dat)) {
+            if let Err(e) = self.format.check(&version) {
+                self.error = Some(e);
+                return;
+            }
+        }
+        if level_dat.len() < LEVEL_DAT_PARSE_MARGIN {
+            return;
+        }
+        if let Some(p) = self.progress.as_ref() {
+            p.set_stage(ParseStage::Prototypes);
+        }
+        if let Ok(stream) = LevelDatStream::parse(level_dat, self.format) {
+            if stream.end_position + LEVEL_DAT_PARSE_MARGIN <= level_dat.len() {
+                self.stream = Some(stream);
+            }
+        }
+    }
+}
+
+fn zip_stream_error(reason: String) -> Error {
+    Error::InvalidPacket(format!("ZIP stream: {}", reason))
+}
+
 // ============================================================================
 // Public types
 // ============================================================================
@@ -4232,6 +4755,8 @@ pub struct MapData {
     pub entities: Vec<MapEntity>,
     pub tiles: Vec<MapTile>,
     pub player_spawn: (f64, f64),
+    /// Player names in player index order
+    pub players: Vec<String>,
     pub raw_files: Vec<String>,
     pub item_prototypes: HashMap<u16, String>,
     pub recipe_prototypes: HashMap<u16, String>,
@@ -4275,6 +4800,7 @@ impl MapData {
             entities: Vec::new(),
             tiles: Vec::new(),
             player_spawn: (0.0, 0.0),
+            players: Vec::new(),
             raw_files: Vec::new(),
             item_prototypes: HashMap::new(),
             recipe_prototypes: HashMap::new(),
@@ -4286,7 +4812,7 @@ impl MapData {
     }
 }
 
-use super::map_types::{MapEntity, MapTile, MapVersion, entity_collision_box};
+use super::map_types::{MapEntity, MapFormat, MapTile, MapVersion, entity_collision_box};
 
 #[cfg(test)]
 mod tests {
@@ -4330,6 +4856,141 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    /// One TransferBlock's worth
+    const TEST_CHUNK: usize = 503;
+
+    fn build_save(entries: &[(&str, zip::CompressionMethod, Vec<u8>)]) -> Vec<u8> {
+        use std::io::Write;
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, method, data) in entries {
+            let options = zip::write::SimpleFileOptions::default().compression_method(*method);
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        use std::io::Write;
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_stream_parser_matches_archive_reader() {
+        use zip::CompressionMethod::{Deflated, Stored};
+        // level.dat opens with a map version the default format accepts
+        let mut part0 = vec![0; 5000];
+        part0[0] = 2;
+        let save = build_save(&[
+            ("save/map-gen-settings.json", Deflated, br#"{"seed": 42}"#.to_vec()),
+            ("save/script.dat", Stored, vec![7; 3000]),
+            // Parts need not arrive in order
+            ("save/level.dat1", Stored, zlib(&[1; 5000])),
+            ("save/level.dat0", Stored, zlib(&part0)),
+            ("save/level-init.dat", Deflated, vec![9; 2000]),
+        ]);
+
+        let mut parser = MapStreamParser::new(None);
+        for chunk in save.chunks(TEST_CHUNK) {
+            parser.feed(chunk);
+        }
+        assert!(parser.is_done());
+        let (streamed, header) = parser.into_contents().unwrap();
+        assert!(header.is_none());
+        assert_eq!(streamed, read_save_archive(&save).unwrap());
+        assert_eq!(streamed.level_dat.len(), 10_000);
+        assert_eq!(streamed.level_dat[4999..5001], [0, 1]);
+    }
+
+    #[test]
+    fn test_stream_parser_rejects_unstreamable() {
+        let save = build_save(&[("save/level.dat0", zip::CompressionMethod::Stored, vec![0; 100])]);
+        let mut truncated = MapStreamParser::new(None);
+        truncated.feed(&save[..save.len() / 2]);
+        assert!(truncated.finish().is_err());
+
+        let mut garbage = MapStreamParser::new(None);
+        garbage.feed(&[0x78, 0x9c, 0, 0, 0, 0]);
+        assert!(garbage.is_done());
+        assert!(garbage.finish().is_err());
+    }
+
+    #[test]
+    fn test_stream_parser_refuses_foreign_map_early() {
+        let mut level_dat = Vec::new();
+        for part in [2u16, 1, 0, 10] {
+            level_dat.extend_from_slice(&part.to_le_bytes());
+        }
+        level_dat.push(0);
+        let save = build_save(&[
+            ("save/level.dat0", zip::CompressionMethod::Stored, zlib(&level_dat)),
+            ("save/script.dat", zip::CompressionMethod::Stored, vec![7; 20_000]),
+        ]);
+
+        let mut parser = MapStreamParser::new(None);
+        parser.feed(&save[..TEST_CHUNK]);
+        assert!(parser.refused_version());
+        match parser.finish() {
+            Err(Error::UnsupportedVersion(v)) => assert_eq!(v, "2.1.0 (map format)"),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_stream_parse_real_save() {
+        let Ok(data) = fs::read("server_map.zip") else {
+            return;
+        };
+        let Ok(expected) = read_save_archive(&data) else {
+            return;
+        };
+        let mut parser = MapStreamParser::new(None);
+        for chunk in data.chunks(TEST_CHUNK) {
+            parser.feed(chunk);
+        }
+        assert!(parser.has_header());
+        let (contents, header) = parser.into_contents().unwrap();
+        assert_eq!(contents, expected);
+        if let (Some(header), Ok(full)) = (header, LevelDatStream::parse(&expected.level_dat, MapFormat::FACTORIO_2_0)) {
+            assert_eq!(header.end_position, full.end_position);
+            assert_eq!(header.prototype_mappings.tables, full.prototype_mappings.tables);
+        }
+    }
+
+    #[test]
+    fn test_truncated_level_dat_error_offsets() {
+        let Ok(data) = fs::read("server_map.zip") else {
+            return;
+        };
+        let level_dat = read_save_archive(&data).unwrap().level_dat;
+        let full = LevelDatStream::parse(&level_dat, MapFormat::FACTORIO_2_0).unwrap();
+        let cut = full.end_position - 3;
+        match LevelDatStream::parse(&level_dat[..cut], MapFormat::FACTORIO_2_0) {
+            Err(Error::Parse { start, offset, .. }) => {
+                assert!(start <= offset && offset <= cut, "{} {} {}", start, offset, cut);
+                assert!(offset + 8 >= cut, "offset {} far from the cut at {}", offset, cut);
+            }
+            other => panic!("unexpected {:?}", other.map(|s| s.end_position)),
+        }
+    }
+
+    #[test]
+    fn test_scan_player_names_real_save() {
+        let Ok(data) = fs::read("server_map.zip") else {
+            return;
+        };
+        let Ok(contents) = read_save_archive(&data) else {
+            return;
+        };
+        let players = scan_for_player_names(&contents.level_dat);
+        assert_eq!(
+            players,
+            vec!["npace", "MapDownloader7522", "MapDownloader5412", "Bot75", "Bot131", "MapDownloader2030"]
+        );
+    }
+
     #[test]
     fn test_delta_position_encoding() {
         let delta_data = [0x0A, 0x00, 0x14, 0x00];
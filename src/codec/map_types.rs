@@ -1,6 +1,6 @@
 use super::BinaryReader;
 use serde::{Deserialize, Serialize};
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::lua::prototype::Prototypes;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -88,6 +88,42 @@ impl MapVersion {
     }
 }
 
+/// Save layouts the map parser can read, carried by the game build's
+/// `VersionProfile`. The version gates inside the parser are only known to
+/// hold for these maps, so anything else is refused as soon as its version is read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapFormat {
+    /// First and last map (major, minor, patch) with this layout
+    pub versions: ((u16, u16, u16), (u16, u16, u16)),
+    /// Bytes of random generator state after MapSettings
+    pub rng_state_len: usize,
+}
+
+impl MapFormat {
+    /// Space Age 2.0 saves
+    pub const FACTORIO_2_0: Self = Self {
+        versions: ((2, 0, 0), (2, 0, u16::MAX)),
+        rng_state_len: 86,
+    };
+
+    pub fn covers(&self, version: &MapVersion) -> bool {
+        let (first, last) = self.versions;
+        (first..=last).contains(&(version.major, version.minor, version.patch))
+    }
+
+    /// `Error::UnsupportedVersion` for a map written by a build outside the format
+    pub fn check(&self, version: &MapVersion) -> Result<()> {
+        if self.covers(version) {
+            Ok(())
+        } else {
+            Err(Error::UnsupportedVersion(format!(
+                "{}.{}.{} (map format)",
+                version.major, version.minor, version.patch
+            )))
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SurfaceData {
     pub name: String,
@@ -8,6 +8,7 @@ pub mod map_transfer;
 pub mod entity_parsers;
 pub mod tick_closure;
 pub mod synchronizer_action;
+pub mod numbering;
 
 pub use reader::BinaryReader;
 pub use writer::BinaryWriter;
@@ -19,11 +20,12 @@ pub use input_action::{
     ShootingState, RidingAcceleration, RidingDirection,
     MouseButton, SwitchState, AdminActionType,
 };
-pub use map_types::{MapEntity, MapTile, MapVersion, SurfaceData, ChunkData, EntityData, TileData, DecorativeData, check_player_collision};
+pub use map_types::{MapEntity, MapTile, MapVersion, MapFormat, SurfaceData, ChunkData, EntityData, TileData, DecorativeData, check_player_collision};
 pub use map_transfer::{
-    MapTransfer, MapData, ParseProgress, ParseStage,
+    MapTransfer, MapData, MapStreamParser, ParseProgress, ParseStage,
     PrototypeMappings,
-    parse_map_data, parse_map_resources, parse_map_data_with_progress,
+    parse_map_data, parse_map_resources, parse_map_data_with_progress, parse_map_data_with_format,
 };
 pub use tick_closure::{TickClosure, TickInputAction, InputActionSegment, calculate_flags, write_tick_closure_count};
-pub use synchronizer_action::{SynchronizerAction, SynchronizerActionType, write_sync_action_count};
+pub use numbering::ActionNumbering;
+pub use synchronizer_action::{MapReadyForDownload, SynchronizerAction, SynchronizerActionType, write_sync_action_count};
@@ -0,0 +1,89 @@
+//! Wire numbering of input and synchronizer actions
+//!
+//! `InputActionType` and `SynchronizerActionType` carry the ids of the build the
+//! codec was reverse engineered from. Other builds insert, drop or reorder
+//! actions, so the id on the wire is translated through an `ActionNumbering`
+//! before it is matched against those enums. A `None` table means the build
+//! uses the canonical ids unchanged.
+
+use super::input_action::InputActionType;
+use super::synchronizer_action::SynchronizerActionType;
+
+/// Action ids used by one game build
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActionNumbering {
+    /// Input action types in wire order: the type with id `n` is `input_actions[n]`
+    pub input_actions: Option<&'static [InputActionType]>,
+    /// Synchronizer action types in wire order
+    pub sync_actions: Option<&'static [SynchronizerActionType]>,
+}
+
+impl Default for ActionNumbering {
+    fn default() -> Self {
+        Self::CANONICAL
+    }
+}
+
+impl ActionNumbering {
+    /// The ids of `InputActionType` / `SynchronizerActionType` as declared
+    pub const CANONICAL: Self = Self { input_actions: None, sync_actions: None };
+
+    pub fn is_canonical(&self) -> bool {
+        self.input_actions.is_none() && self.sync_actions.is_none()
+    }
+
+    /// Wire id of an input action type, `None` if this build lacks it
+    pub fn input_action_id(&self, action_type: InputActionType) -> Option<u16> {
+        match self.input_actions {
+            None => Some(action_type as u16),
+            Some(table) => table.iter().position(|&t| t == action_type).map(|i| i as u16),
+        }
+    }
+
+    /// Input action type behind a wire id
+    pub fn input_action_type(&self, id: u16) -> Option<InputActionType> {
+        match self.input_actions {
+            None => InputActionType::from_u16(id),
+            Some(table) => table.get(id as usize).copied(),
+        }
+    }
+
+    pub fn sync_action_id(&self, action_type: SynchronizerActionType) -> Option<u8> {
+        match self.sync_actions {
+            None => Some(action_type as u8),
+            Some(table) => table.iter().position(|&t| t == action_type).map(|i| i as u8),
+        }
+    }
+
+    pub fn sync_action_type(&self, id: u8) -> Option<SynchronizerActionType> {
+        match self.sync_actions {
+            None => SynchronizerActionType::from_u8(id),
+            Some(table) => table.get(id as usize).copied(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remapped_numbering() {
+        use InputActionType as I;
+        use SynchronizerActionType as S;
+        const INPUT: &[InputActionType] = &[I::Nothing, I::Craft, I::StopWalking];
+        const SYNC: &[SynchronizerActionType] = &[S::GameEnd, S::NewPeerInfo, S::PeerDisconnect];
+        let numbering = ActionNumbering { input_actions: Some(INPUT), sync_actions: Some(SYNC) };
+
+        assert_eq!(numbering.input_action_id(I::StopWalking), Some(2));
+        assert_eq!(numbering.input_action_type(1), Some(I::Craft));
+        assert_eq!(numbering.input_action_id(I::Build), None);
+        assert_eq!(numbering.input_action_type(3), None);
+        assert_eq!(numbering.sync_action_id(S::PeerDisconnect), Some(2));
+        assert_eq!(numbering.sync_action_type(1), Some(S::NewPeerInfo));
+
+        let canonical = ActionNumbering::CANONICAL;
+        assert_eq!(canonical.input_action_id(I::Craft), Some(I::Craft as u16));
+        assert_eq!(canonical.sync_action_type(0x0C), Some(S::PlayerDesynced));
+    }
+}
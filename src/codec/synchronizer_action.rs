@@ -2,7 +2,8 @@
 //!
 //! From binary reverse engineering - these are control actions embedded in heartbeats.
 
-use crate::codec::BinaryWriter;
+use crate::codec::{BinaryReader, BinaryWriter};
+use crate::error::{Error, Result};
 
 /// SynchronizerActionType enum values (Space Age 2.0, from binary RE)
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -59,14 +60,15 @@ impl SynchronizerActionType {
 }
 
 /// Synchronizer action with its data
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SynchronizerAction {
     GameEnd,
     PeerDisconnect { disconnect_type: u8 },
     NewPeerInfo { peer_name: String },
     ClientChangedState { state: u8 },
-    ClientShouldStartSendingTickClosures { tick: u64 },
-    MapReadyForDownload { data: Vec<u8> },
+    /// The tick is a u32 on the wire (pcap), unlike the other sync action ticks
+    ClientShouldStartSendingTickClosures { tick: u32 },
+    MapReadyForDownload(MapReadyForDownload),
     MapLoadingProgressUpdate { progress: u8 },
     MapSavingProgressUpdate { progress: u8 },
     SavingForUpdate,
@@ -112,7 +114,12 @@ impl SynchronizerAction {
     /// Serialize the synchronizer action to bytes
     pub fn write(&self, writer: &mut BinaryWriter) {
         writer.write_u8(self.action_type() as u8);
+        self.write_data(writer);
+    }
 
+    /// Serialize the action data only. In heartbeats the sending peer sits
+    /// between the type and the data.
+    pub fn write_data(&self, writer: &mut BinaryWriter) {
         match self {
             Self::GameEnd => {}
             Self::PeerDisconnect { disconnect_type } => {
@@ -125,11 +132,9 @@ impl SynchronizerAction {
                 writer.write_u8(*state);
             }
             Self::ClientShouldStartSendingTickClosures { tick } => {
-                writer.write_u64_le(*tick);
-            }
-            Self::MapReadyForDownload { data } => {
-                writer.write_bytes(data);
+                writer.write_u32_le(*tick);
             }
+            Self::MapReadyForDownload(info) => info.write(writer),
             Self::MapLoadingProgressUpdate { progress } => {
                 writer.write_u8(*progress);
             }
@@ -165,6 +170,116 @@ impl SynchronizerAction {
             }
         }
     }
+
+    /// Read a type byte followed by the action data
+    pub fn read(reader: &mut BinaryReader) -> Result<Self> {
+        let type_byte = reader.read_u8()?;
+        let action_type = SynchronizerActionType::from_u8(type_byte)
+            .ok_or_else(|| Error::InvalidPacket(format!("unknown synchronizer action type {:#04x}", type_byte)))?;
+        Self::read_data(reader, action_type)
+    }
+
+    /// Read the data of an action whose type was already consumed
+    pub fn read_data(reader: &mut BinaryReader, action_type: SynchronizerActionType) -> Result<Self> {
+        use SynchronizerActionType as T;
+        Ok(match action_type {
+            T::GameEnd => Self::GameEnd,
+            T::PeerDisconnect => Self::PeerDisconnect { disconnect_type: reader.read_u8()? },
+            T::NewPeerInfo => Self::NewPeerInfo { peer_name: reader.read_string()? },
+            T::ClientChangedState => Self::ClientChangedState { state: reader.read_u8()? },
+            T::ClientShouldStartSendingTickClosures => {
+                Self::ClientShouldStartSendingTickClosures { tick: reader.read_u32_le()? }
+            }
+            T::MapReadyForDownload => Self::MapReadyForDownload(MapReadyForDownload::read(reader)?),
+            T::MapLoadingProgressUpdate => Self::MapLoadingProgressUpdate { progress: reader.read_u8()? },
+            T::MapSavingProgressUpdate => Self::MapSavingProgressUpdate { progress: reader.read_u8()? },
+            T::SavingForUpdate => Self::SavingForUpdate,
+            T::MapDownloadingProgressUpdate => Self::MapDownloadingProgressUpdate { progress: reader.read_u8()? },
+            T::CatchingUpProgressUpdate => Self::CatchingUpProgressUpdate { progress: reader.read_u8()? },
+            T::PeerDroppingProgressUpdate => Self::PeerDroppingProgressUpdate { progress: reader.read_u8()? },
+            T::PlayerDesynced => Self::PlayerDesynced,
+            T::BeginPause => Self::BeginPause,
+            T::EndPause => Self::EndPause,
+            T::SkippedTickClosure => Self::SkippedTickClosure { tick: reader.read_u64_le()? },
+            T::SkippedTickClosureConfirm => Self::SkippedTickClosureConfirm { tick: reader.read_u64_le()? },
+            T::ChangeLatency => Self::ChangeLatency { latency: reader.read_u8()? },
+            T::IncreasedLatencyConfirm => Self::IncreasedLatencyConfirm {
+                tick: reader.read_u64_le()?,
+                latency: reader.read_u8()?,
+            },
+            T::SavingCountdown => Self::SavingCountdown {
+                tick: reader.read_u64_le()?,
+                remaining: reader.read_u32_le()?,
+            },
+        })
+    }
+}
+
+/// MapReadyForDownload payload. Only the leading fields are understood; the rest
+/// (script registrations and per-mod lists) is walked for its length and kept verbatim.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MapReadyForDownload {
+    /// Size of the save in bytes, i.e. what the TransferBlocks add up to
+    pub transfer_size: u64,
+    pub auxiliary_size: u64,
+    pub crc: u32,
+    /// Tick the save was made at
+    pub map_tick: u64,
+    pub trailer: Vec<u8>,
+}
+
+impl MapReadyForDownload {
+    pub fn read(reader: &mut BinaryReader) -> Result<Self> {
+        let transfer_size = reader.read_u64_le()?;
+        let auxiliary_size = reader.read_u64_le()?;
+        let crc = reader.read_u32_le()?;
+        let map_tick = reader.read_u64_le()?;
+
+        let start = reader.position();
+        reader.skip(4 + 4 + 1 + 1)?;
+        for _ in 0..reader.read_opt_u32()? {
+            reader.read_string()?;
+            reader.skip(4)?;
+        }
+        for _ in 0..reader.read_opt_u32()? {
+            reader.read_string()?;
+            skip_script_registrations(reader)?;
+        }
+        for _ in 0..reader.read_opt_u32()? {
+            reader.read_string()?;
+            for _ in 0..reader.read_opt_u32()? {
+                reader.read_string()?;
+            }
+        }
+        let len = reader.position() - start;
+        reader.set_position(start);
+        let trailer = reader.read_bytes(len)?.to_vec();
+
+        Ok(Self { transfer_size, auxiliary_size, crc, map_tick, trailer })
+    }
+
+    pub fn write(&self, writer: &mut BinaryWriter) {
+        writer.write_u64_le(self.transfer_size);
+        writer.write_u64_le(self.auxiliary_size);
+        writer.write_u32_le(self.crc);
+        writer.write_u64_le(self.map_tick);
+        if self.trailer.is_empty() {
+            // Nothing registered: two u32s, two bools and three empty lists
+            writer.write_bytes(&[0; 13]);
+        } else {
+            writer.write_bytes(&self.trailer);
+        }
+    }
+}
+
+fn skip_script_registrations(reader: &mut BinaryReader) -> Result<()> {
+    let count = reader.read_opt_u32()? as usize;
+    reader.skip(count.checked_mul(4).ok_or(Error::UnexpectedEof)?)?;
+    let count = reader.read_opt_u32()? as usize;
+    reader.skip(count.checked_mul(8).ok_or(Error::UnexpectedEof)?)?;
+    let count = reader.read_opt_u32()? as usize;
+    reader.skip(count.checked_mul(8).ok_or(Error::UnexpectedEof)?)?;
+    reader.skip(3)
 }
 
 /// Write synchronizer action count to a writer
@@ -189,6 +304,47 @@ mod tests {
         assert_eq!(SynchronizerActionType::from_u8(0x99), None);
     }
 
+    #[test]
+    fn test_read_roundtrip() {
+        let actions = [
+            SynchronizerAction::GameEnd,
+            SynchronizerAction::PeerDisconnect { disconnect_type: 2 },
+            SynchronizerAction::NewPeerInfo { peer_name: "bot".into() },
+            SynchronizerAction::ClientChangedState { state: 7 },
+            SynchronizerAction::ClientShouldStartSendingTickClosures { tick: 216_006 },
+            SynchronizerAction::MapReadyForDownload(MapReadyForDownload {
+                transfer_size: 4000,
+                map_tick: 216_000,
+                trailer: vec![0; 13],
+                ..MapReadyForDownload::default()
+            }),
+            SynchronizerAction::MapLoadingProgressUpdate { progress: 10 },
+            SynchronizerAction::MapSavingProgressUpdate { progress: 20 },
+            SynchronizerAction::SavingForUpdate,
+            SynchronizerAction::MapDownloadingProgressUpdate { progress: 30 },
+            SynchronizerAction::CatchingUpProgressUpdate { progress: 40 },
+            SynchronizerAction::PeerDroppingProgressUpdate { progress: 50 },
+            SynchronizerAction::PlayerDesynced,
+            SynchronizerAction::BeginPause,
+            SynchronizerAction::EndPause,
+            SynchronizerAction::SkippedTickClosure { tick: 7 },
+            SynchronizerAction::SkippedTickClosureConfirm { tick: 8 },
+            SynchronizerAction::ChangeLatency { latency: 6 },
+            SynchronizerAction::IncreasedLatencyConfirm { tick: 9, latency: 12 },
+            SynchronizerAction::SavingCountdown { tick: 10, remaining: 300 },
+        ];
+        for (i, action) in actions.into_iter().enumerate() {
+            assert_eq!(action.action_type() as usize, i);
+            let mut writer = BinaryWriter::new();
+            action.write(&mut writer);
+            let mut reader = BinaryReader::new(writer.as_slice());
+            let read = SynchronizerAction::read(&mut reader).unwrap();
+            assert!(reader.is_empty());
+            assert_eq!(read, action);
+        }
+        assert!(SynchronizerAction::read(&mut BinaryReader::new(&[0x14])).is_err());
+    }
+
     #[test]
     fn test_write_client_changed_state() {
         let action = SynchronizerAction::ClientChangedState { state: 0x7f };
@@ -10,12 +10,13 @@ use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{UnixListener, UnixStream};
 use tokio::sync::{mpsc, oneshot};
 
-use crate::protocol::{Connection, ConnectionState};
+use crate::protocol::{Connection, ConnectionState, Credentials};
+use crate::client::GameEvent;
 use crate::bot::TilePathfinder;
 use crate::codec::{
     ClientItemStackLocation, Direction, ItemStackTransferSpecification, LogisticFilter,
     MapPosition, RelativeItemStackLocation, SignalId, parse_map_data_with_progress,
-    ParseProgress, ParseStage, map_transfer::MapData,
+    ParseProgress, ParseStage, SynchronizerAction, map_transfer::MapData,
 };
 use crate::lua::prototype::Prototypes;
 
@@ -35,16 +36,54 @@ pub fn pid_path() -> PathBuf {
         .join("daemon.pid")
 }
 
+/// Where a daemon that failed to join leaves the error for the CLI that spawned it
+pub fn error_path() -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(".factorio-bot")
+        .join("daemon.error")
+}
+
+/// Record why joining failed as a `connect` response, so `start_daemon` can
+/// report the error and its code rather than only that the daemon exited.
+pub fn write_join_error(err: &crate::error::Error) -> std::io::Result<()> {
+    let result = CommandResult::from_error(err);
+    let response = Response {
+        id: "connect".into(),
+        success: false,
+        result: None,
+        error: result.error,
+        code: result.code.map(String::from),
+    };
+    let json = serde_json::to_string(&response).map_err(std::io::Error::other)?;
+    std::fs::write(error_path(), json)
+}
+
+/// The error a daemon left with `write_join_error`, if any
+pub fn read_join_error() -> Option<Response> {
+    let json = std::fs::read_to_string(error_path()).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
 pub struct Daemon {
     pub connection: Connection,
 }
 
 impl Daemon {
     pub async fn connect(host: &str, port: u16, username: &str) -> crate::error::Result<Self> {
+        Self::connect_with_credentials(host, port, username, Credentials::default()).await
+    }
+
+    pub async fn connect_with_credentials(
+        host: &str,
+        port: u16,
+        username: &str,
+        credentials: Credentials,
+    ) -> crate::error::Result<Self> {
         let addr: SocketAddr = format!("{}:{}", host, port).parse()
             .map_err(|e| crate::error::Error::Io(format!("Invalid address: {}", e)))?;
 
-        let mut connection = Connection::new(addr, username.to_string()).await?;
+        let mut connection = Connection::new_with_credentials(addr, username.to_string(), credentials).await?;
         connection.connect().await?;
         connection.download_map_with_parse(false).await?;
 
@@ -67,7 +106,19 @@ struct MapParseMessage {
     duration: Duration,
 }
 
+/// Chat messages kept for `chat-log`
+const CHAT_HISTORY: usize = 500;
+
+#[derive(Debug, Clone)]
+struct ChatEntry {
+    tick: u32,
+    player_index: Option<u16>,
+    username: Option<String>,
+    message: String,
+}
+
 struct DaemonState {
+    chat_log: VecDeque<ChatEntry>,
     map_parse_started_at: Option<Instant>,
     map_parse_last: Option<Duration>,
     map_parse_cached: Option<bool>,
@@ -80,6 +131,7 @@ struct DaemonState {
 impl DaemonState {
     fn new() -> Self {
         Self {
+            chat_log: VecDeque::new(),
             map_parse_started_at: None,
             map_parse_last: None,
             map_parse_cached: None,
@@ -121,53 +173,24 @@ pub async fn run_daemon(daemon: Daemon, socket_path: PathBuf) -> crate::error::R
     let mut daemon_state = DaemonState::new();
     let (map_parse_tx, mut map_parse_rx) = mpsc::unbounded_channel::<MapParseMessage>();
     if connection.parsed_map.is_none() && !connection.map_data().is_empty() {
-        daemon_state.map_parse_started_at = Some(Instant::now());
-        daemon_state.map_parse_last = None;
-        daemon_state.map_parse_cached = None;
-        daemon_state.map_parse_error = None;
-        daemon_state.map_parse_last_report = None;
-        daemon_state.map_parse_last_done = 0;
-        let progress = Arc::new(ParseProgress::new());
-        daemon_state.map_parse_progress = Some(progress.clone());
-        let map_blob = connection.map_data().to_vec();
-        eprintln!("[daemon] parsing map in background ({} bytes)", map_blob.len());
-        tokio::task::spawn_blocking(move || {
-            let start = Instant::now();
-            if let Some(map) = load_cached_map(&map_blob) {
-                eprintln!("[daemon] map cache hit in {:?}", start.elapsed());
-                let _ = map_parse_tx.send(MapParseMessage {
-                    map: Some(map),
-                    error: None,
-                    cached: true,
-                    duration: start.elapsed(),
-                });
-                return;
-            }
-            let parsed = parse_map_data_with_progress(&map_blob, Some(progress));
-            let mut msg = MapParseMessage {
-                map: None,
-                error: None,
-                cached: false,
-                duration: start.elapsed(),
-            };
-            match parsed {
-                Ok(map) => {
-                    store_cached_map(&map_blob, &map);
-                    msg.map = Some(map);
-                }
-                Err(e) => {
-                    msg.error = Some(e.to_string());
-                }
-            }
-            eprintln!("[daemon] map parse finished in {:?}", start.elapsed());
-            let _ = map_parse_tx.send(msg);
-        });
+        spawn_map_parse(&connection, &mut daemon_state, map_parse_tx.clone());
     }
     let mut path_follower = PathFollower::new();
     let mut action_tracker = ActionTracker::new();
     let mut last_state = connection.state();
+    let (shutdown_tx, mut shutdown_rx) = mpsc::unbounded_channel::<&'static str>();
+    spawn_shutdown_listener(shutdown_tx);
 
     loop {
+        if let Ok(signal) = shutdown_rx.try_recv() {
+            eprintln!("[daemon] {} received, leaving game", signal);
+            match connection.disconnect().await {
+                Ok(()) => eprintln!("[daemon] disconnected cleanly"),
+                Err(e) => eprintln!("[daemon] disconnect: {}", e),
+            }
+            return Ok(());
+        }
+
         // Poll 5 times like play-game does
         for _ in 0..5 {
             let _ = connection.poll().await;
@@ -180,12 +203,60 @@ pub async fn run_daemon(daemon: Daemon, socket_path: PathBuf) -> crate::error::R
                     .last_disconnect_reason()
                     .unwrap_or("unknown");
                 eprintln!("[daemon] connection dropped: {}", reason);
-                path_follower.clear();
-                action_tracker.clear();
+                if connection.is_reconnecting() {
+                    eprintln!("[daemon] waiting for reconnect");
+                } else {
+                    path_follower.clear();
+                    action_tracker.clear();
+                }
             }
             last_state = state;
         }
 
+        for event in connection.drain_events() {
+            match event {
+                GameEvent::Reconnected { player_index, attempts } => {
+                    eprintln!(
+                        "[daemon] reconnected as player {:?} after {} attempt(s)",
+                        player_index, attempts
+                    );
+                    path_follower.resume();
+                    if connection.parsed_map.is_none() && !connection.map_data().is_empty() {
+                        spawn_map_parse(&connection, &mut daemon_state, map_parse_tx.clone());
+                    }
+                    last_state = connection.state();
+                }
+                GameEvent::ChatMessage { tick, player_id, username, message } => {
+                    eprintln!(
+                        "[daemon] chat [{}] {}: {}",
+                        tick,
+                        username.as_deref().unwrap_or("<server>"),
+                        message
+                    );
+                    if daemon_state.chat_log.len() >= CHAT_HISTORY {
+                        daemon_state.chat_log.pop_front();
+                    }
+                    daemon_state.chat_log.push_back(ChatEntry { tick, player_index: player_id, username, message });
+                }
+                GameEvent::SyncAction { peer_id, action } => match action {
+                    SynchronizerAction::NewPeerInfo { peer_name } => {
+                        eprintln!("[daemon] peer {} joined: {}", peer_id, peer_name);
+                    }
+                    SynchronizerAction::PeerDisconnect { .. } => {
+                        eprintln!("[daemon] peer {} left", peer_id);
+                    }
+                    SynchronizerAction::PlayerDesynced => {
+                        eprintln!("[daemon] peer {} desynced", peer_id);
+                    }
+                    SynchronizerAction::BeginPause | SynchronizerAction::EndPause | SynchronizerAction::GameEnd => {
+                        eprintln!("[daemon] {:?}", action);
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+
         // Check for map parse completion (non-blocking)
         while let Ok(msg) = map_parse_rx.try_recv() {
             daemon_state.map_parse_started_at = None;
@@ -305,6 +376,69 @@ pub async fn run_daemon(daemon: Daemon, socket_path: PathBuf) -> crate::error::R
     }
 }
 
+fn spawn_shutdown_listener(shutdown_tx: mpsc::UnboundedSender<&'static str>) {
+    use tokio::signal::unix::{signal, SignalKind};
+    tokio::spawn(async move {
+        let (Ok(mut term), Ok(mut int)) = (signal(SignalKind::terminate()), signal(SignalKind::interrupt())) else {
+            eprintln!("[daemon] failed to install signal handlers");
+            return;
+        };
+        let name = tokio::select! {
+            _ = term.recv() => "SIGTERM",
+            _ = int.recv() => "SIGINT",
+        };
+        let _ = shutdown_tx.send(name);
+    });
+}
+
+fn spawn_map_parse(
+    connection: &Connection,
+    daemon_state: &mut DaemonState,
+    map_parse_tx: mpsc::UnboundedSender<MapParseMessage>,
+) {
+    daemon_state.map_parse_started_at = Some(Instant::now());
+    daemon_state.map_parse_last = None;
+    daemon_state.map_parse_cached = None;
+    daemon_state.map_parse_error = None;
+    daemon_state.map_parse_last_report = None;
+    daemon_state.map_parse_last_done = 0;
+    let progress = Arc::new(ParseProgress::new());
+    daemon_state.map_parse_progress = Some(progress.clone());
+    let map_blob = connection.map_data().to_vec();
+    eprintln!("[daemon] parsing map in background ({} bytes)", map_blob.len());
+    tokio::task::spawn_blocking(move || {
+        let start = Instant::now();
+        if let Some(map) = load_cached_map(&map_blob) {
+            eprintln!("[daemon] map cache hit in {:?}", start.elapsed());
+            let _ = map_parse_tx.send(MapParseMessage {
+                map: Some(map),
+                error: None,
+                cached: true,
+                duration: start.elapsed(),
+            });
+            return;
+        }
+        let parsed = parse_map_data_with_progress(&map_blob, Some(progress));
+        let mut msg = MapParseMessage {
+            map: None,
+            error: None,
+            cached: false,
+            duration: start.elapsed(),
+        };
+        match parsed {
+            Ok(map) => {
+                store_cached_map(&map_blob, &map);
+                msg.map = Some(map);
+            }
+            Err(e) => {
+                msg.error = Some(e.to_string());
+            }
+        }
+        eprintln!("[daemon] map parse finished in {:?}", start.elapsed());
+        let _ = map_parse_tx.send(msg);
+    });
+}
+
 async fn handle_client(
     stream: UnixStream,
     cmd_tx: mpsc::Sender<DaemonCommand>,
@@ -352,11 +486,13 @@ async fn handle_command(
     let result = match request.command.as_str() {
         "status" => cmd_status(conn, daemon_state),
         "position" => cmd_position(conn),
+        "players" => cmd_players(conn),
         "walk" => cmd_walk(conn, path_follower, action_tracker, &request.args).await,
         "stop" => cmd_stop(conn, path_follower, action_tracker).await,
         "mine" => cmd_mine(conn, &request.args).await,
         "stop-mining" => cmd_stop_mine(conn).await,
         "chat" => cmd_chat(conn, &request.args).await,
+        "chat-log" => cmd_chat_log(daemon_state, &request.args),
         "craft" => cmd_craft(conn, &request.args).await,
         "research" => cmd_research(conn, &request.args).await,
         "cancel-craft" => cmd_cancel_craft(conn, &request.args).await,
@@ -434,6 +570,7 @@ async fn handle_command(
         success: result.success,
         result: result.data,
         error: result.error,
+        code: result.code.map(String::from),
     }
 }
 
@@ -497,10 +634,14 @@ fn cmd_status(conn: &Connection, daemon_state: &DaemonState) -> CommandResult {
         (None, None, None)
     };
     let connection_state = format!("{:?}", conn.state());
+    let net = conn.network_stats();
+    let ms = |d: Option<Duration>| d.map(|d| d.as_secs_f64() * 1000.0);
     CommandResult::ok(serde_json::json!({
         "connected": conn.state() == ConnectionState::InGame,
         "connection_state": connection_state,
         "last_disconnect_reason": conn.last_disconnect_reason(),
+        "reconnecting": conn.is_reconnecting(),
+        "reconnect_attempts": conn.reconnect_attempts(),
         "last_server_heartbeat_ms": conn.last_server_heartbeat_age_ms(),
         "player_id": conn.player_index(),
         "position": { "x": pos.0, "y": pos.1 },
@@ -516,6 +657,18 @@ fn cmd_status(conn: &Connection, daemon_state: &DaemonState) -> CommandResult {
             "peer_constant": conn.peer_constant(),
             "latency": conn.latency_value(),
         },
+        "network": {
+            "rtt_ms": ms(net.rtt),
+            "last_rtt_ms": ms(net.last_rtt),
+            "min_rtt_ms": ms(net.min_rtt),
+            "jitter_ms": ms(net.jitter),
+            "loss": net.loss,
+            "pings_sent": net.pings_sent,
+            "replies": net.replies,
+        },
+        "server_run_state": conn.server_run_state().as_str(),
+        "autosave_tick": conn.autosave_tick(),
+        "pending_actions": conn.pending_action_count(),
         "map_ready": map_ready,
         "map_parsing": map_parsing,
         "map_parse_ms": map_parse_ms,
@@ -604,6 +757,31 @@ fn cmd_action_status(
     CommandResult::ok(payload)
 }
 
+fn cmd_players(conn: &mut Connection) -> CommandResult {
+    conn.update_other_players();
+    let own_peer = conn.peer_id();
+    let players: Vec<_> = conn
+        .roster()
+        .map(|entry| {
+            let position = entry
+                .player_index
+                .and_then(|idx| conn.other_players().get(&idx))
+                .map(|state| serde_json::json!({"x": state.x, "y": state.y}));
+            serde_json::json!({
+                "peer_id": entry.peer_id,
+                "player_index": entry.player_index,
+                "username": entry.username,
+                "is_self": Some(entry.peer_id) == own_peer,
+                "position": position,
+            })
+        })
+        .collect();
+    CommandResult::ok(serde_json::json!({
+        "count": players.len(),
+        "players": players
+    }))
+}
+
 fn cmd_position(conn: &mut Connection) -> CommandResult {
     conn.update_position();
     let pos = conn.player_position();
@@ -624,7 +802,7 @@ async fn cmd_walk(
     action_tracker.complete_if_action("move", "interrupted");
     match conn.actions().send_walk(dir).await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -637,7 +815,7 @@ async fn cmd_stop(
     action_tracker.complete_if_action("move", "stopped");
     match conn.actions().send_stop_walk().await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -646,14 +824,14 @@ async fn cmd_mine(conn: &mut Connection, args: &serde_json::Value) -> CommandRes
     let y = arg_f64(args, "y", 0.0);
     match conn.actions().send_mine(x, y).await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
 async fn cmd_stop_mine(conn: &mut Connection) -> CommandResult {
     match conn.actions().send_stop_mine().await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -661,10 +839,29 @@ async fn cmd_chat(conn: &mut Connection, args: &serde_json::Value) -> CommandRes
     let msg = arg_str(args, "message").unwrap_or("");
     match conn.actions().send_chat(msg).await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
+fn cmd_chat_log(daemon_state: &DaemonState, args: &serde_json::Value) -> CommandResult {
+    let since = arg_u64(args, "since", 0);
+    let messages: Vec<_> = daemon_state
+        .chat_log
+        .iter()
+        .filter(|entry| u64::from(entry.tick) >= since)
+        .map(|entry| serde_json::json!({
+            "tick": entry.tick,
+            "player_index": entry.player_index,
+            "username": entry.username,
+            "message": entry.message,
+        }))
+        .collect();
+    CommandResult::ok(serde_json::json!({
+        "count": messages.len(),
+        "messages": messages
+    }))
+}
+
 async fn cmd_craft(conn: &mut Connection, args: &serde_json::Value) -> CommandResult {
     let count = arg_u64(args, "count", 1) as u32;
     let Some(recipe_id) = lookup_recipe_id(conn, args) else {
@@ -675,7 +872,7 @@ async fn cmd_craft(conn: &mut Connection, args: &serde_json::Value) -> CommandRe
             "queued": count,
             "recipe_id": recipe_id
         })),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -687,7 +884,7 @@ async fn cmd_research(conn: &mut Connection, args: &serde_json::Value) -> Comman
         Ok(_) => CommandResult::ok(serde_json::json!({
             "technology_id": tech_id
         })),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -699,7 +896,7 @@ async fn cmd_cancel_craft(conn: &mut Connection, args: &serde_json::Value) -> Co
             "index": index,
             "count": count
         })),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -708,21 +905,21 @@ async fn cmd_select_entity(conn: &mut Connection, args: &serde_json::Value) -> C
     let y = arg_f64(args, "y", 0.0);
     match conn.actions().send_selected_entity_changed(x, y).await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
 async fn cmd_clear_selection(conn: &mut Connection) -> CommandResult {
     match conn.actions().send_selected_entity_cleared().await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
 async fn cmd_clear_cursor(conn: &mut Connection) -> CommandResult {
     match conn.actions().send_clear_cursor().await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -731,7 +928,7 @@ async fn cmd_drop_item(conn: &mut Connection, args: &serde_json::Value) -> Comma
     let y = arg_f64(args, "y", 0.0);
     match conn.actions().send_drop_item(x, y).await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -740,7 +937,7 @@ async fn cmd_use_item(conn: &mut Connection, args: &serde_json::Value) -> Comman
     let y = arg_f64(args, "y", 0.0);
     match conn.actions().send_use_item(x, y).await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -754,7 +951,7 @@ async fn cmd_set_ghost_cursor(conn: &mut Connection, args: &serde_json::Value) -
             "item_id": item_id,
             "quality_id": quality_id
         })),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -775,7 +972,7 @@ async fn cmd_place_ghost(conn: &mut Connection, args: &serde_json::Value) -> Com
             "position": { "x": x, "y": y },
             "direction": direction
         })),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -1374,7 +1571,7 @@ fn cmd_alerts(conn: &Connection, args: &serde_json::Value) -> CommandResult {
 async fn cmd_spawn(conn: &mut Connection) -> CommandResult {
     match conn.actions().send_continue_singleplayer().await {
         Ok(_) => CommandResult::ok(serde_json::json!({"status": "requested"})),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -1836,7 +2033,7 @@ async fn cmd_build(conn: &mut Connection, args: &serde_json::Value) -> CommandRe
     let direction = arg_u64(args, "direction", 0) as u8;
     match conn.actions().send_build(x, y, direction).await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -1873,11 +2070,11 @@ async fn cmd_place(conn: &mut Connection, args: &serde_json::Value) -> CommandRe
 
     let _ = conn.actions().send_clear_cursor().await;
     if let Err(e) = conn.actions().send_cursor_transfer(loc).await {
-        return CommandResult::err(e.to_string());
+        return CommandResult::from_error(&e);
     }
     let _ = conn.actions().send_selected_entity_changed(x, y).await;
     if let Err(e) = conn.actions().send_build(x, y, direction).await {
-        return CommandResult::err(e.to_string());
+        return CommandResult::from_error(&e);
     }
     if clear_cursor {
         let _ = conn.actions().send_clear_cursor().await;
@@ -1916,7 +2113,7 @@ async fn cmd_build_blueprint(conn: &mut Connection, args: &serde_json::Value) ->
     let clear_cursor = arg_bool(args, "clear_cursor", true);
 
     if let Err(e) = conn.actions().send_import_blueprint_string(blueprint, flags, mode).await {
-        return CommandResult::err(e.to_string());
+        return CommandResult::from_error(&e);
     }
 
     let result = conn.actions().send_build(x, y, direction).await;
@@ -1933,7 +2130,7 @@ async fn cmd_build_blueprint(conn: &mut Connection, args: &serde_json::Value) ->
             "mode": mode,
             "clear_cursor": clear_cursor
         })),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -1999,7 +2196,7 @@ async fn cmd_set_logistics(conn: &mut Connection, args: &serde_json::Value) -> C
             "quality_id": quality_id,
             "quality_extra": quality_extra
         })),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -2038,7 +2235,7 @@ async fn cmd_cursor_transfer(
             },
             "mode": if split { "split" } else { "transfer" }
         })),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -2083,7 +2280,7 @@ async fn cmd_stack_transfer(
                 "source": location.source
             }
         })),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -2093,7 +2290,7 @@ async fn cmd_rotate(conn: &mut Connection, args: &serde_json::Value) -> CommandR
     let reverse = arg_bool(args, "reverse", false);
     match conn.actions().send_rotate(x, y, reverse).await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -2129,7 +2326,7 @@ async fn cmd_filter(conn: &mut Connection, args: &serde_json::Value) -> CommandR
             "quality_id": quality_id,
             "quality_extra": quality_extra
         })),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -2156,7 +2353,7 @@ async fn cmd_clear_filter(conn: &mut Connection, args: &serde_json::Value) -> Co
             "inventory": inventory_index,
             "source": source
         })),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -2177,7 +2374,7 @@ async fn cmd_fast_transfer(
             conn.actions().send_fast_transfer(from_player).await
         };
         if let Err(e) = result {
-            return CommandResult::err(e.to_string());
+            return CommandResult::from_error(&e);
         }
     }
     CommandResult::ok(serde_json::json!({
@@ -2220,7 +2417,7 @@ async fn cmd_insert(conn: &mut Connection, args: &serde_json::Value) -> CommandR
                 conn.actions().send_stack_transfer(spec).await
             };
             if let Err(e) = result {
-                return CommandResult::err(e.to_string());
+                return CommandResult::from_error(&e);
             }
         }
         return CommandResult::ok(serde_json::json!({
@@ -2248,7 +2445,7 @@ async fn cmd_insert(conn: &mut Connection, args: &serde_json::Value) -> CommandR
             conn.actions().send_fast_transfer(true).await
         };
         if let Err(e) = result {
-            return CommandResult::err(e.to_string());
+            return CommandResult::from_error(&e);
         }
     }
     CommandResult::ok(serde_json::json!({
@@ -2291,7 +2488,7 @@ async fn cmd_extract(conn: &mut Connection, args: &serde_json::Value) -> Command
                 conn.actions().send_stack_transfer(spec).await
             };
             if let Err(e) = result {
-                return CommandResult::err(e.to_string());
+                return CommandResult::from_error(&e);
             }
         }
         return CommandResult::ok(serde_json::json!({
@@ -2319,7 +2516,7 @@ async fn cmd_extract(conn: &mut Connection, args: &serde_json::Value) -> Command
             conn.actions().send_fast_transfer(false).await
         };
         if let Err(e) = result {
-            return CommandResult::err(e.to_string());
+            return CommandResult::from_error(&e);
         }
     }
     CommandResult::ok(serde_json::json!({
@@ -2392,7 +2589,7 @@ async fn cmd_equip(conn: &mut Connection, args: &serde_json::Value) -> CommandRe
         stack_id,
         location: from_location,
     }).await {
-        return CommandResult::err(e.to_string());
+        return CommandResult::from_error(&e);
     }
     if let Err(e) = conn.actions().send_cursor_transfer(ClientItemStackLocation {
         item_id: 0,
@@ -2400,7 +2597,7 @@ async fn cmd_equip(conn: &mut Connection, args: &serde_json::Value) -> CommandRe
         stack_id: 0,
         location: to_location,
     }).await {
-        return CommandResult::err(e.to_string());
+        return CommandResult::from_error(&e);
     }
 
     CommandResult::ok(serde_json::json!({
@@ -2458,10 +2655,10 @@ async fn cmd_drop(conn: &mut Connection, args: &serde_json::Value) -> CommandRes
 
     let _ = conn.actions().send_clear_cursor().await;
     if let Err(e) = conn.actions().send_cursor_transfer(loc).await {
-        return CommandResult::err(e.to_string());
+        return CommandResult::from_error(&e);
     }
     if let Err(e) = conn.actions().send_drop_item(x, y).await {
-        return CommandResult::err(e.to_string());
+        return CommandResult::from_error(&e);
     }
     if clear_cursor {
         let _ = conn.actions().send_clear_cursor().await;
@@ -2508,11 +2705,11 @@ async fn cmd_pickup(conn: &mut Connection, args: &serde_json::Value) -> CommandR
     let hold_ms = arg_u64(args, "hold_ms", 80);
     let _ = conn.actions().send_selected_entity_changed(x, y).await;
     if let Err(e) = conn.actions().send_change_picking_state(true).await {
-        return CommandResult::err(e.to_string());
+        return CommandResult::from_error(&e);
     }
     tokio::time::sleep(Duration::from_millis(hold_ms)).await;
     if let Err(e) = conn.actions().send_change_picking_state(false).await {
-        return CommandResult::err(e.to_string());
+        return CommandResult::from_error(&e);
     }
     CommandResult::ok(serde_json::json!({
         "position": { "x": x, "y": y },
@@ -2533,7 +2730,7 @@ async fn cmd_set_recipe(conn: &mut Connection, args: &serde_json::Value) -> Comm
             "recipe_id": recipe_id,
             "quality_id": quality_id
         })),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -2542,21 +2739,21 @@ async fn cmd_shoot(conn: &mut Connection, args: &serde_json::Value) -> CommandRe
     let y = arg_f64(args, "y", 0.0);
     match conn.actions().send_shoot(x, y).await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
 async fn cmd_stop_shoot(conn: &mut Connection) -> CommandResult {
     match conn.actions().send_stop_shoot().await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
 async fn cmd_toggle_driving(conn: &mut Connection) -> CommandResult {
     match conn.actions().send_toggle_driving().await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -2565,7 +2762,7 @@ async fn cmd_drive(conn: &mut Connection, args: &serde_json::Value) -> CommandRe
     let direction = arg_u64(args, "direction", 0) as u8;
     match conn.actions().send_drive(acceleration, direction).await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -2614,7 +2811,7 @@ async fn cmd_enter_vehicle(conn: &mut Connection, args: &serde_json::Value) -> C
             }
             CommandResult::ok_empty()
         }
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -2629,7 +2826,7 @@ async fn cmd_exit_vehicle(conn: &mut Connection) -> CommandResult {
             }
             CommandResult::ok_empty()
         }
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -2686,17 +2883,17 @@ async fn cmd_connect_wire(conn: &mut Connection, args: &serde_json::Value) -> Co
         };
         let _ = conn.actions().send_clear_cursor().await;
         if let Err(e) = conn.actions().send_cursor_transfer(loc).await {
-            return CommandResult::err(e.to_string());
+            return CommandResult::from_error(&e);
         }
     }
 
     let _ = conn.actions().send_selected_entity_changed(x1, y1).await;
     if let Err(e) = conn.actions().send_wire_dragging(x1, y1).await {
-        return CommandResult::err(e.to_string());
+        return CommandResult::from_error(&e);
     }
     let _ = conn.actions().send_selected_entity_changed(x2, y2).await;
     if let Err(e) = conn.actions().send_wire_dragging(x2, y2).await {
-        return CommandResult::err(e.to_string());
+        return CommandResult::from_error(&e);
     }
     if wire_item_id.is_some() && wire_clear_cursor {
         let _ = conn.actions().send_clear_cursor().await;
@@ -2728,7 +2925,7 @@ async fn cmd_disconnect_wire(conn: &mut Connection, args: &serde_json::Value) ->
             "wire": wire,
             "note": "remove-cables clears all wire connections"
         })),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -2743,7 +2940,7 @@ async fn cmd_deconstruct(conn: &mut Connection, args: &serde_json::Value) -> Com
     };
     match conn.actions().send_deconstruct_area(left_top, right_bottom).await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -2758,7 +2955,7 @@ async fn cmd_cancel_deconstruct(conn: &mut Connection, args: &serde_json::Value)
     };
     match conn.actions().send_cancel_deconstruct_area(left_top, right_bottom).await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -2768,7 +2965,7 @@ async fn cmd_copy_settings(conn: &mut Connection, args: &serde_json::Value) -> C
     let _ = conn.actions().send_selected_entity_changed(x, y).await;
     match conn.actions().send_copy_entity_settings().await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -2778,7 +2975,7 @@ async fn cmd_paste_settings(conn: &mut Connection, args: &serde_json::Value) ->
     let _ = conn.actions().send_selected_entity_changed(x, y).await;
     match conn.actions().send_paste_entity_settings().await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -2787,7 +2984,7 @@ async fn cmd_remove_cables(conn: &mut Connection, args: &serde_json::Value) -> C
     let y = arg_f64(args, "y", 0.0);
     match conn.actions().send_remove_cables(x, y).await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -2797,7 +2994,7 @@ async fn cmd_launch_rocket(conn: &mut Connection, args: &serde_json::Value) -> C
     let _ = conn.actions().send_selected_entity_changed(x, y).await;
     match conn.actions().send_launch_rocket().await {
         Ok(_) => CommandResult::ok_empty(),
-        Err(e) => CommandResult::err(e.to_string()),
+        Err(e) => CommandResult::from_error(&e),
     }
 }
 
@@ -3268,6 +3465,16 @@ impl PathFollower {
         self.last_debug_tick = None;
     }
 
+    /// Keep the path but restart direction/stall tracking (e.g. after a reconnect).
+    fn resume(&mut self) {
+        self.last_direction = None;
+        self.last_sent_tick = None;
+        self.last_pos = None;
+        self.last_move_tick = None;
+        self.started_tick = None;
+        self.last_debug_tick = None;
+    }
+
     fn reset_tracking(&mut self, tick: u32, pos: (f64, f64)) {
         self.started_tick = Some(tick);
         self.last_move_tick = Some(tick);
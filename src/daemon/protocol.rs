@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::error::Error;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Request {
     pub id: String,
@@ -17,12 +19,16 @@ pub struct Response {
     pub result: Option<Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
+    /// Machine-readable kind of `error` (see `Error::code`), when it has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
 }
 
 pub struct CommandResult {
     pub success: bool,
     pub data: Option<Value>,
     pub error: Option<String>,
+    pub code: Option<&'static str>,
 }
 
 impl CommandResult {
@@ -31,6 +37,7 @@ impl CommandResult {
             success: true,
             data: Some(data),
             error: None,
+            code: None,
         }
     }
 
@@ -39,6 +46,7 @@ impl CommandResult {
             success: true,
             data: None,
             error: None,
+            code: None,
         }
     }
 
@@ -47,6 +55,17 @@ impl CommandResult {
             success: false,
             data: None,
             error: Some(msg.into()),
+            code: None,
+        }
+    }
+
+    /// Failure carrying the error's code alongside its message
+    pub fn from_error(err: &Error) -> Self {
+        Self {
+            success: false,
+            data: None,
+            error: Some(err.to_string()),
+            code: Some(err.code()),
         }
     }
 }
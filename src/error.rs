@@ -1,31 +1,54 @@
+use crate::codec::InputActionType;
+use crate::protocol::message::{ApplicationVersion, DenialReason};
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("connection refused: {reason}")]
     ConnectionRefused { reason: String },
 
-    #[error("connection timeout")]
-    ConnectionTimeout,
+    /// `password_sent` flags that the experimental password hash may be the cause
+    #[error(
+        "connection denied: {reason}{}",
+        if *password_sent { " (a game password was sent; its hashing is experimental and may not match the game's)" } else { "" }
+    )]
+    Denied { reason: DenialReason, password_sent: bool },
 
     #[error("desync detected at tick {tick}: expected CRC {expected:#x}, got {actual:#x}")]
     Desync { tick: u32, expected: u32, actual: u32 },
 
-    #[error("timeout")]
-    Timeout,
-
-    #[error("timeout waiting for {operation}")]
-    TimeoutWaiting { operation: &'static str },
+    #[error("timeout waiting for {stage}")]
+    Timeout { stage: Stage },
 
     #[error("invalid packet: {0}")]
     InvalidPacket(String),
 
+    #[error("malformed heartbeat at byte {offset}: {reason}")]
+    MalformedHeartbeat { offset: usize, reason: String },
+
+    /// `offset` is where parsing failed, `start` where the section began
+    #[error("failed to parse {section} at byte {offset} (section starts at byte {start}): {source}")]
+    Parse { section: &'static str, start: usize, offset: usize, source: Box<Error> },
+
     #[error("invalid message type: {0}")]
     InvalidMessageType(u8),
 
     #[error("invalid input action type: {0}")]
     InvalidInputAction(u8),
 
-    #[error("protocol version mismatch: server {server}, client {client}")]
-    VersionMismatch { server: u32, client: u32 },
+    #[error("{action:?} rejected: {reason}")]
+    ActionRejected { action: Option<InputActionType>, reason: RejectReason },
+
+    #[error("observer connections cannot send input actions")]
+    ObserverReadOnly,
+
+    #[error("unsupported version {0}")]
+    UnsupportedVersion(String),
+
+    #[error("version mismatch: server {server}, client {client}")]
+    VersionMismatch { server: ApplicationVersion, client: ApplicationVersion },
+
+    #[error("mod mismatch: missing {missing:?}, extra {extra:?}")]
+    ModMismatch { missing: Vec<String>, extra: Vec<String> },
 
     #[error("unexpected end of data")]
     UnexpectedEof,
@@ -42,8 +65,134 @@ pub enum Error {
     #[error("not connected")]
     NotConnected,
 
+    #[error("invalid address: {0}")]
+    InvalidAddress(String),
+
+    /// A recorded capture ran out before the exchange it was replaying finished
+    #[error("replay exhausted")]
+    ReplayExhausted,
+
+    /// A spawned task panicked or was cancelled
+    #[error("task failed: {0}")]
+    TaskFailed(String),
+
     #[error("io error: {0}")]
     Io(String),
 }
 
+impl Error {
+    /// Stable snake_case name of the failure, for machine consumers (daemon responses)
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::ConnectionRefused { .. } => "connection_refused",
+            Error::Denied { .. } => "denied",
+            Error::Desync { .. } => "desync",
+            Error::Timeout { .. } => "timeout",
+            Error::InvalidPacket(_) => "invalid_packet",
+            Error::MalformedHeartbeat { .. } => "malformed_heartbeat",
+            Error::Parse { .. } => "parse_error",
+            Error::InvalidMessageType(_) => "invalid_message_type",
+            Error::InvalidInputAction(_) => "invalid_input_action",
+            Error::ActionRejected { .. } => "action_rejected",
+            Error::ObserverReadOnly => "observer_read_only",
+            Error::UnsupportedVersion(_) => "unsupported_version",
+            Error::VersionMismatch { .. } => "version_mismatch",
+            Error::ModMismatch { .. } => "mod_mismatch",
+            Error::UnexpectedEof => "unexpected_eof",
+            Error::StringTooLong { .. } => "string_too_long",
+            Error::BufferOverflow { .. } => "buffer_overflow",
+            Error::Disconnected { .. } => "disconnected",
+            Error::NotConnected => "not_connected",
+            Error::InvalidAddress(_) => "invalid_address",
+            Error::ReplayExhausted => "replay_exhausted",
+            Error::TaskFailed(_) => "task_failed",
+            Error::Io(_) => "io_error",
+        }
+    }
+
+    /// Attribute the error to a section of a save that started at `start` and
+    /// failed at `offset`. The innermost section wins, so nested sections keep
+    /// the most precise one. A refused map version is not a parse failure and
+    /// stays as it is.
+    pub fn in_section(self, section: &'static str, start: usize, offset: usize) -> Self {
+        match self {
+            Error::Parse { .. } | Error::UnsupportedVersion(_) => self,
+            source => Error::Parse { section, start, offset, source: Box::new(source) },
+        }
+    }
+}
+
+/// What a timed out operation was waiting for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// GameInformationRequestReply
+    ServerInfo,
+    /// ConnectionRequestReply
+    ConnectionReply,
+    /// ConnectionAcceptOrDeny
+    Accept,
+    /// MapReadyForDownload
+    MapReady,
+    /// TransferBlocks of the save
+    MapDownload,
+    /// The server acknowledging our disconnect
+    Disconnect,
+}
+
+impl std::fmt::Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Stage::ServerInfo => "server info",
+            Stage::ConnectionReply => "connection reply",
+            Stage::Accept => "accept",
+            Stage::MapReady => "map ready",
+            Stage::MapDownload => "map download",
+            Stage::Disconnect => "disconnect acknowledgement",
+        })
+    }
+}
+
+/// Why an input action was not carried out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// Actions can only be sent once in game
+    NotInGame,
+    /// The server has not told us our player index yet
+    NoPlayer,
+    /// Sent, but the server never ran it
+    NotExecuted,
+    /// Discarded unsent by a disconnect or reset
+    Dropped,
+}
+
+impl std::fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            RejectReason::NotInGame => "not in game",
+            RejectReason::NoPlayer => "player index unknown",
+            RejectReason::NotExecuted => "not executed by the server",
+            RejectReason::Dropped => "dropped before sending",
+        })
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_and_sections() {
+        let denied = Error::Denied { reason: DenialReason::Status(7), password_sent: true };
+        assert_eq!(denied.code(), "denied");
+        assert!(denied.to_string().starts_with("connection denied: status 7 (a game password was sent;"));
+        assert_eq!(Error::Timeout { stage: Stage::Accept }.to_string(), "timeout waiting for accept");
+
+        let err = Error::UnexpectedEof.in_section("train manager", 120, 135).in_section("level.dat", 0, 135);
+        assert!(matches!(&err, Error::Parse { section: "train manager", start: 120, offset: 135, .. }));
+        assert_eq!(err.code(), "parse_error");
+        assert_eq!(err.to_string(), "failed to parse train manager at byte 135 (section starts at byte 120): unexpected end of data");
+        assert!(std::error::Error::source(&err).is_some());
+    }
+}
@@ -19,7 +19,7 @@ pub use factorio_mapgen as noise;
 pub use error::{Error, Result};
 pub use protocol::{
     Connection, ConnectionState, BuildVersion, MessageType,
-    ModInfo, ModVersion, ServerInfo,
+    Credentials, ModInfo, ModVersion, ServerInfo,
 };
 pub use codec::{
     Fixed32, MapPosition, TilePosition, ChunkPosition,
@@ -0,0 +1,265 @@
+//! Raw datagram capture and replay
+//!
+//! A capture file records every datagram a `Transport` sends or receives so a
+//! session can be replayed offline. Layout (all integers little-endian):
+//!
+//! ```text
+//! header:  b"FCAP" [u16 format version]
+//! record:  [u64 micros since capture start][u8 direction][u32 len][len bytes]
+//! ```
+//!
+//! Direction is 0 for client -> server and 1 for server -> client.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::codec::{BinaryReader, BinaryWriter};
+use crate::error::{Error, Result};
+
+const CAPTURE_MAGIC: &[u8; 4] = b"FCAP";
+const CAPTURE_FORMAT_VERSION: u16 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Client -> server
+    Outbound = 0,
+    /// Server -> client
+    Inbound = 1,
+}
+
+impl Direction {
+    fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Direction::Outbound),
+            1 => Ok(Direction::Inbound),
+            other => Err(Error::InvalidPacket(format!("invalid capture direction {}", other))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureRecord {
+    /// Monotonic time since the capture started
+    pub at: Duration,
+    pub direction: Direction,
+    pub data: Vec<u8>,
+}
+
+/// A complete capture loaded into memory
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capture {
+    pub records: Vec<CaptureRecord>,
+}
+
+impl Capture {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let bytes = std::fs::read(path).map_err(|e| Error::Io(e.to_string()))?;
+        Self::parse(&bytes)
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut reader = BinaryReader::new(bytes);
+        if reader.read_bytes(4)? != CAPTURE_MAGIC {
+            return Err(Error::InvalidPacket("not a capture file".into()));
+        }
+        let version = reader.read_u16_le()?;
+        if version != CAPTURE_FORMAT_VERSION {
+            return Err(Error::InvalidPacket(format!("unsupported capture format {}", version)));
+        }
+
+        let mut records = Vec::new();
+        while reader.remaining() > 0 {
+            let at = Duration::from_micros(reader.read_u64_le()?);
+            let direction = Direction::from_u8(reader.read_u8()?)?;
+            let len = reader.read_u32_le()? as usize;
+            let data = reader.read_bytes(len)?.to_vec();
+            records.push(CaptureRecord { at, direction, data });
+        }
+        Ok(Self { records })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = BinaryWriter::new();
+        write_header(&mut writer);
+        for record in &self.records {
+            write_record(&mut writer, record.at, record.direction, &record.data);
+        }
+        writer.into_vec()
+    }
+
+    /// Records in one direction, in capture order
+    pub fn filter(&self, direction: Direction) -> impl Iterator<Item = &CaptureRecord> {
+        self.records.iter().filter(move |r| r.direction == direction)
+    }
+}
+
+fn write_header(writer: &mut BinaryWriter) {
+    writer.write_bytes(CAPTURE_MAGIC);
+    writer.write_u16_le(CAPTURE_FORMAT_VERSION);
+}
+
+fn write_record(writer: &mut BinaryWriter, at: Duration, direction: Direction, data: &[u8]) {
+    writer.write_u64_le(at.as_micros() as u64);
+    writer.write_u8(direction as u8);
+    writer.write_u32_le(data.len() as u32);
+    writer.write_bytes(data);
+}
+
+struct RecorderInner {
+    out: Box<dyn Write + Send>,
+    start: Instant,
+}
+
+/// Appends datagrams to a capture file.
+///
+/// Cheap to clone; clones share the same file and clock, so the probe socket and
+/// the game socket of one `Connection` end up in a single capture.
+#[derive(Clone)]
+pub struct Recorder {
+    inner: Arc<Mutex<RecorderInner>>,
+}
+
+impl std::fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recorder").finish_non_exhaustive()
+    }
+}
+
+impl Recorder {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::create(path).map_err(|e| Error::Io(e.to_string()))?;
+        Self::new(file)
+    }
+
+    pub fn new(out: impl Write + Send + 'static) -> Result<Self> {
+        let mut out: Box<dyn Write + Send> = Box::new(out);
+        let mut writer = BinaryWriter::new();
+        write_header(&mut writer);
+        out.write_all(writer.as_slice()).map_err(|e| Error::Io(e.to_string()))?;
+        Ok(Self {
+            inner: Arc::new(Mutex::new(RecorderInner { out, start: Instant::now() })),
+        })
+    }
+
+    /// Record one datagram. Write errors are ignored so capture never breaks a session.
+    pub fn record(&self, direction: Direction, data: &[u8]) {
+        let Ok(mut inner) = self.inner.lock() else {
+            return;
+        };
+        let mut writer = BinaryWriter::with_capacity(13 + data.len());
+        write_record(&mut writer, inner.start.elapsed(), direction, data);
+        // One write per record keeps the file usable if the process is killed.
+        let _ = inner.out.write_all(writer.as_slice());
+        let _ = inner.out.flush();
+    }
+}
+
+/// Feeds the inbound half of a capture back to a `Transport`.
+///
+/// Pacing is driven by what the client sends rather than by wall time: an inbound
+/// datagram is released once the client has sent at least as many datagrams as had
+/// been sent before it in the recording. This keeps replays deterministic while
+/// still ordering e.g. a ConnectionRequestReply after the ConnectionRequest.
+#[derive(Debug)]
+pub(crate) struct Replay {
+    /// (outbound datagrams sent before this one, data)
+    inbound: VecDeque<(usize, Vec<u8>)>,
+    sent: usize,
+}
+
+impl Replay {
+    pub(crate) fn new(capture: &Capture) -> Self {
+        let mut outbound = 0;
+        let mut inbound = VecDeque::new();
+        for record in &capture.records {
+            match record.direction {
+                Direction::Outbound => outbound += 1,
+                Direction::Inbound => inbound.push_back((outbound, record.data.clone())),
+            }
+        }
+        Self { inbound, sent: 0 }
+    }
+
+    pub(crate) fn on_send(&mut self) {
+        self.sent += 1;
+    }
+
+    /// Next inbound datagram, if the client has sent enough to release it
+    pub(crate) fn next_ready(&mut self) -> Option<Vec<u8>> {
+        match self.inbound.front() {
+            Some((gate, _)) if *gate <= self.sent => self.inbound.pop_front().map(|(_, data)| data),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_exhausted(&self) -> bool {
+        self.inbound.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_recorder_roundtrip() {
+        let buf = SharedBuf::default();
+        let recorder = Recorder::new(buf.clone()).unwrap();
+        recorder.record(Direction::Outbound, &[0x10]);
+        recorder.clone().record(Direction::Inbound, &[0x11, 1, 2, 3]);
+
+        let bytes = buf.0.lock().unwrap().clone();
+        let capture = Capture::parse(&bytes).unwrap();
+        assert_eq!(capture.records.len(), 2);
+        assert_eq!(capture.records[0].direction, Direction::Outbound);
+        assert_eq!(capture.records[1].data, vec![0x11, 1, 2, 3]);
+        assert!(capture.records[0].at <= capture.records[1].at);
+        assert_eq!(Capture::parse(&capture.to_bytes()).unwrap(), capture);
+        assert!(Capture::parse(b"nope").is_err());
+    }
+
+    #[test]
+    fn test_replay_gated_by_sends() {
+        let record = |direction, data: &[u8]| CaptureRecord {
+            at: Duration::ZERO,
+            direction,
+            data: data.to_vec(),
+        };
+        let capture = Capture {
+            records: vec![
+                record(Direction::Inbound, &[1]),
+                record(Direction::Outbound, &[2]),
+                record(Direction::Outbound, &[3]),
+                record(Direction::Inbound, &[4]),
+            ],
+        };
+        let mut replay = Replay::new(&capture);
+        assert_eq!(replay.next_ready(), Some(vec![1]));
+        assert_eq!(replay.next_ready(), None);
+        replay.on_send();
+        assert_eq!(replay.next_ready(), None);
+        replay.on_send();
+        assert_eq!(replay.next_ready(), Some(vec![4]));
+        assert!(replay.is_exhausted());
+        replay.on_send();
+        assert_eq!(replay.next_ready(), None);
+    }
+}
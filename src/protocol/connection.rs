@@ -1,34 +1,58 @@
-use std::collections::{BTreeMap, HashMap, VecDeque};
-use std::fs::File;
-use std::io::{self, BufRead};
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
 use std::time::Duration;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use crate::codec::{
-    BinaryReader, BinaryWriter, InputAction as CodecInputAction, InputActionType,
+    ActionNumbering, BinaryReader, BinaryWriter, InputAction as CodecInputAction, InputActionType,
     ChunkPosition, Direction, MapEntity, MapPosition, ShootingState, TilePosition,
-    SynchronizerActionType, parse_map_data, map_transfer::MapData,
+    SynchronizerAction, SynchronizerActionType, parse_map_data_with_format, map_transfer::MapData, MapStreamParser,
 };
-use crate::error::{Error, Result};
+use crate::error::{Error, RejectReason, Result, Stage};
 use crate::protocol::message::{
     ConnectionRequest, ConnectionRequestReply, ConnectionRequestReplyConfirm,
-    ConnectionAcceptOrDeny, ModInfo, ServerInfo, TransferBlockRequest,
-    InputAction,
+    ApplicationVersion, ConnectionAcceptOrDeny, Credentials, DenialReason, ModInfo,
+    RequestForHeartbeatWhenDisconnecting, TransferBlock, TransferBlockRequest, InputAction,
 };
-use crate::protocol::packet::{PacketHeader, MessageType};
-use crate::protocol::transport::Transport;
+use crate::protocol::profile::{HandshakeConfig, VersionProfile};
+use crate::protocol::packet::{PacketHeader, PacketBuilder, MessageType};
+use crate::protocol::transport::{Transport, TransportFactory, UdpTransportFactory};
+use crate::protocol::capture::{Capture, Recorder};
+use crate::protocol::heartbeat::{ActionSegment, SegmentAssembler, ServerHeartbeat, MAX_SEGMENT_DATA};
+use crate::protocol::map_download::BlockDownloader;
+use crate::protocol::fragment::{split_message, FragmentAssembler, MAX_FRAGMENT_PAYLOAD};
+use crate::protocol::shared_map::{Claim, SaveKey, SharedMaps, SharedSave};
 use crate::simulation::{TickExecutor, tick::TickClosureData, tick::TickAction};
 use crate::state::{GameWorld, surface::Tile, entity::{Entity, entity_type_from_name, EntityData, EntityType}};
 use crate::state::recipe::{Recipe, RecipeItem};
 use crate::lua::prototype::Prototypes;
+use crate::client::events::{DisconnectReason, GameEvent};
 
 mod actions;
+mod desync;
+mod observer;
+mod ping;
+mod receipts;
+mod roster;
 pub use actions::ConnectionActions;
+pub use desync::{DesyncCause, DesyncReport};
+use desync::{DesyncMonitor, TickLeadSample};
+pub use observer::ObserverConnection;
+pub use ping::NetworkStats;
+use ping::PingTracker;
+pub use receipts::{ActionOutcome, ActionReceipt, DEFAULT_ACTION_TIMEOUT};
+use receipts::{ActionReceipts, QueuedAction};
+pub use roster::RosterEntry;
+use roster::Roster;
 
 const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 
-const MAP_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+/// Give up (keeping the blocks for a resume) when no new block arrives for this long
+const MAP_DOWNLOAD_STALL_TIMEOUT: Duration = Duration::from_secs(10);
+const MAP_DOWNLOAD_PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+/// How long to wait for another connection to download a shared save
+const SHARED_SAVE_WAIT_TIMEOUT: Duration = Duration::from_secs(60);
 const RECV_TIMEOUT: Duration = Duration::from_millis(500);
 const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(16); // ~60 Hz
 const MAX_CATCHUP_TICKS_PER_FLUSH: u32 = 60;
@@ -37,7 +61,54 @@ const CLIENT_TICK_LEAD_INITIAL: u32 = 32; // PCAP: client_tick=5066319, confirme
 const CLIENT_TICK_LEAD_BIAS: i32 = 0;
 const CLIENT_TICK_LEAD_MIN: u32 = 32; // Must match PCAP observation of ~32 tick lead
 const CLIENT_TICK_LEAD_MAX: u32 = 256;
-const INITIAL_BLOCK_REQUEST_MAX: u32 = 8192;
+const MAX_PENDING_EVENTS: usize = 1024;
+/// Gameplay heartbeats kept for resending (~5 seconds at 60 Hz)
+const SENT_HEARTBEAT_HISTORY: usize = 300;
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const DISCONNECT_REQUEST_INTERVAL: Duration = Duration::from_millis(200);
+/// ClientChangedState value for a scheduled disconnect. Observed states:
+/// 4 = loading map, 6 = waiting for start command, 7 = in game, 8 = disconnect scheduled.
+const CLIENT_STATE_DISCONNECT_SCHEDULED: u8 = 0x08;
+
+/// Opt-in automatic reconnect after an unexpected disconnect (exponential backoff).
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    /// Give up after this many failed attempts (`None` = retry forever).
+    pub max_attempts: Option<u32>,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: None,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            multiplier: 2.0,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Delay before retry number `attempt` (1-based).
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.max(1.0).powi(attempt.saturating_sub(1) as i32);
+        self.initial_backoff.mul_f64(factor).min(self.max_backoff)
+    }
+}
+
+/// What to do with a server heartbeat the structured decoder rejects.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HeartbeatParsing {
+    /// Apply only what decoded before the error; the error is counted and
+    /// kept in `last_heartbeat_error()`.
+    #[default]
+    Strict,
+    /// Retry with the older byte-scanning heuristics (FACTORIO_HEARTBEAT_FALLBACK).
+    Fallback,
+}
 
 /// Connection state
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -52,6 +123,27 @@ pub enum ConnectionState {
     InGame,        // Fully synced, can send InputActions
 }
 
+/// Whether the server simulation is advancing, as told by its sync actions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ServerRunState {
+    #[default]
+    Running,
+    /// Between BeginPause and EndPause
+    Paused,
+    /// After SavingForUpdate, until tick closures advance again
+    Saving,
+}
+
+impl ServerRunState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Running => "running",
+            Self::Paused => "paused",
+            Self::Saving => "saving",
+        }
+    }
+}
+
 /// State for tracking other players
 #[derive(Debug, Clone, Default)]
 pub struct PlayerState {
@@ -68,7 +160,8 @@ pub struct PlayerState {
 }
 
 struct SimulationState {
-    world: GameWorld,
+    /// Shared with the other connections of a pool until this one changes it
+    world: Arc<GameWorld>,
     executor: TickExecutor,
 }
 
@@ -117,9 +210,16 @@ pub struct Connection {
     username: String,
 
     // Connection info
+    credentials: Credentials,
+    handshake: HandshakeConfig,
+    version: ApplicationVersion, // Negotiated from ConnectionRequestReply
+    /// Protocol details for `version`
+    profile: VersionProfile,
     client_request_id: u32,
     server_request_id: Option<u32>,
     server_mods: Vec<ModInfo>,
+    /// Mods the server listed in GameInformationRequestReply, to explain a mod mismatch
+    advertised_mods: Option<Vec<ModInfo>>,
 
     // Player info
     peer_id: Option<u16>,
@@ -149,6 +249,7 @@ pub struct Connection {
     confirmed_tick: u32,
     client_tick_lead: u32,
     latency_value: Option<u8>,
+    pings: PingTracker,
     accept_latency: Option<u8>,
     start_sending_tick: Option<u32>,
     allow_actions: bool,
@@ -163,24 +264,42 @@ pub struct Connection {
     map_tick: Option<u32>,
 
     // Map data
-    map_data: Vec<u8>,
-    entities: Vec<MapEntity>,
-    pub(crate) parsed_map: Option<MapData>,
+    map_data: Arc<Vec<u8>>,
+    pub(crate) parsed_map: Option<Arc<MapData>>,
 
     // Pending confirmations for reliable messages we received
     pending_confirms: Vec<u32>,
-    fragmented_heartbeats: HashMap<u16, FragmentAssembly>,
-
-    // Chat sequence number (pcap shows incrementing u32 in chat actions)
-    chat_seq: u32,
+    fragments: FragmentAssembler,
+    // Recently sent gameplay heartbeats by sequence, resent when the server asks for them
+    sent_heartbeats: VecDeque<(u32, Vec<Vec<u8>>)>,
+    requested_heartbeats: Vec<u32>,
+    heartbeat_resends: u64,
+
+    // Id of the next segmented action. Chat is always segmented; pcap shows the
+    // id as an incrementing u32 there.
+    segment_id: u32,
+    // Segmented actions from the server until all their segments are in
+    segmented_actions: SegmentAssembler,
 
     // Pending input actions to send (one per gameplay tick)
-    pending_actions: VecDeque<InputAction>,
+    pending_actions: VecDeque<QueuedAction>,
+    /// Receipts handed out by queue_action, until the server echoes the action
+    action_receipts: ActionReceipts,
+
+    // Pause/save tracking; pending_actions are held while not Running
+    server_run_state: ServerRunState,
+    /// server_tick when server_run_state last changed
+    server_run_state_tick: u32,
+    /// Tick of the announced upcoming autosave (SavingCountdown)
+    autosave_tick: Option<u64>,
 
     // Send init action once player_index is known
     pending_init_action: bool,
     // Send the first gameplay heartbeat with ClientChangedState(0x07)
     pending_start_gameplay: bool,
+    // Graceful leave in progress / server acknowledged it
+    disconnecting: bool,
+    disconnect_acknowledged: bool,
     // Pending IncreasedLatencyConfirm (latency increase amount to confirm)
     pending_latency_confirm: Option<u8>,
     pending_skipped_tick_confirms: VecDeque<u64>,
@@ -208,6 +327,7 @@ pub struct Connection {
 
     // Other player tracking
     other_players: HashMap<u16, PlayerState>,
+    roster: Roster,
     initial_player_positions: Vec<(f64, f64)>,
     /// Tracks which character positions from the map have been assigned
     assigned_position_indices: std::collections::HashSet<usize>,
@@ -216,31 +336,113 @@ pub struct Connection {
 
     // Deterministic simulation state (client-side)
     simulation: Option<SimulationState>,
+
+    // Events for Session/daemon consumers (bounded; oldest dropped)
+    events: VecDeque<GameEvent>,
+
+    // Automatic reconnect
+    transport_factory: Arc<dyn TransportFactory>,
+    reconnect_policy: Option<ReconnectPolicy>,
+    reconnect_pending: bool,
+    reconnect_attempts: u32,
+    next_reconnect_at: Option<std::time::Instant>,
+    /// Reconnect running in the background, started from `poll()`
+    reconnect_task: Option<ReconnectTask>,
+    /// Parsed map from before a reconnect, keyed by (crc32, len) of the raw map blob.
+    reusable_map: Option<(u32, usize, Arc<MapData>)>,
+    /// Blocks of a failed map download, resumed if the server offers the same save
+    partial_download: Option<BlockDownloader>,
+    /// Saves shared with other connections in the process (see `ClientPool`)
+    shared_maps: Option<SharedMaps>,
+    /// CRC of the offered save, from MapReadyForDownload
+    map_crc: Option<u32>,
+
+    // Raw traffic capture (FACTORIO_CAPTURE_FILE or set_recorder)
+    recorder: Option<Recorder>,
+
+    // Server heartbeat decoding
+    heartbeat_parsing: HeartbeatParsing,
+    heartbeat_parse_failures: u64,
+    last_heartbeat_error: Option<Error>,
+
+    // Desync detection; reports go to FACTORIO_DESYNC_DIR or set_desync_report_dir
+    desync: DesyncMonitor,
+    desynced: bool,
+    last_desync_report: Option<DesyncReport>,
+    desync_report_dir: Option<PathBuf>,
+    rejoin_on_desync: bool,
+    desync_rejoin_pending: bool,
+
+    /// Read-only session (see `ObserverConnection`): no init action, no input actions
+    observer: bool,
+}
+
+/// A reconnect's handshake and map download, running on its own task.
+/// Dropping it (on `disconnect()` or a new policy) aborts the attempt.
+struct ReconnectTask {
+    handle: tokio::task::JoinHandle<(Box<Connection>, Result<()>)>,
+    attempt: u32,
+    previous_player_index: Option<u16>,
 }
 
-struct FragmentAssembly {
-    type_byte: u8,
-    fragments: BTreeMap<u16, Vec<u8>>,
-    max_len: usize,
-    last_id: Option<u16>,
-    created_at: std::time::Instant,
+impl Drop for ReconnectTask {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
 }
 
 impl Connection {
     pub async fn new(addr: SocketAddr, username: String) -> Result<Self> {
+        Self::new_with_credentials(addr, username, Credentials::default()).await
+    }
+
+    /// Like `new`, but authenticates with a game password and/or server key during the handshake.
+    pub async fn new_with_credentials(
+        addr: SocketAddr,
+        username: String,
+        credentials: Credentials,
+    ) -> Result<Self> {
         let transport = Transport::new(addr).await?;
+        let mut connection = Self::with_transport(addr, transport, username, credentials);
+        if let Ok(path) = std::env::var("FACTORIO_CAPTURE_FILE") {
+            connection.set_recorder(Some(Recorder::create(path)?));
+        }
+        Ok(connection)
+    }
+
+    /// Connection driven by a recorded capture instead of a server. `connect()` and
+    /// everything after it consume the capture's inbound datagrams deterministically.
+    pub fn new_replay(capture: &Capture, username: String) -> Self {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 34197));
+        Self::with_transport(addr, Transport::replay(capture), username, Credentials::default())
+    }
+
+    /// Connection over an arbitrary transport (e.g. a `ChannelSocket` to a scripted peer).
+    /// Non-UDP transports are kept for the whole session, including the server info
+    /// query; reconnects open a new one from `set_transport_factory`.
+    pub fn with_transport(
+        addr: SocketAddr,
+        transport: Transport,
+        username: String,
+        credentials: Credentials,
+    ) -> Self {
         let client_request_id = super::rand_u32();
         let reliable_seed = (rand_u64() ^ ((client_request_id as u64) << 32)).max(1);
         let reliable_rng = ReliableRng::new(reliable_seed, 0x9e3779b97f4a7c15);
 
-        Ok(Self {
+        Self {
             addr,
             transport,
             state: ConnectionState::Disconnected,
             username,
+            credentials,
+            handshake: HandshakeConfig::from_env(),
+            version: ApplicationVersion::FACTORIO_2_0_72,
+            profile: VersionProfile::FACTORIO_2_0_72,
             client_request_id,
             server_request_id: None,
             server_mods: Vec::new(),
+            advertised_mods: None,
             peer_id: None,
             player_index: None,
             player_index_confirmed: false,
@@ -262,6 +464,7 @@ impl Connection {
             confirmed_tick: 0,
             client_tick_lead: CLIENT_TICK_LEAD_INITIAL,
             latency_value: None,
+            pings: PingTracker::default(),
             accept_latency: None,
             start_sending_tick: None,
             allow_actions: false,
@@ -274,15 +477,24 @@ impl Connection {
             reliable_rng,
             map_transfer_size: None,
             map_tick: None,
-            map_data: Vec::new(),
-            entities: Vec::new(),
+            map_data: Arc::default(),
             parsed_map: None,
             pending_confirms: Vec::new(),
-            fragmented_heartbeats: HashMap::new(),
-            chat_seq: 1,
+            fragments: FragmentAssembler::new(Duration::from_secs(2)),
+            sent_heartbeats: VecDeque::new(),
+            requested_heartbeats: Vec::new(),
+            heartbeat_resends: 0,
+            segment_id: 1,
+            segmented_actions: SegmentAssembler::new(),
             pending_actions: VecDeque::new(),
+            action_receipts: ActionReceipts::new(),
+            server_run_state: ServerRunState::Running,
+            server_run_state_tick: 0,
+            autosave_tick: None,
             pending_init_action: false,
             pending_start_gameplay: false,
+            disconnecting: false,
+            disconnect_acknowledged: false,
             pending_latency_confirm: None,
             pending_skipped_tick_confirms: VecDeque::new(),
             pending_skipped_ticks: std::collections::HashSet::new(),
@@ -300,11 +512,163 @@ impl Connection {
             debug_confirm_failures: 0,
             last_action_player_index: 0xFFFF,
             other_players: HashMap::new(),
+            roster: Roster::default(),
             initial_player_positions: Vec::new(),
             assigned_position_indices: std::collections::HashSet::new(),
             character_speed: 0.15, // Default, updated from map data
             simulation: None,
-        })
+            events: VecDeque::new(),
+            transport_factory: Arc::new(UdpTransportFactory),
+            reconnect_policy: None,
+            reconnect_pending: false,
+            reconnect_attempts: 0,
+            next_reconnect_at: None,
+            reconnect_task: None,
+            reusable_map: None,
+            partial_download: None,
+            shared_maps: None,
+            map_crc: None,
+            recorder: None,
+            heartbeat_parsing: if std::env::var("FACTORIO_HEARTBEAT_FALLBACK").is_ok() {
+                HeartbeatParsing::Fallback
+            } else {
+                HeartbeatParsing::Strict
+            },
+            heartbeat_parse_failures: 0,
+            last_heartbeat_error: None,
+            desync: DesyncMonitor::new(),
+            desynced: false,
+            last_desync_report: None,
+            desync_report_dir: std::env::var_os("FACTORIO_DESYNC_DIR").map(PathBuf::from),
+            rejoin_on_desync: false,
+            desync_rejoin_pending: false,
+            observer: false,
+        }
+    }
+
+    /// Record all raw traffic (including the server info probe) to a capture file.
+    pub fn set_recorder(&mut self, recorder: Option<Recorder>) {
+        self.transport.set_recorder(recorder.clone());
+        self.recorder = recorder;
+    }
+
+    /// Override the version, mod list or checksums presented during the handshake.
+    /// Takes effect on the next `connect()`.
+    pub fn set_handshake_config(&mut self, config: HandshakeConfig) {
+        self.handshake = config;
+    }
+
+    /// Enable (or disable with `None`) automatic reconnect from `poll()`.
+    pub fn set_reconnect_policy(&mut self, policy: Option<ReconnectPolicy>) {
+        self.reconnect_policy = policy;
+        if self.reconnect_policy.is_none() {
+            self.reconnect_pending = false;
+            self.reconnect_task = None;
+        }
+    }
+
+    /// How `connect()` and reconnects open their transport (a fresh UDP socket
+    /// by default). Needed to reconnect over anything but UDP.
+    pub fn set_transport_factory(&mut self, factory: Arc<dyn TransportFactory>) {
+        self.transport_factory = factory;
+    }
+
+    pub fn set_heartbeat_parsing(&mut self, mode: HeartbeatParsing) {
+        self.heartbeat_parsing = mode;
+    }
+
+    /// How long after sending a `queue_action` receipt times out (default `DEFAULT_ACTION_TIMEOUT`).
+    pub fn set_action_timeout(&mut self, timeout: Duration) {
+        self.action_receipts.set_timeout(timeout);
+    }
+
+    /// Directory for desync reports (`None` keeps them in memory only).
+    pub fn set_desync_report_dir(&mut self, dir: Option<PathBuf>) {
+        self.desync_report_dir = dir;
+    }
+
+    /// Take saves other connections already downloaded, and publish ours.
+    pub fn set_shared_maps(&mut self, maps: Option<SharedMaps>) {
+        self.shared_maps = maps;
+    }
+
+    /// The parsed map, shared with other connections that joined on the same save.
+    pub fn shared_map(&self) -> Option<Arc<MapData>> {
+        self.parsed_map.clone()
+    }
+
+    /// Leave and rejoin the game from `poll()` after a desync.
+    pub fn set_rejoin_on_desync(&mut self, enabled: bool) {
+        self.rejoin_on_desync = enabled;
+    }
+
+    /// True once this connection detected a desync; actions are refused from then on.
+    pub fn is_desynced(&self) -> bool {
+        self.desynced
+    }
+
+    /// Diagnostics of the most recent desync, kept across a rejoin.
+    pub fn last_desync_report(&self) -> Option<&DesyncReport> {
+        self.last_desync_report.as_ref()
+    }
+
+    /// Server heartbeats the structured decoder rejected so far.
+    pub fn heartbeat_parse_failures(&self) -> u64 {
+        self.heartbeat_parse_failures
+    }
+
+    /// Why the most recent rejected heartbeat failed to decode.
+    pub fn last_heartbeat_error(&self) -> Option<&Error> {
+        self.last_heartbeat_error.as_ref()
+    }
+
+    /// True while a dropped connection is reconnecting or waiting for its next attempt.
+    pub fn is_reconnecting(&self) -> bool {
+        self.reconnect_pending || self.reconnect_task.is_some()
+    }
+
+    pub fn reconnect_attempts(&self) -> u32 {
+        self.reconnect_attempts
+    }
+
+    /// Take events queued since the last call.
+    pub fn drain_events(&mut self) -> Vec<GameEvent> {
+        self.events.drain(..).collect()
+    }
+
+    fn emit(&mut self, event: GameEvent) {
+        if self.events.len() >= MAX_PENDING_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Application version negotiated with the server.
+    pub fn version(&self) -> ApplicationVersion {
+        self.version
+    }
+
+    /// True for connections made through `ObserverConnection`.
+    pub fn is_observer(&self) -> bool {
+        self.observer
+    }
+
+    /// Input actions are refused on observer connections.
+    fn check_can_act(&self) -> Result<()> {
+        if self.observer {
+            return Err(Error::ObserverReadOnly);
+        }
+        Ok(())
+    }
+
+    /// Protocol profile chosen for the negotiated version.
+    pub fn version_profile(&self) -> &VersionProfile {
+        &self.profile
+    }
+
+    /// Wire id of a synchronizer action in the negotiated build
+    fn sync_id(&self, action: SynchronizerActionType) -> u8 {
+        self.profile.numbering.sync_action_id(action).unwrap_or(action as u8)
     }
 
     pub fn peer_constant(&self) -> u16 {
@@ -356,6 +720,30 @@ impl Connection {
         self.latency_value
     }
 
+    /// RTT, jitter and loss measured with pings while in game
+    pub fn network_stats(&self) -> NetworkStats {
+        self.pings.stats()
+    }
+
+    pub fn server_run_state(&self) -> ServerRunState {
+        self.server_run_state
+    }
+
+    /// Tick of the next autosave, once the server has announced it.
+    pub fn autosave_tick(&self) -> Option<u64> {
+        self.autosave_tick
+    }
+
+    /// Input actions queued but not yet sent (held while the server is paused or saving).
+    pub fn pending_action_count(&self) -> usize {
+        self.pending_actions.len()
+    }
+
+    /// Actions sent by `queue_action` that the server has not echoed back yet.
+    pub fn unconfirmed_action_count(&self) -> usize {
+        self.action_receipts.len()
+    }
+
     pub fn actions(&mut self) -> ConnectionActions<'_> {
         ConnectionActions::new(self)
     }
@@ -377,14 +765,15 @@ impl Connection {
     }
 
     pub fn sim_world(&self) -> Option<&GameWorld> {
-        self.simulation.as_ref().map(|s| &s.world)
+        self.simulation.as_ref().map(|s| &*s.world)
     }
 
     pub fn sim_world_mut(&mut self) -> Option<&mut GameWorld> {
-        self.simulation.as_mut().map(|s| &mut s.world)
+        self.simulation.as_mut().map(|s| Arc::make_mut(&mut s.world))
     }
 
-    fn init_simulation_from_map(&mut self, map: &MapData) {
+    /// The simulation's starting point for a freshly loaded map
+    fn world_from_map(map: &MapData) -> GameWorld {
         let mut world = GameWorld::new();
         world.tick = map.ticks_played;
         world.seed = map.seed;
@@ -508,10 +897,7 @@ impl Connection {
             }
         }
 
-        self.simulation = Some(SimulationState {
-            world,
-            executor: TickExecutor::new(),
-        });
+        world
     }
 
     pub fn player_position(&self) -> (f64, f64) {
@@ -552,7 +938,7 @@ impl Connection {
         self.walk_last_tick = movement_tick;
         if let Some(player_index) = self.player_index {
             if let Some(sim) = self.simulation.as_mut() {
-                if let Some(player) = sim.world.players.get_mut(&player_index) {
+                if let Some(player) = Arc::make_mut(&mut sim.world).players.get_mut(&player_index) {
                     player.position = MapPosition::from_tiles(self.player_x, self.player_y);
                 }
             }
@@ -560,20 +946,28 @@ impl Connection {
     }
 
     pub fn entities(&self) -> &[MapEntity] {
-        &self.entities
+        self.parsed_map.as_deref().map_or(&[], |map| &map.entities)
+    }
+
+    pub(crate) fn apply_parsed_map(&mut self, parsed: impl Into<Arc<MapData>>) {
+        self.apply_map(parsed.into(), None);
     }
 
-    pub(crate) fn apply_parsed_map(&mut self, parsed: MapData) {
+    /// Take a parsed map, starting the simulation from `world` if given (the
+    /// world another connection built from the same map) or from the map.
+    fn apply_map(&mut self, parsed: Arc<MapData>, world: Option<Arc<GameWorld>>) {
         self.initial_player_positions = parsed.character_positions();
         self.character_speed = parsed.character_speed();
-        self.entities = parsed.entities.clone();
         if parsed.player_spawn != (0.0, 0.0) && (self.player_x == 0.0 && self.player_y == 0.0) {
             self.player_x = parsed.player_spawn.0;
             self.player_y = parsed.player_spawn.1;
         }
         if self.simulation.is_none() {
-            self.init_simulation_from_map(&parsed);
+            let world = world.unwrap_or_else(|| Arc::new(Self::world_from_map(&parsed)));
+            self.simulation = Some(SimulationState { world, executor: TickExecutor::new() });
         }
+        // Peers who were in game before us never send us a PlayerJoinGame
+        self.roster.link_by_name(&parsed.players);
         self.parsed_map = Some(parsed);
     }
 
@@ -582,6 +976,20 @@ impl Connection {
         &self.other_players
     }
 
+    /// Connected peers, ordered by peer id
+    pub fn roster(&self) -> impl Iterator<Item = &RosterEntry> {
+        self.roster.entries()
+    }
+
+    pub fn roster_peer(&self, peer_id: u16) -> Option<&RosterEntry> {
+        self.roster.by_peer(peer_id)
+    }
+
+    /// Roster entry of the peer playing as `player_index`
+    pub fn roster_player(&self, player_index: u16) -> Option<&RosterEntry> {
+        self.roster.by_player(player_index)
+    }
+
     /// Get initial player positions from map
     pub fn initial_player_positions(&self) -> &[(f64, f64)] {
         &self.initial_player_positions
@@ -605,6 +1013,7 @@ impl Connection {
         self.last_disconnect_reason = None;
         self.last_server_heartbeat_at = None;
         self.pending_actions.clear();
+        self.action_receipts.clear();
         self.pending_confirms.clear();
         self.start_sending_tick = None;
         self.allow_actions = false;
@@ -613,29 +1022,60 @@ impl Connection {
         self.pending_latency_confirm = None;
         self.pending_skipped_tick_confirms.clear();
         self.pending_skipped_ticks.clear();
+        self.advertised_mods = None;
+        self.version = self.handshake.version.unwrap_or(ApplicationVersion::FACTORIO_2_0_72);
 
-        // Step 1: Query server info to get mod list (optional - use defaults if it fails)
+        // Step 1: Query server info to get mod list (optional - fall back to local mods/defaults)
         self.state = ConnectionState::QueryingServerInfo;
         if let Err(e) = self.query_server_info().await {
             let debug = std::env::var("FACTORIO_DEBUG").is_ok();
             if debug {
-                eprintln!("[DEBUG] query_server_info failed: {:?}, using default mods", e);
+                eprintln!("[DEBUG] query_server_info failed: {:?}, using local/default mods", e);
+            }
+            if self.handshake.mods.is_none() {
+                self.server_mods = self.fallback_mods()?;
             }
-            // Use default Space Age mods (from pcap analysis)
-            self.server_mods = Self::default_space_age_mods();
+        }
+        if let Some(mods) = &self.handshake.mods {
+            self.server_mods = mods.clone();
         }
 
-        // Create a fresh transport for the actual connection
-        // (Factorio expects a fresh socket after server info query)
-        self.transport = Transport::new(self.addr).await?;
+        // Step 2/3: ConnectionRequest -> ConnectionRequestReply. The reply carries the
+        // server's version; if it differs from ours, retry once with the server's version.
+        let mut retried = false;
+        let reply = loop {
+            // Create a fresh transport for the actual connection
+            // (Factorio expects a fresh socket after server info query)
+            if self.transport.is_udp() {
+                self.transport = self.transport_factory.open(self.addr).await?;
+                self.transport.set_recorder(self.recorder.clone());
+            }
 
-        // Step 2: Send ConnectionRequest
-        self.state = ConnectionState::Connecting;
-        self.send_connection_request().await?;
+            self.state = ConnectionState::Connecting;
+            self.send_connection_request().await?;
 
-        // Step 3: Wait for ConnectionRequestReply
-        self.state = ConnectionState::WaitingForReply;
-        let reply = self.wait_for_reply().await?;
+            self.state = ConnectionState::WaitingForReply;
+            let r = self.wait_for_reply().await?;
+            if r.version == self.version {
+                break r;
+            }
+            if retried {
+                return Err(Error::VersionMismatch { server: r.version, client: self.version });
+            }
+            if std::env::var("FACTORIO_DEBUG").is_ok() {
+                eprintln!("[DEBUG] Server version {} differs from {}, retrying", r.version, self.version);
+            }
+            retried = true;
+            self.version = r.version;
+            self.client_request_id = super::rand_u32();
+            // The server's own list and an explicit one stay; a fallback list was
+            // built for the version we guessed
+            if self.advertised_mods.is_none() && self.handshake.mods.is_none() {
+                self.server_mods = self.fallback_mods()?;
+            }
+        };
+        // Refuse builds we can't speak to before joining rather than desyncing later
+        self.profile = self.handshake.resolve_profile(self.version)?;
         self.server_request_id = Some(reply.server_request_id);
 
         // Step 4: Send ConnectionRequestReplyConfirm
@@ -646,10 +1086,8 @@ impl Connection {
         let accept = self.wait_for_accept().await?;
 
         if !accept.accepted {
-            let reason = accept.denial_reason
-                .map(|r| format!("{:?}", r))
-                .unwrap_or_else(|| "unknown".into());
-            return Err(Error::ConnectionRefused { reason });
+            let reason = accept.denial_reason.unwrap_or(DenialReason::Unknown);
+            return Err(self.denial_error(reason));
         }
 
         // Accept payload carries both peer identifier and player index.
@@ -675,6 +1113,10 @@ impl Connection {
             );
         }
         self.server_name = accept.server_name;
+        self.roster.clear();
+        for (peer_id, name) in &accept.peers {
+            self.roster.add_peer(*peer_id, name);
+        }
 
         if let Some(msg_id) = accept.initial_msg_id {
             self.msg_id = msg_id;
@@ -720,66 +1162,42 @@ impl Connection {
         self.reliable_rng.reseed(seed, stream);
     }
 
-    async fn query_server_info(&mut self) -> Result<()> {
-        // Use the LAN discovery port (34196) if available, matching real client behavior.
-        let bind_addr = match self.addr {
-            SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 34196)),
-            SocketAddr::V6(_) => SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 0], 34196)),
-        };
-        let mut transport = match Transport::new_with_bind(self.addr, bind_addr).await {
-            Ok(t) => t,
-            Err(_) => Transport::new(self.addr).await?,
-        };
-
-        // Send GameInformationRequest (0x10) and the reliable-flag variant (0x30).
-        transport.send_raw(&[0x10]).await?;
-        transport.send_raw(&[0x30]).await?;
-
-        // Wait for response
-        let start = std::time::Instant::now();
-        while start.elapsed() < CONNECT_TIMEOUT {
-            if let Some(data) = transport.recv_raw_timeout(RECV_TIMEOUT).await? {
-                if !data.is_empty() && (data[0] & 0x1F) == MessageType::GameInformationRequestReply as u8 {
-                    // Parse mod list from server info
-                    let info = ServerInfo::parse(&data)?;
-                    self.server_mods = info.mods;
-                    return Ok(());
-                }
-            }
+    /// Mod list to offer when the server's can't be queried: the local
+    /// install's, else the built-in mods of a build whose CRCs we know.
+    fn fallback_mods(&self) -> Result<Vec<ModInfo>> {
+        if let Some(mods) = self.handshake.local_mods(self.version).filter(|mods| !mods.is_empty()) {
+            return Ok(mods);
         }
-
-        Err(Error::ConnectionTimeout)
+        self.handshake
+            .resolve_profile(self.version)
+            .ok()
+            .and_then(|profile| profile.default_mods(self.version))
+            .ok_or_else(|| {
+                Error::UnsupportedVersion(format!(
+                    "{} (mod CRCs unknown, set HandshakeConfig.mods or mods_dir)",
+                    self.version
+                ))
+            })
     }
 
-    /// Default Space Age mods with CRCs from pcap analysis
-    fn default_space_age_mods() -> Vec<ModInfo> {
-        use crate::protocol::message::ModVersion;
-        vec![
-            ModInfo {
-                name: "base".to_string(),
-                version: ModVersion::new(2, 0, 72),
-                crc: 0x70059c86,
-            },
-            ModInfo {
-                name: "elevated-rails".to_string(),
-                version: ModVersion::new(2, 0, 72),
-                crc: 0x31790248,
-            },
-            ModInfo {
-                name: "quality".to_string(),
-                version: ModVersion::new(2, 0, 72),
-                crc: 0x441a8746,
-            },
-            ModInfo {
-                name: "space-age".to_string(),
-                version: ModVersion::new(2, 0, 72),
-                crc: 0x5a0ae76b,
-            },
-        ]
+    async fn query_server_info(&mut self) -> Result<()> {
+        let info = if !self.transport.is_udp() {
+            super::probe::probe_with_transport(&mut self.transport, CONNECT_TIMEOUT).await?
+        } else {
+            let mut transport = super::probe::probe_transport(self.addr).await?;
+            transport.set_recorder(self.recorder.clone());
+            super::probe::probe_with_transport(&mut transport, CONNECT_TIMEOUT).await?
+        };
+        if !info.partial && self.handshake.version.is_none() {
+            self.version = info.version;
+        }
+        self.advertised_mods = Some(info.mods.clone());
+        self.server_mods = info.mods;
+        Ok(())
     }
 
     async fn send_connection_request(&mut self) -> Result<()> {
-        let request = ConnectionRequest::new(self.client_request_id);
+        let request = ConnectionRequest::with_version(self.client_request_id, self.version);
 
         let mut writer = BinaryWriter::new();
         request.write(&mut writer);
@@ -811,30 +1229,28 @@ impl Connection {
             }
         }
 
-        Err(Error::ConnectionTimeout)
+        Err(Error::Timeout { stage: Stage::ConnectionReply })
     }
 
     async fn send_confirm(&mut self) -> Result<()> {
         let server_req_id = self.server_request_id
             .ok_or_else(|| Error::InvalidPacket("no server request ID".into()))?;
 
-        let mut confirm = ConnectionRequestReplyConfirm::new(
+        let checksums = self.handshake.resolve_checksums(self.version, &self.server_mods)?;
+        if std::env::var("FACTORIO_DEBUG").is_ok() {
+            eprintln!(
+                "[DEBUG] Using checksums for {}: core={} prototype={}",
+                self.version, checksums.core, checksums.prototype_list
+            );
+        }
+        let confirm = ConnectionRequestReplyConfirm::new(
             self.client_request_id,
             server_req_id,
             self.username.clone(),
             self.server_mods.clone(),
-        );
-        if let Some((core_checksum, prototype_checksum)) = read_local_checksums() {
-            let debug = std::env::var("FACTORIO_DEBUG").is_ok();
-            if debug {
-                eprintln!(
-                    "[DEBUG] Using local checksums: core={} prototype={}",
-                    core_checksum, prototype_checksum
-                );
-            }
-            confirm.core_checksum = core_checksum;
-            confirm.prototype_list_checksum = prototype_checksum;
-        }
+            checksums,
+        )
+        .with_credentials(&self.credentials);
 
         let mut writer = BinaryWriter::new();
         confirm.write(&mut writer);
@@ -878,7 +1294,39 @@ impl Connection {
             }
         }
 
-        Err(Error::ConnectionTimeout)
+        Err(Error::Timeout { stage: Stage::Accept })
+    }
+
+    /// Error for a denied join. The status is passed on as is, since what its
+    /// values mean is unconfirmed; a mod list that differs from the one the
+    /// server advertised is spelled out instead, as that much is certain.
+    fn denial_error(&self, reason: DenialReason) -> Error {
+        if let Some(advertised) = self.advertised_mods.as_deref() {
+            let same_release = |a: &ModInfo, b: &ModInfo| a.name == b.name && a.version == b.version;
+            // Two builds of one release differ only by CRC, so name it when that is all that differs
+            let describe = |m: &ModInfo, others: &[ModInfo]| {
+                if others.iter().any(|o| same_release(o, m)) {
+                    format!("{} {} (crc {:#010x})", m.name, m.version, m.crc)
+                } else {
+                    format!("{} {}", m.name, m.version)
+                }
+            };
+            let missing: Vec<_> = advertised
+                .iter()
+                .filter(|m| !self.server_mods.iter().any(|s| same_release(s, m) && s.crc == m.crc))
+                .map(|m| describe(m, &self.server_mods))
+                .collect();
+            let extra: Vec<_> = self
+                .server_mods
+                .iter()
+                .filter(|s| !advertised.iter().any(|m| same_release(m, s) && m.crc == s.crc))
+                .map(|s| describe(s, advertised))
+                .collect();
+            if !missing.is_empty() || !extra.is_empty() {
+                return Error::ModMismatch { missing, extra };
+            }
+        }
+        Error::Denied { reason, password_sent: self.credentials.password.is_some() }
     }
 
     /// Parse ConnectionAcceptOrDeny payload to extract player_index, server_name, etc.
@@ -894,6 +1342,28 @@ impl Connection {
 
             let _client_req_id = reader.read_u32_le()?;
             let status = reader.read_u8()?;
+            // A deny carries only the status; don't let the heuristic fallback treat it as an accept.
+            if let Some(reason) = DenialReason::from_status(status) {
+                if debug {
+                    eprintln!("[DEBUG] Connection denied: status={} reason={:?}", status, reason);
+                }
+                return Ok(ConnectionAcceptOrDeny {
+                    accepted: false,
+                    peer_id: None,
+                    player_index: None,
+                    server_name: None,
+                    denial_reason: Some(reason),
+                    initial_tick: None,
+                    initial_msg_id: None,
+                    session_constant: None,
+                    latency: None,
+                    peer_count: None,
+                    map_tick: None,
+                    steam_id: None,
+                    latency_window: None,
+                    peers: Vec::new(),
+                });
+            }
             let server_name = reader.read_string().ok();
             let _server_key = reader.read_string()?;
             let _unused_auth = reader.read_string()?;
@@ -902,7 +1372,7 @@ impl Connection {
             let _game_id = reader.read_u32_le()?;
             let steam_id = reader.read_u64_le().ok();
 
-            let peer_id = self.parse_clients_peer_info(&mut reader, debug).ok().flatten();
+            let (peer_id, peers) = self.parse_clients_peer_info(&mut reader, debug).unwrap_or_default();
 
             let initial_tick = reader.read_u32_le()?;
             // packed_ids is u32: (session_constant << 16) | initial_msg_id
@@ -917,13 +1387,12 @@ impl Connection {
                 let _ = ModInfo::read(&mut reader)?;
             }
 
-            let accepted = status == 0;
             let accept = ConnectionAcceptOrDeny {
-                accepted,
+                accepted: true,
                 peer_id,
                 player_index: Some(player_index),
                 server_name,
-                denial_reason: if accepted { None } else { Some(crate::protocol::message::DenialReason::Unknown) },
+                denial_reason: None,
                 initial_tick: Some(initial_tick),
                 initial_msg_id: Some(initial_msg_id),
                 session_constant: Some(session_constant),
@@ -932,6 +1401,7 @@ impl Connection {
                 map_tick: None,
                 steam_id,
                 latency_window: None,
+                peers,
             };
 
             if debug {
@@ -1043,6 +1513,7 @@ impl Connection {
             map_tick: None,
             steam_id: None,
             latency_window: None,
+            peers: Vec::new(),
         }
     }
 
@@ -1050,7 +1521,7 @@ impl Connection {
         &self,
         reader: &mut BinaryReader,
         debug: bool,
-    ) -> Result<Option<u16>> {
+    ) -> Result<(Option<u16>, Vec<(u16, String)>)> {
         let _server_state_name = reader.read_string()?;
         let _server_state = reader.read_u8()?;
         let state_count = reader.read_opt_u16()? as usize;
@@ -1060,6 +1531,7 @@ impl Connection {
 
         let peer_count = reader.read_opt_u32()? as usize;
         let mut peer_id = None;
+        let mut peers = Vec::with_capacity(peer_count.min(256));
         for _ in 0..peer_count {
             let id = reader.read_opt_u16()?;
             let name = reader.read_string()?;
@@ -1072,16 +1544,47 @@ impl Connection {
             if name == self.username {
                 peer_id = Some(id);
             }
+            peers.push((id, name));
         }
 
         if debug {
-            eprintln!("[DEBUG] ClientsPeerInfo parsed: peer_id={:?}", peer_id);
+            eprintln!("[DEBUG] ClientsPeerInfo parsed: peer_id={:?} peers={}", peer_id, peers.len());
         }
 
-        Ok(peer_id)
+        Ok((peer_id, peers))
     }
 
 
+    /// Claim a save in `maps`. While another connection downloads it, keep
+    /// reporting download progress so the server doesn't drop us.
+    async fn claim_shared_save(&mut self, maps: &SharedMaps, key: SaveKey) -> Claim {
+        let started = std::time::Instant::now();
+        let mut last_heartbeat = started;
+        loop {
+            let claim = maps.claim(key);
+            if !matches!(claim, Claim::Wait) || started.elapsed() >= SHARED_SAVE_WAIT_TIMEOUT {
+                return claim;
+            }
+            if last_heartbeat.elapsed() >= self.heartbeat_interval() {
+                let progress = self.sync_id(SynchronizerActionType::MapDownloadingProgressUpdate);
+                let _ = self.send_state_heartbeat(&[0x01, progress, 0x08]).await;
+                last_heartbeat = std::time::Instant::now();
+            }
+            if let Ok(Some(data)) = self.transport.recv_raw_timeout(Duration::from_millis(5)).await {
+                if data.is_empty() {
+                    continue;
+                }
+                if data[0] & 0x20 != 0 && data.len() >= 3 {
+                    let msg_id = u16::from_le_bytes([data[1], data[2]]) & 0x7FFF;
+                    self.pending_confirms.push(msg_id as u32);
+                }
+                if (data[0] & 0x1F) == MessageType::ServerToClientHeartbeat as u8 {
+                    let _ = self.process_server_heartbeat(&data);
+                }
+            }
+        }
+    }
+
     /// Download map data from server
     /// Call this after connect() succeeds
     pub async fn download_map(&mut self) -> Result<usize> {
@@ -1092,7 +1595,7 @@ impl Connection {
     pub async fn download_map_with_parse(&mut self, parse_map: bool) -> Result<usize> {
         eprintln!("[TRACE] download_map_with_parse: entering function");
         self.state = ConnectionState::DownloadingMap;
-        self.map_data.clear();
+        self.map_data = Arc::default();
 
         let debug = std::env::var("FACTORIO_DEBUG").is_ok();
         let download_started = std::time::Instant::now();
@@ -1197,7 +1700,11 @@ impl Connection {
         }
 
         // Phase 2: Signal ready for map with trailer 02 03 03 09 00
-        self.send_state_heartbeat(&[0x02, 0x03, 0x03, 0x09, 0x00]).await?;
+        let (changed_state, progress) = (
+            self.sync_id(SynchronizerActionType::ClientChangedState),
+            self.sync_id(SynchronizerActionType::MapDownloadingProgressUpdate),
+        );
+        self.send_state_heartbeat(&[0x02, changed_state, 0x03, progress, 0x00]).await?;
 
         // Continue looking for transfer size (MapReadyForDownload) before requesting blocks.
         if max_block.is_none() {
@@ -1228,7 +1735,7 @@ impl Connection {
                         .get(ready_progress_idx)
                         .copied()
                         .unwrap_or(0xfe);
-                    let _ = self.send_state_heartbeat(&[0x01, 0x09, step]).await;
+                    let _ = self.send_state_heartbeat(&[0x01, self.sync_id(SynchronizerActionType::MapDownloadingProgressUpdate), step]).await;
                     if ready_progress_idx + 1 < ready_progress_steps.len() {
                         ready_progress_idx += 1;
                     }
@@ -1238,24 +1745,67 @@ impl Connection {
         }
 
         if max_block.is_none() {
-            return Err(Error::InvalidPacket(
-                "MapReadyForDownload not received (transfer size unknown)".to_string(),
-            ));
+            return Err(Error::Timeout { stage: Stage::MapReady });
         }
 
         if debug {
             eprintln!("[DEBUG] Starting map download, transfer_size={:?} max_block={:?}", transfer_size, max_block);
         }
 
-        // Phase 3: Request and receive map blocks
-        // From pcap: real client sends ALL block requests RAPIDLY (burst), not waiting for responses
-        let start = std::time::Instant::now();
-        let mut received_blocks: std::collections::HashSet<u32> = std::collections::HashSet::new();
-        let mut blocks: Vec<Option<Vec<u8>>> = Vec::new();
-        let mut last_heartbeat = std::time::Instant::now();
-        let mut last_new_block_time = std::time::Instant::now();
-        let mut last_resend = std::time::Instant::now();
-        let mut got_all_blocks = false;
+        // Phase 3: request blocks through a sliding window, re-requesting lost ones
+        // (see map_download.rs). A failed attempt is kept for resuming.
+        let now = std::time::Instant::now();
+        let transfer_size = transfer_size.unwrap_or_default();
+        let mut download = match self.partial_download.take() {
+            Some(mut partial) if partial.matches(transfer_size, self.map_crc) => {
+                if debug {
+                    eprintln!("[DEBUG] Resuming map download, {} blocks missing", partial.missing());
+                }
+                partial.resume(now);
+                partial
+            }
+            _ => BlockDownloader::new(transfer_size, self.map_crc, now),
+        };
+
+        // With shared maps, only one connection per save downloads it.
+        let mut shared_save = None;
+        let mut download_guard = None;
+        if let (Some(maps), Some(crc)) = (self.shared_maps.clone(), self.map_crc) {
+            match self.claim_shared_save(&maps, (crc, transfer_size)).await {
+                Claim::Ready(save) => {
+                    if debug {
+                        eprintln!("[DEBUG] download_map: using shared save crc={:#x}", crc);
+                    }
+                    download = BlockDownloader::finished(transfer_size, self.map_crc, now);
+                    shared_save = Some(save);
+                }
+                Claim::Download(guard) => download_guard = Some(guard),
+                // Timed out waiting for another connection: download it ourselves
+                Claim::Wait => {}
+            }
+        }
+
+        // Parse on a worker thread as the blocks come in, so parsing ends soon after
+        // the download. Not when a previous parse may be reused, or without blocks.
+        let skip_parse = !parse_map || std::env::var("FACTORIO_SKIP_MAP_PARSE").is_ok();
+        let reuse_parse = self.reusable_map.is_some() || shared_save.is_some();
+        let map_format = self.profile.map_format;
+        let mut stream_parse = (!skip_parse && !reuse_parse).then(|| {
+            let (tx, rx) = std::sync::mpsc::channel::<Vec<u8>>();
+            let worker = std::thread::spawn(move || {
+                let mut parser = MapStreamParser::with_format(map_format, None);
+                for chunk in rx {
+                    parser.feed(&chunk);
+                    if parser.refused_version() {
+                        break;
+                    }
+                }
+                parser.finish()
+            });
+            (tx, worker)
+        });
+        let mut last_heartbeat = now;
+        let mut last_progress_event = now;
         // Keep progress markers aligned with observed official values.
         let progress_markers: [u8; 12] = [
             0x08, 0x1e, 0x23, 0x35, 0x44, 0x5c, 0x73, 0x8a, 0xa0, 0xbc, 0xd3, 0xfe,
@@ -1273,73 +1823,45 @@ impl Connection {
             value
         };
 
-        // Send block requests in rapid batches, but intersperse heartbeats to stay in latency window
-        let initial_max = max_block.unwrap_or(INITIAL_BLOCK_REQUEST_MAX);
-        let mut sent = 0u32;
-        let mut burst_heartbeat = std::time::Instant::now();
-        let mut requested_max = initial_max;
-        let mut next_request_block = initial_max.saturating_add(1);
-        let mut last_expand = std::time::Instant::now();
-        let mut all_requested = max_block.map_or(false, |max| max <= initial_max);
-        while sent <= initial_max {
-            let batch_end = (sent + 200).min(initial_max + 1);
-            for i in sent..batch_end {
-                let reliable = false;
-                let request = TransferBlockRequest::new(i, reliable);
-                let _ = self.transport.send_raw(&request.to_bytes()).await;
+        while !download.is_complete() {
+            // Draining also frees the blocks, so do it without a parser too
+            if let Some(bytes) = download.drain_in_order() {
+                if let Some((tx, _)) = stream_parse.as_ref() {
+                    let _ = tx.send(bytes.to_vec());
+                }
             }
-            sent = batch_end;
-            if burst_heartbeat.elapsed() >= self.heartbeat_interval() {
-                if let Some(max) = max_block {
-                    let progress = ((received_blocks.len() as f64 / (max + 1) as f64) * 255.0).round() as u8;
-                    last_progress = progress_marker_for(progress, last_progress);
-                }
-                let _ = self.send_state_heartbeat(&[0x01, 0x09, last_progress]).await;
-                burst_heartbeat = std::time::Instant::now();
-            }
-            // Drain incoming blocks while sending to prevent socket buffer overflow
-            while let Ok(Some(data)) = self.transport.try_recv_raw() {
-                if data.is_empty() { break; }
-                if (data[0] & 0x1F) == MessageType::TransferBlock as u8 && data.len() >= 5 {
-                    let recv_block = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
-                    if !received_blocks.contains(&recv_block) {
-                        if blocks.len() <= recv_block as usize {
-                            blocks.resize(recv_block as usize + 1, None);
-                        }
-                        blocks[recv_block as usize] = Some(data[5..].to_vec());
-                        received_blocks.insert(recv_block);
-                        last_new_block_time = std::time::Instant::now();
+            // The worker only stops before the download ends when the map is
+            // from a build the profile's map format does not cover
+            if stream_parse.as_ref().is_some_and(|(_, worker)| worker.is_finished()) {
+                if let Some((_, worker)) = stream_parse.take() {
+                    if let Ok(Err(e)) = worker.join() {
+                        return Err(e);
                     }
                 }
             }
-            if let Some(max) = max_block {
-                if received_blocks.len() as u32 >= max + 1 {
-                    got_all_blocks = true;
-                    break;
+            let now = std::time::Instant::now();
+            if download.idle_for(now) > MAP_DOWNLOAD_STALL_TIMEOUT {
+                if debug {
+                    eprintln!("[DEBUG] download_map: stalled, missing {} map blocks", download.missing());
                 }
+                self.partial_download = Some(download);
+                return Err(Error::Timeout { stage: Stage::MapDownload });
             }
-        }
-        if debug {
-            eprintln!("[DEBUG] Burst done: sent {} requests, received {} blocks, max_block={:?} got_all={}", initial_max + 1, received_blocks.len(), max_block, got_all_blocks);
-        }
 
-        while start.elapsed() < MAP_DOWNLOAD_TIMEOUT {
-            if last_heartbeat.elapsed() >= self.heartbeat_interval() {
-                if let Some(max) = max_block {
-                    let progress = ((received_blocks.len() as f64 / (max + 1) as f64) * 255.0).round() as u8;
-                    last_progress = progress_marker_for(progress, last_progress);
-                }
-                let _ = self.send_state_heartbeat(&[0x01, 0x09, last_progress]).await;
-                last_heartbeat = std::time::Instant::now();
+            for block in download.poll(now) {
+                let request = TransferBlockRequest::new(block, false);
+                let _ = self.transport.send_raw(&request.to_bytes()).await;
             }
 
-            if got_all_blocks {
-                break;
+            if last_heartbeat.elapsed() >= self.heartbeat_interval() {
+                let progress = (download.progress(now).fraction() * 255.0).round() as u8;
+                last_progress = progress_marker_for(progress, last_progress);
+                let _ = self.send_state_heartbeat(&[0x01, self.sync_id(SynchronizerActionType::MapDownloadingProgressUpdate), last_progress]).await;
+                last_heartbeat = std::time::Instant::now();
             }
-
-            // Timeout: if no new blocks for 1000ms, assume complete when size is known.
-            if max_block.is_some() && !received_blocks.is_empty() && last_new_block_time.elapsed() > Duration::from_millis(1000) {
-                break;
+            if last_progress_event.elapsed() >= MAP_DOWNLOAD_PROGRESS_INTERVAL {
+                self.emit(download.progress(now).into());
+                last_progress_event = now;
             }
 
             let data = match self.transport.try_recv_raw() {
@@ -1364,231 +1886,158 @@ impl Connection {
                 }
             }
 
-            // Still look for transfer size if we haven't found it
+            // Heuristic size detection may still find a larger transfer size
             if let Some(size) = self.extract_transfer_size_from_packet(&data, debug) {
-                if let Some(block_count) = update_transfer_size(&mut transfer_size, &mut max_block, size) {
-                    if let Some(max) = max_block {
-                        if max > requested_max {
-                            for block_id in next_request_block..=max {
-                                let reliable = false;
-                                let request = TransferBlockRequest::new(block_id, reliable);
-                                let _ = self.transport.send_raw(&request.to_bytes()).await;
-                            }
-                            requested_max = max;
-                            next_request_block = max.saturating_add(1);
-                        }
-                        all_requested = true;
-                    }
-                    let block_count = block_count as usize;
-                    if blocks.len() < block_count {
-                        blocks.resize(block_count, None);
-                    }
-                }
+                download.grow(size);
             }
 
             if msg_type == MessageType::TransferBlock as u8 {
-                if data.len() < 5 {
-                    continue;
-                }
-                let recv_block = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
-
-                if !received_blocks.contains(&recv_block) {
-                    if blocks.len() <= recv_block as usize {
-                        blocks.resize(recv_block as usize + 1, None);
-                    }
-                    blocks[recv_block as usize] = Some(data[5..].to_vec());
-                    received_blocks.insert(recv_block);
-                    last_new_block_time = std::time::Instant::now();
-                }
-
-                if let Some(max) = max_block {
-                    if received_blocks.len() as u32 >= max + 1 {
-                        got_all_blocks = true;
-                        continue;
-                    }
-                } else if data.len() < 500 {
-                    max_block = Some(recv_block);
-                    requested_max = requested_max.max(recv_block);
-                    got_all_blocks = received_blocks.len() as u32 >= recv_block + 1;
-                    continue;
-                }
-
-                // If we discover more blocks are needed (max_block updated), request them.
-                if !all_requested {
-                    if let Some(max) = max_block {
-                        if max > requested_max {
-                            for i in next_request_block..=max {
-                                let reliable = false;
-                                let request = TransferBlockRequest::new(i, reliable);
-                                let _ = self.transport.send_raw(&request.to_bytes()).await;
-                            }
-                            requested_max = max;
-                            next_request_block = max.saturating_add(1);
-                        }
-                        all_requested = true;
-                    }
+                if let Ok(block) = TransferBlock::parse(&data) {
+                    download.on_block(block.block_number, block.data, std::time::Instant::now());
                 }
             } else if msg_type == MessageType::ServerToClientHeartbeat as u8 {
                 let _ = self.process_server_heartbeat(&data);
             }
+        }
+
+        let progress = download.progress(std::time::Instant::now());
+        if debug {
+            eprintln!(
+                "[DEBUG] download_map: {} blocks in {:?}, {} retransmits, {} duplicates",
+                progress.blocks_total,
+                progress.elapsed,
+                progress.retransmits,
+                download.duplicates()
+            );
+        }
+        self.emit(progress.into());
+        self.emit(GameEvent::MapDownloadComplete);
+        let stream_worker = stream_parse.map(|(tx, worker)| {
+            if let Some(bytes) = download.drain_in_order() {
+                let _ = tx.send(bytes.to_vec());
+            }
+            worker
+        });
 
-            if last_resend.elapsed() > Duration::from_millis(200)
-                && all_requested
-                && last_new_block_time.elapsed() > Duration::from_millis(80)
-            {
-                let resend_max = max_block.unwrap_or(requested_max);
-                let mut resent = 0;
-                for block_id in 0..=resend_max {
-                    let idx = block_id as usize;
-                    let missing = blocks.get(idx).map_or(true, |b| b.is_none());
-                    if missing {
-                        let reliable = false;
-                        let request = TransferBlockRequest::new(block_id, reliable);
-                        let _ = self.transport.send_raw(&request.to_bytes()).await;
-                        resent += 1;
-                        if resent >= 100 {
-                            break;
-                        }
-                    }
+        // No trailing marker flush during download; we only send a final 0xfe once we
+        // know the map is complete.
+
+        self.map_data = match &shared_save {
+            Some(save) => save.data.clone(),
+            None => Arc::new(download.into_save().unwrap_or_default()),
+        };
+        if debug {
+            eprintln!(
+                "[DEBUG] download_map: {} bytes in {:?}",
+                self.map_data.len(),
+                download_started.elapsed()
+            );
+        }
+
+        if last_progress != 0xfe {
+            let _ = self.send_state_heartbeat(&[0x01, self.sync_id(SynchronizerActionType::MapDownloadingProgressUpdate), 0xfe]).await;
+            if let Ok(Some(data)) = self.transport.recv_raw_timeout(Duration::from_millis(8)).await {
+                if !data.is_empty()
+                    && (data[0] & 0x1F) == MessageType::ServerToClientHeartbeat as u8
+                {
+                    let _ = self.process_server_heartbeat(&data);
                 }
-                last_resend = std::time::Instant::now();
             }
+            tokio::time::sleep(self.heartbeat_interval()).await;
+            last_progress = 0xfe;
+        }
 
-            if transfer_size.is_none()
-                && max_block.is_none()
-                && !got_all_blocks
-                && received_blocks.len() as u32 >= requested_max.saturating_sub(32)
-                && last_expand.elapsed() > Duration::from_millis(50)
-            {
-                let new_max = requested_max.saturating_add(256);
-                for block_id in next_request_block..=new_max {
-                    let reliable = false;
-                    let request = TransferBlockRequest::new(block_id, reliable);
-                    let _ = self.transport.send_raw(&request.to_bytes()).await;
+        let reusable = self.reusable_map.take().filter(|(crc, len, _)| {
+            *len == self.map_data.len() && *crc == crc32fast::hash(&self.map_data)
+        });
+        if let Some(SharedSave { parsed: Some(parsed), world, .. }) = shared_save {
+            self.apply_map(parsed, world);
+        } else if let Some((_, _, parsed)) = reusable {
+            if debug {
+                eprintln!("[DEBUG] download_map: map unchanged, reusing previous parse");
+            }
+            self.apply_parsed_map(parsed);
+        } else if !skip_parse {
+            let streamed = match stream_worker.map(|worker| worker.join()) {
+                Some(Ok(Ok(parsed))) => Some(parsed),
+                Some(Ok(Err(e @ Error::UnsupportedVersion(_)))) => return Err(e),
+                Some(Ok(Err(e))) => {
+                    if debug {
+                        eprintln!("[DEBUG] download_map: streaming parse failed ({}), parsing whole save", e);
+                    }
+                    None
                 }
+                _ => None,
+            };
+            // Try to parse entities from the map
+            let parsed = match streamed {
+                Some(parsed) => Some(parsed),
+                None => match parse_map_data_with_format(&self.map_data, map_format, None) {
+                    Ok(parsed) => Some(parsed),
+                    Err(e @ Error::UnsupportedVersion(_)) => return Err(e),
+                    Err(_) => None,
+                },
+            };
+            if let Some(parsed) = parsed {
                 if debug {
                     eprintln!(
-                        "[DEBUG] Expanding block requests: {} -> {}",
-                        requested_max, new_max
+                        "[DEBUG] download_map: map parsed in {:?}",
+                        download_started.elapsed()
                     );
                 }
-                requested_max = new_max;
-                next_request_block = new_max.saturating_add(1);
-                last_expand = std::time::Instant::now();
+                self.apply_parsed_map(parsed);
             }
+        } else if debug {
+            eprintln!("[DEBUG] download_map: skipping parse_map_data due to FACTORIO_SKIP_MAP_PARSE");
+        }
+        if let Some(guard) = download_guard {
+            guard.publish(SharedSave {
+                data: self.map_data.clone(),
+                parsed: self.parsed_map.clone(),
+                world: self.simulation.as_ref().map(|sim| sim.world.clone()),
+            });
         }
 
-        // No trailing marker flush during download; we only send a final 0xfe once we
-        // know the map is complete.
-
-        // If we got some data, parse it and transition to InGame
-        if !blocks.is_empty() {
-            let max_index = max_block
-                .or_else(|| blocks.len().checked_sub(1).map(|v| v as u32))
-                .unwrap_or(0);
-            let mut map_blob = Vec::with_capacity(((max_index + 1) as usize) * 503);
-            let mut missing = 0;
-            for i in 0..=max_index {
-                match blocks.get(i as usize).and_then(|b| b.as_ref()) {
-                    Some(chunk) => map_blob.extend_from_slice(chunk),
-                    None => missing += 1,
-                }
-            }
-            if missing > 0 {
-                return Err(Error::InvalidPacket(format!("missing {} map blocks", missing)));
-            }
-            if let Some(size) = transfer_size {
-                map_blob.truncate(size as usize);
-            }
-            self.map_data = map_blob;
-            if debug {
-                eprintln!(
-                    "[DEBUG] download_map: assembled {} bytes in {:?}",
-                    self.map_data.len(),
-                    download_started.elapsed()
-                );
-            }
-
-            if max_block.is_some() && last_progress != 0xfe {
-                let _ = self.send_state_heartbeat(&[0x01, 0x09, 0xfe]).await;
-                if let Ok(Some(data)) = self.transport.recv_raw_timeout(Duration::from_millis(8)).await {
-                    if !data.is_empty()
-                        && (data[0] & 0x1F) == MessageType::ServerToClientHeartbeat as u8
-                    {
-                        let _ = self.process_server_heartbeat(&data);
-                    }
-                }
-                tokio::time::sleep(self.heartbeat_interval()).await;
-                last_progress = 0xfe;
-            }
+        // Send state transition to signal we're ready for gameplay
+        // The server expects specific state change signals before we can use gameplay heartbeats
+        self.send_state_transition().await?;
+        if debug {
+            eprintln!(
+                "[DEBUG] download_map: sent state transition in {:?}",
+                download_started.elapsed()
+            );
+        }
 
-            let skip_parse = !parse_map || std::env::var("FACTORIO_SKIP_MAP_PARSE").is_ok();
-            if !skip_parse {
-                // Try to parse entities from the map
-                if let Ok(parsed) = parse_map_data(&self.map_data) {
-                    if debug {
-                        eprintln!(
-                            "[DEBUG] download_map: parse_map_data ok in {:?}",
-                            download_started.elapsed()
-                        );
-                    }
-                    self.apply_parsed_map(parsed);
-                }
-            } else if debug {
-                eprintln!("[DEBUG] download_map: skipping parse_map_data due to FACTORIO_SKIP_MAP_PARSE");
-            }
+        // Sync to the latest confirmed tick before starting gameplay heartbeats.
+        self.sync_gameplay_clock().await;
 
-            // Send state transition to signal we're ready for gameplay
-            // The server expects specific state change signals before we can use gameplay heartbeats
-            self.send_state_transition().await?;
-            if debug {
-                eprintln!(
-                    "[DEBUG] download_map: sent state transition in {:?}",
-                    download_started.elapsed()
-                );
-            }
+        // Per doc lines 272-285, 541-542: after state trailers, send init action then gameplay heartbeats
+        let _ = self.maybe_send_start_gameplay_heartbeat().await?;
+        if debug {
+            eprintln!(
+                "[DEBUG] download_map: sent start gameplay heartbeat in {:?}",
+                download_started.elapsed()
+            );
+        }
 
-            // Sync to the latest confirmed tick before starting gameplay heartbeats.
-            self.sync_gameplay_clock().await;
+        // Always wait for player index - it's needed for any gameplay actions
+        self.pending_init_action = true;
+        self.await_player_index(Duration::from_millis(2000)).await;
 
-            // Per doc lines 272-285, 541-542: after state trailers, send init action then gameplay heartbeats
+        if std::env::var("FACTORIO_SKIP_INIT_ACTION").is_err() {
             let _ = self.maybe_send_start_gameplay_heartbeat().await?;
-            if debug {
-                eprintln!(
-                    "[DEBUG] download_map: sent start gameplay heartbeat in {:?}",
-                    download_started.elapsed()
-                );
-            }
-
-            // Always wait for player index - it's needed for any gameplay actions
-            self.pending_init_action = true;
-            self.await_player_index(Duration::from_millis(2000)).await;
-
-            if std::env::var("FACTORIO_SKIP_INIT_ACTION").is_err() {
-                let _ = self.maybe_send_start_gameplay_heartbeat().await?;
-                
-                // Retry init action until it succeeds (may need to wait for rate limiting)
-                // The init action MUST be the first tick closure after 0x1e.
-                // We also need to wait for ClientShouldStartSendingTickClosures (sets start_sending_tick).
-                let init_start = std::time::Instant::now();
-                while self.pending_init_action && init_start.elapsed() < Duration::from_millis(2000) {
-                    let _ = self.maybe_send_init_action().await?;
-                    if self.pending_init_action {
-                        // Still pending - wait a bit and try again
-                        tokio::time::sleep(self.heartbeat_interval()).await;
-                        // Process any server heartbeats while waiting
-                        // This is critical to receive ClientShouldStartSendingTickClosures
-                        if let Ok(Some(data)) = self.transport.recv_raw_timeout(Duration::from_millis(10)).await {
-                            if !data.is_empty() && (data[0] & 0x1F) == MessageType::ServerToClientHeartbeat as u8 {
-                                let _ = self.process_server_heartbeat(&data);
-                                let _ = self.flush_pending_confirmations().await;
-                            }
-                        }
-                    }
-                }
-
-                // Wait for server responses after init
-                for _ in 0..5 {
+            
+            // Retry init action until it succeeds (may need to wait for rate limiting)
+            // The init action MUST be the first tick closure after 0x1e.
+            // We also need to wait for ClientShouldStartSendingTickClosures (sets start_sending_tick).
+            let init_start = std::time::Instant::now();
+            while self.pending_init_action && init_start.elapsed() < Duration::from_millis(2000) {
+                let _ = self.maybe_send_init_action().await?;
+                if self.pending_init_action {
+                    // Still pending - wait a bit and try again
+                    tokio::time::sleep(self.heartbeat_interval()).await;
+                    // Process any server heartbeats while waiting
+                    // This is critical to receive ClientShouldStartSendingTickClosures
                     if let Ok(Some(data)) = self.transport.recv_raw_timeout(Duration::from_millis(10)).await {
                         if !data.is_empty() && (data[0] & 0x1F) == MessageType::ServerToClientHeartbeat as u8 {
                             let _ = self.process_server_heartbeat(&data);
@@ -1598,69 +2047,77 @@ impl Connection {
                 }
             }
 
-            let _ = self.maybe_send_start_gameplay_heartbeat().await?;
-
-            // Only NOW start sending gameplay heartbeats with flags=0x0e
-            // The init action should already have consumed start_tick+1, so this will use start_tick+2
-            for _ in 0..1 {
-                let _ = self.send_heartbeat_raw().await;
-                if let Ok(Some(data)) = self.transport.recv_raw_timeout(Duration::from_millis(8)).await {
+            // Wait for server responses after init
+            for _ in 0..5 {
+                if let Ok(Some(data)) = self.transport.recv_raw_timeout(Duration::from_millis(10)).await {
                     if !data.is_empty() && (data[0] & 0x1F) == MessageType::ServerToClientHeartbeat as u8 {
                         let _ = self.process_server_heartbeat(&data);
                         let _ = self.flush_pending_confirmations().await;
                     }
                 }
-                tokio::time::sleep(Duration::from_millis(16)).await;
             }
+        }
 
-            // Ensure allow_actions is true now that we're entering InGame.
-            // Without this, we stay in the limited sending mode and fall behind.
-            self.pending_init_action = false;
-            self.allow_actions = true;
+        let _ = self.maybe_send_start_gameplay_heartbeat().await?;
 
-            // Fallback: if we didn't receive ClientShouldStartSendingTickClosures,
-            // derive start_sending_tick from confirmed_tick. This ensures we can
-            // send proper gameplay heartbeats.
-            // Only use confirmed_tick if it's a reasonable value (> 10000)
-            if self.start_sending_tick.is_none() && self.confirmed_tick > 10_000 {
-                let start = self.confirmed_tick.wrapping_add(2);
-                if std::env::var("FACTORIO_DEBUG").is_ok() {
-                    eprintln!("[DEBUG] Fallback: setting start_sending_tick={} from confirmed_tick={}", start, self.confirmed_tick);
-                }
-                self.start_sending_tick = Some(start);
-                self.client_tick = start;
-            }
-
-            // CRITICAL: If start_sending_tick is more than 30 ticks behind confirmed_tick,
-            // the server will ignore our heartbeats as being too old. Update it to be
-            // closer to current time. This can happen when there's a delay between
-            // receiving ClientShouldStartSendingTickClosures and actually transitioning
-            // to InGame (e.g., while waiting for player_index confirmation).
-            if let Some(start) = self.start_sending_tick {
-                if self.confirmed_tick > 10_000 && self.confirmed_tick > start {
-                    let lag = self.confirmed_tick - start;
-                    if lag > 30 {
-                        let new_start = self.confirmed_tick.saturating_sub(2);
-                        if std::env::var("FACTORIO_DEBUG").is_ok() {
-                            eprintln!("[DEBUG] start_sending_tick {} is {} ticks behind confirmed_tick {}, updating to {}",
-                                start, lag, self.confirmed_tick, new_start);
-                        }
-                        self.start_sending_tick = Some(new_start);
-                        self.client_tick = new_start;
-                    }
+        // Only NOW start sending gameplay heartbeats with flags=0x0e
+        // The init action should already have consumed start_tick+1, so this will use start_tick+2
+        for _ in 0..1 {
+            let _ = self.send_heartbeat_raw().await;
+            if let Ok(Some(data)) = self.transport.recv_raw_timeout(Duration::from_millis(8)).await {
+                if !data.is_empty() && (data[0] & 0x1F) == MessageType::ServerToClientHeartbeat as u8 {
+                    let _ = self.process_server_heartbeat(&data);
+                    let _ = self.flush_pending_confirmations().await;
                 }
             }
+            tokio::time::sleep(Duration::from_millis(16)).await;
+        }
 
-            // Allow immediate heartbeat send when entering InGame
-            self.mark_needs_immediate_heartbeat();
-            self.state = ConnectionState::InGame;
+        // Ensure allow_actions is true now that we're entering InGame.
+        // Without this, we stay in the limited sending mode and fall behind.
+        self.pending_init_action = false;
+        self.allow_actions = true;
+
+        // Fallback: if we didn't receive ClientShouldStartSendingTickClosures,
+        // derive start_sending_tick from confirmed_tick. This ensures we can
+        // send proper gameplay heartbeats.
+        // Only use confirmed_tick if it's a reasonable value (> 10000)
+        if self.start_sending_tick.is_none() && self.confirmed_tick > 10_000 {
+            let start = self.confirmed_tick.wrapping_add(2);
             if std::env::var("FACTORIO_DEBUG").is_ok() {
-                eprintln!("[DEBUG] *** STATE TRANSITION TO INGAME ***");
+                eprintln!("[DEBUG] Fallback: setting start_sending_tick={} from confirmed_tick={}", start, self.confirmed_tick);
+            }
+            self.start_sending_tick = Some(start);
+            self.client_tick = start;
+        }
+
+        // CRITICAL: If start_sending_tick is more than 30 ticks behind confirmed_tick,
+        // the server will ignore our heartbeats as being too old. Update it to be
+        // closer to current time. This can happen when there's a delay between
+        // receiving ClientShouldStartSendingTickClosures and actually transitioning
+        // to InGame (e.g., while waiting for player_index confirmation).
+        if let Some(start) = self.start_sending_tick {
+            if self.confirmed_tick > 10_000 && self.confirmed_tick > start {
+                let lag = self.confirmed_tick - start;
+                if lag > 30 {
+                    let new_start = self.confirmed_tick.saturating_sub(2);
+                    if std::env::var("FACTORIO_DEBUG").is_ok() {
+                        eprintln!("[DEBUG] start_sending_tick {} is {} ticks behind confirmed_tick {}, updating to {}",
+                            start, lag, self.confirmed_tick, new_start);
+                    }
+                    self.start_sending_tick = Some(new_start);
+                    self.client_tick = new_start;
+                }
             }
-            return Ok(self.map_data.len());
         }
 
-        Err(Error::ConnectionTimeout)
+        // Allow immediate heartbeat send when entering InGame
+        self.mark_needs_immediate_heartbeat();
+        self.state = ConnectionState::InGame;
+        if std::env::var("FACTORIO_DEBUG").is_ok() {
+            eprintln!("[DEBUG] *** STATE TRANSITION TO INGAME ***");
+        }
+        Ok(self.map_data.len())
     }
 
     /// Send state transition heartbeats to signal ready for gameplay.
@@ -1969,8 +2426,9 @@ impl Connection {
                 }
                 return Ok(false);
             }
-            // Send init action by default. Set FACTORIO_SKIP_INIT_ACTION=1 to disable.
-            let skip_init = std::env::var("FACTORIO_SKIP_INIT_ACTION").is_ok();
+            // Send init action by default. Set FACTORIO_SKIP_INIT_ACTION=1 to disable;
+            // observers never send it.
+            let skip_init = self.observer || std::env::var("FACTORIO_SKIP_INIT_ACTION").is_ok();
             if !skip_init {
                 if debug {
                     eprintln!("[DEBUG] maybe_send_init_action: sending init action");
@@ -2179,6 +2637,10 @@ impl Connection {
         if lead == 0 {
             lead = CLIENT_TICK_LEAD_INITIAL;
         }
+        // The server's latency setting can undershoot a slow link
+        if let Some(rtt_ticks) = self.pings.stats().rtt_ticks() {
+            lead = lead.max(rtt_ticks);
+        }
         lead = lead.saturating_add(CLIENT_TICK_LEAD_BIAS.max(0) as u32);
         lead.clamp(CLIENT_TICK_LEAD_MIN, CLIENT_TICK_LEAD_MAX)
     }
@@ -2384,14 +2846,15 @@ impl Connection {
     /// Download map and return raw bytes (for analysis)
     pub async fn download_map_raw(&mut self) -> Result<Vec<u8>> {
         self.download_map().await?;
-        Ok(self.map_data.clone())
+        Ok(self.map_data.to_vec())
     }
 
     fn extract_transfer_size_from_packet(&mut self, data: &[u8], debug: bool) -> Option<u32> {
         if let Some(size) = self.map_transfer_size.take() {
             return Some(size);
         }
-        if data.len() < 16 {
+        // Decoded heartbeats set map_transfer_size; peeking at raw fragments is heuristic.
+        if self.heartbeat_parsing == HeartbeatParsing::Strict || data.len() < 16 {
             return None;
         }
         let type_byte = data[0];
@@ -2445,7 +2908,7 @@ impl Connection {
         let max_size = 50_000_000u64;
         let needed = 1 + 8 + 8 + 4 + 8;
         for i in 0..payload.len().saturating_sub(needed) {
-            if payload[i] != SynchronizerActionType::MapReadyForDownload as u8 {
+            if payload[i] != self.sync_id(SynchronizerActionType::MapReadyForDownload) {
                 continue;
             }
             let size = u64::from_le_bytes([
@@ -2531,7 +2994,7 @@ impl Connection {
                 Ok(v) => v,
                 Err(_) => return None,
             };
-            if action_type == SynchronizerActionType::MapReadyForDownload as u8 {
+            if action_type == self.sync_id(SynchronizerActionType::MapReadyForDownload) {
                 let transfer_size = match reader.read_u64_le() {
                     Ok(v) => v,
                     Err(_) => return None,
@@ -2560,7 +3023,7 @@ impl Connection {
                 return None;
             }
 
-            let action = match SynchronizerActionType::from_u8(action_type) {
+            let action = match self.profile.numbering.sync_action_type(action_type) {
                 Some(v) => v,
                 None => return None,
             };
@@ -2693,6 +3156,7 @@ impl Connection {
     /// Send heartbeat with action data (TickClosure payload only).
     /// C2S heartbeat format: flags + heartbeat_sequence(u32) + tick closures + echo_tick(u64).
     async fn send_action_packet(&mut self, flags: u8, action_data: &[u8]) -> Result<()> {
+        self.check_can_act()?;
         if self.start_sending_tick.is_none() {
             return self.send_heartbeat_raw().await;
         }
@@ -2898,7 +3362,40 @@ impl Connection {
         self.pending_confirms.clear();
         self.last_gameplay_send_at = Some(std::time::Instant::now());
         self.mark_sent_heartbeat();
-        self.transport.send_raw(&packet).await
+        // Segmented actions keep action heartbeats within one datagram; this splits
+        // whatever is still too big. A heartbeat's message id is the low half of its
+        // sequence, so its fragments carry that, less bit 15 (the confirm flag).
+        let packets = split_message(packet[0], seq as u16, &packet[1..], MAX_FRAGMENT_PAYLOAD);
+        if debug && packets.len() > 1 {
+            eprintln!("[DEBUG] C2S hb seq={} split into {} fragments ({} bytes)", seq, packets.len(), packet.len());
+        }
+        for part in &packets {
+            self.transport.send_raw(part).await?;
+        }
+        if self.sent_heartbeats.len() >= SENT_HEARTBEAT_HISTORY {
+            self.sent_heartbeats.pop_front();
+        }
+        self.sent_heartbeats.push_back((seq, packets));
+        Ok(())
+    }
+
+    /// Resend heartbeats the server listed as missing, e.g. after a lost fragment.
+    async fn resend_requested_heartbeats(&mut self) -> Result<()> {
+        for seq in std::mem::take(&mut self.requested_heartbeats) {
+            let Some((_, packets)) = self.sent_heartbeats.iter().find(|(sent, _)| *sent == seq) else {
+                continue;
+            };
+            for part in packets.clone() {
+                self.transport.send_raw(&part).await?;
+            }
+            self.heartbeat_resends += 1;
+        }
+        Ok(())
+    }
+
+    /// Heartbeats resent because the server requested them.
+    pub fn heartbeat_resends(&self) -> u64 {
+        self.heartbeat_resends
     }
 
     async fn send_empty_action_tick(&mut self) -> Result<()> {
@@ -2922,12 +3419,16 @@ impl Connection {
         player_index.wrapping_add(1)
     }
 
-    fn encode_codec_action_payload(action: &CodecInputAction, player_index: u16) -> Vec<u8> {
+    fn encode_codec_action_payload(
+        action: &CodecInputAction,
+        player_index: u16,
+        numbering: &ActionNumbering,
+    ) -> Result<Vec<u8>> {
         let mut writer = BinaryWriter::with_capacity(32);
         let count_and_segments = 2u32; // count=1, hasSegments=0
         writer.write_opt_u32(count_and_segments);
         let player_delta = Self::action_player_delta(player_index);
-        action.write_protocol_order(&mut writer, player_delta);
+        action.write_protocol_order_numbered(&mut writer, player_delta, numbering)?;
         let data = writer.into_vec();
         // Always log walk actions to file for debugging
         if let CodecInputAction::StartWalking { direction_x, direction_y } = action {
@@ -2949,19 +3450,44 @@ impl Connection {
                 );
             }
         }
-        data
+        Ok(data)
     }
 
     async fn send_codec_action(&mut self, action: CodecInputAction) -> Result<()> {
+        let closures: Vec<InputAction> = self.codec_action_closures(&action)?.into_iter().map(InputAction::raw).collect();
+        self.send_heartbeat_with_actions(&closures).await
+    }
+
+    /// Queue any input action for the next gameplay ticks. The receipt resolves
+    /// once the server runs the action for our player, or times out.
+    pub fn queue_action(&mut self, action: CodecInputAction) -> Result<ActionReceipt> {
+        let closures = self.codec_action_closures(&action)?;
+        let receipt = self.action_receipts.issue(&action);
+        // A segmented action is sent once its last segment is
+        let last = closures.len() - 1;
+        for (n, data) in closures.into_iter().enumerate() {
+            self.pending_actions.push_back(QueuedAction {
+                action: InputAction::raw(data),
+                receipt: (n == last).then(|| receipt.id()),
+            });
+        }
+        Ok(receipt)
+    }
+
+    /// Tick closure payloads for `action` from our player: one, or one per
+    /// segment for an action too big to share a heartbeat with anything.
+    fn codec_action_closures(&mut self, action: &CodecInputAction) -> Result<Vec<Vec<u8>>> {
+        self.check_can_act()?;
+        self.check_not_desynced()?;
         if self.state != ConnectionState::InGame {
             if std::env::var("FACTORIO_DEBUG").is_ok() {
                 eprintln!("[DEBUG] send_codec_action: state={:?} (expected InGame)", self.state);
             }
-            return Err(Error::InvalidPacket("must be in game".into()));
+            return Err(Error::ActionRejected { action: Some(action.action_type()), reason: RejectReason::NotInGame });
         }
         let player_index = self
             .player_index
-            .ok_or_else(|| Error::InvalidPacket("player index unknown".into()))?;
+            .ok_or(Error::ActionRejected { action: Some(action.action_type()), reason: RejectReason::NoPlayer })?;
         // Always log player_index to HB log for debugging
         if let Ok(mut file) = std::fs::OpenOptions::new()
             .create(true)
@@ -2975,11 +3501,49 @@ impl Connection {
                 player_index, self.peer_id, action.action_type()
             );
         }
-        let data = Self::encode_codec_action_payload(&action, player_index);
+        let data = Self::encode_codec_action_payload(action, player_index, &self.profile.numbering)?;
         if std::env::var("FACTORIO_DEBUG").is_ok() {
             eprintln!("[DEBUG] send_codec_action: queuing action player_index={} peer_id={:?}", player_index, self.peer_id);
         }
-        self.send_heartbeat_with_actions(&[InputAction::raw(data)]).await
+        if data.len() <= MAX_SEGMENT_DATA {
+            return Ok(vec![data]);
+        }
+        let action_type = self.profile.numbering.input_action_id(action.action_type()).ok_or_else(|| {
+            Error::InvalidPacket(format!("input action {:?} does not exist in this game version", action.action_type()))
+        })?;
+        let mut writer = BinaryWriter::with_capacity(data.len());
+        writer.write_opt_u16(player_index);
+        action.write_data(&mut writer);
+        Ok(self.segment_closures(action_type, player_index, &writer.into_vec()))
+    }
+
+    /// One single-segment tick closure payload per segment of an action, under
+    /// the next segment id. `data` is the player index as opt_u16 followed by
+    /// the action's data.
+    pub(crate) fn segment_closures(&mut self, action_type: u16, player_index: u16, data: &[u8]) -> Vec<Vec<u8>> {
+        let id = self.segment_id;
+        self.segment_id = self.segment_id.wrapping_add(1);
+        ActionSegment::split(action_type, id, player_index, data, MAX_SEGMENT_DATA)
+            .iter()
+            .map(|segment| {
+                let mut writer = BinaryWriter::with_capacity(32 + segment.data.len());
+                writer.write_opt_u32(1); // count=0, hasSegments=1
+                writer.write_opt_u32(1); // one segment
+                segment.write(&mut writer);
+                writer.into_vec()
+            })
+            .collect()
+    }
+
+    fn check_not_desynced(&self) -> Result<()> {
+        match &self.last_desync_report {
+            Some(report) if self.desynced => Err(Error::Desync {
+                tick: report.tick,
+                expected: report.server_crc.unwrap_or(0),
+                actual: report.local_crc,
+            }),
+            _ => Ok(()),
+        }
     }
 
     fn next_reliable(&mut self) -> bool {
@@ -2989,11 +3553,13 @@ impl Connection {
     /// Send a heartbeat with input actions
     pub async fn send_heartbeat_with_actions(&mut self, actions: &[InputAction]) -> Result<()> {
         if !actions.is_empty() {
+            self.check_can_act()?;
+            self.check_not_desynced()?;
             if std::env::var("FACTORIO_DEBUG_HB").is_ok() {
                 eprintln!("[DEBUG] send_heartbeat_with_actions: queuing {} actions, pending_actions before={}", actions.len(), self.pending_actions.len());
             }
             for action in actions {
-                self.pending_actions.push_back(action.clone());
+                self.pending_actions.push_back(action.clone().into());
             }
             if std::env::var("FACTORIO_DEBUG_HB").is_ok() {
                 eprintln!("[DEBUG] send_heartbeat_with_actions: pending_actions after={}", self.pending_actions.len());
@@ -3002,6 +3568,15 @@ impl Connection {
         Ok(())
     }
 
+    /// Next queued action, unless the server is paused or saving; then actions stay
+    /// queued and flush_gameplay keeps sending empty ticks.
+    fn take_pending_action(&mut self) -> Option<QueuedAction> {
+        if self.server_run_state != ServerRunState::Running || self.desynced {
+            return None;
+        }
+        self.pending_actions.pop_front()
+    }
+
     async fn flush_gameplay(&mut self) -> Result<()> {
         let debug = std::env::var("FACTORIO_DEBUG_FLUSH").is_ok();
         if self.state != ConnectionState::InGame {
@@ -3010,6 +3585,7 @@ impl Connection {
             }
             return Ok(());
         }
+        self.action_receipts.expire(std::time::Instant::now());
 
         // Fallback: if we're in InGame and don't have start_sending_tick yet,
         // derive it from confirmed_tick. This handles cases where the server's
@@ -3115,23 +3691,26 @@ impl Connection {
             } else {
                 let _ = self.send_heartbeat_raw().await?;
             }
-        } else if let Some(action) = self.pending_actions.pop_front() {
+        } else if let Some(queued) = self.take_pending_action() {
             if std::env::var("FACTORIO_DEBUG_HB").is_ok() {
                 eprintln!("[DEBUG] flush_gameplay: popped action, pending_actions remaining={}", self.pending_actions.len());
             }
             let player_index = match self.player_index {
                 Some(idx) => idx,
                 None => {
-                    self.pending_actions.push_front(action);
+                    self.pending_actions.push_front(queued);
                     let _ = self.send_heartbeat_raw().await?;
                     return Ok(());
                 }
             };
-            let encoded = action.encode(&mut self.chat_seq, player_index)?;
+            let encoded = queued.action.encode(&mut self.segment_id, player_index)?;
             if std::env::var("FACTORIO_DEBUG_HB").is_ok() {
                 eprintln!("[DEBUG] flush_gameplay: encoded action flags=0x{:02x} data_len={}", encoded.flags, encoded.data.len());
             }
             let _ = self.send_action_packet(encoded.flags, &encoded.data).await?;
+            if let Some(id) = queued.receipt {
+                self.action_receipts.mark_sent(id, std::time::Instant::now());
+            }
         } else {
         // No pending actions: send empty tick closures.
         // We need to keep sending to maintain the connection, but cap drift to prevent
@@ -3225,6 +3804,9 @@ impl Connection {
                     result = Some(ReceivedPacket::Heartbeat { tick: self.server_tick });
                 } else if msg_type == MessageType::TransferBlock as u8 {
                     result = Some(ReceivedPacket::MapBlock { size: data.len() });
+                } else if msg_type == MessageType::PingReply as u8 || msg_type == MessageType::Ping as u8 {
+                    let _ = self.handle_ping(&data).await;
+                    result = Some(ReceivedPacket::Unknown { msg_type, size: data.len() });
                 } else {
                     result = Some(ReceivedPacket::Unknown { msg_type, size: data.len() });
                 }
@@ -3232,6 +3814,13 @@ impl Connection {
         }
 
         if self.state == ConnectionState::InGame {
+            if let Some(id) = self.pings.due(std::time::Instant::now()) {
+                let mut writer = BinaryWriter::new();
+                writer.write_u8(MessageType::Ping as u8);
+                writer.write_u32_le(id);
+                let _ = self.transport.send_raw(&writer.into_vec()).await;
+            }
+            let _ = self.resend_requested_heartbeats().await;
             let flush_start = std::time::Instant::now();
             let _ = self.flush_gameplay().await;
             let flush_elapsed = flush_start.elapsed().as_millis();
@@ -3247,6 +3836,8 @@ impl Connection {
             }
         }
         self.check_heartbeat_timeout();
+        self.maybe_reconnect().await;
+        self.maybe_rejoin_after_desync().await;
 
         Ok(result)
     }
@@ -3292,7 +3883,256 @@ impl Connection {
             return Ok(());
         }
 
-        // Timestamp already updated above at line 3252
+        match ServerHeartbeat::parse_partial(data, self.last_action_player_index, &self.profile.numbering) {
+            (heartbeat, None) => {
+                self.apply_server_heartbeat(heartbeat);
+                Ok(())
+            }
+            (partial, Some(e)) => {
+                self.heartbeat_parse_failures += 1;
+                if debug {
+                    eprintln!("[DEBUG] HB: {} (len={})", e, data.len());
+                }
+                self.last_heartbeat_error = Some(e);
+                match self.heartbeat_parsing {
+                    // Keep the ticks, confirmations and sync actions decoded before the error
+                    HeartbeatParsing::Strict => {
+                        if !partial.tick_closures.is_empty()
+                            || !partial.confirm_records.is_empty()
+                            || !partial.sync_actions.is_empty()
+                        {
+                            self.apply_server_heartbeat(partial);
+                        }
+                        Ok(())
+                    }
+                    HeartbeatParsing::Fallback => self.process_server_heartbeat_heuristic(data, payload_start),
+                }
+            }
+        }
+    }
+
+    /// Time a PingReply, or answer a Ping from the server with the same id.
+    async fn handle_ping(&mut self, data: &[u8]) -> Result<()> {
+        let (header, payload_start) = PacketHeader::parse(data)?;
+        let id = BinaryReader::new(&data[payload_start..]).read_u32_le()?;
+        if header.message_type == MessageType::PingReply {
+            self.pings.on_reply(id, std::time::Instant::now());
+            return Ok(());
+        }
+        let mut writer = BinaryWriter::new();
+        writer.write_u8(MessageType::PingReply as u8);
+        writer.write_u32_le(id);
+        self.transport.send_raw(&writer.into_vec()).await
+    }
+
+    /// Apply a decoded heartbeat: closures, confirmed tick and sync actions.
+    fn apply_server_heartbeat(&mut self, heartbeat: ServerHeartbeat) {
+        let debug = std::env::var("FACTORIO_DEBUG").is_ok();
+        self.server_seq = heartbeat.sequence;
+
+        if debug && self.debug_server_heartbeat_dumped < 6 {
+            eprintln!(
+                "[DEBUG] S2C heartbeat: flags=0x{:02x} seq={} closures={} sync_actions={} requests={}",
+                heartbeat.flags.bits(),
+                heartbeat.sequence,
+                heartbeat.tick_closures.len(),
+                heartbeat.sync_actions.len(),
+                heartbeat.heartbeat_requests.len()
+            );
+            self.debug_server_heartbeat_dumped += 1;
+        }
+
+        let last_tick = heartbeat.last_tick();
+        let mut closures = Vec::with_capacity(heartbeat.tick_closures.len());
+        for closure in heartbeat.tick_closures {
+            let tick = closure.tick as u32;
+            let mut actions = closure.actions;
+            for segment in closure.segments {
+                match self.segmented_actions.insert(segment, &self.profile.numbering) {
+                    Ok(Some(action)) => actions.push(action),
+                    Ok(None) => {}
+                    Err(e) => {
+                        if debug {
+                            eprintln!("[DEBUG] HB: dropped segmented action: {}", e);
+                        }
+                    }
+                }
+            }
+            for action in &actions {
+                self.apply_player_action(action.player_index, &action.action, Some(tick));
+            }
+            if self.walk_active {
+                let dx = (self.character_speed * self.walk_dir.0 * 256.0).trunc() / 256.0;
+                let dy = (self.character_speed * self.walk_dir.1 * 256.0).trunc() / 256.0;
+                self.player_x += dx;
+                self.player_y += dy;
+            }
+            self.walk_last_tick = self.server_tick;
+            closures.push(TickClosureData {
+                update_tick: tick,
+                input_actions: actions
+                    .into_iter()
+                    .map(|a| TickAction { player_index: a.player_index, action: a.action })
+                    .collect(),
+            });
+        }
+        self.last_action_player_index = heartbeat.last_player_index;
+
+        if let Some(tick) = last_tick.map(|t| t as u32).filter(|&t| t > 0) {
+            self.update_server_tick(tick, debug, "s2c-heartbeat");
+            // Also update confirmed_tick so can_send_tick_closure permits sending
+            self.update_confirmed_tick(tick, debug, "s2c-closure");
+            // Saves have no end marker: closures for new ticks mean it is over.
+            // A pause only ends with EndPause.
+            if self.server_run_state == ServerRunState::Saving && tick > self.server_run_state_tick {
+                self.set_server_run_state(ServerRunState::Running);
+            }
+        }
+        if !closures.is_empty() {
+            for closure in &closures {
+                self.desync.record_closure(closure);
+            }
+            self.execute_tick_closures(closures);
+            self.desync.record_tick_lead(TickLeadSample {
+                server_tick: self.server_tick,
+                confirmed_tick: self.confirmed_tick,
+                client_tick: self.client_tick,
+                lead: self.client_tick_lead,
+            });
+        }
+        if let Some(record) = heartbeat.confirm_records.last() {
+            self.update_confirmed_tick(record.tick, debug, "confirm-record");
+        }
+        for &record in &heartbeat.confirm_records {
+            self.desync.record_server_crc(record);
+        }
+
+        for sync in &heartbeat.sync_actions {
+            self.apply_sync_action(sync.peer_id, &sync.action);
+        }
+        self.requested_heartbeats.extend(&heartbeat.heartbeat_requests);
+    }
+
+    /// Side effects of one decoded synchronizer action.
+    fn apply_sync_action(&mut self, peer_id: u16, action: &SynchronizerAction) {
+        let debug = std::env::var("FACTORIO_DEBUG").is_ok();
+        match action {
+            SynchronizerAction::PeerDisconnect { .. } if self.disconnecting && self.peer_id == Some(peer_id) => {
+                self.disconnect_acknowledged = true;
+            }
+            SynchronizerAction::PeerDisconnect { disconnect_type } => {
+                self.on_peer_left(peer_id, *disconnect_type);
+            }
+            SynchronizerAction::NewPeerInfo { peer_name } => {
+                self.roster.add_peer(peer_id, peer_name);
+            }
+            SynchronizerAction::ClientShouldStartSendingTickClosures { tick } => {
+                if self.is_tick_plausible(*tick) {
+                    self.handle_start_sending_tick(*tick as u64);
+                } else if debug {
+                    eprintln!(
+                        "[DEBUG] HB: start tick {} not plausible (server_tick={}, confirmed_tick={})",
+                        tick, self.server_tick, self.confirmed_tick
+                    );
+                }
+            }
+            SynchronizerAction::MapReadyForDownload(info) => {
+                self.map_crc = Some(info.crc);
+                self.note_map_transfer_size(info.transfer_size);
+                self.note_map_tick(info.map_tick);
+            }
+            SynchronizerAction::SkippedTickClosure { tick } => {
+                self.handle_skipped_tick_closure(*tick);
+            }
+            SynchronizerAction::ChangeLatency { latency } => {
+                self.update_latency(*latency, SynchronizerActionType::ChangeLatency);
+            }
+            SynchronizerAction::IncreasedLatencyConfirm { latency, .. } => {
+                self.update_latency(*latency, SynchronizerActionType::IncreasedLatencyConfirm);
+            }
+            SynchronizerAction::BeginPause => self.set_server_run_state(ServerRunState::Paused),
+            SynchronizerAction::EndPause => self.set_server_run_state(ServerRunState::Running),
+            SynchronizerAction::SavingForUpdate => {
+                self.autosave_tick = None;
+                self.set_server_run_state(ServerRunState::Saving);
+            }
+            SynchronizerAction::SavingCountdown { tick, .. } => {
+                self.autosave_tick = Some(*tick);
+            }
+            SynchronizerAction::PlayerDesynced if self.peer_id == Some(peer_id) => {
+                self.on_desync(self.server_tick, DesyncCause::ServerReported);
+            }
+            _ => {}
+        }
+        self.emit(GameEvent::SyncAction { peer_id, action: action.clone() });
+    }
+
+    /// Stop sending actions, keep a report of what led here and, if enabled,
+    /// schedule a rejoin. Only the first desync of a connection is reported.
+    fn on_desync(&mut self, tick: u32, cause: DesyncCause) {
+        if self.desynced {
+            return;
+        }
+        self.desynced = true;
+        let mut report = self.desync.report(tick, cause, self.sim_world());
+        eprintln!(
+            "[conn] desync ({}) at tick {}: local crc {:#010x}, server crc {}",
+            cause.as_str(),
+            tick,
+            report.local_crc,
+            report.server_crc.map_or("unknown".to_string(), |crc| format!("{:#010x}", crc))
+        );
+        if let Some(dir) = &self.desync_report_dir {
+            match desync::write_report(&report, dir) {
+                Ok(path) => {
+                    eprintln!("[conn] desync report written to {}", path.display());
+                    report.path = Some(path);
+                }
+                Err(e) => eprintln!("[conn] failed to write desync report: {}", e),
+            }
+        }
+        self.pending_actions.clear();
+        self.action_receipts.clear();
+        self.emit(GameEvent::Desync {
+            tick,
+            local_crc: report.local_crc,
+            server_crc: report.server_crc.unwrap_or(0),
+        });
+        self.last_desync_report = Some(report);
+        self.desync_rejoin_pending = self.rejoin_on_desync;
+    }
+
+    fn set_server_run_state(&mut self, state: ServerRunState) {
+        if state == self.server_run_state {
+            return;
+        }
+        if std::env::var("FACTORIO_DEBUG").is_ok() {
+            eprintln!(
+                "[DEBUG] server {} -> {} at tick {} ({} actions held)",
+                self.server_run_state.as_str(),
+                state.as_str(),
+                self.server_tick,
+                self.pending_actions.len()
+            );
+        }
+        self.server_run_state = state;
+        self.server_run_state_tick = self.server_tick;
+        if state == ServerRunState::Running {
+            // Held actions get their ticks when popped. If the server moved past
+            // our next tick while we waited, start from its tick instead of
+            // sending closures it already considers late.
+            let server_echo = if self.confirmed_tick != 0 { self.confirmed_tick } else { self.server_tick };
+            if self.client_tick != 0 && self.client_tick <= server_echo {
+                self.client_tick = server_echo.wrapping_add(1);
+            }
+            self.mark_needs_immediate_heartbeat();
+        }
+    }
+
+    /// Pre-decoder heartbeat handling: byte-scanning heuristics that tolerate
+    /// layouts the strict decoder rejects. Only used in `HeartbeatParsing::Fallback`.
+    fn process_server_heartbeat_heuristic(&mut self, data: &[u8], payload_start: usize) -> Result<()> {
+        let debug = std::env::var("FACTORIO_DEBUG").is_ok();
 
         // Payload layout per binary RE (docs/heartbeat-architecture.md):
         // [0] flags
@@ -3582,64 +4422,7 @@ impl Connection {
         if header.has_confirmations {
             return None;
         }
-        let fragment_id = header.fragment_id?;
-        let msg_id = header.message_id;
-        if payload_start >= data.len() {
-            return None;
-        }
-        let payload = &data[payload_start..];
-
-        // Drop stale assemblies.
-        self.fragmented_heartbeats
-            .retain(|_, asm| asm.created_at.elapsed() < Duration::from_secs(2));
-
-        let (type_byte, reassembled) = {
-            let entry = self.fragmented_heartbeats.entry(msg_id).or_insert_with(|| {
-                FragmentAssembly {
-                    type_byte: data[0],
-                    fragments: BTreeMap::new(),
-                    max_len: 0,
-                    last_id: None,
-                    created_at: std::time::Instant::now(),
-                }
-            });
-
-            entry.type_byte = data[0];
-            entry.fragments.insert(fragment_id, payload.to_vec());
-            let len = payload.len();
-            if len > entry.max_len {
-                entry.max_len = len;
-            } else if entry.max_len > 0 && len < entry.max_len {
-                entry.last_id = Some(fragment_id);
-            }
-
-            let Some(last_id) = entry.last_id else {
-                return None;
-            };
-
-            for id in 0..=last_id {
-                if !entry.fragments.contains_key(&id) {
-                    return None;
-                }
-            }
-
-            let mut reassembled = Vec::new();
-            for id in 0..=last_id {
-                if let Some(part) = entry.fragments.get(&id) {
-                    reassembled.extend_from_slice(part);
-                }
-            }
-
-            (entry.type_byte, reassembled)
-        };
-
-        self.fragmented_heartbeats.remove(&msg_id);
-
-        // Build a synthetic unfragmented packet: type byte + payload.
-        let mut packet = Vec::with_capacity(1 + reassembled.len());
-        packet.push(type_byte & !0x40);
-        packet.extend_from_slice(&reassembled);
-        Some(packet)
+        self.fragments.insert(header, data, payload_start)
     }
 
     fn update_server_tick(&mut self, tick: u32, debug: bool, source: &str) {
@@ -4020,7 +4803,7 @@ impl Connection {
         // Format observed in Factorio 2.0: [0x04][tick:u64]
         // The tick is u64 directly after the type byte (no player_index prefix).
         for i in 0..=data.len().saturating_sub(9) {
-            if data[i] != SynchronizerActionType::ClientShouldStartSendingTickClosures as u8 {
+            if data[i] != self.sync_id(SynchronizerActionType::ClientShouldStartSendingTickClosures) {
                 continue;
             }
 
@@ -4120,7 +4903,9 @@ impl Connection {
         }
         if let Some(sim) = self.simulation.as_mut() {
             for closure in &closures {
-                let _ = sim.executor.execute_tick(&mut sim.world, closure);
+                if let Ok(result) = sim.executor.execute_tick(Arc::make_mut(&mut sim.world), closure) {
+                    self.desync.record_local_crc(result.tick, result.checksum);
+                }
             }
         }
     }
@@ -4285,18 +5070,7 @@ impl Connection {
                         self.debug_action_packets += 1;
                     }
 
-                    let remaining = reader.remaining_slice();
-                    // Encode action_type as varint (u8 if < 255, else 0xFF + u16 LE)
-                    let mut temp = Vec::with_capacity(3 + remaining.len());
-                    if action_type < 255 {
-                        temp.push(action_type as u8);
-                    } else {
-                        temp.push(0xFF);
-                        temp.extend_from_slice(&action_type.to_le_bytes());
-                    }
-                    temp.extend_from_slice(remaining);
-                    let mut temp_reader = BinaryReader::new(&temp);
-                    let action = match CodecInputAction::read(&mut temp_reader) {
+                    let action = match CodecInputAction::read_data_numbered(action_type, reader, &self.profile.numbering) {
                         Ok(action) => action,
                         Err(e) => {
                             if debug {
@@ -4305,15 +5079,6 @@ impl Connection {
                             return None;
                         }
                     };
-                    // Calculate bytes consumed minus the action_type encoding (1 or 3 bytes)
-                    let action_type_bytes = if action_type < 255 { 1 } else { 3 };
-                    let consumed = temp_reader.position().saturating_sub(action_type_bytes);
-                    if reader.skip(consumed).is_err() {
-                        if debug {
-                            eprintln!("[DEBUG] HB: failed to skip action payload");
-                        }
-                        return None;
-                    }
 
                     self.apply_player_action(current_player_index, &action, Some(tick_u32));
                     actions.push(TickAction {
@@ -4595,18 +5360,7 @@ impl Connection {
                     }
                 };
 
-                let remaining = reader.remaining_slice();
-                // For action types > 255, create a temp buffer with the type as varint
-                let mut temp = Vec::with_capacity(3 + remaining.len());
-                if action_type < 255 {
-                    temp.push(action_type as u8);
-                } else {
-                    temp.push(0xFF);
-                    temp.extend_from_slice(&action_type.to_le_bytes());
-                }
-                temp.extend_from_slice(remaining);
-                let mut temp_reader = BinaryReader::new(&temp);
-                let action = match crate::codec::InputAction::read(&mut temp_reader) {
+                let action = match CodecInputAction::read_data_numbered(action_type, reader, &self.profile.numbering) {
                     Ok(action) => action,
                     Err(e) => {
                         if debug {
@@ -4615,15 +5369,6 @@ impl Connection {
                         return None;
                     }
                 };
-                // Calculate bytes consumed minus the action_type encoding (1 or 3 bytes)
-                let action_type_bytes = if action_type < 255 { 1 } else { 3 };
-                let consumed = temp_reader.position().saturating_sub(action_type_bytes);
-                if reader.skip(consumed).is_err() {
-                    if debug {
-                        eprintln!("[DEBUG] HB: failed to skip action payload");
-                    }
-                    return None;
-                }
 
                 self.apply_player_action(current_player_index, &action, Some(tick_u32));
                 actions.push(TickAction {
@@ -4691,7 +5436,7 @@ impl Connection {
                     Ok(v) => v,
                     Err(_) => return false,
                 };
-                let action = match SynchronizerActionType::from_u8(action_type) {
+                let action = match self.profile.numbering.sync_action_type(action_type) {
                     Some(v) => v,
                     None => return false,
                 };
@@ -4751,6 +5496,22 @@ impl Connection {
         let reason = reason.into();
         if self.state != ConnectionState::Disconnected {
             eprintln!("[conn] disconnected: {}", reason);
+            let event_reason = if self.disconnecting {
+                DisconnectReason::UserRequested
+            } else if reason.contains("timeout") {
+                DisconnectReason::Timeout
+            } else {
+                DisconnectReason::Other(reason.clone())
+            };
+            self.emit(GameEvent::Disconnected { reason: event_reason });
+            if self.state == ConnectionState::InGame && self.reconnect_policy.is_some() && !self.disconnecting {
+                self.reconnect_pending = true;
+                self.reconnect_attempts = 0;
+                self.next_reconnect_at = self
+                    .reconnect_policy
+                    .as_ref()
+                    .map(|p| std::time::Instant::now() + p.backoff(1));
+            }
         }
         self.state = ConnectionState::Disconnected;
         self.last_disconnect_reason = Some(reason);
@@ -4759,6 +5520,7 @@ impl Connection {
         self.pending_init_action = false;
         self.pending_start_gameplay = false;
         self.pending_actions.clear();
+        self.action_receipts.clear();
         self.pending_confirms.clear();
         self.pending_latency_confirm = None;
         self.client_seq = 0;
@@ -4769,16 +5531,249 @@ impl Connection {
         self.next_closure_tick = None;
     }
 
-    fn check_heartbeat_timeout(&mut self) {
-        if self.state != ConnectionState::InGame {
-            return;
+    /// Leave the game cleanly: flush queued actions, announce the disconnect and wait
+    /// (up to 2s) for the server to acknowledge it. Returns a `Timeout` if no
+    /// acknowledgement arrived; the connection is Disconnected either way.
+    pub async fn disconnect(&mut self) -> Result<()> {
+        if self.state == ConnectionState::Disconnected {
+            return Ok(());
         }
-        let poll_id = self.debug_current_poll_id;
-        let Some(last) = self.last_server_heartbeat_at else {
-            if std::env::var("FACTORIO_DEBUG_TIMEOUT").is_ok() {
-                eprintln!("[DEBUG] poll#{}: check_heartbeat_timeout: last_server_heartbeat_at is None", poll_id);
-            }
-            return;
+        let debug = std::env::var("FACTORIO_DEBUG").is_ok();
+        self.reconnect_pending = false;
+        self.reconnect_task = None;
+        self.disconnecting = true;
+        self.disconnect_acknowledged = false;
+
+        if self.state == ConnectionState::InGame {
+            let flush_start = std::time::Instant::now();
+            while !self.pending_actions.is_empty() && flush_start.elapsed() < DISCONNECT_TIMEOUT {
+                let _ = self.poll().await;
+                tokio::time::sleep(self.heartbeat_interval()).await;
+            }
+            if debug && !self.pending_actions.is_empty() {
+                eprintln!("[DEBUG] disconnect: dropping {} unsent actions", self.pending_actions.len());
+            }
+            self.flush_pending_confirmations().await?;
+            let _ = self
+                .send_gameplay_sync_action(
+                    SynchronizerActionType::ClientChangedState,
+                    |writer| writer.write_u8(CLIENT_STATE_DISCONNECT_SCHEDULED),
+                    true,
+                )
+                .await;
+        }
+
+        let start = std::time::Instant::now();
+        let mut last_request: Option<std::time::Instant> = None;
+        while !self.disconnect_acknowledged && start.elapsed() < DISCONNECT_TIMEOUT {
+            if last_request.is_none_or(|t| t.elapsed() >= DISCONNECT_REQUEST_INTERVAL) {
+                self.send_disconnect_request().await?;
+                last_request = Some(std::time::Instant::now());
+            }
+            let Some(data) = self.transport.recv_raw_timeout(Duration::from_millis(16)).await? else {
+                continue;
+            };
+            if data.is_empty() {
+                continue;
+            }
+            let msg_type = data[0] & 0x1F;
+            if msg_type == MessageType::ServerToClientHeartbeat as u8 {
+                let _ = self.process_server_heartbeat(&data);
+            } else if msg_type == MessageType::Empty as u8 {
+                // Server has already dropped us.
+                self.disconnect_acknowledged = true;
+            }
+        }
+
+        let acknowledged = self.disconnect_acknowledged;
+        if debug {
+            eprintln!("[DEBUG] disconnect: acknowledged={} after {:?}", acknowledged, start.elapsed());
+        }
+        self.mark_disconnected(if acknowledged {
+            "client disconnected"
+        } else {
+            "client disconnected (not acknowledged)"
+        });
+        self.disconnecting = false;
+        self.disconnect_acknowledged = false;
+
+        if acknowledged {
+            Ok(())
+        } else {
+            Err(Error::Timeout { stage: Stage::Disconnect })
+        }
+    }
+
+    async fn send_disconnect_request(&mut self) -> Result<()> {
+        let request = RequestForHeartbeatWhenDisconnecting::new(vec![self.server_seq.wrapping_add(1)]);
+        let mut writer = BinaryWriter::with_capacity(8);
+        request.write(&mut writer);
+        let msg_id = self.next_msg_id();
+        let packet = PacketBuilder::new(MessageType::RequestForHeartbeatWhenDisconnecting, msg_id, true)
+            .payload(writer.as_slice())
+            .build();
+        self.transport.send_raw(&packet).await
+    }
+
+    /// Redo the handshake and map download with the same address, username and credentials.
+    /// The previous map parse is reused if the server sends an identical map; otherwise
+    /// `parsed_map` is left empty for the caller to re-parse (as after `download_map_with_parse(false)`).
+    pub async fn reconnect(&mut self) -> Result<()> {
+        let previous_player_index = self.player_index;
+        let fresh = self.fresh_connection().await?;
+        let (fresh, result) = Self::rejoin(fresh).await;
+        self.adopt(fresh, result, previous_player_index)
+    }
+
+    /// A new connection with this one's settings, over a transport from the
+    /// factory. It takes the previous parse and any partial map download.
+    async fn fresh_connection(&mut self) -> Result<Connection> {
+        let transport = self.transport_factory.open(self.addr).await?;
+        let mut fresh = Connection::with_transport(self.addr, transport, self.username.clone(), self.credentials.clone());
+        fresh.set_recorder(self.recorder.clone());
+        fresh.handshake = self.handshake.clone();
+        fresh.transport_factory = self.transport_factory.clone();
+        fresh.heartbeat_parsing = self.heartbeat_parsing;
+        fresh.action_receipts.set_timeout(self.action_receipts.timeout());
+        fresh.desync_report_dir = self.desync_report_dir.clone();
+        fresh.rejoin_on_desync = self.rejoin_on_desync;
+        fresh.shared_maps = self.shared_maps.clone();
+        fresh.observer = self.observer;
+        fresh.reusable_map = self.parsed_map.take().map(|map| {
+            (crc32fast::hash(&self.map_data), self.map_data.len(), map)
+        });
+        fresh.partial_download = self.partial_download.take();
+        Ok(fresh)
+    }
+
+    async fn rejoin(mut fresh: Connection) -> (Connection, Result<()>) {
+        let result = async {
+            fresh.connect().await?;
+            fresh.download_map_with_parse(false).await.map(|_| ())
+        }
+        .await;
+        (fresh, result)
+    }
+
+    /// Take over a fresh connection that joined, or take back what it was
+    /// lent (the previous parse and partial download) for the next attempt.
+    fn adopt(&mut self, mut fresh: Connection, result: Result<()>, previous_player_index: Option<u16>) -> Result<()> {
+        if let Err(e) = result {
+            if let Some((_, _, map)) = fresh.reusable_map.take() {
+                self.parsed_map = Some(map);
+            } else if let Some(map) = fresh.parsed_map.take() {
+                self.parsed_map = Some(map);
+            }
+            self.partial_download = fresh.partial_download.take();
+            return Err(e);
+        }
+
+        if fresh.player_index.is_none() {
+            fresh.player_index = previous_player_index;
+        }
+        fresh.events = std::mem::take(&mut self.events);
+        fresh.reconnect_policy = self.reconnect_policy.take();
+        fresh.last_desync_report = self.last_desync_report.take();
+        *self = fresh;
+        Ok(())
+    }
+
+    /// Start or finish a pending reconnect. The handshake and map download run
+    /// on a spawned task, so `poll()` keeps returning while they do.
+    async fn maybe_reconnect(&mut self) {
+        if let Some(task) = self.reconnect_task.as_mut() {
+            if !task.handle.is_finished() {
+                return;
+            }
+            let attempt = task.attempt;
+            let previous_player_index = task.previous_player_index;
+            let outcome = (&mut task.handle).await;
+            self.reconnect_task = None;
+            let result = match outcome {
+                Ok((fresh, result)) => self.adopt(*fresh, result, previous_player_index),
+                Err(e) => Err(Error::TaskFailed(format!("reconnect: {}", e))),
+            };
+            match result {
+                Ok(()) => {
+                    eprintln!("[conn] reconnected after {} attempt(s)", attempt);
+                    self.emit(GameEvent::Reconnected {
+                        player_index: self.player_index,
+                        attempts: attempt,
+                    });
+                }
+                Err(e) => self.reconnect_failed(attempt, e),
+            }
+            return;
+        }
+
+        if !self.reconnect_pending || self.state != ConnectionState::Disconnected {
+            return;
+        }
+        if let Some(at) = self.next_reconnect_at {
+            if std::time::Instant::now() < at {
+                return;
+            }
+        }
+
+        let attempt = self.reconnect_attempts + 1;
+        eprintln!("[conn] reconnect attempt {}", attempt);
+        let previous_player_index = self.player_index;
+        match self.fresh_connection().await {
+            Ok(fresh) => {
+                let handle = tokio::spawn(async move {
+                    let (fresh, result) = Self::rejoin(fresh).await;
+                    (Box::new(fresh), result)
+                });
+                self.reconnect_task = Some(ReconnectTask { handle, attempt, previous_player_index });
+            }
+            Err(e) => self.reconnect_failed(attempt, e),
+        }
+    }
+
+    /// Schedule the next attempt under the reconnect policy, or give up.
+    fn reconnect_failed(&mut self, attempt: u32, error: Error) {
+        eprintln!("[conn] reconnect attempt {} failed: {}", attempt, error);
+        self.reconnect_attempts = attempt;
+        match self.reconnect_policy.clone() {
+            Some(policy) if policy.max_attempts.is_none_or(|max| attempt < max) => {
+                self.reconnect_pending = true;
+                self.next_reconnect_at = Some(std::time::Instant::now() + policy.backoff(attempt + 1));
+            }
+            _ => {
+                eprintln!("[conn] giving up after {} reconnect attempts", attempt);
+                self.reconnect_pending = false;
+                self.next_reconnect_at = None;
+            }
+        }
+    }
+
+    /// Leave the desynced game and join again with a fresh map, through the
+    /// same background reconnect as a dropped connection. A failed rejoin is
+    /// retried if a reconnect policy is set.
+    async fn maybe_rejoin_after_desync(&mut self) {
+        if !self.desync_rejoin_pending {
+            return;
+        }
+        self.desync_rejoin_pending = false;
+        eprintln!("[conn] rejoining after desync");
+        // disconnect() polls while flushing, so box the cycle
+        let _ = Box::pin(self.disconnect()).await;
+        self.reconnect_pending = true;
+        self.reconnect_attempts = 0;
+        self.next_reconnect_at = None;
+        self.maybe_reconnect().await;
+    }
+
+    fn check_heartbeat_timeout(&mut self) {
+        if self.state != ConnectionState::InGame {
+            return;
+        }
+        let poll_id = self.debug_current_poll_id;
+        let Some(last) = self.last_server_heartbeat_at else {
+            if std::env::var("FACTORIO_DEBUG_TIMEOUT").is_ok() {
+                eprintln!("[DEBUG] poll#{}: check_heartbeat_timeout: last_server_heartbeat_at is None", poll_id);
+            }
+            return;
         };
         let elapsed = last.elapsed();
         if std::env::var("FACTORIO_DEBUG_TIMEOUT2").is_ok() && elapsed.as_millis() > 100 {
@@ -4833,7 +5828,7 @@ impl Connection {
                     Ok(v) => v,
                     Err(_) => return false,
                 };
-                let action = match SynchronizerActionType::from_u8(action_type) {
+                let action = match self.profile.numbering.sync_action_type(action_type) {
                     Some(v) => v,
                     None => return false,
                 };
@@ -4904,7 +5899,7 @@ impl Connection {
                     Ok(v) => v,
                     Err(_) => return None,
                 };
-                let action = match SynchronizerActionType::from_u8(action_type) {
+                let action = match self.profile.numbering.sync_action_type(action_type) {
                     Some(v) => v,
                     None => return None,
                 };
@@ -5067,19 +6062,12 @@ impl Connection {
     ) -> Result<()> {
         // Per binary RE: each sync action includes a player_index. Most traces put it
         // immediately after action_type (VarShort). Some legacy paths appear trailing.
-        if player_index_first {
-            let _player_index = reader.read_opt_u16()?;
-        }
-        match action {
-            SynchronizerActionType::PeerDisconnect => {
-                let _ = reader.read_u8()?;
-            }
-            SynchronizerActionType::NewPeerInfo => {
-                let _ = reader.read_string()?;
-            }
-            SynchronizerActionType::ClientChangedState => {
-                let _ = reader.read_u8()?;
-            }
+        let mut sync_peer = if player_index_first {
+            Some(reader.read_opt_u16()?)
+        } else {
+            None
+        };
+        let decoded = match action {
             SynchronizerActionType::ClientShouldStartSendingTickClosures => {
                 // Format: [action_type:u8][player_index:opt_u16][tick:u32] when player_index_first=true
                 // Format: [action_type:u8][tick:u32][player_index:opt_u16] when player_index_first=false
@@ -5098,187 +6086,165 @@ impl Connection {
                         tick_u32, self.server_tick, self.confirmed_tick
                     )));
                 }
-
-                // Always apply server's start tick - it overrides any fallback we might have set
-                if apply {
-                    self.handle_start_sending_tick(tick_u32 as u64);
-                }
+                Some(SynchronizerAction::ClientShouldStartSendingTickClosures { tick: tick_u32 })
             }
             SynchronizerActionType::MapReadyForDownload => {
+                // Lenient about truncated trailers, unlike MapReadyForDownload::read
                 self.skip_map_ready_for_download(reader)?;
+                None
             }
-            SynchronizerActionType::MapLoadingProgressUpdate
-            | SynchronizerActionType::MapSavingProgressUpdate
-            | SynchronizerActionType::MapDownloadingProgressUpdate
-            | SynchronizerActionType::CatchingUpProgressUpdate
-            | SynchronizerActionType::PeerDroppingProgressUpdate => {
-                let _ = reader.read_u8()?;
-            }
-            SynchronizerActionType::SavingForUpdate
-            | SynchronizerActionType::PlayerDesynced
-            | SynchronizerActionType::BeginPause
-            | SynchronizerActionType::EndPause
-            | SynchronizerActionType::GameEnd => {}
-            SynchronizerActionType::SkippedTickClosure => {
-                let tick = reader.read_u64_le()?;
-                if apply {
-                    let tick_u32 = (tick & 0xffff_ffff) as u32;
-                    if !self.is_tick_plausible(tick_u32) {
-                        if std::env::var("FACTORIO_DEBUG").is_ok() {
-                            eprintln!(
-                                "[DEBUG] HB: ignoring SkippedTickClosure tick={} (server_tick={}, confirmed_tick={})",
-                                tick_u32, self.server_tick, self.confirmed_tick
-                            );
-                        }
-                        // Consume player_index before returning
-                        if !player_index_first {
-                            let _ = reader.read_opt_u16()?;
-                        }
-                        return Ok(());
-                    }
-                    if std::env::var("FACTORIO_DEBUG").is_ok() {
-                        eprintln!(
-                            "[DEBUG] HB: SkippedTickClosure tick={} (client_tick={}, confirmed_tick={}, server_tick={})",
-                            tick_u32, self.client_tick, self.confirmed_tick, self.server_tick
-                        );
-                    }
-                    if std::env::var("FACTORIO_DEBUG_HB_FILE").is_ok() {
-                        if let Ok(mut file) = std::fs::OpenOptions::new()
-                            .create(true)
-                            .append(true)
-                            .open("/tmp/factorio-client-sync.log")
-                        {
-                            use std::io::Write;
-                            let _ = writeln!(
-                                file,
-                                "sync skipped_tick={} client_tick={} confirmed={} server={}",
-                                tick_u32, self.client_tick, self.confirmed_tick, self.server_tick
-                            );
-                        }
-                    }
-                    // SkippedTickClosure means the server wants to skip tick X.
-                    // If we've already sent tick closures past tick_u32, we should NOT
-                    // confirm - the server will reject it as "too late".
-                    // We should only update client_tick if we're exactly at tick_u32 (about to send it).
-                    let already_sent = self.client_tick > tick_u32;
-                    let about_to_send = self.client_tick == tick_u32;
-                    
-                    if about_to_send {
-                        // We're exactly at the skipped tick - skip over it.
-                        let old_tick = self.client_tick;
-                        self.client_tick = tick_u32.saturating_add(1);
-                        if std::env::var("FACTORIO_DEBUG_HB_FILE").is_ok() {
-                            if let Ok(mut file) = std::fs::OpenOptions::new()
-                                .create(true)
-                                .append(true)
-                                .open("/tmp/factorio-client-tick.log")
-                            {
-                                use std::io::Write;
-                                let _ = writeln!(
-                                    file,
-                                    "SKIPPED_TICK: client_tick {} -> {} (skipped={})",
-                                    old_tick, self.client_tick, tick_u32
-                                );
-                            }
-                        }
-                        // Queue confirmation for the tick we skipped
-                        if self
-                            .pending_skipped_tick_confirms
-                            .back()
-                            .copied()
-                            != Some(tick)
-                        {
-                            self.pending_skipped_tick_confirms.push_back(tick);
-                        }
-                    } else if already_sent {
-                        // We've already sent past this tick - don't confirm, it would be too late.
-                        if std::env::var("FACTORIO_DEBUG_HB_FILE").is_ok() {
-                            if let Ok(mut file) = std::fs::OpenOptions::new()
-                                .create(true)
-                                .append(true)
-                                .open("/tmp/factorio-client-tick.log")
-                            {
-                                use std::io::Write;
-                                let _ = writeln!(
-                                    file,
-                                    "SKIPPED_TICK_IGNORED: already_sent client_tick={} > skipped={}",
-                                    self.client_tick, tick_u32
-                                );
-                            }
-                        }
-                    } else {
-                        // We're behind the skipped tick (client_tick < tick_u32).
-                        // The server will skip this tick, but we still need to send the ticks before it.
-                        // Track this tick so we skip it when we reach it in compute_client_tick.
-                        if std::env::var("FACTORIO_DEBUG_HB_FILE").is_ok() {
-                            if let Ok(mut file) = std::fs::OpenOptions::new()
-                                .create(true)
-                                .append(true)
-                                .open("/tmp/factorio-client-tick.log")
-                            {
-                                use std::io::Write;
-                                let _ = writeln!(
-                                    file,
-                                    "SKIPPED_TICK_QUEUED: client_tick={} < skipped={} (will skip when we reach it)",
-                                    self.client_tick, tick_u32
-                                );
-                            }
-                        }
-                        // Track this tick to skip when we reach it
-                        self.pending_skipped_ticks.insert(tick_u32);
-                        // Queue the confirmation for later (when we actually skip)
-                        if self
-                            .pending_skipped_tick_confirms
-                            .back()
-                            .copied()
-                            != Some(tick)
-                        {
-                            self.pending_skipped_tick_confirms.push_back(tick);
-                        }
-                    }
+            _ => Some(SynchronizerAction::read_data(reader, action)?),
+        };
+
+        // Read trailing player_index for legacy layout.
+        if !player_index_first {
+            sync_peer = Some(reader.read_opt_u16()?);
+        }
+        if let (true, Some(decoded)) = (apply, decoded) {
+            self.apply_sync_action(sync_peer.unwrap_or_default(), &decoded);
+        }
+        Ok(())
+    }
+
+    /// The server skipped tick closure `tick`; skip it on our side too and queue its confirm.
+    fn handle_skipped_tick_closure(&mut self, tick: u64) {
+        let tick_u32 = (tick & 0xffff_ffff) as u32;
+        if !self.is_tick_plausible(tick_u32) {
+            if std::env::var("FACTORIO_DEBUG").is_ok() {
+                eprintln!(
+                    "[DEBUG] HB: ignoring SkippedTickClosure tick={} (server_tick={}, confirmed_tick={})",
+                    tick_u32, self.server_tick, self.confirmed_tick
+                );
+            }
+            return;
+        }
+        if std::env::var("FACTORIO_DEBUG").is_ok() {
+            eprintln!(
+                "[DEBUG] HB: SkippedTickClosure tick={} (client_tick={}, confirmed_tick={}, server_tick={})",
+                tick_u32, self.client_tick, self.confirmed_tick, self.server_tick
+            );
+        }
+        if std::env::var("FACTORIO_DEBUG_HB_FILE").is_ok() {
+            if let Ok(mut file) = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open("/tmp/factorio-client-sync.log")
+            {
+                use std::io::Write;
+                let _ = writeln!(
+                    file,
+                    "sync skipped_tick={} client_tick={} confirmed={} server={}",
+                    tick_u32, self.client_tick, self.confirmed_tick, self.server_tick
+                );
+            }
+        }
+        // SkippedTickClosure means the server wants to skip tick X.
+        // If we've already sent tick closures past tick_u32, we should NOT
+        // confirm - the server will reject it as "too late".
+        // We should only update client_tick if we're exactly at tick_u32 (about to send it).
+        let already_sent = self.client_tick > tick_u32;
+        let about_to_send = self.client_tick == tick_u32;
+        
+        if about_to_send {
+            // We're exactly at the skipped tick - skip over it.
+            let old_tick = self.client_tick;
+            self.client_tick = tick_u32.saturating_add(1);
+            if std::env::var("FACTORIO_DEBUG_HB_FILE").is_ok() {
+                if let Ok(mut file) = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open("/tmp/factorio-client-tick.log")
+                {
+                    use std::io::Write;
+                    let _ = writeln!(
+                        file,
+                        "SKIPPED_TICK: client_tick {} -> {} (skipped={})",
+                        old_tick, self.client_tick, tick_u32
+                    );
                 }
             }
-            SynchronizerActionType::SkippedTickClosureConfirm => {
-                let _ = reader.read_u64_le()?;
+            // Queue confirmation for the tick we skipped
+            if self
+                .pending_skipped_tick_confirms
+                .back()
+                .copied()
+                != Some(tick)
+            {
+                self.pending_skipped_tick_confirms.push_back(tick);
             }
-            SynchronizerActionType::ChangeLatency => {
-                let latency = reader.read_u8()?;
-                if apply {
-                    self.update_latency(latency, action);
+        } else if already_sent {
+            // We've already sent past this tick - don't confirm, it would be too late.
+            if std::env::var("FACTORIO_DEBUG_HB_FILE").is_ok() {
+                if let Ok(mut file) = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open("/tmp/factorio-client-tick.log")
+                {
+                    use std::io::Write;
+                    let _ = writeln!(
+                        file,
+                        "SKIPPED_TICK_IGNORED: already_sent client_tick={} > skipped={}",
+                        self.client_tick, tick_u32
+                    );
                 }
             }
-            SynchronizerActionType::IncreasedLatencyConfirm => {
-                let _tick = reader.read_u64_le()?;
-                let latency = reader.read_u8()?;
-                if apply {
-                    self.update_latency(latency, action);
+        } else {
+            // We're behind the skipped tick (client_tick < tick_u32).
+            // The server will skip this tick, but we still need to send the ticks before it.
+            // Track this tick so we skip it when we reach it in compute_client_tick.
+            if std::env::var("FACTORIO_DEBUG_HB_FILE").is_ok() {
+                if let Ok(mut file) = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open("/tmp/factorio-client-tick.log")
+                {
+                    use std::io::Write;
+                    let _ = writeln!(
+                        file,
+                        "SKIPPED_TICK_QUEUED: client_tick={} < skipped={} (will skip when we reach it)",
+                        self.client_tick, tick_u32
+                    );
                 }
             }
-            SynchronizerActionType::SavingCountdown => {
-                let _ = reader.read_u64_le()?;
-                let _ = reader.read_u32_le()?;
+            // Track this tick to skip when we reach it
+            self.pending_skipped_ticks.insert(tick_u32);
+            // Queue the confirmation for later (when we actually skip)
+            if self
+                .pending_skipped_tick_confirms
+                .back()
+                .copied()
+                != Some(tick)
+            {
+                self.pending_skipped_tick_confirms.push_back(tick);
             }
         }
-
-        // Read trailing player_index for legacy layout.
-        if !player_index_first {
-            let _player_index = reader.read_opt_u16()?;
-        }
-        Ok(())
     }
 
-    fn skip_map_ready_for_download(&mut self, reader: &mut BinaryReader) -> Result<()> {
-        let transfer_size = reader.read_u64_le()?;
-        let debug = std::env::var("FACTORIO_DEBUG").is_ok();
+    fn note_map_transfer_size(&mut self, transfer_size: u64) {
         if transfer_size > 0 && transfer_size < 50_000_000 {
             self.map_transfer_size = Some(transfer_size as u32);
-            if debug {
+            if std::env::var("FACTORIO_DEBUG").is_ok() {
                 eprintln!(
                     "[DEBUG] MapReadyForDownload transfer_size={} bytes",
                     transfer_size
                 );
             }
         }
+    }
+
+    fn note_map_tick(&mut self, map_tick: u64) {
+        if map_tick > 0 && map_tick <= u32::MAX as u64 {
+            let tick_u32 = map_tick as u32;
+            self.map_tick = Some(tick_u32);
+            if self.client_tick == 0 {
+                self.client_tick = tick_u32;
+            }
+        }
+    }
+
+    fn skip_map_ready_for_download(&mut self, reader: &mut BinaryReader) -> Result<()> {
+        let transfer_size = reader.read_u64_le()?;
+        self.note_map_transfer_size(transfer_size);
         // Best-effort parse: these fields are not fully mapped, so stop early
         // if the payload is shorter than expected.
         if reader.remaining_slice().len() < 8 + 4 + 8 {
@@ -5287,13 +6253,7 @@ impl Connection {
         let _auxiliary = reader.read_u64_le()?;
         let _crc = reader.read_u32_le()?;
         let map_tick = reader.read_u64_le()?;
-        if map_tick > 0 && map_tick <= u32::MAX as u64 {
-            let tick_u32 = map_tick as u32;
-            self.map_tick = Some(tick_u32);
-            if self.client_tick == 0 {
-                self.client_tick = tick_u32;
-            }
-        }
+        self.note_map_tick(map_tick);
 
         if reader.remaining_slice().len() < 4 + 4 + 1 + 1 {
             return Ok(());
@@ -5880,6 +6840,9 @@ impl Connection {
     fn apply_player_action(&mut self, player_index: u16, action: &CodecInputAction, tick: Option<u32>) {
         let debug = std::env::var("FACTORIO_DEBUG").is_ok();
         let current_tick = tick.unwrap_or(self.server_tick);
+        if self.player_index == Some(player_index) {
+            self.action_receipts.on_executed(action, current_tick);
+        }
 
         match action {
             CodecInputAction::PlayerJoinGame { peer_id, player_index_plus_one, username, .. } => {
@@ -5888,6 +6851,9 @@ impl Connection {
                 // Check if this is our own join
                 let is_self = username == &self.username
                     || self.peer_id.map_or(false, |pid| pid == *peer_id);
+                if join_index != 0xFFFF && self.roster.link_player(*peer_id, join_index, username) {
+                    self.emit(GameEvent::PlayerJoined { player_id: join_index, name: username.clone() });
+                }
                 if is_self && join_index != 0xFFFF {
                     self.player_index = Some(join_index);
                     self.player_index_confirmed = true;
@@ -5928,14 +6894,12 @@ impl Connection {
                 }
             }
 
-            CodecInputAction::PlayerLeaveGame { peer_id, .. } => {
-                // Mark player as disconnected
-                if let Some(state) = self.other_players.get_mut(&player_index) {
-                    state.connected = false;
-                    if debug {
-                        eprintln!("[DEBUG] Player left: index={} peer={}", player_index, peer_id);
-                    }
+            CodecInputAction::PlayerLeaveGame { peer_id, reason } => {
+                if debug {
+                    eprintln!("[DEBUG] Player left: index={} peer={}", player_index, peer_id);
                 }
+                self.on_peer_left(*peer_id, *reason);
+                self.other_players.remove(&player_index);
             }
 
             CodecInputAction::StartWalking { direction_x, direction_y } => {
@@ -6015,6 +6979,23 @@ impl Connection {
                 }
             }
 
+            CodecInputAction::WriteToConsole { message } => {
+                // Text typed into the server console arrives from player 0xFFFF.
+                // Lua game.print output is computed locally by every peer and never
+                // crosses the wire, so this is all the chat there is to see.
+                let player_id = (player_index != 0xFFFF).then_some(player_index);
+                let username = player_id.and_then(|idx| self.player_username(idx));
+                if debug {
+                    eprintln!("[DEBUG] Chat at tick {} from {:?} ({:?}): {}", current_tick, player_id, username, message);
+                }
+                self.emit(GameEvent::ChatMessage {
+                    tick: current_tick,
+                    player_id,
+                    username,
+                    message: message.clone(),
+                });
+            }
+
             CodecInputAction::ChangeShootingState { state, .. } => {
                 self.ensure_sim_player(player_index, None);
                 if Some(player_index) != self.player_index {
@@ -6035,7 +7016,8 @@ impl Connection {
             self.sync_simulation_to_server_tick();
             if let Some(sim) = self.simulation.as_ref() {
                 for (id, player) in &sim.world.players {
-                    if Some(*id) == self.player_index {
+                    // Players who left stay in the world but not in other_players
+                    if Some(*id) == self.player_index || !player.connected {
                         continue;
                     }
                     let state = self.other_players.entry(*id).or_default();
@@ -6070,6 +7052,39 @@ impl Connection {
         }
     }
 
+    /// Drop a peer from the roster and, if it had a player, stop tracking it.
+    /// Both PlayerLeaveGame and PeerDisconnect lead here; only the first emits.
+    fn on_peer_left(&mut self, peer_id: u16, reason: u8) {
+        let Some(entry) = self.roster.remove_peer(peer_id) else {
+            return;
+        };
+        if let Some(player_index) = entry.player_index {
+            self.other_players.remove(&player_index);
+            self.emit(GameEvent::PlayerLeft {
+                player_id: player_index,
+                reason: DisconnectReason::from_code(reason),
+            });
+        }
+    }
+
+    /// Username of a player, from the roster, join action or loaded map.
+    pub fn player_username(&self, player_index: u16) -> Option<String> {
+        if self.player_index == Some(player_index) {
+            return Some(self.username.clone());
+        }
+        if let Some(entry) = self.roster.by_player(player_index) {
+            return Some(entry.username.clone());
+        }
+        if let Some(name) = self.other_players.get(&player_index).and_then(|p| p.username.clone()) {
+            return Some(name);
+        }
+        self.simulation
+            .as_ref()
+            .and_then(|sim| sim.world.players.get(&player_index))
+            .map(|p| p.name.clone())
+            .filter(|name| !name.is_empty())
+    }
+
     fn ensure_sim_player(&mut self, player_index: u16, username: Option<&str>) {
         let sim_exists = match self.simulation.as_ref() {
             Some(s) => s.world.players.contains_key(&player_index),
@@ -6078,7 +7093,7 @@ impl Connection {
         if sim_exists {
             if let Some(name) = username {
                 if let Some(sim) = self.simulation.as_mut() {
-                    if let Some(p) = sim.world.players.get_mut(&player_index) {
+                    if let Some(p) = Arc::make_mut(&mut sim.world).players.get_mut(&player_index) {
                         if p.name.is_empty() {
                             p.name = name.to_string();
                         }
@@ -6102,7 +7117,7 @@ impl Connection {
         }
         player.position = MapPosition::from_tiles(pos.0, pos.1);
         if let Some(sim) = self.simulation.as_mut() {
-            sim.world.players.insert(player_index, player);
+            Arc::make_mut(&mut sim.world).players.insert(player_index, player);
         }
     }
 
@@ -6118,46 +7133,8 @@ impl Connection {
             update_tick: self.server_tick,
             input_actions: Vec::new(),
         };
-        let _ = sim.executor.execute_tick(&mut sim.world, &closure);
-    }
-}
-
-fn read_local_checksums() -> Option<(u32, u32)> {
-    let home = std::env::var("HOME").ok();
-    let mut paths = Vec::new();
-    if let Some(home) = home {
-        paths.push(format!("{}/Library/Application Support/factorio-server/factorio-current.log", home));
-        paths.push(format!("{}/Library/Application Support/factorio/factorio-current.log", home));
-    }
-    paths.push("/tmp/factorio-console.log".to_string());
-
-    for path in paths {
-        let file = match File::open(&path) {
-            Ok(file) => file,
-            Err(_) => continue,
-        };
-        let mut core_checksum = None;
-        let mut prototype_checksum = None;
-        for line in io::BufReader::new(file).lines().flatten() {
-            if core_checksum.is_none() {
-                core_checksum = parse_checksum(&line, "Checksum for core:");
-            }
-            if prototype_checksum.is_none() {
-                prototype_checksum = parse_checksum(&line, "Prototype list checksum:");
-            }
-            if core_checksum.is_some() && prototype_checksum.is_some() {
-                return Some((core_checksum.unwrap(), prototype_checksum.unwrap()));
-            }
-        }
+        let _ = sim.executor.execute_tick(Arc::make_mut(&mut sim.world), &closure);
     }
-
-    None
-}
-
-fn parse_checksum(line: &str, label: &str) -> Option<u32> {
-    let idx = line.find(label)?;
-    let rest = &line[idx + label.len()..];
-    rest.trim().split_whitespace().next()?.parse().ok()
 }
 
 fn default_factorio_data_path() -> Option<PathBuf> {
@@ -6200,9 +7177,744 @@ fn rand_u64() -> u64 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::protocol::profile::HandshakeChecksums;
+    use crate::protocol::mock_server::{mock_session, MockServerConfig, MOCK_SERVER_ADDR};
+    use crate::protocol::packet::MAX_PACKET_SIZE;
 
     #[test]
     fn test_connection_state() {
         assert_eq!(ConnectionState::Disconnected, ConnectionState::Disconnected);
     }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let policy = ReconnectPolicy {
+            max_attempts: Some(5),
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(3),
+            multiplier: 2.0,
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(500));
+        assert_eq!(policy.backoff(2), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(2));
+        assert_eq!(policy.backoff(4), Duration::from_secs(3));
+        assert_eq!(policy.backoff(10), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_pause_and_save_hold_actions() {
+        use crate::protocol::transport::ChannelSocket;
+
+        let addr: SocketAddr = "127.0.0.1:34197".parse().unwrap();
+        let (client, _server) = ChannelSocket::pair("127.0.0.1:40000".parse().unwrap(), addr);
+        let mut conn = Connection::with_transport(addr, Transport::with_socket(client), "bot".into(), Credentials::default());
+        conn.server_tick = 216_000;
+        conn.confirmed_tick = 216_000;
+        conn.client_tick = 216_004;
+        conn.pending_actions.push_back(InputAction::raw(vec![0x02]).into());
+
+        let closure_at = |tick| ServerHeartbeat {
+            flags: crate::protocol::message::DeserializationMask::SINGLE_TICK_CLOSURE,
+            sequence: 1,
+            tick_closures: vec![crate::protocol::heartbeat::ServerTickClosure {
+                tick,
+                actions: Vec::new(),
+                segments: Vec::new(),
+            }],
+            confirm_records: Vec::new(),
+            sync_actions: Vec::new(),
+            heartbeat_requests: Vec::new(),
+            last_player_index: 0xFFFF,
+        };
+
+        conn.apply_sync_action(0, &SynchronizerAction::BeginPause);
+        assert_eq!(conn.server_run_state(), ServerRunState::Paused);
+        assert!(conn.take_pending_action().is_none());
+        assert_eq!(conn.pending_action_count(), 1);
+        // Only EndPause ends a pause, whatever the closures say
+        conn.apply_server_heartbeat(closure_at(216_050));
+        assert_eq!(conn.server_run_state(), ServerRunState::Paused);
+
+        // The server moved on while we were held back: resume past its tick.
+        conn.server_tick = 216_100;
+        conn.confirmed_tick = 216_100;
+        conn.apply_sync_action(0, &SynchronizerAction::EndPause);
+        assert_eq!(conn.server_run_state(), ServerRunState::Running);
+        assert_eq!(conn.client_tick, 216_101);
+
+        // Saves end when closures for new ticks arrive.
+        conn.apply_sync_action(0, &SynchronizerAction::SavingCountdown { tick: 216_200, remaining: 60 });
+        assert_eq!(conn.autosave_tick(), Some(216_200));
+        conn.apply_sync_action(0, &SynchronizerAction::SavingForUpdate);
+        assert_eq!(conn.server_run_state(), ServerRunState::Saving);
+        assert!(conn.take_pending_action().is_none());
+        conn.apply_server_heartbeat(closure_at(216_102));
+        assert_eq!(conn.server_run_state(), ServerRunState::Running);
+        assert!(conn.take_pending_action().is_some());
+    }
+
+    #[test]
+    fn test_strict_heartbeat_keeps_partial_progress() {
+        use crate::protocol::transport::ChannelSocket;
+
+        let addr: SocketAddr = "127.0.0.1:34197".parse().unwrap();
+        let (client, _server) = ChannelSocket::pair("127.0.0.1:40000".parse().unwrap(), addr);
+        let mut conn = Connection::with_transport(addr, Transport::with_socket(client), "bot".into(), Credentials::default());
+        conn.server_tick = 216_000;
+        conn.confirmed_tick = 216_000;
+
+        let mut writer = BinaryWriter::new();
+        writer.write_u8(MessageType::ServerToClientHeartbeat as u8);
+        writer.write_u8(0x02);
+        writer.write_u32_le(9);
+        writer.write_opt_u32(2);
+        writer.write_u64_le(216_010);
+        writer.write_opt_u32(0);
+        writer.write_u64_le(216_011);
+        writer.write_opt_u32(2);
+        writer.write_opt_u16(1);
+        writer.write_opt_u16(crate::codec::InputActionType::StartWalking as u16); // direction missing
+        conn.process_server_heartbeat(&writer.into_vec()).unwrap();
+
+        assert_eq!(conn.heartbeat_parse_failures(), 1);
+        assert_eq!(conn.server_tick(), 216_011);
+        assert_eq!(conn.confirmed_tick(), 216_011);
+    }
+
+    #[test]
+    fn test_chat_messages_from_closures() {
+        use crate::codec::TickInputAction;
+        use crate::protocol::transport::ChannelSocket;
+
+        let addr: SocketAddr = "127.0.0.1:34197".parse().unwrap();
+        let (client, _server) = ChannelSocket::pair("127.0.0.1:40000".parse().unwrap(), addr);
+        let mut conn = Connection::with_transport(addr, Transport::with_socket(client), "bot".into(), Credentials::default());
+        conn.player_index = Some(1);
+        conn.other_players.insert(3, PlayerState {
+            player_index: 3,
+            username: Some("alice".into()),
+            ..Default::default()
+        });
+
+        let chat = |player_index, message: &str| TickInputAction {
+            player_index,
+            action: CodecInputAction::WriteToConsole { message: message.into() },
+        };
+        conn.apply_server_heartbeat(ServerHeartbeat {
+            flags: crate::protocol::message::DeserializationMask::SINGLE_TICK_CLOSURE,
+            sequence: 1,
+            tick_closures: vec![crate::protocol::heartbeat::ServerTickClosure {
+                tick: 500,
+                actions: vec![chat(3, "hi bot"), chat(0xFFFF, "server restart soon"), chat(1, "hello")],
+                segments: Vec::new(),
+            }],
+            confirm_records: Vec::new(),
+            sync_actions: Vec::new(),
+            heartbeat_requests: Vec::new(),
+            last_player_index: 1,
+        });
+
+        let chats: Vec<_> = conn
+            .drain_events()
+            .into_iter()
+            .filter_map(|e| match e {
+                GameEvent::ChatMessage { tick, player_id, username, message } => Some((tick, player_id, username, message)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(chats, vec![
+            (500, Some(3), Some("alice".to_string()), "hi bot".to_string()),
+            (500, None, None, "server restart soon".to_string()),
+            (500, Some(1), Some("bot".to_string()), "hello".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_roster_join_and_leave() {
+        use crate::codec::TickInputAction;
+        use crate::protocol::transport::ChannelSocket;
+
+        let addr: SocketAddr = "127.0.0.1:34197".parse().unwrap();
+        let (client, _server) = ChannelSocket::pair("127.0.0.1:40000".parse().unwrap(), addr);
+        let mut conn = Connection::with_transport(addr, Transport::with_socket(client), "bot".into(), Credentials::default());
+        conn.peer_id = Some(1);
+        conn.roster.add_peer(1, "bot");
+
+        conn.apply_sync_action(5, &SynchronizerAction::NewPeerInfo { peer_name: "alice".into() });
+        assert_eq!(conn.roster().count(), 2);
+        conn.apply_server_heartbeat(ServerHeartbeat {
+            flags: crate::protocol::message::DeserializationMask::SINGLE_TICK_CLOSURE,
+            sequence: 1,
+            tick_closures: vec![crate::protocol::heartbeat::ServerTickClosure {
+                tick: 100,
+                actions: vec![TickInputAction {
+                    player_index: 3,
+                    action: CodecInputAction::PlayerJoinGame {
+                        peer_id: 5,
+                        player_index_plus_one: 4,
+                        mode: 0,
+                        username: "alice".into(),
+                        flag_a: false,
+                        flag_b: false,
+                    },
+                }],
+                segments: Vec::new(),
+            }],
+            confirm_records: Vec::new(),
+            sync_actions: Vec::new(),
+            heartbeat_requests: Vec::new(),
+            last_player_index: 3,
+        });
+        assert_eq!(conn.roster_player(3).map(|e| e.peer_id), Some(5));
+        assert_eq!(conn.player_username(3).as_deref(), Some("alice"));
+        assert!(conn.other_players().contains_key(&3));
+
+        conn.apply_sync_action(5, &SynchronizerAction::PeerDisconnect { disconnect_type: 3 });
+        conn.apply_sync_action(5, &SynchronizerAction::PeerDisconnect { disconnect_type: 3 });
+        assert!(conn.roster_player(3).is_none());
+        assert!(!conn.other_players().contains_key(&3));
+
+        let events: Vec<_> = conn
+            .drain_events()
+            .into_iter()
+            .filter(|e| matches!(e, GameEvent::PlayerJoined { .. } | GameEvent::PlayerLeft { .. }))
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], GameEvent::PlayerJoined { player_id: 3, name } if name == "alice"));
+        assert!(matches!(&events[1], GameEvent::PlayerLeft { player_id: 3, reason: DisconnectReason::Timeout }));
+    }
+
+    #[test]
+    fn test_roster_links_players_already_in_game() {
+        use crate::protocol::transport::ChannelSocket;
+
+        let addr: SocketAddr = "127.0.0.1:34197".parse().unwrap();
+        let (client, _server) = ChannelSocket::pair("127.0.0.1:40000".parse().unwrap(), addr);
+        let mut conn = Connection::with_transport(addr, Transport::with_socket(client), "bot".into(), Credentials::default());
+        conn.peer_id = Some(1);
+        conn.roster.add_peer(0, "server");
+        conn.roster.add_peer(1, "bot");
+        conn.roster.add_peer(4, "alice");
+
+        let mut map = MapData::parse(&[]).unwrap();
+        map.players = vec!["alice".into(), "bot".into()];
+        conn.apply_parsed_map(map);
+        assert_eq!(conn.roster_player(0).map(|e| e.peer_id), Some(4));
+        assert_eq!(conn.player_username(0).as_deref(), Some("alice"));
+
+        conn.apply_sync_action(4, &SynchronizerAction::PeerDisconnect { disconnect_type: 3 });
+        assert!(conn.roster_player(0).is_none());
+        let events = conn.drain_events();
+        assert!(
+            events.iter().any(|e| matches!(e, GameEvent::PlayerLeft { player_id: 0, reason: DisconnectReason::Timeout })),
+            "{:?}",
+            events
+        );
+    }
+
+    /// Server side of a handshake that ends in a deny: (info reply, request reply, deny)
+    fn denied_handshake(status: u8) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let mods = VersionProfile::FACTORIO_2_0_72.default_mods(ApplicationVersion::FACTORIO_2_0_72).unwrap();
+        denied_handshake_with_mods(status, mods)
+    }
+
+    fn denied_handshake_with_mods(status: u8, mods: Vec<ModInfo>) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        use crate::protocol::message::ServerInfo;
+
+        let version = ApplicationVersion::FACTORIO_2_0_72;
+        let info = ServerInfo {
+            name: "scripted".into(),
+            description: String::new(),
+            version,
+            max_players: 0,
+            has_password: true,
+            mods,
+            players: Vec::new(),
+            tick: 0,
+            partial: false,
+        };
+        let mut info_reply = BinaryWriter::new();
+        info_reply.write_u8(MessageType::GameInformationRequestReply as u8);
+        info.write(&mut info_reply);
+
+        let reply = connection_reply(version);
+
+        let mut deny = BinaryWriter::new();
+        deny.write_u8(MessageType::ConnectionAcceptOrDeny as u8);
+        deny.write_u32_le(1);
+        deny.write_u8(status);
+
+        (info_reply.into_vec(), reply, deny.into_vec())
+    }
+
+    fn connection_reply(version: ApplicationVersion) -> Vec<u8> {
+        let mut reply = BinaryWriter::new();
+        reply.write_u8(MessageType::ConnectionRequestReply as u8);
+        version.write(&mut reply);
+        reply.write_u32_le(1);
+        reply.write_u32_le(2);
+        reply.write_u16_le(1500);
+        reply.into_vec()
+    }
+
+    #[tokio::test]
+    async fn test_replay_version_still_differs_after_retry() {
+        use crate::protocol::capture::{CaptureRecord, Direction};
+
+        let (info_reply, _, _) = denied_handshake(0);
+        let patch = |patch| ApplicationVersion { patch, ..ApplicationVersion::FACTORIO_2_0_72 };
+        let record = |direction, data: Vec<u8>| CaptureRecord { at: Duration::ZERO, direction, data };
+        let capture = Capture {
+            records: vec![
+                record(Direction::Outbound, vec![0x10]),
+                record(Direction::Outbound, vec![0x30]),
+                record(Direction::Inbound, info_reply),
+                record(Direction::Outbound, Vec::new()), // ConnectionRequest
+                record(Direction::Inbound, connection_reply(patch(73))),
+                record(Direction::Outbound, Vec::new()), // ConnectionRequest again, as 2.0.73
+                record(Direction::Inbound, connection_reply(patch(74))),
+            ],
+        };
+
+        let mut conn = Connection::new_replay(&capture, "replayer".into());
+        match conn.connect().await {
+            Err(Error::VersionMismatch { server, client }) => {
+                assert_eq!(server, patch(74));
+                assert_eq!(client, patch(73));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_handshake_denied() {
+        use crate::protocol::capture::{CaptureRecord, Direction};
+
+        let (info_reply, reply, deny) = denied_handshake(7); // wrong password
+        let record = |direction, data: Vec<u8>| CaptureRecord { at: Duration::ZERO, direction, data };
+        let capture = Capture {
+            records: vec![
+                record(Direction::Outbound, vec![0x10]),
+                record(Direction::Outbound, vec![0x30]),
+                record(Direction::Inbound, info_reply),
+                record(Direction::Outbound, Vec::new()), // ConnectionRequest
+                record(Direction::Inbound, reply),
+                record(Direction::Outbound, Vec::new()), // ConnectionRequestReplyConfirm
+                record(Direction::Inbound, deny),
+            ],
+        };
+
+        let mut conn = Connection::new_replay(&capture, "replayer".into());
+        let result = conn.connect().await;
+        assert!(
+            matches!(result, Err(Error::Denied { reason: DenialReason::Status(7), password_sent: false })),
+            "{:?}",
+            result.err()
+        );
+        assert_eq!(conn.server_request_id, Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_replay_mod_mismatch_denied() {
+        use crate::protocol::capture::{CaptureRecord, Direction};
+        use crate::protocol::message::ModVersion;
+
+        let mod_info = |name: &str, version| ModInfo { name: name.into(), version, crc: 0 };
+        let base = mod_info("base", ModVersion::new(2, 0, 72));
+        let quality = mod_info("quality", ModVersion::new(2, 0, 72));
+        let rebuilt = ModInfo { crc: 0x1234, ..quality.clone() };
+        let advertised = vec![base.clone(), mod_info("space-age", ModVersion::new(2, 0, 72)), quality];
+        let (info_reply, reply, deny) = denied_handshake_with_mods(1, advertised);
+        let record = |direction, data: Vec<u8>| CaptureRecord { at: Duration::ZERO, direction, data };
+        let capture = Capture {
+            records: vec![
+                record(Direction::Outbound, vec![0x10]),
+                record(Direction::Outbound, vec![0x30]),
+                record(Direction::Inbound, info_reply),
+                record(Direction::Outbound, Vec::new()), // ConnectionRequest
+                record(Direction::Inbound, reply),
+                record(Direction::Outbound, Vec::new()), // ConnectionRequestReplyConfirm
+                record(Direction::Inbound, deny),
+            ],
+        };
+
+        let mut conn = Connection::new_replay(&capture, "replayer".into());
+        conn.set_handshake_config(HandshakeConfig {
+            mods: Some(vec![base, mod_info("my-mod", ModVersion::new(1, 0, 0)), rebuilt]),
+            checksums: Some(HandshakeChecksums::FACTORIO_2_0_72_SPACE_AGE),
+            ..HandshakeConfig::default()
+        });
+        let err = conn.connect().await.unwrap_err();
+        assert_eq!(err.code(), "mod_mismatch");
+        match err {
+            Error::ModMismatch { missing, extra } => {
+                assert_eq!(missing, vec!["space-age 2.0.72".to_string(), "quality 2.0.72 (crc 0x00000000)".to_string()]);
+                assert_eq!(extra, vec!["my-mod 1.0.0".to_string(), "quality 2.0.72 (crc 0x00001234)".to_string()]);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_channel_handshake_denied() {
+        use crate::protocol::transport::ChannelSocket;
+
+        let addr: SocketAddr = "127.0.0.1:34197".parse().unwrap();
+        let (client, server) = ChannelSocket::pair("127.0.0.1:40000".parse().unwrap(), addr);
+        let mut server = Transport::with_socket(server);
+        let peer = tokio::spawn(async move {
+            let (info_reply, reply, deny) = denied_handshake(6); // password required
+            let mut seen = Vec::new();
+            while let Ok(Some(data)) = server.recv_raw_timeout(Duration::from_secs(5)).await {
+                let msg_type = data[0] & 0x1F;
+                seen.push(msg_type);
+                let response = match MessageType::from_u8(msg_type) {
+                    Some(MessageType::GameInformationRequest) if seen.len() == 1 => &info_reply,
+                    Some(MessageType::ConnectionRequest) => &reply,
+                    Some(MessageType::ConnectionRequestReplyConfirm) => &deny,
+                    _ => continue,
+                };
+                server.send_raw(response).await.unwrap();
+            }
+            seen
+        });
+
+        let mut conn = Connection::with_transport(addr, Transport::with_socket(client), "scripted".into(), Credentials::default());
+        let result = conn.connect().await;
+        assert!(matches!(result, Err(Error::Denied { reason: DenialReason::Status(6), .. })), "{:?}", result.err());
+        drop(conn);
+
+        let seen = peer.await.unwrap();
+        assert!(seen.contains(&(MessageType::ConnectionRequestReplyConfirm as u8)));
+    }
+
+    #[tokio::test]
+    async fn test_mock_server_join() {
+        let map: Vec<u8> = (0..4000u32).map(|i| (i * 7) as u8).collect();
+        let (client, server) = mock_session(MockServerConfig { map: map.clone(), ..MockServerConfig::default() });
+
+        let mut conn = Connection::with_transport(MOCK_SERVER_ADDR, Transport::with_socket(client), "mock".into(), Credentials::default());
+        conn.connect().await.unwrap();
+        let size = conn.download_map_with_parse(false).await.unwrap();
+        assert_eq!(size, map.len());
+        assert_eq!(conn.map_data(), &map[..]);
+        assert_eq!(conn.state(), ConnectionState::InGame);
+        assert_eq!(conn.player_index(), Some(0));
+        assert_eq!(conn.heartbeat_parse_failures(), 0, "{:?}", conn.last_heartbeat_error());
+        assert!(conn.roster().any(|e| e.username == "mock"), "{:?}", conn.roster().collect::<Vec<_>>());
+        let started = std::time::Instant::now();
+        while conn.network_stats().replies == 0 {
+            conn.poll().await.unwrap();
+            assert!(started.elapsed() < Duration::from_secs(5), "no ping reply");
+        }
+        assert!(conn.network_stats().rtt.is_some());
+        let events = conn.drain_events();
+        assert!(events.iter().any(|e| matches!(e, GameEvent::MapDownloadComplete)));
+        assert!(events.iter().any(|e| matches!(
+            e,
+            GameEvent::SyncAction { action: SynchronizerAction::MapReadyForDownload(info), .. } if info.transfer_size == 4000
+        )));
+
+        drop(conn);
+        assert!(server.await.unwrap().is_err());
+    }
+
+    /// Opens a channel to a new mock server on every connect
+    struct MockFactory {
+        map: Vec<u8>,
+        opened: std::sync::atomic::AtomicUsize,
+        /// `MockServerConfig::desync_after` for the first server only
+        desync_first_after: Option<u32>,
+    }
+
+    #[async_trait::async_trait]
+    impl TransportFactory for MockFactory {
+        async fn open(&self, addr: SocketAddr) -> Result<Transport> {
+            assert_eq!(addr, MOCK_SERVER_ADDR);
+            let first = self.opened.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0;
+            let (client, _server) = mock_session(MockServerConfig {
+                map: self.map.clone(),
+                desync_after: self.desync_first_after.filter(|_| first),
+                ..MockServerConfig::default()
+            });
+            Ok(Transport::with_socket(client))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_runs_beside_poll() {
+        let addr = MOCK_SERVER_ADDR;
+        let factory = Arc::new(MockFactory { map: vec![3; 4000], opened: Default::default(), desync_first_after: None });
+        let transport = factory.open(addr).await.unwrap();
+        let mut conn = Connection::with_transport(addr, transport, "mock".into(), Credentials::default());
+        conn.set_transport_factory(factory.clone());
+        conn.set_reconnect_policy(Some(ReconnectPolicy {
+            max_attempts: Some(3),
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(10),
+            multiplier: 1.0,
+        }));
+        conn.connect().await.unwrap();
+        conn.download_map_with_parse(false).await.unwrap();
+        conn.drain_events();
+
+        conn.mark_disconnected("dropped by test");
+        assert!(conn.is_reconnecting());
+        let started = std::time::Instant::now();
+        while conn.state() != ConnectionState::InGame {
+            let poll_started = std::time::Instant::now();
+            conn.poll().await.unwrap();
+            assert!(poll_started.elapsed() < Duration::from_millis(500), "poll waited for the reconnect");
+            assert!(started.elapsed() < Duration::from_secs(10), "no reconnect");
+        }
+        assert!(!conn.is_reconnecting());
+        assert_eq!(factory.opened.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert_eq!(conn.map_data(), &[3; 4000][..]);
+        assert!(conn
+            .drain_events()
+            .iter()
+            .any(|e| matches!(e, GameEvent::Reconnected { attempts: 1, .. })));
+    }
+
+    #[tokio::test]
+    async fn test_rejoin_after_server_reported_desync() {
+        let addr = MOCK_SERVER_ADDR;
+        let factory = Arc::new(MockFactory { map: vec![5; 4000], opened: Default::default(), desync_first_after: Some(20) });
+        let transport = factory.open(addr).await.unwrap();
+        let mut conn = Connection::with_transport(addr, transport, "mock".into(), Credentials::default());
+        conn.set_transport_factory(factory.clone());
+        conn.set_rejoin_on_desync(true);
+        conn.connect().await.unwrap();
+        conn.download_map_with_parse(false).await.unwrap();
+
+        let started = std::time::Instant::now();
+        let mut events = Vec::new();
+        while !events.iter().any(|e| matches!(e, GameEvent::Reconnected { .. })) {
+            conn.poll().await.unwrap();
+            events.extend(conn.drain_events());
+            assert!(started.elapsed() < Duration::from_secs(10), "no rejoin after the desync");
+        }
+        assert!(events.iter().any(|e| matches!(e, GameEvent::Desync { .. })));
+        assert_eq!(conn.last_desync_report().unwrap().cause, DesyncCause::ServerReported);
+        assert_eq!(conn.state(), ConnectionState::InGame);
+        assert!(!conn.is_desynced());
+        assert_eq!(factory.opened.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failed_reconnect_keeps_partial_download() {
+        use crate::protocol::transport::ChannelSocket;
+
+        /// Transports whose server end is already gone
+        struct DeadFactory;
+
+        #[async_trait::async_trait]
+        impl TransportFactory for DeadFactory {
+            async fn open(&self, addr: SocketAddr) -> Result<Transport> {
+                let (client, _) = ChannelSocket::pair("127.0.0.1:40000".parse().unwrap(), addr);
+                Ok(Transport::with_socket(client))
+            }
+        }
+
+        let addr: SocketAddr = "127.0.0.1:34197".parse().unwrap();
+        let mut conn = Connection::with_transport(addr, DeadFactory.open(addr).await.unwrap(), "mock".into(), Credentials::default());
+        conn.set_transport_factory(Arc::new(DeadFactory));
+        conn.partial_download = Some(BlockDownloader::new(4000, Some(7), std::time::Instant::now()));
+        assert!(conn.reconnect().await.is_err());
+        assert!(conn.partial_download.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_server_version() {
+        let version = ApplicationVersion { minor: 1, patch: 0, ..ApplicationVersion::FACTORIO_2_0_72 };
+        let (client, server) = mock_session(MockServerConfig { version, ..MockServerConfig::default() });
+
+        let mut conn = Connection::with_transport(MOCK_SERVER_ADDR, Transport::with_socket(client), "future".into(), Credentials::default());
+        match conn.connect().await {
+            Err(Error::UnsupportedVersion(v)) => assert_eq!(v, version.to_string()),
+            other => panic!("unexpected {:?}", other),
+        }
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_other_patch_joins_and_refuses_foreign_map() {
+        use std::io::Write;
+
+        // A save whose level.dat starts with a 2.1 map version
+        let mut level_dat = Vec::new();
+        for part in [2u16, 1, 0, 10] {
+            level_dat.extend_from_slice(&part.to_le_bytes());
+        }
+        level_dat.resize(5000, 0);
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        writer.start_file("save/level.dat0", options).unwrap();
+        writer.write_all(&level_dat).unwrap();
+        let map = writer.finish().unwrap().into_inner();
+
+        let version = ApplicationVersion { patch: 99, ..ApplicationVersion::FACTORIO_2_0_72 };
+        let (client, server) = mock_session(MockServerConfig { version, map, ..MockServerConfig::default() });
+
+        let mut conn = Connection::with_transport(MOCK_SERVER_ADDR, Transport::with_socket(client), "patch".into(), Credentials::default());
+        // 2.0.72's checksums are not sent for another build
+        match conn.connect().await {
+            Err(Error::UnsupportedVersion(v)) => assert!(v.starts_with("2.0.99 (build 84292) (checksums unknown"), "{}", v),
+            other => panic!("unexpected {:?}", other),
+        }
+        let checksums = HandshakeChecksums { core: 1, prototype_list: 2 };
+        conn.set_handshake_config(HandshakeConfig { checksums: Some(checksums), ..HandshakeConfig::default() });
+        conn.connect().await.unwrap();
+        assert_eq!(conn.version_profile().name, "2.0");
+        match conn.download_map_with_parse(true).await {
+            Err(Error::UnsupportedVersion(v)) => assert_eq!(v, "2.1.0 (map format)"),
+            other => panic!("unexpected {:?}", other),
+        }
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_queue_action_receipt() {
+        let (client, server) = mock_session(MockServerConfig { map: vec![0; 1000], ..MockServerConfig::default() });
+
+        let mut conn = Connection::with_transport(MOCK_SERVER_ADDR, Transport::with_socket(client), "receipts".into(), Credentials::default());
+        assert!(conn.queue_action(CodecInputAction::StopWalking).is_err());
+        conn.connect().await.unwrap();
+        conn.download_map_with_parse(false).await.unwrap();
+
+        let mut receipt = conn.queue_action(CodecInputAction::Craft { recipe_id: 7, count: 2 }).unwrap();
+        assert_eq!(receipt.action_type(), crate::codec::InputActionType::Craft);
+        let started = std::time::Instant::now();
+        let outcome = loop {
+            conn.poll().await.unwrap();
+            if let Some(outcome) = receipt.try_outcome() {
+                break outcome;
+            }
+            assert!(started.elapsed() < Duration::from_secs(5), "no echo");
+        };
+        match outcome {
+            ActionOutcome::Executed { tick } => assert!(tick > 0 && tick <= conn.server_tick()),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(conn.unconfirmed_action_count(), 0);
+
+        // Never sent before the connection went away
+        let mut unsent = conn.queue_action(CodecInputAction::StopWalking).unwrap();
+        drop(conn);
+        assert_eq!(unsent.try_outcome(), Some(ActionOutcome::Dropped));
+        server.abort();
+    }
+
+    /// Drops the first heartbeat carrying an action segment, and fails on any
+    /// datagram over `MAX_PACKET_SIZE`
+    struct DropOneSegment<S> {
+        inner: S,
+        dropped: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl<S: crate::protocol::transport::DatagramSocket> crate::protocol::transport::DatagramSocket for DropOneSegment<S> {
+        async fn send(&mut self, data: &[u8]) -> Result<()> {
+            assert!(data.len() <= MAX_PACKET_SIZE, "{} byte datagram", data.len());
+            if !self.dropped && data.len() > MAX_SEGMENT_DATA {
+                self.dropped = true;
+                return Ok(());
+            }
+            self.inner.send(data).await
+        }
+
+        async fn recv(&mut self) -> Result<Vec<u8>> {
+            self.inner.recv().await
+        }
+
+        fn try_recv(&mut self) -> Result<Option<Vec<u8>>> {
+            self.inner.try_recv()
+        }
+
+        fn local_addr(&self) -> Result<SocketAddr> {
+            self.inner.local_addr()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_large_action_segmented_and_resent() {
+        let (client, server) = mock_session(MockServerConfig { map: vec![0; 1000], ..MockServerConfig::default() });
+
+        let client = DropOneSegment { inner: client, dropped: false };
+        let mut conn = Connection::with_transport(MOCK_SERVER_ADDR, Transport::with_socket(client), "segments".into(), Credentials::default());
+        conn.connect().await.unwrap();
+        conn.download_map_with_parse(false).await.unwrap();
+
+        let blueprint_string: String = (0..6000u32).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+        let mut receipt = conn
+            .queue_action(CodecInputAction::ImportBlueprintString { blueprint_string, flags: 0, mode: 0 })
+            .unwrap();
+        let started = std::time::Instant::now();
+        let outcome = loop {
+            conn.poll().await.unwrap();
+            if let Some(outcome) = receipt.try_outcome() {
+                break outcome;
+            }
+            assert!(started.elapsed() < Duration::from_secs(5), "no echo");
+        };
+        assert!(matches!(outcome, ActionOutcome::Executed { .. }), "unexpected {:?}", outcome);
+        assert!(conn.heartbeat_resends() > 0);
+
+        drop(conn);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_mock_server_join_degraded_network() {
+        use crate::protocol::netsim::{NetworkConditions, SimulatedSocket};
+
+        let map: Vec<u8> = (0..20_000u32).map(|i| (i * 13) as u8).collect();
+        let (client, server) = mock_session(MockServerConfig { map: map.clone(), ..MockServerConfig::default() });
+
+        let conditions = NetworkConditions {
+            duplicate: 0.1,
+            reorder: 0.1,
+            latency: Duration::from_millis(5),
+            jitter: Duration::from_millis(5),
+            seed: 0xfac7,
+            ..NetworkConditions::default()
+        };
+        // Downlink-only loss: lost TransferBlocks have to be re-requested.
+        let downlink = NetworkConditions { loss: 0.05, ..conditions.clone() };
+        let client = SimulatedSocket::asymmetric(client, conditions, downlink);
+        let mut conn = Connection::with_transport(MOCK_SERVER_ADDR, Transport::with_socket(client), "lossy".into(), Credentials::default());
+        conn.connect().await.unwrap();
+        conn.download_map_with_parse(false).await.unwrap();
+        assert_eq!(conn.map_data(), &map[..]);
+        assert_eq!(conn.state(), ConnectionState::InGame);
+
+        drop(conn);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_tick_lead_grows_with_latency() {
+        use crate::protocol::netsim::{NetworkConditions, SimulatedSocket};
+
+        let (client, server) = mock_session(MockServerConfig { map: vec![0x5A; 1200], ..MockServerConfig::default() });
+
+        // 600ms round trips, well past the 32 tick (~533ms) floor
+        let conditions = NetworkConditions { latency: Duration::from_millis(300), seed: 7, ..NetworkConditions::default() };
+        let client = SimulatedSocket::new(client, conditions);
+        let mut conn = Connection::with_transport(MOCK_SERVER_ADDR, Transport::with_socket(client), "slow".into(), Credentials::default());
+        conn.connect().await.unwrap();
+        conn.download_map_with_parse(false).await.unwrap();
+        assert_eq!(conn.desired_tick_lead(), CLIENT_TICK_LEAD_MIN);
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while conn.network_stats().rtt.is_none() && std::time::Instant::now() < deadline {
+            conn.poll().await.unwrap();
+        }
+        let rtt_ticks = conn.network_stats().rtt_ticks().expect("a ping was answered");
+        assert!(rtt_ticks > CLIENT_TICK_LEAD_MIN, "rtt {} ticks", rtt_ticks);
+        assert_eq!(conn.desired_tick_lead(), rtt_ticks);
+
+        drop(conn);
+        server.abort();
+    }
 }
@@ -22,25 +22,18 @@ impl<'a> ConnectionActions<'a> {
             return Err(Error::InvalidPacket("must be in game to send chat".into()));
         }
 
+        // Chat always goes out segmented, even when it fits in one segment
         let player_index = self.conn.player_index.unwrap_or(1);
-        let mut payload_writer = BinaryWriter::with_capacity(3 + message.len() + 5);
-        payload_writer.write_opt_u16(player_index);
-        payload_writer.write_string(message);
-        let payload = payload_writer.into_vec();
-
-        let mut writer = BinaryWriter::with_capacity(32 + payload.len());
-        writer.write_opt_u32(0x01);
-        writer.write_opt_u32(0x01);
-        writer.write_opt_u16(0x68);
-        writer.write_u32_le(0);
+        let mut writer = BinaryWriter::with_capacity(3 + message.len() + 5);
         writer.write_opt_u16(player_index);
-        writer.write_opt_u32(1);
-        writer.write_opt_u32(0);
-        writer.write_opt_u32(payload.len() as u32);
-        writer.write_bytes(&payload);
-        let data = writer.into_vec();
-
-        self.conn.send_action_packet(0x06, &data).await
+        writer.write_string(message);
+        let closures: Vec<InputAction> = self
+            .conn
+            .segment_closures(0x68, player_index, &writer.into_vec())
+            .into_iter()
+            .map(InputAction::raw)
+            .collect();
+        self.conn.send_heartbeat_with_actions(&closures).await
     }
 
     /// Execute a server command (e.g. "/c ...")
@@ -0,0 +1,230 @@
+//! Desync detection and diagnostics
+//!
+//! The server tells a desynced client so with a `PlayerDesynced` synchronizer
+//! action for its peer; that is the only trigger. The CRCs the server reports
+//! for past ticks are kept for the report but not compared: the local checksum
+//! only hashes a simplified world and could never match them.
+//!
+//! `DesyncMonitor` keeps the recent history needed to make sense of a desync
+//! afterwards: tick closures, CRCs on both sides and the client tick lead.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::protocol::heartbeat::ConfirmRecord;
+use crate::simulation::ChecksumCalculator;
+use crate::simulation::tick::TickClosureData;
+use crate::state::GameWorld;
+
+/// Tick closures kept for the report (about 10 seconds of game time)
+const CLOSURE_HISTORY: usize = 600;
+const CRC_HISTORY: usize = 600;
+const TICK_LEAD_HISTORY: usize = 300;
+
+/// Why the connection considers itself desynced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DesyncCause {
+    /// The server sent PlayerDesynced for our peer
+    ServerReported,
+}
+
+impl DesyncCause {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ServerReported => "server-reported",
+        }
+    }
+}
+
+/// Diagnostics gathered when a desync was detected
+#[derive(Debug, Clone)]
+pub struct DesyncReport {
+    pub tick: u32,
+    pub cause: DesyncCause,
+    /// Local checksum for `tick`, or the latest one (0 without a simulation)
+    pub local_crc: u32,
+    /// Server CRC for `tick`, or the latest one reported
+    pub server_crc: Option<u32>,
+    /// Recent tick closures, CRCs, tick-lead history and a world snapshot
+    pub bundle: Value,
+    /// Where the bundle was written, if a report directory is set
+    pub path: Option<PathBuf>,
+}
+
+/// Client tick lead at one server heartbeat
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TickLeadSample {
+    pub server_tick: u32,
+    pub confirmed_tick: u32,
+    pub client_tick: u32,
+    pub lead: u32,
+}
+
+#[derive(Debug)]
+struct ClosureSummary {
+    tick: u32,
+    actions: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct DesyncMonitor {
+    closures: VecDeque<ClosureSummary>,
+    /// (tick, crc) from the local simulation
+    local_crcs: VecDeque<(u32, u32)>,
+    server_crcs: VecDeque<ConfirmRecord>,
+    tick_leads: VecDeque<TickLeadSample>,
+}
+
+fn push_bounded<T>(queue: &mut VecDeque<T>, item: T, max: usize) {
+    if queue.len() >= max {
+        queue.pop_front();
+    }
+    queue.push_back(item);
+}
+
+impl DesyncMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_closure(&mut self, closure: &TickClosureData) {
+        let actions = closure
+            .input_actions
+            .iter()
+            .map(|a| format!("{}: {:?}", a.player_index, a.action))
+            .collect();
+        push_bounded(&mut self.closures, ClosureSummary { tick: closure.update_tick, actions }, CLOSURE_HISTORY);
+    }
+
+    pub fn record_local_crc(&mut self, tick: u32, crc: u32) {
+        push_bounded(&mut self.local_crcs, (tick, crc), CRC_HISTORY);
+    }
+
+    pub fn record_tick_lead(&mut self, sample: TickLeadSample) {
+        push_bounded(&mut self.tick_leads, sample, TICK_LEAD_HISTORY);
+    }
+
+    pub fn record_server_crc(&mut self, record: ConfirmRecord) {
+        push_bounded(&mut self.server_crcs, record, CRC_HISTORY);
+    }
+
+    fn local_crc(&self, tick: u32) -> Option<u32> {
+        self.local_crcs.iter().rev().find(|&&(t, _)| t == tick).map(|&(_, crc)| crc)
+    }
+
+    fn server_crc(&self, tick: u32) -> Option<u32> {
+        self.server_crcs.iter().rev().find(|r| r.tick == tick).map(|r| r.crc)
+    }
+
+    /// Build the report for a desync at `tick`.
+    pub fn report(&self, tick: u32, cause: DesyncCause, world: Option<&GameWorld>) -> DesyncReport {
+        let local_crc = self
+            .local_crc(tick)
+            .or_else(|| self.local_crcs.back().map(|&(_, crc)| crc))
+            .unwrap_or(0);
+        let server_crc = self.server_crc(tick).or_else(|| self.server_crcs.back().map(|r| r.crc));
+        let bundle = json!({
+            "tick": tick,
+            "cause": cause.as_str(),
+            "local_crc": local_crc,
+            "server_crc": server_crc,
+            "tick_closures": self.closures.iter().map(|c| json!({
+                "tick": c.tick,
+                "actions": c.actions,
+            })).collect::<Vec<_>>(),
+            "local_crcs": self.local_crcs.iter().map(|&(tick, crc)| json!({
+                "tick": tick,
+                "crc": crc,
+            })).collect::<Vec<_>>(),
+            "server_crcs": self.server_crcs.iter().map(|r| json!({
+                "tick": r.tick,
+                "crc": r.crc,
+                "kind": r.kind,
+                "flag": r.flag,
+            })).collect::<Vec<_>>(),
+            "tick_leads": self.tick_leads.iter().map(|s| json!({
+                "server_tick": s.server_tick,
+                "confirmed_tick": s.confirmed_tick,
+                "client_tick": s.client_tick,
+                "lead": s.lead,
+            })).collect::<Vec<_>>(),
+            "world": world.map(world_snapshot),
+        });
+        DesyncReport { tick, cause, local_crc, server_crc, bundle, path: None }
+    }
+}
+
+/// What the local simulation believed: enough to compare against the server's
+/// view, not a full save.
+fn world_snapshot(world: &GameWorld) -> Value {
+    let mut players: Vec<_> = world.players.values().collect();
+    players.sort_by_key(|p| p.id);
+    let mut surfaces: Vec<_> = world.surfaces.values().collect();
+    surfaces.sort_by_key(|s| s.id);
+    json!({
+        "tick": world.tick,
+        "seed": world.seed,
+        "checksum": ChecksumCalculator::calculate_world_checksum(world),
+        "players": players.iter().map(|p| {
+            let (x, y) = p.position.to_tiles();
+            json!({
+                "id": p.id,
+                "name": p.name,
+                "connected": p.connected,
+                "position": [x, y],
+                "walking": p.walking,
+                "crafting_queue_size": p.crafting_queue_size,
+            })
+        }).collect::<Vec<_>>(),
+        "surfaces": surfaces.iter().map(|s| json!({
+            "id": s.id,
+            "name": s.name,
+            "entities": s.entities.len(),
+            "chunks": s.chunks.len(),
+        })).collect::<Vec<_>>(),
+    })
+}
+
+/// Write the bundle as `desync-<tick>-<unix seconds>.json` under `dir`.
+pub(crate) fn write_report(report: &DesyncReport, dir: &Path) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let path = dir.join(format!("desync-{}-{}.json", report.tick, secs));
+    let json = serde_json::to_vec_pretty(&report.bundle).map_err(std::io::Error::other)?;
+    std::fs::write(&path, json)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::InputAction;
+    use crate::simulation::tick::TickAction;
+
+    #[test]
+    fn test_report() {
+        let mut monitor = DesyncMonitor::new();
+        monitor.record_closure(&TickClosureData {
+            update_tick: 100,
+            input_actions: vec![TickAction { player_index: 1, action: InputAction::StopWalking }],
+        });
+        monitor.record_local_crc(100, 0xaaaa);
+        monitor.record_tick_lead(TickLeadSample { server_tick: 100, confirmed_tick: 98, client_tick: 132, lead: 32 });
+
+        let record = ConfirmRecord { kind: 2, flag: 0, crc: 0xbbbb, tick: 100 };
+        monitor.record_server_crc(record);
+        monitor.record_server_crc(ConfirmRecord { crc: 0xcccc, tick: 101, ..record });
+
+        let report = monitor.report(100, DesyncCause::ServerReported, Some(&GameWorld::new()));
+        assert_eq!(report.local_crc, 0xaaaa);
+        assert_eq!(report.server_crc, Some(0xbbbb));
+        assert_eq!(report.bundle["cause"], "server-reported");
+        assert_eq!(report.bundle["tick_closures"][0]["actions"][0], "1: StopWalking");
+        assert_eq!(report.bundle["tick_leads"][0]["lead"], 32);
+        assert_eq!(report.bundle["server_crcs"].as_array().unwrap().len(), 2);
+        assert_eq!(report.bundle["world"]["tick"], 0);
+    }
+}
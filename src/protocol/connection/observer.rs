@@ -0,0 +1,163 @@
+//! Read-only connections for recording, dashboards and replay capture
+//!
+//! An `ObserverConnection` joins like any other client and keeps the heartbeat,
+//! tick confirmation and ping traffic going, but skips the init action and can
+//! never send an input action. The guarantee is the wrapper's API: it only
+//! hands out `&Connection` (through `Deref`), so `actions()`, `queue_action()`
+//! and the other senders that need `&mut Connection` are out of reach. The
+//! wrapped connection is flagged as an observer as well and refuses actions
+//! with `Error::ObserverReadOnly` should one get queued some other way.
+
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::client::events::GameEvent;
+use crate::error::Result;
+use crate::protocol::capture::{Capture, Recorder};
+use crate::protocol::message::Credentials;
+use crate::protocol::profile::HandshakeConfig;
+use crate::protocol::transport::Transport;
+use super::{Connection, HeartbeatParsing, ReceivedPacket, ReconnectPolicy};
+
+/// A connection that watches the game without taking part in it
+pub struct ObserverConnection {
+    conn: Connection,
+}
+
+impl ObserverConnection {
+    pub async fn new(addr: SocketAddr, username: String) -> Result<Self> {
+        Ok(Self::wrap(Connection::new(addr, username).await?))
+    }
+
+    pub async fn new_with_credentials(addr: SocketAddr, username: String, credentials: Credentials) -> Result<Self> {
+        Ok(Self::wrap(Connection::new_with_credentials(addr, username, credentials).await?))
+    }
+
+    /// Observe a recorded capture instead of a live server.
+    pub fn new_replay(capture: &Capture, username: String) -> Self {
+        Self::wrap(Connection::new_replay(capture, username))
+    }
+
+    pub fn with_transport(addr: SocketAddr, transport: Transport, username: String, credentials: Credentials) -> Self {
+        Self::wrap(Connection::with_transport(addr, transport, username, credentials))
+    }
+
+    fn wrap(mut conn: Connection) -> Self {
+        conn.observer = true;
+        Self { conn }
+    }
+
+    pub fn set_recorder(&mut self, recorder: Option<Recorder>) {
+        self.conn.set_recorder(recorder);
+    }
+
+    pub fn set_handshake_config(&mut self, config: HandshakeConfig) {
+        self.conn.set_handshake_config(config);
+    }
+
+    pub fn set_reconnect_policy(&mut self, policy: Option<ReconnectPolicy>) {
+        self.conn.set_reconnect_policy(policy);
+    }
+
+    pub fn set_heartbeat_parsing(&mut self, mode: HeartbeatParsing) {
+        self.conn.set_heartbeat_parsing(mode);
+    }
+
+    pub fn set_desync_report_dir(&mut self, dir: Option<PathBuf>) {
+        self.conn.set_desync_report_dir(dir);
+    }
+
+    pub fn set_rejoin_on_desync(&mut self, enabled: bool) {
+        self.conn.set_rejoin_on_desync(enabled);
+    }
+
+    pub async fn connect(&mut self) -> Result<()> {
+        self.conn.connect().await
+    }
+
+    pub async fn download_map(&mut self) -> Result<usize> {
+        self.conn.download_map().await
+    }
+
+    pub async fn download_map_with_parse(&mut self, parse_map: bool) -> Result<usize> {
+        self.conn.download_map_with_parse(parse_map).await
+    }
+
+    /// Receive and process packets, sending only heartbeats and confirmations.
+    pub async fn poll(&mut self) -> Result<Option<ReceivedPacket>> {
+        self.conn.poll().await
+    }
+
+    pub async fn run_for(&mut self, duration: Duration) -> Result<()> {
+        self.conn.run_for(duration).await
+    }
+
+    pub fn drain_events(&mut self) -> Vec<GameEvent> {
+        self.conn.drain_events()
+    }
+
+    pub fn update_other_players(&mut self) {
+        self.conn.update_other_players();
+    }
+
+    pub async fn reconnect(&mut self) -> Result<()> {
+        self.conn.reconnect().await
+    }
+
+    pub async fn disconnect(&mut self) -> Result<()> {
+        self.conn.disconnect().await
+    }
+}
+
+impl Deref for ObserverConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        &self.conn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::InputAction as CodecInputAction;
+    use crate::error::Error;
+    use crate::protocol::connection::ConnectionState;
+    use crate::protocol::message::InputAction;
+    use crate::protocol::mock_server::{mock_session, MockServerConfig, MOCK_SERVER_ADDR};
+
+    #[tokio::test]
+    async fn test_observer_joins_and_stays_read_only() {
+        let (client, server) = mock_session(MockServerConfig { map: vec![0; 1000], ..MockServerConfig::default() });
+
+        let mut observer = ObserverConnection::with_transport(MOCK_SERVER_ADDR, Transport::with_socket(client), "watcher".into(), Credentials::default());
+        observer.connect().await.unwrap();
+        observer.download_map_with_parse(false).await.unwrap();
+        assert_eq!(observer.state(), ConnectionState::InGame);
+        assert!(observer.is_observer());
+
+        // Keeps up with the server on heartbeats alone
+        let tick = observer.server_tick();
+        let started = std::time::Instant::now();
+        while observer.server_tick() < tick + 30 {
+            observer.poll().await.unwrap();
+            assert!(started.elapsed() < Duration::from_secs(5), "server stopped ticking");
+        }
+        assert_eq!(observer.state(), ConnectionState::InGame);
+
+        // The wrapped connection refuses actions too
+        let conn = &mut observer.conn;
+        assert!(matches!(conn.queue_action(CodecInputAction::StopWalking), Err(Error::ObserverReadOnly)));
+        assert!(matches!(conn.actions().send_stop_walk().await, Err(Error::ObserverReadOnly)));
+        assert!(matches!(
+            conn.send_heartbeat_with_actions(&[InputAction::raw(vec![0x02])]).await,
+            Err(Error::ObserverReadOnly)
+        ));
+        assert_eq!(conn.unconfirmed_action_count(), 0);
+
+        drop(observer);
+        server.abort();
+    }
+}
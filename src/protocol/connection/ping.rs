@@ -0,0 +1,174 @@
+//! Round-trip measurement with Ping/PingReply
+//!
+//! While in game the client sends a Ping carrying a u32 id once per
+//! `PING_INTERVAL`; the reply echoes the id back. RTT is smoothed like TCP's
+//! SRTT (1/8 gain), jitter is the RFC 3550 interarrival estimate (1/16 gain) and
+//! loss is the share of the last `LOSS_WINDOW` pings that got no reply within
+//! `PING_TIMEOUT`. Servers that never answer pings leave all estimates empty
+//! rather than reporting 100% loss.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+pub(crate) const PING_INTERVAL: Duration = Duration::from_secs(1);
+const PING_TIMEOUT: Duration = Duration::from_secs(3);
+const LOSS_WINDOW: usize = 30;
+
+/// Network quality as seen by the client
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NetworkStats {
+    /// Smoothed round-trip time
+    pub rtt: Option<Duration>,
+    pub last_rtt: Option<Duration>,
+    pub min_rtt: Option<Duration>,
+    pub jitter: Option<Duration>,
+    /// Fraction of recent pings without a reply, 0.0..=1.0
+    pub loss: Option<f64>,
+    pub pings_sent: u64,
+    pub replies: u64,
+}
+
+impl NetworkStats {
+    /// Smoothed RTT in game ticks (60 per second), rounded up
+    pub fn rtt_ticks(&self) -> Option<u32> {
+        self.rtt.map(|rtt| rtt.as_micros().div_ceil(16_667) as u32)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct PingTracker {
+    next_id: u32,
+    last_sent: Option<Instant>,
+    outstanding: VecDeque<(u32, Instant)>,
+    /// true = answered, false = timed out
+    outcomes: VecDeque<bool>,
+    stats: NetworkStats,
+}
+
+impl Default for PingTracker {
+    fn default() -> Self {
+        Self {
+            next_id: 1,
+            last_sent: None,
+            outstanding: VecDeque::new(),
+            outcomes: VecDeque::new(),
+            stats: NetworkStats::default(),
+        }
+    }
+}
+
+impl PingTracker {
+    pub fn stats(&self) -> NetworkStats {
+        self.stats
+    }
+
+    /// Id for the next ping, if one is due
+    pub fn due(&mut self, now: Instant) -> Option<u32> {
+        self.expire(now);
+        if self.last_sent.is_some_and(|last| now.duration_since(last) < PING_INTERVAL) {
+            return None;
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        self.last_sent = Some(now);
+        self.outstanding.push_back((id, now));
+        self.stats.pings_sent += 1;
+        Some(id)
+    }
+
+    /// Record a reply; unknown or late ids are ignored.
+    pub fn on_reply(&mut self, id: u32, now: Instant) {
+        let Some(pos) = self.outstanding.iter().position(|&(pending, _)| pending == id) else {
+            return;
+        };
+        let (_, sent_at) = self.outstanding.remove(pos).unwrap();
+        let sample = now.duration_since(sent_at);
+        let stats = &mut self.stats;
+        if let Some(last) = stats.last_rtt {
+            let delta = sample.abs_diff(last);
+            let jitter = stats.jitter.unwrap_or_default();
+            stats.jitter = Some(if delta > jitter { jitter + (delta - jitter) / 16 } else { jitter - (jitter - delta) / 16 });
+        }
+        stats.rtt = Some(match stats.rtt {
+            Some(rtt) => (rtt * 7 + sample) / 8,
+            None => sample,
+        });
+        stats.min_rtt = Some(stats.min_rtt.map_or(sample, |min| min.min(sample)));
+        stats.last_rtt = Some(sample);
+        stats.replies += 1;
+        self.push_outcome(true);
+    }
+
+    /// Count pings that have waited too long as lost.
+    fn expire(&mut self, now: Instant) {
+        while let Some(&(_, sent_at)) = self.outstanding.front() {
+            if now.duration_since(sent_at) < PING_TIMEOUT {
+                break;
+            }
+            self.outstanding.pop_front();
+            self.push_outcome(false);
+        }
+    }
+
+    fn push_outcome(&mut self, answered: bool) {
+        if self.outcomes.len() >= LOSS_WINDOW {
+            self.outcomes.pop_front();
+        }
+        self.outcomes.push_back(answered);
+        self.stats.loss = if self.stats.replies == 0 {
+            None
+        } else {
+            let lost = self.outcomes.iter().filter(|&&answered| !answered).count();
+            Some(lost as f64 / self.outcomes.len() as f64)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rtt_jitter_and_loss() {
+        let mut tracker = PingTracker::default();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        let first = tracker.due(at(0)).unwrap();
+        assert_eq!(tracker.due(at(500)), None);
+        tracker.on_reply(first, at(40));
+        assert_eq!(tracker.stats().rtt, Some(Duration::from_millis(40)));
+        assert_eq!(tracker.stats().jitter, None);
+
+        let second = tracker.due(at(1000)).unwrap();
+        tracker.on_reply(second, at(1080));
+        tracker.on_reply(second, at(1090)); // duplicate
+        let stats = tracker.stats();
+        assert_eq!(stats.rtt, Some(Duration::from_millis(45)));
+        assert_eq!(stats.min_rtt, Some(Duration::from_millis(40)));
+        assert_eq!(stats.jitter, Some(Duration::from_micros(2500)));
+        assert_eq!(stats.rtt_ticks(), Some(3));
+        assert_eq!(stats.loss, Some(0.0));
+
+        // Third ping never comes back
+        tracker.due(at(2000)).unwrap();
+        tracker.due(at(5000)).unwrap();
+        let stats = tracker.stats();
+        assert_eq!(stats.pings_sent, 4);
+        assert_eq!(stats.replies, 2);
+        assert_eq!(stats.loss, Some(1.0 / 3.0));
+    }
+
+    #[test]
+    fn test_silent_server_reports_nothing() {
+        let mut tracker = PingTracker::default();
+        let start = Instant::now();
+        for s in 0..10 {
+            tracker.due(start + Duration::from_secs(s));
+        }
+        let stats = tracker.stats();
+        assert_eq!(stats.pings_sent, 10);
+        assert_eq!(stats.rtt, None);
+        assert_eq!(stats.loss, None);
+    }
+}
@@ -0,0 +1,244 @@
+//! Receipts for queued input actions
+//!
+//! The server echoes every input action back in the tick closure it runs in,
+//! including our own. `Connection::queue_action` hands out an `ActionReceipt`;
+//! it resolves when an action with the same type and data comes back for our
+//! player, or times out if none does within the receipt timeout after sending.
+
+use std::time::{Duration, Instant};
+
+use tokio::sync::oneshot;
+
+use crate::codec::{BinaryWriter, InputAction as CodecInputAction, InputActionType};
+use crate::error::{Error, RejectReason, Result};
+use crate::protocol::message::InputAction;
+
+/// How long a sent action may take to come back before its receipt times out
+pub const DEFAULT_ACTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What became of a queued action
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActionOutcome {
+    /// The server ran it on this tick
+    Executed { tick: u32 },
+    /// Sent, but not echoed back in time
+    TimedOut,
+    /// Discarded unsent, because the connection was closed or reset
+    Dropped,
+}
+
+/// Handle for an action queued with `Connection::queue_action`
+#[derive(Debug)]
+pub struct ActionReceipt {
+    id: u64,
+    action_type: InputActionType,
+    rx: oneshot::Receiver<ActionOutcome>,
+    outcome: Option<ActionOutcome>,
+}
+
+impl ActionReceipt {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn action_type(&self) -> InputActionType {
+        self.action_type
+    }
+
+    /// The outcome, if the action has been resolved yet. For callers that drive
+    /// `Connection::poll` themselves.
+    pub fn try_outcome(&mut self) -> Option<ActionOutcome> {
+        if self.outcome.is_none() {
+            self.outcome = match self.rx.try_recv() {
+                Ok(outcome) => Some(outcome),
+                Err(oneshot::error::TryRecvError::Empty) => None,
+                Err(oneshot::error::TryRecvError::Closed) => Some(ActionOutcome::Dropped),
+            };
+        }
+        self.outcome
+    }
+
+    /// Wait for the outcome while another task drives the connection.
+    pub async fn outcome(mut self) -> ActionOutcome {
+        match self.outcome.take() {
+            Some(outcome) => outcome,
+            None => self.rx.await.unwrap_or(ActionOutcome::Dropped),
+        }
+    }
+
+    /// Wait for the action to run, returning its tick, or `ActionRejected` if it never did.
+    pub async fn executed(self) -> Result<u32> {
+        let action = Some(self.action_type);
+        match self.outcome().await {
+            ActionOutcome::Executed { tick } => Ok(tick),
+            ActionOutcome::TimedOut => Err(Error::ActionRejected { action, reason: RejectReason::NotExecuted }),
+            ActionOutcome::Dropped => Err(Error::ActionRejected { action, reason: RejectReason::Dropped }),
+        }
+    }
+}
+
+/// An entry of the outgoing action queue
+#[derive(Debug, Clone)]
+pub(crate) struct QueuedAction {
+    pub action: InputAction,
+    pub receipt: Option<u64>,
+}
+
+impl From<InputAction> for QueuedAction {
+    fn from(action: InputAction) -> Self {
+        Self { action, receipt: None }
+    }
+}
+
+#[derive(Debug)]
+struct Outstanding {
+    id: u64,
+    action_type: InputActionType,
+    /// Encoded action data, compared against the echo
+    data: Vec<u8>,
+    sent_at: Option<Instant>,
+    tx: oneshot::Sender<ActionOutcome>,
+}
+
+/// Receipts not resolved yet, oldest first
+#[derive(Debug)]
+pub(crate) struct ActionReceipts {
+    next_id: u64,
+    timeout: Duration,
+    outstanding: Vec<Outstanding>,
+}
+
+fn action_data(action: &CodecInputAction) -> Vec<u8> {
+    let mut writer = BinaryWriter::new();
+    action.write_data(&mut writer);
+    writer.into_vec()
+}
+
+impl ActionReceipts {
+    pub fn new() -> Self {
+        Self { next_id: 1, timeout: DEFAULT_ACTION_TIMEOUT, outstanding: Vec::new() }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn len(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    pub fn issue(&mut self, action: &CodecInputAction) -> ActionReceipt {
+        let id = self.next_id;
+        self.next_id += 1;
+        let (tx, rx) = oneshot::channel();
+        self.outstanding.push(Outstanding {
+            id,
+            action_type: action.action_type(),
+            data: action_data(action),
+            sent_at: None,
+            tx,
+        });
+        ActionReceipt { id, action_type: action.action_type(), rx, outcome: None }
+    }
+
+    pub fn mark_sent(&mut self, id: u64, now: Instant) {
+        if let Some(entry) = self.outstanding.iter_mut().find(|o| o.id == id) {
+            entry.sent_at = Some(now);
+        }
+    }
+
+    /// One of our actions came back on `tick`: resolve the oldest sent receipt for
+    /// an identical action. Returns its id.
+    pub fn on_executed(&mut self, action: &CodecInputAction, tick: u32) -> Option<u64> {
+        let action_type = action.action_type();
+        let mut data = None;
+        let index = self.outstanding.iter().position(|o| {
+            o.sent_at.is_some()
+                && o.action_type == action_type
+                && o.data == *data.get_or_insert_with(|| action_data(action))
+        })?;
+        let entry = self.outstanding.remove(index);
+        let _ = entry.tx.send(ActionOutcome::Executed { tick });
+        Some(entry.id)
+    }
+
+    /// Time out sent actions that did not come back. Returns how many did.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let timeout = self.timeout;
+        let (expired, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.outstanding)
+            .into_iter()
+            .partition(|o| o.sent_at.is_some_and(|t| now.duration_since(t) >= timeout));
+        self.outstanding = kept;
+        let count = expired.len();
+        for entry in expired {
+            let _ = entry.tx.send(ActionOutcome::TimedOut);
+        }
+        count
+    }
+
+    /// The queue was cleared: resolve unsent receipts as dropped and sent ones as
+    /// timed out, since their echo can no longer be seen.
+    pub fn clear(&mut self) {
+        for entry in self.outstanding.drain(..) {
+            let outcome = if entry.sent_at.is_some() { ActionOutcome::TimedOut } else { ActionOutcome::Dropped };
+            let _ = entry.tx.send(outcome);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_receipts_resolve_in_order() {
+        let mut receipts = ActionReceipts::new();
+        let craft = CodecInputAction::Craft { recipe_id: 3, count: 1 };
+        let mut first = receipts.issue(&craft);
+        let mut second = receipts.issue(&craft);
+        let mut other = receipts.issue(&CodecInputAction::Craft { recipe_id: 4, count: 1 });
+        let now = Instant::now();
+
+        // Nothing was sent yet, so an identical echo is not ours
+        assert_eq!(receipts.on_executed(&craft, 10), None);
+        receipts.mark_sent(first.id(), now);
+        receipts.mark_sent(second.id(), now);
+        receipts.mark_sent(other.id(), now);
+        assert_eq!(receipts.on_executed(&craft, 11), Some(first.id()));
+        assert_eq!(first.try_outcome(), Some(ActionOutcome::Executed { tick: 11 }));
+        assert_eq!(first.try_outcome(), Some(ActionOutcome::Executed { tick: 11 }));
+        assert_eq!(second.try_outcome(), None);
+
+        assert_eq!(receipts.expire(now + DEFAULT_ACTION_TIMEOUT), 2);
+        assert_eq!(second.try_outcome(), Some(ActionOutcome::TimedOut));
+        assert_eq!(other.try_outcome(), Some(ActionOutcome::TimedOut));
+
+        let mut unsent = receipts.issue(&craft);
+        receipts.clear();
+        assert_eq!(unsent.try_outcome(), Some(ActionOutcome::Dropped));
+        assert_eq!(receipts.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_executed_reports_rejection() {
+        let mut receipts = ActionReceipts::new();
+        let craft = CodecInputAction::Craft { recipe_id: 3, count: 1 };
+        let ran = receipts.issue(&craft);
+        let lost = receipts.issue(&craft);
+        let now = Instant::now();
+        receipts.mark_sent(ran.id(), now);
+        receipts.mark_sent(lost.id(), now);
+        receipts.on_executed(&craft, 20);
+        receipts.expire(now + DEFAULT_ACTION_TIMEOUT);
+
+        assert_eq!(ran.executed().await.unwrap(), 20);
+        assert!(matches!(
+            lost.executed().await,
+            Err(Error::ActionRejected { action: Some(InputActionType::Craft), reason: RejectReason::NotExecuted })
+        ));
+    }
+}
@@ -0,0 +1,142 @@
+//! Who is in the game
+//!
+//! Peers come from the ClientsPeerInfo list in ConnectionAcceptOrDeny and from
+//! `NewPeerInfo` synchronizer actions; `PeerDisconnect` (or the player's
+//! `PlayerLeaveGame` action, whichever arrives first) removes them. A peer gets
+//! its player index from its `PlayerJoinGame` action; players who were already
+//! in the game when we joined joined before we could see that, so they are
+//! linked by name against the downloaded save's player list instead.
+
+use std::collections::BTreeMap;
+
+/// One connected peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RosterEntry {
+    pub peer_id: u16,
+    /// Player index, once the peer's PlayerJoinGame has been seen
+    pub player_index: Option<u16>,
+    pub username: String,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Roster {
+    peers: BTreeMap<u16, RosterEntry>,
+}
+
+impl Roster {
+    pub fn clear(&mut self) {
+        self.peers.clear();
+    }
+
+    /// Add a peer, or rename it if already known.
+    pub fn add_peer(&mut self, peer_id: u16, username: &str) {
+        self.peers
+            .entry(peer_id)
+            .and_modify(|entry| entry.username = username.to_string())
+            .or_insert_with(|| RosterEntry { peer_id, player_index: None, username: username.to_string() });
+    }
+
+    /// Record that `peer_id` plays as `player_index`. Returns true the first
+    /// time the two are linked.
+    pub fn link_player(&mut self, peer_id: u16, player_index: u16, username: &str) -> bool {
+        // A player index belongs to one peer at a time
+        for entry in self.peers.values_mut() {
+            if entry.peer_id != peer_id && entry.player_index == Some(player_index) {
+                entry.player_index = None;
+            }
+        }
+        let entry = self
+            .peers
+            .entry(peer_id)
+            .or_insert_with(|| RosterEntry { peer_id, player_index: None, username: String::new() });
+        if !username.is_empty() {
+            entry.username = username.to_string();
+        }
+        let linked = entry.player_index != Some(player_index);
+        entry.player_index = Some(player_index);
+        linked
+    }
+
+    /// Link unlinked peers to the index of the save player with their name.
+    /// `players` holds the save's player names in player index order.
+    pub fn link_by_name(&mut self, players: &[String]) {
+        for (index, name) in players.iter().enumerate() {
+            let Ok(player_index) = u16::try_from(index) else {
+                break;
+            };
+            if self.by_player(player_index).is_some() {
+                continue;
+            }
+            if let Some(entry) = self
+                .peers
+                .values_mut()
+                .find(|entry| entry.player_index.is_none() && entry.username == *name)
+            {
+                entry.player_index = Some(player_index);
+            }
+        }
+    }
+
+    pub fn remove_peer(&mut self, peer_id: u16) -> Option<RosterEntry> {
+        self.peers.remove(&peer_id)
+    }
+
+    pub fn by_peer(&self, peer_id: u16) -> Option<&RosterEntry> {
+        self.peers.get(&peer_id)
+    }
+
+    pub fn by_player(&self, player_index: u16) -> Option<&RosterEntry> {
+        self.peers.values().find(|entry| entry.player_index == Some(player_index))
+    }
+
+    /// Entries ordered by peer id
+    pub fn entries(&self) -> impl Iterator<Item = &RosterEntry> {
+        self.peers.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roster_links_and_removes() {
+        let mut roster = Roster::default();
+        roster.add_peer(0, "server");
+        roster.add_peer(4, "alice");
+        assert_eq!(roster.by_peer(4).unwrap().player_index, None);
+
+        assert!(roster.link_player(4, 2, "alice"));
+        assert!(!roster.link_player(4, 2, "alice"));
+        assert_eq!(roster.by_player(2).unwrap().username, "alice");
+
+        // Rejoining under a new peer id takes the player index along
+        assert!(roster.link_player(7, 2, "alice"));
+        assert_eq!(roster.by_peer(4).unwrap().player_index, None);
+        assert_eq!(roster.by_player(2).unwrap().peer_id, 7);
+
+        assert_eq!(roster.remove_peer(7).unwrap().player_index, Some(2));
+        assert!(roster.remove_peer(7).is_none());
+        assert!(roster.by_player(2).is_none());
+        let names: Vec<_> = roster.entries().map(|e| e.username.as_str()).collect();
+        assert_eq!(names, vec!["server", "alice"]);
+    }
+
+    #[test]
+    fn test_roster_links_players_from_save() {
+        let mut roster = Roster::default();
+        roster.add_peer(0, "server");
+        roster.add_peer(2, "alice");
+        roster.add_peer(3, "bob");
+        roster.add_peer(6, "carol");
+        roster.link_player(6, 1, "carol");
+
+        // Links made by PlayerJoinGame stay; "server" is not a player
+        let players: Vec<String> = ["bob", "carol", "alice"].iter().map(|s| s.to_string()).collect();
+        roster.link_by_name(&players);
+        assert_eq!(roster.by_peer(3).unwrap().player_index, Some(0));
+        assert_eq!(roster.by_peer(6).unwrap().player_index, Some(1));
+        assert_eq!(roster.by_peer(2).unwrap().player_index, Some(2));
+        assert_eq!(roster.by_peer(0).unwrap().player_index, None);
+    }
+}
@@ -0,0 +1,244 @@
+//! LAN server discovery
+//!
+//! Factorio servers with LAN visibility periodically broadcast a LANBroadcast
+//! (type 15) datagram to UDP port 34196. The payload (after the type byte) is:
+//! [ApplicationVersion][u16 game port][String name][opt_u32 player count]
+//! This layout has not yet been checked against a broadcast from a real server.
+
+use std::collections::HashMap;
+use std::net::{SocketAddr, SocketAddrV4};
+use std::time::{Duration, Instant};
+
+use tokio::net::UdpSocket;
+
+use crate::codec::{BinaryReader, BinaryWriter};
+use crate::error::{Error, Result};
+use super::message::ApplicationVersion;
+use super::packet::MessageType;
+
+/// UDP port servers broadcast LANBroadcast to (also used for GameInformationRequest).
+pub const LAN_BROADCAST_PORT: u16 = 34196;
+
+/// Decoded LANBroadcast payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LanBroadcast {
+    pub version: ApplicationVersion,
+    pub game_port: u16,
+    pub name: String,
+    pub player_count: u32,
+}
+
+impl LanBroadcast {
+    /// Parse a full datagram including the type byte.
+    pub fn parse(packet: &[u8]) -> Result<Self> {
+        let (&type_byte, payload) = packet.split_first().ok_or(Error::UnexpectedEof)?;
+        if (type_byte & 0x1F) != MessageType::LANBroadcast as u8 {
+            return Err(Error::InvalidMessageType(type_byte));
+        }
+        let mut reader = BinaryReader::new(payload);
+        Self::read(&mut reader)
+    }
+
+    pub fn read(reader: &mut BinaryReader) -> Result<Self> {
+        Ok(Self {
+            version: ApplicationVersion::read(reader)?,
+            game_port: reader.read_u16_le()?,
+            name: reader.read_string()?,
+            player_count: reader.read_opt_u32()?,
+        })
+    }
+
+    pub fn write(&self, writer: &mut BinaryWriter) {
+        self.version.write(writer);
+        writer.write_u16_le(self.game_port);
+        writer.write_string(&self.name);
+        writer.write_opt_u32(self.player_count);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = BinaryWriter::new();
+        writer.write_u8(MessageType::LANBroadcast as u8);
+        self.write(&mut writer);
+        writer.into_vec()
+    }
+}
+
+/// A server seen during discovery
+#[derive(Debug, Clone)]
+pub struct DiscoveredServer {
+    /// Game address (broadcast source IP + advertised game port)
+    pub addr: SocketAddr,
+    pub name: String,
+    pub version: ApplicationVersion,
+    pub player_count: u32,
+    pub last_seen: Instant,
+}
+
+/// Listen on the LAN broadcast port for `window` and return the servers seen.
+pub async fn discover(window: Duration) -> Result<Vec<DiscoveredServer>> {
+    discover_on(SocketAddr::from(([0, 0, 0, 0], LAN_BROADCAST_PORT)), window).await
+}
+
+/// Like `discover`, listening on an explicit address (IPv4 only).
+pub async fn discover_on(bind_addr: SocketAddr, window: Duration) -> Result<Vec<DiscoveredServer>> {
+    let socket = bind_reusable(bind_addr)?;
+    discover_with_socket(&socket, window).await
+}
+
+async fn discover_with_socket(socket: &UdpSocket, window: Duration) -> Result<Vec<DiscoveredServer>> {
+    let debug = std::env::var("FACTORIO_DEBUG").is_ok();
+    let deadline = tokio::time::Instant::now() + window;
+    let mut servers: HashMap<SocketAddr, DiscoveredServer> = HashMap::new();
+    let mut buf = [0u8; 2048];
+
+    loop {
+        let recv = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await;
+        let Ok(recv) = recv else {
+            break;
+        };
+        let (len, from) = recv.map_err(|e| Error::Io(e.to_string()))?;
+        match LanBroadcast::parse(&buf[..len]) {
+            Ok(broadcast) => {
+                let addr = SocketAddr::new(from.ip(), broadcast.game_port);
+                servers.insert(addr, DiscoveredServer {
+                    addr,
+                    name: broadcast.name,
+                    version: broadcast.version,
+                    player_count: broadcast.player_count,
+                    last_seen: Instant::now(),
+                });
+            }
+            Err(e) if debug => {
+                eprintln!("[DEBUG] discovery: ignoring {} bytes from {}: {}", len, from, e);
+            }
+            Err(_) => {}
+        }
+    }
+
+    let mut servers: Vec<_> = servers.into_values().collect();
+    servers.sort_by(|a, b| a.name.cmp(&b.name).then(a.addr.cmp(&b.addr)));
+    Ok(servers)
+}
+
+/// Bind with SO_REUSEADDR/SO_REUSEPORT so discovery can share 34196 with a local
+/// Factorio client or a concurrent server-info query.
+fn bind_reusable(bind_addr: SocketAddr) -> Result<UdpSocket> {
+    let SocketAddr::V4(v4) = bind_addr else {
+        return Err(Error::InvalidAddress(format!("{} (LAN discovery only supports IPv4)", bind_addr)));
+    };
+    let std_socket = bind_reusable_v4(v4).map_err(|e| Error::Io(e.to_string()))?;
+    std_socket.set_nonblocking(true).map_err(|e| Error::Io(e.to_string()))?;
+    UdpSocket::from_std(std_socket).map_err(|e| Error::Io(e.to_string()))
+}
+
+#[cfg(unix)]
+fn bind_reusable_v4(addr: SocketAddrV4) -> std::io::Result<std::net::UdpSocket> {
+    use std::os::unix::io::FromRawFd;
+
+    // Keep the socket out of child processes
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+    const SOCK_TYPE: libc::c_int = libc::SOCK_DGRAM | libc::SOCK_CLOEXEC;
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+    const SOCK_TYPE: libc::c_int = libc::SOCK_DGRAM;
+
+    unsafe {
+        let fd = libc::socket(libc::AF_INET, SOCK_TYPE, 0);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // Owns the fd from here, so the early returns below close it
+        let socket = std::net::UdpSocket::from_raw_fd(fd);
+        #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+        if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let one: libc::c_int = 1;
+        for opt in [libc::SO_REUSEADDR, libc::SO_REUSEPORT, libc::SO_BROADCAST] {
+            let rc = libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                opt,
+                &one as *const _ as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            );
+            if rc != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        let mut sin: libc::sockaddr_in = std::mem::zeroed();
+        sin.sin_family = libc::AF_INET as libc::sa_family_t;
+        sin.sin_port = addr.port().to_be();
+        sin.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+        let rc = libc::bind(
+            fd,
+            &sin as *const libc::sockaddr_in as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        );
+        if rc != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(socket)
+    }
+}
+
+#[cfg(not(unix))]
+fn bind_reusable_v4(addr: SocketAddrV4) -> std::io::Result<std::net::UdpSocket> {
+    std::net::UdpSocket::bind(addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> LanBroadcast {
+        LanBroadcast {
+            version: ApplicationVersion::FACTORIO_2_0_72,
+            game_port: 34197,
+            name: "test rig".into(),
+            player_count: 3,
+        }
+    }
+
+    #[test]
+    fn test_lan_broadcast_roundtrip() {
+        let bytes = sample().to_bytes();
+        assert_eq!(bytes[0], MessageType::LANBroadcast as u8);
+        assert_eq!(LanBroadcast::parse(&bytes).unwrap(), sample());
+        assert!(LanBroadcast::parse(&[MessageType::Ping as u8]).is_err());
+    }
+
+    #[test]
+    fn test_lan_broadcast_bytes() {
+        // Laid out by hand from the module doc, not through `write`
+        let packet = [
+            0x0F, // LANBroadcast
+            0x02, 0x00, 0x48, 0x44, 0x49, 0x01, 0x00, // 2.0.72 build 84292
+            0x95, 0x87, // port 34709
+            0x03, b'r', b'i', b'g',
+            0xFF, 0x2C, 0x01, 0x00, 0x00, // 300 players, long form
+        ];
+        let broadcast = LanBroadcast::parse(&packet).unwrap();
+        assert_eq!(broadcast.version, ApplicationVersion::FACTORIO_2_0_72);
+        assert_eq!(broadcast.game_port, 34709);
+        assert_eq!(broadcast.name, "rig");
+        assert_eq!(broadcast.player_count, 300);
+        assert!(matches!(LanBroadcast::parse(&packet[..12]), Err(Error::UnexpectedEof)));
+    }
+
+    #[tokio::test]
+    async fn test_discover_local() {
+        let socket = bind_reusable(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let target = socket.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let bytes = sample().to_bytes();
+        sender.send_to(&bytes, target).await.unwrap();
+        sender.send_to(&bytes, target).await.unwrap();
+        sender.send_to(&[0xff, 0x00], target).await.unwrap();
+
+        let servers = discover_with_socket(&socket, Duration::from_millis(200)).await.unwrap();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].name, "test rig");
+        assert_eq!(servers[0].addr, SocketAddr::from(([127, 0, 0, 1], 34197)));
+        assert_eq!(servers[0].player_count, 3);
+    }
+}
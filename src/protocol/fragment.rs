@@ -0,0 +1,231 @@
+//! Splitting and reassembling fragmented messages
+//!
+//! A message too large for one datagram goes out as several, each with the
+//! fragmented bit (0x40) set in the type byte, the message id and a VarShort
+//! fragment number in front of its slice of the payload. The last fragment also
+//! carries bit 0x80, the same bits as the 0xC3 type byte of a one-fragment
+//! ConnectionRequestReply (see `packet.rs`). Fragments that never carry the
+//! marker are still put together the way the client always has: a fragment
+//! shorter than the others ends the message.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use super::packet::{PacketHeader, MAX_PACKET_SIZE, TYPE_LAST_FRAGMENT_BIT};
+
+const FRAGMENTED_BIT: u8 = 0x40;
+
+/// Room left for payload after type byte, message id and a 3-byte fragment number
+pub const MAX_FRAGMENT_PAYLOAD: usize = MAX_PACKET_SIZE - 6;
+
+/// Split `payload` (everything after the type byte) into fragment datagrams.
+/// Returns one unfragmented datagram if it fits.
+pub fn split_message(type_byte: u8, msg_id: u16, payload: &[u8], max_payload: usize) -> Vec<Vec<u8>> {
+    let type_byte = type_byte & !(FRAGMENTED_BIT | TYPE_LAST_FRAGMENT_BIT);
+    if payload.len() <= max_payload {
+        let mut packet = Vec::with_capacity(1 + payload.len());
+        packet.push(type_byte);
+        packet.extend_from_slice(payload);
+        return vec![packet];
+    }
+    let count = payload.len().div_ceil(max_payload);
+    payload
+        .chunks(max_payload)
+        .enumerate()
+        .map(|(index, part)| {
+            let mut packet = Vec::with_capacity(6 + part.len());
+            let last = if index + 1 == count { TYPE_LAST_FRAGMENT_BIT } else { 0 };
+            packet.push(type_byte | FRAGMENTED_BIT | last);
+            packet.extend_from_slice(&(msg_id & 0x7FFF).to_le_bytes());
+            write_var_short(&mut packet, index as u16);
+            packet.extend_from_slice(part);
+            packet
+        })
+        .collect()
+}
+
+fn write_var_short(out: &mut Vec<u8>, value: u16) {
+    if value < 0xFF {
+        out.push(value as u8);
+    } else {
+        out.push(0xFF);
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+#[derive(Debug)]
+struct Assembly {
+    type_byte: u8,
+    fragments: BTreeMap<u16, Vec<u8>>,
+    /// Fragment that came with the last-fragment bit
+    last: Option<u16>,
+    created_at: Instant,
+}
+
+impl Assembly {
+    /// Number of fragments, once the last one and everything before it are in.
+    /// Without a marked fragment the last is the one shorter than the rest.
+    fn complete_count(&self) -> Option<u16> {
+        let last = match self.last {
+            Some(last) => last,
+            None => {
+                let full = self.fragments.values().map(Vec::len).max()?;
+                *self.fragments.iter().find(|(_, part)| part.len() < full)?.0
+            }
+        };
+        (0..=last).all(|id| self.fragments.contains_key(&id)).then_some(last + 1)
+    }
+}
+
+/// Collects fragments per message id until a message is complete
+#[derive(Debug)]
+pub struct FragmentAssembler {
+    assemblies: HashMap<u16, Assembly>,
+    timeout: Duration,
+}
+
+impl FragmentAssembler {
+    /// Incomplete messages older than `timeout` are dropped.
+    pub fn new(timeout: Duration) -> Self {
+        Self { assemblies: HashMap::new(), timeout }
+    }
+
+    /// Add one fragment. Returns the whole message, as an unfragmented
+    /// datagram (type byte + payload), once every fragment has arrived.
+    pub fn insert(&mut self, header: &PacketHeader, data: &[u8], payload_start: usize) -> Option<Vec<u8>> {
+        let fragment_id = header.fragment_id?;
+        if payload_start >= data.len() {
+            return None;
+        }
+        let now = Instant::now();
+        let timeout = self.timeout;
+        self.assemblies.retain(|_, asm| now.duration_since(asm.created_at) < timeout);
+
+        let msg_id = header.message_id;
+        let entry = self.assemblies.entry(msg_id).or_insert_with(|| Assembly {
+            type_byte: data[0],
+            fragments: BTreeMap::new(),
+            last: None,
+            created_at: now,
+        });
+        entry.type_byte = data[0];
+        entry.fragments.insert(fragment_id, data[payload_start..].to_vec());
+        if header.last_fragment {
+            entry.last = Some(fragment_id);
+        }
+        let count = entry.complete_count()?;
+
+        let entry = self.assemblies.remove(&msg_id)?;
+        let mut packet = vec![entry.type_byte & !(FRAGMENTED_BIT | TYPE_LAST_FRAGMENT_BIT)];
+        for id in 0..count {
+            packet.extend_from_slice(&entry.fragments[&id]);
+        }
+        Some(packet)
+    }
+
+    /// Message ids still incomplete after `age`; they are forgotten, so each is
+    /// reported once.
+    pub fn take_stale(&mut self, age: Duration) -> Vec<u16> {
+        let now = Instant::now();
+        let stale: Vec<u16> = self
+            .assemblies
+            .iter()
+            .filter(|(_, asm)| now.duration_since(asm.created_at) >= age)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in &stale {
+            self.assemblies.remove(id);
+        }
+        stale
+    }
+
+    pub fn pending(&self) -> usize {
+        self.assemblies.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reassemble(assembler: &mut FragmentAssembler, packets: &[Vec<u8>]) -> Option<Vec<u8>> {
+        let mut out = None;
+        for packet in packets {
+            let (header, start) = PacketHeader::parse(packet).unwrap();
+            out = assembler.insert(&header, packet, start);
+        }
+        out
+    }
+
+    #[test]
+    fn test_split_and_reassemble() {
+        let payload: Vec<u8> = (0..5000u32).map(|i| (i * 31) as u8).collect();
+        let packets = split_message(0x06, 0x1234, &payload, MAX_FRAGMENT_PAYLOAD);
+        assert_eq!(packets.len(), 4);
+        assert!(packets.iter().all(|p| p.len() <= MAX_PACKET_SIZE));
+        let types: Vec<_> = packets.iter().map(|p| p[0]).collect();
+        assert_eq!(types, vec![0x46, 0x46, 0x46, 0xC6]);
+
+        // Out of order, with a duplicate
+        let shuffled = vec![packets[3].clone(), packets[1].clone(), packets[0].clone(), packets[1].clone(), packets[2].clone()];
+        let mut assembler = FragmentAssembler::new(Duration::from_secs(2));
+        let message = reassemble(&mut assembler, &shuffled).unwrap();
+        assert_eq!(message[0], 0x06);
+        assert_eq!(&message[1..], &payload[..]);
+        assert_eq!(assembler.pending(), 0);
+
+        // Small messages are not fragmented at all
+        assert_eq!(split_message(0x06, 1, &[1, 2, 3], MAX_FRAGMENT_PAYLOAD), vec![vec![0x06, 1, 2, 3]]);
+    }
+
+    #[test]
+    fn test_fragment_wire_bytes() {
+        let payload: Vec<u8> = (1..=10).collect();
+        let packets = split_message(0x06, 0x1234, &payload, 4);
+        assert_eq!(
+            packets,
+            vec![
+                vec![0x46, 0x34, 0x12, 0x00, 1, 2, 3, 4],
+                vec![0x46, 0x34, 0x12, 0x01, 5, 6, 7, 8],
+                vec![0xC6, 0x34, 0x12, 0x02, 9, 10],
+            ]
+        );
+
+        // Equal-length fragments end on the marker, not on a short one
+        let payload = vec![7u8; 400];
+        let packets = split_message(0x06, 9, &payload, 100);
+        let lens: Vec<_> = packets.iter().map(|p| p.len() - 4).collect();
+        assert_eq!(lens, vec![100, 100, 100, 100]);
+        let mut assembler = FragmentAssembler::new(Duration::from_secs(2));
+        assert!(reassemble(&mut assembler, &packets[..3]).is_none());
+        assert_eq!(&reassemble(&mut assembler, &packets[3..]).unwrap()[1..], &payload[..]);
+    }
+
+    #[test]
+    fn test_single_marked_fragment() {
+        // ConnectionRequestReply header 0xC3: fragmented and last, fragment 0
+        let packet = [0xC3, 0x05, 0x00, 0x00, 0xAA, 0xBB];
+        let (header, start) = PacketHeader::parse(&packet).unwrap();
+        assert!(header.fragmented && header.last_fragment);
+        let mut assembler = FragmentAssembler::new(Duration::from_secs(2));
+        assert_eq!(assembler.insert(&header, &packet, start), Some(vec![0x03, 0xAA, 0xBB]));
+    }
+
+    #[test]
+    fn test_unmarked_fragments_end_on_short_one() {
+        let mut packets = split_message(0x06, 3, &[5u8; 250], 100);
+        packets[2][0] &= !TYPE_LAST_FRAGMENT_BIT;
+        let mut assembler = FragmentAssembler::new(Duration::from_secs(2));
+        assert_eq!(reassemble(&mut assembler, &packets).unwrap(), [&[0x06][..], &[5u8; 250][..]].concat());
+    }
+
+    #[test]
+    fn test_missing_fragment_goes_stale() {
+        let payload = vec![1u8; 3000];
+        let packets = split_message(0x06, 42, &payload, MAX_FRAGMENT_PAYLOAD);
+        let mut assembler = FragmentAssembler::new(Duration::from_secs(2));
+        assert!(reassemble(&mut assembler, &packets[1..]).is_none());
+        assert_eq!(assembler.take_stale(Duration::ZERO), vec![42]);
+        assert!(assembler.take_stale(Duration::ZERO).is_empty());
+    }
+}
@@ -0,0 +1,688 @@
+//! ServerToClientHeartbeat decoding
+//!
+//! Payload layout after the packet header (see `DeserializationMask`):
+//!
+//! ```text
+//! [u8 flags][u32 heartbeat sequence]
+//! tick closures      if flags & 0x06: one closure (0x04) or [opt_u32 count] closures
+//!                    closure = [u64 tick] then, unless flags & 0x08,
+//!                    [opt_u32 count*2 | has_segments]
+//!                    {[opt_u16 player delta][opt_u16 type][action data]}
+//!                    [opt_u32 n]{segment} if has_segments
+//! confirm records    after each closure, zero or more CheckCRCHeuristic entries
+//!                    [u8 02|03][opt_u16 type][u8 flag][u32 crc][u64 tick]
+//! sync actions       if flags & 0x10: [opt_u32 count]{[u8 type][opt_u16 peer][data]}
+//! heartbeat requests if flags & 0x01: [opt_u32 count]{u32}
+//! ```
+//!
+//! Confirm records have no count in front of them. Whether one follows a
+//! closure is decided by what the flags say comes next: the next closure's
+//! tick, a sync action list, the request list or the end of the payload.
+//! Whatever cannot be that is read as a record, field by field.
+//!
+//! Anything left over is an error. `parse` is all or nothing; `parse_partial`
+//! also hands back what decoded before the error, so the tick still advances
+//! when a single action is not understood.
+//!
+//! A segment is one part of an action cut up to spread it over several ticks:
+//!
+//! ```text
+//! [opt_u16 type][u32 id][opt_u16 player][opt_u32 total][opt_u32 number]
+//! [opt_u32 len]{data}
+//! ```
+//!
+//! Put together, the data is the player index as opt_u16 followed by the
+//! action's own data, which is how the game sends chat messages.
+//! `SegmentAssembler` turns the segments back into the action.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::codec::{ActionNumbering, BinaryReader, BinaryWriter, InputAction, InputActionType, SynchronizerAction, TickInputAction};
+use crate::error::{Error, Result};
+use super::message::DeserializationMask;
+use super::packet::{MessageType, PacketHeader};
+
+/// Sanity limits; real heartbeats stay far below these.
+const MAX_TICK_CLOSURES: u32 = 1024;
+const MAX_CLOSURE_ACTIONS: u32 = 8192;
+const MAX_SYNC_ACTIONS: u32 = 1024;
+
+/// Most action data per segment, which keeps a heartbeat carrying one segment
+/// within a single datagram
+pub const MAX_SEGMENT_DATA: usize = 1000;
+
+/// Segmented actions kept waiting for their missing segments
+const MAX_PENDING_SEGMENTED: usize = 64;
+
+/// A fully decoded server heartbeat
+#[derive(Debug, Clone)]
+pub struct ServerHeartbeat {
+    pub flags: DeserializationMask,
+    pub sequence: u32,
+    pub tick_closures: Vec<ServerTickClosure>,
+    pub confirm_records: Vec<ConfirmRecord>,
+    pub sync_actions: Vec<ServerSyncAction>,
+    pub heartbeat_requests: Vec<u32>,
+    /// Player index of the last action, the base for the next heartbeat's deltas
+    pub last_player_index: u16,
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerTickClosure {
+    pub tick: u64,
+    /// Actions with absolute player indices
+    pub actions: Vec<TickInputAction>,
+    pub segments: Vec<ActionSegment>,
+}
+
+/// One part of an input action too large for a single closure
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActionSegment {
+    pub action_type: u16,
+    pub id: u32,
+    pub player_index: u16,
+    pub total_segments: u32,
+    pub segment_number: u32,
+    pub data: Vec<u8>,
+}
+
+/// Server CRC of a past tick, trailing the tick closures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfirmRecord {
+    /// 0x02 or 0x03
+    pub kind: u8,
+    pub flag: u8,
+    pub crc: u32,
+    pub tick: u32,
+}
+
+/// A synchronizer action and the peer it concerns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerSyncAction {
+    pub peer_id: u16,
+    pub action: SynchronizerAction,
+}
+
+impl ServerHeartbeat {
+    /// Decode a complete (unfragmented or reassembled) heartbeat datagram.
+    ///
+    /// `last_player_index` is where the player deltas of the first action start
+    /// from: 0xFFFF for a fresh connection, then the previous heartbeat's
+    /// `last_player_index`. Error offsets count from the start of `packet`.
+    pub fn parse(packet: &[u8], last_player_index: u16) -> Result<Self> {
+        Self::parse_numbered(packet, last_player_index, &ActionNumbering::CANONICAL)
+    }
+
+    /// `parse` for a build whose action ids differ from the codec's
+    pub fn parse_numbered(packet: &[u8], last_player_index: u16, numbering: &ActionNumbering) -> Result<Self> {
+        match Self::parse_partial(packet, last_player_index, numbering) {
+            (heartbeat, None) => Ok(heartbeat),
+            (_, Some(e)) => Err(e),
+        }
+    }
+
+    /// Decode as much as possible. On an error the heartbeat holds everything
+    /// before it: earlier closures and confirm records, and the failing closure
+    /// with its tick and the actions before the one that did not decode.
+    pub fn parse_partial(packet: &[u8], last_player_index: u16, numbering: &ActionNumbering) -> (Self, Option<Error>) {
+        let mut parser = Parser {
+            reader: BinaryReader::new(&[]),
+            base: 0,
+            numbering,
+            out: ServerHeartbeat {
+                flags: DeserializationMask::empty(),
+                sequence: 0,
+                tick_closures: Vec::new(),
+                confirm_records: Vec::new(),
+                sync_actions: Vec::new(),
+                heartbeat_requests: Vec::new(),
+                last_player_index,
+            },
+        };
+        let result = Self::check_header(packet).and_then(|payload_start| {
+            parser.reader = BinaryReader::new(&packet[payload_start..]);
+            parser.base = payload_start;
+            parser.heartbeat()
+        });
+        (parser.out, result.err())
+    }
+
+    fn check_header(packet: &[u8]) -> Result<usize> {
+        let (header, payload_start) = PacketHeader::parse(packet)
+            .map_err(|e| malformed(0, format!("header: {}", e)))?;
+        if header.message_type != MessageType::ServerToClientHeartbeat {
+            return Err(malformed(0, format!("not a server heartbeat ({:?})", header.message_type)));
+        }
+        if header.fragmented {
+            return Err(malformed(0, "fragment; reassemble first"));
+        }
+        Ok(payload_start)
+    }
+
+    /// Tick of the last closure, if any
+    pub fn last_tick(&self) -> Option<u64> {
+        self.tick_closures.last().map(|c| c.tick)
+    }
+}
+
+fn malformed(offset: usize, reason: impl Into<String>) -> Error {
+    Error::MalformedHeartbeat { offset, reason: reason.into() }
+}
+
+struct Parser<'a> {
+    reader: BinaryReader<'a>,
+    /// Offset of the payload within the datagram
+    base: usize,
+    numbering: &'a ActionNumbering,
+    /// Everything decoded so far
+    out: ServerHeartbeat,
+}
+
+impl<'a> Parser<'a> {
+    fn offset(&self) -> usize {
+        self.base + self.reader.position()
+    }
+
+    /// Run `read`, tagging any failure with `what` and the offset it started at.
+    fn field<T>(&mut self, what: &str, read: impl FnOnce(&mut BinaryReader<'a>) -> Result<T>) -> Result<T> {
+        let offset = self.offset();
+        read(&mut self.reader).map_err(|e| malformed(offset, format!("{}: {}", what, e)))
+    }
+
+    fn count(&mut self, what: &str, max: u32) -> Result<u32> {
+        let offset = self.offset();
+        let count = self.field(what, |r| r.read_opt_u32())?;
+        if count > max {
+            return Err(malformed(offset, format!("{} {} exceeds {}", what, count, max)));
+        }
+        Ok(count)
+    }
+
+    fn heartbeat(&mut self) -> Result<()> {
+        let flags_offset = self.offset();
+        let flag_bits = self.field("flags", |r| r.read_u8())?;
+        let flags = DeserializationMask::from_bits(flag_bits)
+            .ok_or_else(|| malformed(flags_offset, format!("unknown flags {:#04x}", flag_bits)))?;
+        self.out.flags = flags;
+        self.out.sequence = self.field("heartbeat sequence", |r| r.read_u32_le())?;
+
+        if flags.intersects(DeserializationMask::HAS_TICK_CLOSURES | DeserializationMask::SINGLE_TICK_CLOSURE) {
+            let count = if flags.contains(DeserializationMask::SINGLE_TICK_CLOSURE) {
+                1
+            } else {
+                self.count("tick closure count", MAX_TICK_CLOSURES)?
+            };
+            for n in 0..count {
+                let load_tick_only = flags.contains(DeserializationMask::LOAD_TICK_ONLY);
+                let tick = self.tick_closure(load_tick_only)?;
+                while self.confirm_record_follows(flags, n + 1 < count, tick) {
+                    let record = self.confirm_record()?;
+                    self.out.confirm_records.push(record);
+                }
+            }
+        }
+
+        if flags.contains(DeserializationMask::HAS_SYNC_ACTIONS) {
+            for _ in 0..self.count("sync action count", MAX_SYNC_ACTIONS)? {
+                let action = self.sync_action()?;
+                self.out.sync_actions.push(action);
+            }
+        }
+
+        if flags.contains(DeserializationMask::HAS_REQUESTS) {
+            for _ in 0..self.count("heartbeat request count", MAX_SYNC_ACTIONS)? {
+                let request = self.field("heartbeat request", |r| r.read_u32_le())?;
+                self.out.heartbeat_requests.push(request);
+            }
+        }
+
+        if !self.reader.is_empty() {
+            return Err(malformed(self.offset(), format!("{} trailing bytes", self.reader.remaining())));
+        }
+        Ok(())
+    }
+
+    /// Read one closure into `out`, returning its tick.
+    fn tick_closure(&mut self, load_tick_only: bool) -> Result<u64> {
+        let tick = self.field("closure tick", |r| r.read_u64_le())?;
+        self.out.tick_closures.push(ServerTickClosure { tick, actions: Vec::new(), segments: Vec::new() });
+        if load_tick_only {
+            return Ok(tick);
+        }
+
+        let count_and_segments = self.count("closure action count", MAX_CLOSURE_ACTIONS * 2 + 1)?;
+        for _ in 0..count_and_segments / 2 {
+            let delta = self.field("player delta", |r| r.read_opt_u16())?;
+            let player_index = self.out.last_player_index.wrapping_add(delta);
+            let action = self.input_action()?;
+            self.out.last_player_index = player_index;
+            self.closure().actions.push(TickInputAction { player_index, action });
+        }
+        if count_and_segments & 1 != 0 {
+            for _ in 0..self.count("segment count", MAX_CLOSURE_ACTIONS)? {
+                let segment = self.segment()?;
+                self.closure().segments.push(segment);
+            }
+        }
+        Ok(tick)
+    }
+
+    fn closure(&mut self) -> &mut ServerTickClosure {
+        self.out.tick_closures.last_mut().expect("closure pushed before its actions")
+    }
+
+    /// S2C actions carry the type as opt_u16 rather than the codec's type encoding,
+    /// so the data is read for the type on its own.
+    fn input_action(&mut self) -> Result<InputAction> {
+        let type_offset = self.offset();
+        let action_type = self.field("action type", |r| r.read_opt_u16())?;
+        InputAction::read_data_numbered(action_type, &mut self.reader, self.numbering)
+            .map_err(|e| malformed(type_offset, format!("input action type {}: {}", action_type, e)))
+    }
+
+    fn segment(&mut self) -> Result<ActionSegment> {
+        let action_type = self.field("segment action type", |r| r.read_opt_u16())?;
+        let id = self.field("segment id", |r| r.read_u32_le())?;
+        let player_index = self.field("segment player", |r| r.read_opt_u16())?;
+        let total_segments = self.field("segment total", |r| r.read_opt_u32())?;
+        let segment_number = self.field("segment number", |r| r.read_opt_u32())?;
+        let data = self.field("segment data", |r| {
+            let len = r.read_opt_u32()? as usize;
+            Ok(r.read_bytes(len)?.to_vec())
+        })?;
+        Ok(ActionSegment { action_type, id, player_index, total_segments, segment_number, data })
+    }
+
+    /// Whether a confirm record comes next, judged by whether the bytes can
+    /// be what the flags announce after closure `tick` instead.
+    fn confirm_record_follows(&self, flags: DeserializationMask, more_closures: bool, tick: u64) -> bool {
+        let rest = self.reader.remaining_slice();
+        if rest.is_empty() {
+            return false;
+        }
+        let mut peek = BinaryReader::new(rest);
+        if more_closures {
+            // The next closure is for a later tick, within the closure limit
+            return peek
+                .read_u64_le()
+                .map_or(true, |next| next <= tick || next - tick > MAX_TICK_CLOSURES as u64);
+        }
+        if flags.contains(DeserializationMask::HAS_SYNC_ACTIONS) {
+            // A record's kind would be the list count and its CRC type the first sync action type
+            return match (peek.read_opt_u32(), peek.read_u8()) {
+                (Ok(0), _) => false,
+                (Ok(_), Ok(type_byte)) => self.numbering.sync_action_type(type_byte).is_none(),
+                _ => true,
+            };
+        }
+        if flags.contains(DeserializationMask::HAS_REQUESTS) {
+            return peek
+                .read_opt_u32()
+                .map_or(true, |count| peek.remaining() != count as usize * 4);
+        }
+        true
+    }
+
+    /// One CheckCRCHeuristic entry: [u8 02|03][opt_u16 type][u8 flag][u32 crc][u64 tick]
+    fn confirm_record(&mut self) -> Result<ConfirmRecord> {
+        let kind_offset = self.offset();
+        let kind = self.field("confirm record kind", |r| r.read_u8())?;
+        if !matches!(kind, 0x02 | 0x03) {
+            return Err(malformed(kind_offset, format!("confirm record kind {:#04x}", kind)));
+        }
+        let type_offset = self.offset();
+        let action_type = self.field("confirm record type", |r| r.read_opt_u16())?;
+        if self.numbering.input_action_type(action_type) != Some(InputActionType::CheckCRCHeuristic) {
+            return Err(malformed(type_offset, format!("confirm record type {} is not CheckCRCHeuristic", action_type)));
+        }
+        let flag = self.field("confirm record flag", |r| r.read_u8())?;
+        let crc = self.field("confirm record crc", |r| r.read_u32_le())?;
+        let tick_offset = self.offset();
+        let tick = self.field("confirm record tick", |r| r.read_u64_le())?;
+        let tick = u32::try_from(tick)
+            .map_err(|_| malformed(tick_offset, format!("confirm record tick {} out of range", tick)))?;
+        Ok(ConfirmRecord { kind, flag, crc, tick })
+    }
+
+    fn sync_action(&mut self) -> Result<ServerSyncAction> {
+        let type_offset = self.offset();
+        let type_byte = self.field("sync action type", |r| r.read_u8())?;
+        let action_type = self.numbering.sync_action_type(type_byte)
+            .ok_or_else(|| malformed(type_offset, format!("unknown sync action type {:#04x}", type_byte)))?;
+        let peer_id = self.field("sync action peer", |r| r.read_opt_u16())?;
+        let action = self.field(&format!("{:?}", action_type), |r| SynchronizerAction::read_data(r, action_type))?;
+        Ok(ServerSyncAction { peer_id, action })
+    }
+}
+
+impl ActionSegment {
+    /// Cut an action into segments of at most `max_len` bytes of `data`, which
+    /// is the player index as opt_u16 followed by the action's data.
+    pub fn split(action_type: u16, id: u32, player_index: u16, data: &[u8], max_len: usize) -> Vec<Self> {
+        let parts: Vec<&[u8]> = if data.is_empty() { vec![data] } else { data.chunks(max_len).collect() };
+        let total_segments = parts.len() as u32;
+        parts
+            .into_iter()
+            .enumerate()
+            .map(|(n, part)| Self {
+                action_type,
+                id,
+                player_index,
+                total_segments,
+                segment_number: n as u32,
+                data: part.to_vec(),
+            })
+            .collect()
+    }
+
+    pub fn write(&self, writer: &mut BinaryWriter) {
+        writer.write_opt_u16(self.action_type);
+        writer.write_u32_le(self.id);
+        writer.write_opt_u16(self.player_index);
+        writer.write_opt_u32(self.total_segments);
+        writer.write_opt_u32(self.segment_number);
+        writer.write_opt_u32(self.data.len() as u32);
+        writer.write_bytes(&self.data);
+    }
+}
+
+#[derive(Debug)]
+struct SegmentedAction {
+    action_type: u16,
+    id: u32,
+    player_index: u16,
+    total_segments: u32,
+    parts: BTreeMap<u32, Vec<u8>>,
+}
+
+/// Collects segments per player and segment id until their action is complete
+#[derive(Debug, Default)]
+pub struct SegmentAssembler {
+    pending: VecDeque<SegmentedAction>,
+}
+
+impl SegmentAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add one segment. Returns the action once every segment has arrived;
+    /// an action whose data does not decode is dropped with the error.
+    pub fn insert(&mut self, segment: ActionSegment, numbering: &ActionNumbering) -> Result<Option<TickInputAction>> {
+        if segment.segment_number >= segment.total_segments {
+            return Err(Error::InvalidPacket(format!(
+                "segment {} of {} for action {}",
+                segment.segment_number, segment.total_segments, segment.id
+            )));
+        }
+        let index = match self
+            .pending
+            .iter()
+            .position(|p| p.player_index == segment.player_index && p.id == segment.id)
+        {
+            Some(index) => index,
+            None => {
+                if self.pending.len() >= MAX_PENDING_SEGMENTED {
+                    self.pending.pop_front();
+                }
+                self.pending.push_back(SegmentedAction {
+                    action_type: segment.action_type,
+                    id: segment.id,
+                    player_index: segment.player_index,
+                    total_segments: segment.total_segments,
+                    parts: BTreeMap::new(),
+                });
+                self.pending.len() - 1
+            }
+        };
+        let entry = &mut self.pending[index];
+        entry.parts.insert(segment.segment_number, segment.data);
+        if entry.parts.len() < entry.total_segments as usize {
+            return Ok(None);
+        }
+        let Some(entry) = self.pending.remove(index) else {
+            return Ok(None);
+        };
+
+        let data: Vec<u8> = entry.parts.into_values().flatten().collect();
+        let mut reader = BinaryReader::new(&data);
+        let decoded = reader
+            .read_opt_u16()
+            .and_then(|_| InputAction::read_data_numbered(entry.action_type, &mut reader, numbering));
+        let action = decoded.map_err(|e| {
+            Error::InvalidPacket(format!("segmented action type {} (id {}): {}", entry.action_type, entry.id, e))
+        })?;
+        Ok(Some(TickInputAction { player_index: entry.player_index, action }))
+    }
+
+    /// Actions still waiting for segments
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{InputActionType, MapReadyForDownload, SynchronizerActionType};
+
+    fn heartbeat(flags: u8, body: impl FnOnce(&mut BinaryWriter)) -> Vec<u8> {
+        let mut writer = BinaryWriter::new();
+        writer.write_u8(MessageType::ServerToClientHeartbeat as u8);
+        writer.write_u8(flags);
+        writer.write_u32_le(77);
+        body(&mut writer);
+        writer.into_vec()
+    }
+
+    #[test]
+    fn test_parse_closures_and_sync_actions() {
+        let packet = heartbeat(0x16, |w| {
+            w.write_u64_le(216_010);
+            w.write_opt_u32(2 * 2);
+            w.write_opt_u16(1); // 0xFFFF + 1 = player 0
+            w.write_opt_u16(InputAction::StopWalking.action_type() as u16);
+            w.write_opt_u16(3); // player 3
+            w.write_opt_u16(InputAction::StopWalking.action_type() as u16);
+            // confirm record
+            w.write_bytes(&[0x02, 0x52, 0x00]);
+            w.write_u32_le(0xdeadbeef);
+            w.write_u32_le(216_008);
+            w.write_u32_le(0);
+            w.write_opt_u32(2);
+            w.write_u8(SynchronizerActionType::ClientShouldStartSendingTickClosures as u8);
+            w.write_opt_u16(4);
+            w.write_u32_le(216_016);
+            w.write_u8(SynchronizerActionType::MapReadyForDownload as u8);
+            w.write_opt_u16(4);
+            MapReadyForDownload { transfer_size: 4000, map_tick: 216_000, ..MapReadyForDownload::default() }.write(w);
+        });
+
+        let hb = ServerHeartbeat::parse(&packet, 0xFFFF).unwrap();
+        assert_eq!(hb.sequence, 77);
+        assert_eq!(hb.last_tick(), Some(216_010));
+        let players: Vec<_> = hb.tick_closures[0].actions.iter().map(|a| a.player_index).collect();
+        assert_eq!(players, vec![0, 3]);
+        assert_eq!(hb.last_player_index, 3);
+        assert_eq!(hb.confirm_records, vec![ConfirmRecord { kind: 2, flag: 0, crc: 0xdeadbeef, tick: 216_008 }]);
+        assert_eq!(hb.sync_actions[0], ServerSyncAction {
+            peer_id: 4,
+            action: SynchronizerAction::ClientShouldStartSendingTickClosures { tick: 216_016 },
+        });
+        assert!(matches!(
+            &hb.sync_actions[1].action,
+            SynchronizerAction::MapReadyForDownload(info) if info.transfer_size == 4000 && info.map_tick == 216_000
+        ));
+    }
+
+    #[test]
+    fn test_parse_numbered() {
+        use InputActionType as I;
+        use SynchronizerActionType as S;
+        const INPUT: &[InputActionType] = &[I::Nothing, I::StopMining, I::StopWalking];
+        const SYNC: &[SynchronizerActionType] = &[S::GameEnd, S::ClientShouldStartSendingTickClosures];
+        let numbering = ActionNumbering { input_actions: Some(INPUT), sync_actions: Some(SYNC) };
+        let packet = heartbeat(0x14, |w| {
+            w.write_u64_le(100);
+            w.write_opt_u32(2);
+            w.write_opt_u16(1);
+            w.write_opt_u16(2);
+            w.write_opt_u32(1);
+            w.write_u8(1);
+            w.write_opt_u16(4);
+            w.write_u32_le(120);
+        });
+
+        let hb = ServerHeartbeat::parse_numbered(&packet, 0xFFFF, &numbering).unwrap();
+        assert!(matches!(hb.tick_closures[0].actions[0].action, InputAction::StopWalking));
+        assert_eq!(hb.sync_actions[0].action, SynchronizerAction::ClientShouldStartSendingTickClosures { tick: 120 });
+        // Canonically id 2 is BeginMining, which has data; the packet doesn't parse
+        assert!(ServerHeartbeat::parse(&packet, 0xFFFF).is_err());
+    }
+
+    #[test]
+    fn test_empty_closure_and_requests() {
+        let packet = heartbeat(0x0f, |w| {
+            w.write_u64_le(5000);
+            w.write_opt_u32(1);
+            w.write_u32_le(42);
+        });
+        let hb = ServerHeartbeat::parse(&packet, 9).unwrap();
+        assert!(hb.tick_closures[0].actions.is_empty());
+        assert_eq!(hb.heartbeat_requests, vec![42]);
+        assert_eq!(hb.last_player_index, 9);
+    }
+
+    fn confirm_record(w: &mut BinaryWriter, crc: u32, tick: u64) {
+        w.write_u8(0x02);
+        w.write_opt_u16(InputActionType::CheckCRCHeuristic as u16);
+        w.write_u8(0);
+        w.write_u32_le(crc);
+        w.write_u64_le(tick);
+    }
+
+    #[test]
+    fn test_confirm_records_between_closures_and_before_requests() {
+        let packet = heartbeat(0x03, |w| {
+            w.write_opt_u32(2);
+            w.write_u64_le(300);
+            w.write_opt_u32(0);
+            confirm_record(w, 0x11, 298);
+            confirm_record(w, 0x22, 299);
+            w.write_u64_le(301);
+            w.write_opt_u32(0);
+            confirm_record(w, 0x33, 300);
+            w.write_opt_u32(1);
+            w.write_u32_le(0x0252_0002);
+        });
+        let hb = ServerHeartbeat::parse(&packet, 0xFFFF).unwrap();
+        let ticks: Vec<_> = hb.tick_closures.iter().map(|c| c.tick).collect();
+        assert_eq!(ticks, vec![300, 301]);
+        let records: Vec<_> = hb.confirm_records.iter().map(|r| (r.crc, r.tick)).collect();
+        assert_eq!(records, vec![(0x11, 298), (0x22, 299), (0x33, 300)]);
+        assert_eq!(hb.heartbeat_requests, vec![0x0252_0002]);
+
+        // A record's tick is a u64 on the wire, but never beyond u32
+        let packet = heartbeat(0x06, |w| {
+            w.write_u64_le(300);
+            w.write_opt_u32(0);
+            confirm_record(w, 0x11, 1 << 40);
+        });
+        match ServerHeartbeat::parse(&packet, 0xFFFF) {
+            Err(Error::MalformedHeartbeat { offset, reason }) => {
+                assert_eq!(offset, 6 + 9 + 7);
+                assert!(reason.contains("out of range"), "{}", reason);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_partial_keeps_progress_before_error() {
+        let packet = heartbeat(0x02, |w| {
+            w.write_opt_u32(2);
+            w.write_u64_le(300);
+            w.write_opt_u32(0);
+            confirm_record(w, 0x11, 299);
+            w.write_u64_le(301);
+            w.write_opt_u32(2 * 2);
+            w.write_opt_u16(1);
+            w.write_opt_u16(InputAction::StopWalking.action_type() as u16);
+            w.write_opt_u16(2);
+            w.write_opt_u16(InputActionType::StartWalking as u16); // direction missing
+        });
+        let (hb, error) = ServerHeartbeat::parse_partial(&packet, 0xFFFF, &ActionNumbering::CANONICAL);
+        assert!(matches!(error, Some(Error::MalformedHeartbeat { .. })), "{:?}", error);
+        assert_eq!(hb.last_tick(), Some(301));
+        assert_eq!(hb.confirm_records.len(), 1);
+        assert_eq!(hb.tick_closures[1].actions.len(), 1);
+        assert_eq!(hb.last_player_index, 0);
+        assert!(ServerHeartbeat::parse(&packet, 0xFFFF).is_err());
+    }
+
+    #[test]
+    fn test_errors_carry_offset() {
+        // Unknown sync action type: [type][flags][u32 seq][count] puts it at 7
+        let packet = heartbeat(0x10, |w| {
+            w.write_opt_u32(1);
+            w.write_u8(0x7f);
+        });
+        match ServerHeartbeat::parse(&packet, 0xFFFF) {
+            Err(Error::MalformedHeartbeat { offset, reason }) => {
+                assert_eq!(offset, 7);
+                assert!(reason.contains("0x7f"), "{}", reason);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // Truncated closure tick
+        let packet = heartbeat(0x0e, |w| w.write_u32_le(5000));
+        assert!(matches!(ServerHeartbeat::parse(&packet, 0xFFFF), Err(Error::MalformedHeartbeat { offset: 6, .. })));
+
+        // Leftover bytes are not silently ignored
+        let packet = heartbeat(0x00, |w| w.write_u8(0xaa));
+        assert!(matches!(ServerHeartbeat::parse(&packet, 0xFFFF), Err(Error::MalformedHeartbeat { offset: 6, .. })));
+    }
+
+    #[test]
+    fn test_segmented_action_round_trip() {
+        let message: String = (0..2500u32).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+        let action = InputAction::WriteToConsole { message: message.clone() };
+        let mut data = BinaryWriter::new();
+        data.write_opt_u16(3);
+        action.write_data(&mut data);
+        let segments = ActionSegment::split(0x68, 9, 3, data.as_slice(), MAX_SEGMENT_DATA);
+        assert_eq!(segments.len(), 3);
+        assert!(segments.iter().all(|s| s.total_segments == 3 && s.data.len() <= MAX_SEGMENT_DATA));
+
+        // One closure per segment, arriving out of order
+        let packet = heartbeat(0x02, |w| {
+            w.write_opt_u32(3);
+            for (tick, n) in [(100u64, 2usize), (101, 0), (102, 1)] {
+                w.write_u64_le(tick);
+                w.write_opt_u32(1);
+                w.write_opt_u32(1);
+                segments[n].write(w);
+            }
+        });
+        let hb = ServerHeartbeat::parse(&packet, 0xFFFF).unwrap();
+        let mut assembler = SegmentAssembler::new();
+        let mut done = Vec::new();
+        for closure in hb.tick_closures {
+            assert!(closure.actions.is_empty());
+            for segment in closure.segments {
+                if let Some(action) = assembler.insert(segment, &ActionNumbering::CANONICAL).unwrap() {
+                    done.push((closure.tick, action));
+                }
+            }
+        }
+        assert_eq!(done.len(), 1);
+        assert_eq!(done[0].0, 102);
+        assert_eq!(done[0].1.player_index, 3);
+        assert!(matches!(&done[0].1.action, InputAction::WriteToConsole { message: m } if *m == message));
+        assert_eq!(assembler.pending(), 0);
+
+        let bad = ActionSegment { segment_number: 1, total_segments: 1, ..segments[0].clone() };
+        assert!(assembler.insert(bad, &ActionNumbering::CANONICAL).is_err());
+    }
+}
@@ -0,0 +1,395 @@
+//! Windowed map download
+//!
+//! `BlockDownloader` decides which TransferBlocks to request and when; the caller
+//! does the I/O. It keeps at most `window` requests outstanding, re-requests a
+//! block whose answer is overdue, and sizes the window like TCP congestion
+//! control: it doubles per round trip until the first loss, then grows by one
+//! block per round trip and halves whenever requests time out.
+//!
+//! As gaps fill, blocks move front to back into one buffer holding the start
+//! of the save, and are dropped; `drain_in_order` hands out what joined it, so
+//! the save can be parsed while the rest is still arriving. `into_save` returns
+//! that buffer once complete, without copying it again.
+//!
+//! A download that fails part-way keeps its blocks. `Connection` resumes from it
+//! when the server offers the same save again (same size and CRC).
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use super::message::TRANSFER_BLOCK_SIZE;
+
+const INITIAL_WINDOW: f64 = 256.0;
+const MIN_WINDOW: f64 = 16.0;
+const MAX_WINDOW: f64 = 8192.0;
+/// Retransmit timeout before the first round trip has been measured
+const INITIAL_RTO: Duration = Duration::from_millis(250);
+const MIN_RTO: Duration = Duration::from_millis(50);
+const MAX_RTO: Duration = Duration::from_secs(2);
+
+/// Snapshot of a running download, reported through `GameEvent::MapDownloadProgress`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MapDownloadProgress {
+    pub blocks_received: u32,
+    pub blocks_total: u32,
+    pub bytes_received: u64,
+    pub bytes_total: u64,
+    /// Requests currently allowed in flight
+    pub window: u32,
+    pub retransmits: u64,
+    /// Fraction of requests that had to be repeated
+    pub loss_rate: f64,
+    /// Time since this attempt started (a resumed download restarts the clock)
+    pub elapsed: Duration,
+}
+
+impl MapDownloadProgress {
+    /// 0.0 to 1.0
+    pub fn fraction(&self) -> f64 {
+        if self.blocks_total == 0 {
+            return 0.0;
+        }
+        self.blocks_received as f64 / self.blocks_total as f64
+    }
+}
+
+#[derive(Debug)]
+struct Outstanding {
+    sent_at: Instant,
+    retries: u32,
+}
+
+/// Per-block bookkeeping for one map transfer
+#[derive(Debug)]
+pub struct BlockDownloader {
+    transfer_size: u32,
+    /// CRC from MapReadyForDownload; without it a partial download is never resumed
+    crc: Option<u32>,
+    blocks: Vec<Option<Vec<u8>>>,
+    received: u32,
+    bytes_received: u64,
+    outstanding: BTreeMap<u32, Outstanding>,
+    /// Lowest block never requested in this attempt
+    next_block: u32,
+    window: f64,
+    /// Slow-start threshold; the window doubles per round trip below it
+    threshold: f64,
+    srtt: Option<Duration>,
+    requests: u64,
+    retransmits: u64,
+    duplicates: u64,
+    started: Instant,
+    last_block_at: Instant,
+    last_backoff: Option<Instant>,
+    /// The contiguous start of the save, blocks `..delivered` of it
+    save: Vec<u8>,
+    delivered: u32,
+    /// Bytes of `save` already handed out by `drain_in_order`
+    handed_out: usize,
+}
+
+impl BlockDownloader {
+    pub fn new(transfer_size: u32, crc: Option<u32>, now: Instant) -> Self {
+        let mut download = Self {
+            transfer_size,
+            crc,
+            blocks: Vec::new(),
+            received: 0,
+            bytes_received: 0,
+            outstanding: BTreeMap::new(),
+            next_block: 0,
+            window: INITIAL_WINDOW,
+            threshold: MAX_WINDOW,
+            srtt: None,
+            requests: 0,
+            retransmits: 0,
+            duplicates: 0,
+            started: now,
+            last_block_at: now,
+            last_backoff: None,
+            save: Vec::new(),
+            delivered: 0,
+            handed_out: 0,
+        };
+        download.blocks.resize(download.block_count() as usize, None);
+        download
+    }
+
+    /// A download with nothing left to fetch, for a save obtained some other
+    /// way (see `SharedMaps`). It holds no blocks.
+    pub fn finished(transfer_size: u32, crc: Option<u32>, now: Instant) -> Self {
+        let mut download = Self::new(transfer_size, crc, now);
+        download.received = download.block_count();
+        download.bytes_received = transfer_size as u64;
+        download
+    }
+
+    pub fn block_count(&self) -> u32 {
+        (self.transfer_size as usize).div_ceil(TRANSFER_BLOCK_SIZE).max(1) as u32
+    }
+
+    pub fn transfer_size(&self) -> u32 {
+        self.transfer_size
+    }
+
+    /// Whether this (partial) download is for the save described by `transfer_size` and `crc`.
+    pub fn matches(&self, transfer_size: u32, crc: Option<u32>) -> bool {
+        self.crc.is_some() && self.crc == crc && self.transfer_size == transfer_size
+    }
+
+    /// Heuristic transfer-size detection can find a larger size mid-download.
+    pub fn grow(&mut self, transfer_size: u32) {
+        if transfer_size > self.transfer_size {
+            self.transfer_size = transfer_size;
+            self.blocks.resize(self.block_count() as usize, None);
+        }
+    }
+
+    /// Start a new attempt, keeping the blocks received so far. `drain_in_order`
+    /// starts over from the first block.
+    pub fn resume(&mut self, now: Instant) {
+        self.handed_out = 0;
+        self.outstanding.clear();
+        self.next_block = 0;
+        self.window = INITIAL_WINDOW;
+        self.threshold = MAX_WINDOW;
+        self.started = now;
+        self.last_block_at = now;
+        self.last_backoff = None;
+    }
+
+    fn rto(&self) -> Duration {
+        self.srtt.map_or(INITIAL_RTO, |srtt| (srtt * 2).clamp(MIN_RTO, MAX_RTO))
+    }
+
+    /// Blocks to request now: overdue ones again, then new ones up to the window.
+    pub fn poll(&mut self, now: Instant) -> Vec<u32> {
+        let rto = self.rto();
+        let mut requests: Vec<u32> = self
+            .outstanding
+            .iter_mut()
+            .filter(|(_, o)| now.duration_since(o.sent_at) >= rto)
+            .map(|(&block, o)| {
+                o.sent_at = now;
+                o.retries += 1;
+                block
+            })
+            .collect();
+        self.retransmits += requests.len() as u64;
+        // Halve at most once per timeout period, however many blocks were lost.
+        if !requests.is_empty() && self.last_backoff.is_none_or(|t| now.duration_since(t) >= rto) {
+            self.threshold = (self.window / 2.0).max(MIN_WINDOW);
+            self.window = self.threshold;
+            self.last_backoff = Some(now);
+        }
+
+        let total = self.block_count();
+        while (self.outstanding.len() as f64) < self.window.floor() && self.next_block < total {
+            let block = self.next_block;
+            self.next_block += 1;
+            if self.has_block(block) {
+                continue;
+            }
+            self.outstanding.insert(block, Outstanding { sent_at: now, retries: 0 });
+            requests.push(block);
+        }
+        self.requests += requests.len() as u64;
+        requests
+    }
+
+    fn has_block(&self, block: u32) -> bool {
+        block < self.delivered || self.blocks[block as usize].is_some()
+    }
+
+    /// Store a received block. Returns false for duplicates and blocks past the end.
+    pub fn on_block(&mut self, block: u32, data: Vec<u8>, now: Instant) -> bool {
+        if block >= self.block_count() {
+            return false;
+        }
+        if self.has_block(block) {
+            self.duplicates += 1;
+            return false;
+        }
+        let slot = &mut self.blocks[block as usize];
+        self.bytes_received += data.len() as u64;
+        *slot = Some(data);
+        self.received += 1;
+        self.last_block_at = now;
+
+        if let Some(request) = self.outstanding.remove(&block) {
+            // Only first tries give an unambiguous round trip (Karn)
+            if request.retries == 0 {
+                let sample = now.duration_since(request.sent_at);
+                self.srtt = Some(self.srtt.map_or(sample, |srtt| (srtt * 7 + sample) / 8));
+            }
+        }
+        self.window = if self.window < self.threshold {
+            self.window + 1.0
+        } else {
+            self.window + 1.0 / self.window
+        }
+        .min(MAX_WINDOW);
+        true
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.received >= self.block_count()
+    }
+
+    pub fn missing(&self) -> u32 {
+        self.block_count().saturating_sub(self.received)
+    }
+
+    /// Time since a new block last arrived (or since the attempt started)
+    pub fn idle_for(&self, now: Instant) -> Duration {
+        now.duration_since(self.last_block_at)
+    }
+
+    pub fn loss_rate(&self) -> f64 {
+        if self.requests == 0 {
+            return 0.0;
+        }
+        self.retransmits as f64 / self.requests as f64
+    }
+
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    pub fn progress(&self, now: Instant) -> MapDownloadProgress {
+        MapDownloadProgress {
+            blocks_received: self.received,
+            blocks_total: self.block_count(),
+            bytes_received: self.bytes_received,
+            bytes_total: self.transfer_size as u64,
+            window: self.window as u32,
+            retransmits: self.retransmits,
+            loss_rate: self.loss_rate(),
+            elapsed: now.duration_since(self.started),
+        }
+    }
+
+    /// Bytes that joined the contiguous start of the save since the last call.
+    /// The last block waits for the download to complete, since heuristic size
+    /// detection may still grow the transfer.
+    pub fn drain_in_order(&mut self) -> Option<&[u8]> {
+        let ready = if self.is_complete() { self.block_count() } else { self.block_count() - 1 };
+        while self.delivered < ready {
+            let Some(block) = self.blocks[self.delivered as usize].take() else {
+                break;
+            };
+            let len = block.len().min(self.transfer_size as usize - self.save.len());
+            self.save.extend_from_slice(&block[..len]);
+            self.delivered += 1;
+        }
+        let start = std::mem::replace(&mut self.handed_out, self.save.len());
+        (start < self.save.len()).then(|| &self.save[start..])
+    }
+
+    /// The save, once every block is in.
+    pub fn into_save(mut self) -> Option<Vec<u8>> {
+        if !self.is_complete() {
+            return None;
+        }
+        self.drain_in_order();
+        Some(self.save)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(n: u32) -> Vec<u8> {
+        vec![n as u8; TRANSFER_BLOCK_SIZE]
+    }
+
+    #[test]
+    fn test_window_and_retransmit() {
+        let start = Instant::now();
+        let total = 1000u32;
+        let mut download = BlockDownloader::new(total * TRANSFER_BLOCK_SIZE as u32 - 3, Some(7), start);
+        assert_eq!(download.block_count(), total);
+
+        let first = download.poll(start);
+        assert_eq!(first, (0..INITIAL_WINDOW as u32).collect::<Vec<_>>());
+        assert!(download.poll(start).is_empty());
+
+        // Everything but block 5 arrives after 20ms; the window grows past its start.
+        let t = start + Duration::from_millis(20);
+        for &n in &first {
+            if n != 5 {
+                assert!(download.on_block(n, block(n), t));
+            }
+        }
+        assert!(!download.on_block(1, block(1), t));
+        assert_eq!(download.duplicates(), 1);
+        let next = download.poll(t);
+        assert_eq!(next[0], INITIAL_WINDOW as u32);
+        assert!(download.progress(t).window > INITIAL_WINDOW as u32);
+
+        // Block 5 is overdue after 2 x srtt and is asked for again; the window halves.
+        let window_before = download.progress(t).window;
+        let t = t + Duration::from_millis(45);
+        let retry = download.poll(t);
+        assert!(retry.contains(&5));
+        assert!(download.progress(t).window <= window_before / 2 + 1);
+        assert!(download.loss_rate() > 0.0);
+
+        let t = t + Duration::from_millis(10);
+        download.on_block(5, block(5), t);
+        while !download.is_complete() {
+            for n in download.poll(t) {
+                download.on_block(n, block(n), t);
+            }
+        }
+        let transfer_size = download.transfer_size() as usize;
+        let data = download.into_save().unwrap();
+        assert_eq!(data.len(), transfer_size);
+        assert_eq!(data[5 * TRANSFER_BLOCK_SIZE], 5);
+    }
+
+    #[test]
+    fn test_resume_skips_received_blocks() {
+        let start = Instant::now();
+        let mut download = BlockDownloader::new(10 * TRANSFER_BLOCK_SIZE as u32, Some(1), start);
+        for n in download.poll(start) {
+            if n % 2 == 0 {
+                download.on_block(n, block(n), start);
+            }
+        }
+        assert_eq!(download.missing(), 5);
+        assert!(download.matches(10 * TRANSFER_BLOCK_SIZE as u32, Some(1)));
+        assert!(!download.matches(10 * TRANSFER_BLOCK_SIZE as u32, Some(2)));
+
+        download.resume(start);
+        assert_eq!(download.poll(start), vec![1, 3, 5, 7, 9]);
+        assert!(download.into_save().is_none());
+    }
+
+    #[test]
+    fn test_drain_in_order() {
+        let start = Instant::now();
+        let mut download = BlockDownloader::new(3 * TRANSFER_BLOCK_SIZE as u32 + 10, None, start);
+        download.on_block(1, block(1), start);
+        assert_eq!(download.drain_in_order(), None);
+        download.on_block(0, block(0), start);
+        assert_eq!(download.drain_in_order().unwrap().len(), 2 * TRANSFER_BLOCK_SIZE);
+        // The last block is held back until everything is in
+        download.on_block(3, block(3), start);
+        assert_eq!(download.drain_in_order(), None);
+        download.on_block(2, block(2), start);
+        let tail = download.drain_in_order().unwrap();
+        assert_eq!(tail.len(), TRANSFER_BLOCK_SIZE + 10);
+        assert_eq!(tail[TRANSFER_BLOCK_SIZE], 3);
+        assert_eq!(download.drain_in_order(), None);
+
+        // Drained blocks are not kept twice
+        assert!(download.blocks.iter().all(Option::is_none));
+
+        download.resume(start);
+        let again = download.drain_in_order().unwrap().to_vec();
+        assert_eq!(again.len(), 3 * TRANSFER_BLOCK_SIZE + 10);
+        assert_eq!(download.into_save().unwrap(), again);
+    }
+}
@@ -4,6 +4,8 @@
 use crate::codec::{BinaryReader, BinaryWriter, Direction, MapPosition};
 use crate::codec::input_action::InputAction as CodecInputAction;
 use crate::error::{Error, Result};
+use super::heartbeat::ActionSegment;
+use super::profile::HandshakeChecksums;
 use super::rand_u32;
 
 use bitflags::bitflags;
@@ -248,12 +250,23 @@ pub struct ConnectionRequest {
 
 impl ConnectionRequest {
     pub fn new(client_request_id: u32) -> Self {
+        Self::with_version(client_request_id, ApplicationVersion::FACTORIO_2_0_72)
+    }
+
+    pub fn with_version(client_request_id: u32, version: ApplicationVersion) -> Self {
         Self {
-            version: ApplicationVersion::FACTORIO_2_0_72,
+            version,
             client_request_id,
         }
     }
 
+    pub fn read(reader: &mut BinaryReader) -> Result<Self> {
+        Ok(Self {
+            version: ApplicationVersion::read(reader)?,
+            client_request_id: reader.read_u32_le()?,
+        })
+    }
+
     pub fn write(&self, writer: &mut BinaryWriter) {
         self.version.write(writer);
         writer.write_u32_le(self.client_request_id);
@@ -280,6 +293,13 @@ impl ConnectionRequestReply {
             max_packet_size: reader.read_u16_le()?,
         })
     }
+
+    pub fn write(&self, writer: &mut BinaryWriter) {
+        self.version.write(writer);
+        writer.write_u32_le(self.client_request_id);
+        writer.write_u32_le(self.server_request_id);
+        writer.write_u16_le(self.max_packet_size);
+    }
 }
 
 /// ConnectionRequestReplyConfirm payload (type 4)
@@ -304,6 +324,7 @@ impl ConnectionRequestReplyConfirm {
         server_request_id: u32,
         username: String,
         mods: Vec<ModInfo>,
+        checksums: HandshakeChecksums,
     ) -> Self {
         Self {
             client_request_id,
@@ -313,13 +334,56 @@ impl ConnectionRequestReplyConfirm {
             password_hash: String::new(),
             server_key: String::new(),
             timestamp: String::new(),
-            // Hardcoded for Factorio 2.0.72 - must match server
-            core_checksum: 3316885848,
-            prototype_list_checksum: 748475845,
+            core_checksum: checksums.core,
+            prototype_list_checksum: checksums.prototype_list,
             mods,
         }
     }
 
+    /// Fill in the password hash and server key fields from `credentials`.
+    pub fn with_credentials(mut self, credentials: &Credentials) -> Self {
+        if let Some(password) = credentials.password.as_deref() {
+            self.password_hash = hash_password(password, self.server_request_id);
+        }
+        if let Some(key) = credentials.server_key.as_deref() {
+            self.server_key = key.to_string();
+        }
+        if let Some(timestamp) = credentials.server_key_timestamp.as_deref() {
+            self.timestamp = timestamp.to_string();
+        }
+        self
+    }
+
+    /// Read a confirm; the trailing settings bytes are left unread.
+    pub fn read(reader: &mut BinaryReader) -> Result<Self> {
+        let client_request_id = reader.read_u32_le()?;
+        let server_request_id = reader.read_u32_le()?;
+        let instance_id = reader.read_u32_le()?;
+        let username = reader.read_simple_string()?;
+        let password_hash = reader.read_simple_string()?;
+        let server_key = reader.read_simple_string()?;
+        let timestamp = reader.read_simple_string()?;
+        let core_checksum = reader.read_u32_le()?;
+        let prototype_list_checksum = reader.read_u32_le()?;
+        let mod_count = reader.read_opt_u32()? as usize;
+        let mut mods = Vec::with_capacity(mod_count.min(256));
+        for _ in 0..mod_count {
+            mods.push(ModInfo::read(reader)?);
+        }
+        Ok(Self {
+            client_request_id,
+            server_request_id,
+            instance_id,
+            username,
+            password_hash,
+            server_key,
+            timestamp,
+            core_checksum,
+            prototype_list_checksum,
+            mods,
+        })
+    }
+
     pub fn write(&self, writer: &mut BinaryWriter) {
         writer.write_u32_le(self.client_request_id);
         writer.write_u32_le(self.server_request_id);
@@ -342,6 +406,42 @@ impl ConnectionRequestReplyConfirm {
     }
 }
 
+/// Optional authentication data sent in ConnectionRequestReplyConfirm.
+/// `server_key`/`server_key_timestamp` are the user verification token pair
+/// handed out by the auth server; both are sent verbatim.
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    pub password: Option<String>,
+    pub server_key: Option<String>,
+    pub server_key_timestamp: Option<String>,
+}
+
+impl Credentials {
+    pub fn with_password(password: impl Into<String>) -> Self {
+        Self {
+            password: Some(password.into()),
+            ..Self::default()
+        }
+    }
+}
+
+/// Hash a game password for the handshake: base64(SHA-256(password || server_request_id LE)).
+///
+/// Experimental: this scheme is our own and has not been checked against the
+/// game client joining a passworded server, so a real server may reject
+/// correct passwords. The mock server checks the same function, so its
+/// password tests only show the two sides agree. `test_password_hash_known_answer`
+/// pins the bytes sent so a capture can be compared against them.
+pub fn hash_password(password: &str, server_request_id: u32) -> String {
+    use base64::Engine;
+    use sha2::{Digest, Sha256};
+
+    let mut hasher = Sha256::new();
+    hasher.update(password.as_bytes());
+    hasher.update(server_request_id.to_le_bytes());
+    base64::engine::general_purpose::STANDARD.encode(hasher.finalize())
+}
+
 /// ConnectionAcceptOrDeny payload (type 5)
 /// From binary RE - full format when accepted:
 /// [1 byte]  status
@@ -371,6 +471,8 @@ pub struct ConnectionAcceptOrDeny {
     pub map_tick: Option<u32>,
     pub steam_id: Option<u64>,
     pub latency_window: Option<u16>,
+    /// (peer id, username) of everyone in ClientsPeerInfo
+    pub peers: Vec<(u16, String)>,
 }
 
 impl ConnectionAcceptOrDeny {
@@ -404,6 +506,7 @@ impl ConnectionAcceptOrDeny {
                 map_tick: None,
                 steam_id: None,
                 latency_window: None,
+                peers: Vec::new(),
                 });
             }
         }
@@ -423,25 +526,40 @@ impl ConnectionAcceptOrDeny {
             map_tick: None,
             steam_id: None,
             latency_window: None,
+            peers: Vec::new(),
         })
     }
 }
 
 /// Reason for connection denial
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DenialReason {
-    VersionMismatch,
-    ModMismatch,
-    CoreModMismatch,
-    PasswordRequired,
-    WrongPassword,
-    UsernameTaken,
-    UserBanned,
-    ServerFull,
-    NotWhitelisted,
+    /// The ConnectionRequestStatus byte. What each value means has not been
+    /// confirmed against the game, so it is passed on rather than named.
+    Status(u8),
+    /// Denied without a status we could read
     Unknown,
 }
 
+impl DenialReason {
+    /// Map the ConnectionRequestStatus byte of a deny reply (0 = accepted).
+    pub fn from_status(status: u8) -> Option<Self> {
+        match status {
+            0 => None,
+            status => Some(Self::Status(status)),
+        }
+    }
+}
+
+impl std::fmt::Display for DenialReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Status(status) => write!(f, "status {}", status),
+            Self::Unknown => f.write_str("unknown reason"),
+        }
+    }
+}
+
 /// GameInformationRequest payload (type 16)
 /// Just a single byte 0x10 with no additional payload
 pub struct GameInformationRequest;
@@ -453,15 +571,87 @@ impl GameInformationRequest {
 }
 
 /// Parsed server info from GameInformationRequestReply
+/// Wire format (after the type byte):
+/// [String name][String description][ApplicationVersion version]
+/// [opt_u32 max_players (0 = unlimited)][bool has_password]
+/// [opt_u32 mod count][ModInfo...][opt_u32 player count][String...][u32 tick]
 #[derive(Debug, Clone)]
 pub struct ServerInfo {
+    pub name: String,
+    pub description: String,
+    pub version: ApplicationVersion,
+    pub max_players: u32,
+    pub has_password: bool,
     pub mods: Vec<ModInfo>,
+    pub players: Vec<String>,
+    pub tick: u32,
+    /// Set when the structured parse failed and only the mod list was recovered.
+    pub partial: bool,
 }
 
 impl ServerInfo {
-    /// Parse server info by finding the mod list
-    /// The format is complex, so we search for "base" which is always first
+    /// Parse a full GameInformationRequestReply datagram (including the type byte).
+    /// Falls back to scanning for the mod list if the structured parse fails;
+    /// such results have `partial` set.
     pub fn parse(data: &[u8]) -> Result<Self> {
+        if let Some(payload) = data.get(1..) {
+            let mut reader = BinaryReader::new(payload);
+            if let Ok(info) = Self::read(&mut reader) {
+                return Ok(info);
+            }
+        }
+        Self::parse_mods_heuristic(data)
+    }
+
+    pub fn read(reader: &mut BinaryReader) -> Result<Self> {
+        let name = reader.read_string()?;
+        let description = reader.read_string()?;
+        let version = ApplicationVersion::read(reader)?;
+        let max_players = reader.read_opt_u32()?;
+        let has_password = reader.read_bool()?;
+        let mod_count = reader.read_opt_u32()? as usize;
+        let mut mods = Vec::with_capacity(mod_count.min(256));
+        for _ in 0..mod_count {
+            mods.push(ModInfo::read(reader)?);
+        }
+        let player_count = reader.read_opt_u32()? as usize;
+        let mut players = Vec::with_capacity(player_count.min(256));
+        for _ in 0..player_count {
+            players.push(reader.read_string()?);
+        }
+        let tick = reader.read_u32_le()?;
+        Ok(Self {
+            name,
+            description,
+            version,
+            max_players,
+            has_password,
+            mods,
+            players,
+            tick,
+            partial: false,
+        })
+    }
+
+    pub fn write(&self, writer: &mut BinaryWriter) {
+        writer.write_string(&self.name);
+        writer.write_string(&self.description);
+        self.version.write(writer);
+        writer.write_opt_u32(self.max_players);
+        writer.write_bool(self.has_password);
+        writer.write_opt_u32(self.mods.len() as u32);
+        for m in &self.mods {
+            m.write(writer);
+        }
+        writer.write_opt_u32(self.players.len() as u32);
+        for p in &self.players {
+            writer.write_string(p);
+        }
+        writer.write_u32_le(self.tick);
+    }
+
+    /// Recover just the mod list by finding "base" (always the first mod)
+    pub fn parse_mods_heuristic(data: &[u8]) -> Result<Self> {
         // Search for "base" mod name (length 4 + "base")
         for i in 0..data.len().saturating_sub(10) {
             if data[i] == 4 && &data[i + 1..i + 5] == b"base" {
@@ -514,7 +704,17 @@ impl ServerInfo {
             mods.push(ModInfo { name, version, crc });
         }
 
-        Ok(Self { mods })
+        Ok(Self {
+            name: String::new(),
+            description: String::new(),
+            version: ApplicationVersion::FACTORIO_2_0_72,
+            max_players: 0,
+            has_password: false,
+            mods,
+            players: Vec::new(),
+            tick: 0,
+            partial: true,
+        })
     }
 }
 
@@ -540,6 +740,31 @@ impl TransferBlockRequest {
     }
 }
 
+/// RequestForHeartbeatWhenDisconnecting (type 14)
+/// Sent by a leaving client: asks the server to answer the listed heartbeat
+/// sequence numbers so the leave is acknowledged instead of timing out.
+/// Wire format: [opt_u32 count][u32 sequence...]
+#[derive(Debug, Clone)]
+pub struct RequestForHeartbeatWhenDisconnecting {
+    pub requests: Vec<u32>,
+}
+
+impl RequestForHeartbeatWhenDisconnecting {
+    pub fn new(requests: Vec<u32>) -> Self {
+        Self { requests }
+    }
+
+    pub fn write(&self, writer: &mut BinaryWriter) {
+        writer.write_opt_u32(self.requests.len() as u32);
+        for seq in &self.requests {
+            writer.write_u32_le(*seq);
+        }
+    }
+}
+
+/// Payload bytes per TransferBlock; only the last block of a map is shorter
+pub const TRANSFER_BLOCK_SIZE: usize = 503;
+
 /// TransferBlock (type 13)
 /// Server sends a block of map/mod data
 #[derive(Debug, Clone)]
@@ -673,7 +898,7 @@ impl InputAction {
         player_index.wrapping_add(1)
     }
 
-    pub fn encode(&self, chat_seq: &mut u32, player_index: u16) -> Result<EncodedAction> {
+    pub fn encode(&self, segment_id: &mut u32, player_index: u16) -> Result<EncodedAction> {
         let player_delta = Self::initial_player_delta(player_index);
         match self {
             InputAction::MoveDirection { direction } => {
@@ -736,8 +961,8 @@ impl InputAction {
                 Ok(EncodedAction { flags: 0x06, data })
             }
             InputAction::Chat { message } => {
-                let seq = if *chat_seq == 0 { 1 } else { *chat_seq };
-                *chat_seq = seq.wrapping_add(1);
+                let seq = if *segment_id == 0 { 1 } else { *segment_id };
+                *segment_id = seq.wrapping_add(1);
 
                 let mut payload_writer = BinaryWriter::with_capacity(3 + message.len() + 5);
                 payload_writer.write_opt_u16(player_index);
@@ -747,13 +972,15 @@ impl InputAction {
                 let mut writer = BinaryWriter::with_capacity(32 + payload.len());
                 writer.write_u8(0x01); // count=0, hasSegments=1
                 writer.write_u8(0x01); // one segment
-                writer.write_opt_u16(0x68);
-                writer.write_u32_le(seq);
-                writer.write_opt_u16(player_index);
-                writer.write_opt_u32(1);
-                writer.write_opt_u32(0);
-                writer.write_opt_u32(payload.len() as u32);
-                writer.write_bytes(&payload);
+                ActionSegment {
+                    action_type: 0x68,
+                    id: seq,
+                    player_index,
+                    total_segments: 1,
+                    segment_number: 0,
+                    data: payload,
+                }
+                .write(&mut writer);
                 let data = writer.into_vec();
 
                 Ok(EncodedAction { flags: 0x06, data })
@@ -776,6 +1003,7 @@ impl InputAction {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::protocol::packet::MessageType;
 
     #[test]
     fn test_mod_version() {
@@ -821,4 +1049,85 @@ mod tests {
         assert_eq!(reply.server_request_id, 0x12EFCDAB);
         assert_eq!(reply.max_packet_size, 0x01FC);
     }
+
+    #[test]
+    fn test_password_hash_known_answer() {
+        // Our scheme computed outside the crate, not a value taken from the game:
+        // base64(sha256(b"hunter2" + (0xdeadbeef).to_bytes(4, "little")))
+        assert_eq!(hash_password("hunter2", 0xdeadbeef), "M6YZvtZtP6PnTFLkUbWD3VotqOxSiFbAX2LXHQy4adA=");
+        assert_eq!(hash_password("", 1), "Z6vdchAk8P9OCz9ML8E7xbrULQt4UdRW2I0gPRWqpFA=");
+    }
+
+    #[test]
+    fn test_confirm_with_credentials() {
+        let credentials = Credentials {
+            password: Some("hunter2".into()),
+            server_key: Some("key".into()),
+            server_key_timestamp: Some("1700000000".into()),
+        };
+        let checksums = HandshakeChecksums::FACTORIO_2_0_72_SPACE_AGE;
+        let confirm = ConnectionRequestReplyConfirm::new(1, 0xdeadbeef, "bot".into(), Vec::new(), checksums)
+            .with_credentials(&credentials);
+        assert_eq!(confirm.password_hash, hash_password("hunter2", 0xdeadbeef));
+        assert_ne!(confirm.password_hash, hash_password("hunter2", 0xdeadbef0));
+        assert_eq!(confirm.password_hash.len(), 44);
+        assert_eq!(confirm.server_key, "key");
+        assert_eq!(confirm.timestamp, "1700000000");
+
+        let anonymous = ConnectionRequestReplyConfirm::new(1, 2, "bot".into(), Vec::new(), checksums)
+            .with_credentials(&Credentials::default());
+        assert!(anonymous.password_hash.is_empty());
+    }
+
+    #[test]
+    fn test_server_info_roundtrip() {
+        let info = ServerInfo {
+            name: "My server".into(),
+            description: "desc".into(),
+            version: ApplicationVersion::FACTORIO_2_0_72,
+            max_players: 8,
+            has_password: true,
+            mods: vec![ModInfo {
+                name: "base".into(),
+                version: ModVersion::new(2, 0, 72),
+                crc: 0x70059c86,
+            }],
+            players: vec!["alice".into(), "bob".into()],
+            tick: 123456,
+            partial: false,
+        };
+        let mut writer = BinaryWriter::new();
+        writer.write_u8(MessageType::GameInformationRequestReply as u8);
+        info.write(&mut writer);
+        let parsed = ServerInfo::parse(writer.as_slice()).unwrap();
+        assert!(!parsed.partial);
+        assert_eq!(parsed.name, "My server");
+        assert_eq!(parsed.version, ApplicationVersion::FACTORIO_2_0_72);
+        assert!(parsed.has_password);
+        assert_eq!(parsed.mods.len(), 1);
+        assert_eq!(parsed.mods[0].crc, 0x70059c86);
+        assert_eq!(parsed.players, vec!["alice".to_string(), "bob".to_string()]);
+        assert_eq!(parsed.tick, 123456);
+
+        // Unknown layout: recover the mod list only.
+        let mut junk = vec![0x11, 0xff, 0xff, 0xff, 0x01];
+        junk.extend_from_slice(&[1, 4, b'b', b'a', b's', b'e', 2, 0, 72, 1, 2, 3, 4, 0, 0]);
+        let partial = ServerInfo::parse(&junk).unwrap();
+        assert!(partial.partial);
+        assert_eq!(partial.mods[0].name, "base");
+    }
+
+    #[test]
+    fn test_request_for_heartbeat_when_disconnecting() {
+        let mut writer = BinaryWriter::new();
+        RequestForHeartbeatWhenDisconnecting::new(vec![0x01020304]).write(&mut writer);
+        assert_eq!(writer.as_slice(), &[0x01, 0x04, 0x03, 0x02, 0x01]);
+    }
+
+    #[test]
+    fn test_denial_reason_from_status() {
+        assert!(DenialReason::from_status(0).is_none());
+        assert_eq!(DenialReason::from_status(7), Some(DenialReason::Status(7)));
+        assert_eq!(DenialReason::from_status(200).unwrap().to_string(), "status 200");
+    }
 }
@@ -0,0 +1,987 @@
+//! Local stand-in server for integration tests
+//!
+//! Speaks just enough of the protocol to take a `Connection` from the handshake
+//! through the map download into gameplay, with no Factorio install:
+//!
+//! - GameInformationRequest and ConnectionRequest/Reply/Confirm/AcceptOrDeny,
+//!   including the password check
+//! - MapReadyForDownload once the client reports ClientChangedState(3), then
+//!   TransferBlocks cut from the configured save
+//! - ClientShouldStartSendingTickClosures plus a PlayerJoinGame action once the
+//!   client reports ClientChangedState(6)
+//! - one server heartbeat per tick; input actions from any client are echoed to
+//!   every in-game client in the next tick closure
+//! - fragmented heartbeats both ways; a client heartbeat with a fragment missing
+//!   for `FRAGMENT_REQUEST_AFTER` is asked for again through a heartbeat request,
+//!   as are heartbeats skipped in the client's sequence. Action segments are
+//!   relayed as they came
+//!
+//! No game logic runs here: actions are relayed, never applied.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use tokio::net::UdpSocket;
+
+use crate::codec::{ActionNumbering, BinaryReader, BinaryWriter, InputAction as CodecInputAction, SynchronizerActionType};
+use crate::error::{Error, Result};
+use super::message::{
+    hash_password, ApplicationVersion, ConnectionRequest, ConnectionRequestReply,
+    ConnectionRequestReplyConfirm, DeserializationMask, ModInfo, ServerInfo, TRANSFER_BLOCK_SIZE,
+};
+use super::fragment::{split_message, FragmentAssembler, MAX_FRAGMENT_PAYLOAD};
+use super::packet::{MessageType, PacketHeader, MAX_PACKET_SIZE};
+use super::profile::VersionProfile;
+use super::transport::DatagramSocket;
+
+const TICK_INTERVAL: Duration = Duration::from_micros(16_667); // 60 UPS
+const PEER_TIMEOUT: Duration = Duration::from_secs(10);
+const INITIAL_MSG_ID: u16 = 2;
+const FRAGMENT_REQUEST_AFTER: Duration = Duration::from_millis(100);
+/// Fragmented client messages remembered as complete, so late duplicates are ignored
+const COMPLETED_FRAGMENTED_HISTORY: usize = 64;
+/// Client heartbeat sequences remembered, so repeats are ignored
+const CLIENT_SEQUENCE_HISTORY: usize = 256;
+/// Most heartbeats asked for at once when the client's sequence skips ahead
+const MAX_SKIPPED_REQUESTS: usize = 32;
+
+// ClientChangedState values reported by the client
+const CLIENT_STATE_READY_FOR_MAP: u8 = 0x03;
+const CLIENT_STATE_READY_FOR_GAMEPLAY: u8 = 0x06;
+
+// ConnectionAcceptOrDeny status bytes. Guesses: the game's values are unconfirmed,
+// which is why the client reports statuses as numbers (DenialReason::Status)
+const STATUS_PASSWORD_REQUIRED: u8 = 6;
+const STATUS_WRONG_PASSWORD: u8 = 7;
+const STATUS_USERNAME_TAKEN: u8 = 12;
+
+#[derive(Debug, Clone)]
+pub struct MockServerConfig {
+    pub name: String,
+    pub version: ApplicationVersion,
+    /// Game password; `None` lets anyone join
+    pub password: Option<String>,
+    /// Bytes served through TransferBlock, normally a save zip
+    pub map: Vec<u8>,
+    /// Advertised in server info; the 2.0.72 Space Age set by default
+    pub mods: Vec<ModInfo>,
+    /// Game tick the server starts counting from
+    pub start_tick: u32,
+    /// Ticks between ClientShouldStartSendingTickClosures and the tick it names
+    pub latency: u8,
+    /// Report every peer desynced (PlayerDesynced) this many ticks after it joined
+    pub desync_after: Option<u32>,
+}
+
+impl Default for MockServerConfig {
+    fn default() -> Self {
+        Self {
+            name: "mock server".into(),
+            version: ApplicationVersion::FACTORIO_2_0_72,
+            password: None,
+            map: Vec::new(),
+            mods: VersionProfile::FACTORIO_2_0_72
+                .default_mods(ApplicationVersion::FACTORIO_2_0_72)
+                .unwrap_or_default(),
+            start_tick: 216_000,
+            latency: 6,
+            desync_after: None,
+        }
+    }
+}
+
+impl MockServerConfig {
+    pub fn with_map_file(mut self, path: impl AsRef<Path>) -> Result<Self> {
+        self.map = std::fs::read(path).map_err(|e| Error::Io(e.to_string()))?;
+        Ok(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PeerStage {
+    /// ConnectionRequest answered, waiting for the confirm
+    Connecting,
+    /// Accepted; heartbeats flowing but the map was not asked for yet
+    Connected,
+    /// MapReadyForDownload is repeated until the first block request
+    MapOffered,
+    Downloading,
+    InGame,
+}
+
+/// An input action as it travels through a tick closure
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RelayedAction {
+    player_index: u16,
+    action_type: u16,
+    /// Action payload after the type
+    data: Vec<u8>,
+}
+
+impl RelayedAction {
+    fn new(player_index: u16, action: &CodecInputAction) -> Self {
+        let mut writer = BinaryWriter::with_capacity(32);
+        action.write_data(&mut writer);
+        Self {
+            player_index,
+            action_type: action.action_type() as u16,
+            data: writer.into_vec(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Peer {
+    client_request_id: u32,
+    server_request_id: u32,
+    stage: PeerStage,
+    peer_id: u16,
+    player_index: u16,
+    username: String,
+    heartbeat_sequence: u32,
+    /// Tick written into MapReadyForDownload
+    map_tick: u32,
+    /// Base the client adds the next action's player delta to
+    last_action_player: u16,
+    sync_actions: Vec<Vec<u8>>,
+    leaving: bool,
+    last_seen: Instant,
+    fragments: FragmentAssembler,
+    completed_fragmented: VecDeque<u16>,
+    /// Highest client heartbeat sequence, to rebuild the ones missing a fragment
+    last_client_sequence: u32,
+    recent_client_sequences: VecDeque<u32>,
+    heartbeat_requests: Vec<u32>,
+    /// Message id for fragmented server heartbeats
+    next_msg_id: u16,
+    /// Tick at which to send PlayerDesynced for this peer
+    desync_tick: Option<u32>,
+}
+
+impl Peer {
+    fn new(client_request_id: u32, server_request_id: u32) -> Self {
+        Self {
+            client_request_id,
+            server_request_id,
+            stage: PeerStage::Connecting,
+            peer_id: 0,
+            player_index: 0,
+            username: String::new(),
+            heartbeat_sequence: 0,
+            map_tick: 0,
+            last_action_player: 0xFFFF,
+            sync_actions: Vec::new(),
+            leaving: false,
+            last_seen: Instant::now(),
+            fragments: FragmentAssembler::new(Duration::from_secs(2)),
+            completed_fragmented: VecDeque::new(),
+            last_client_sequence: 0,
+            recent_client_sequences: VecDeque::new(),
+            heartbeat_requests: Vec::new(),
+            next_msg_id: 1,
+            desync_tick: None,
+        }
+    }
+
+    /// Ask again for client heartbeats whose fragments did not all arrive.
+    fn request_stale_heartbeats(&mut self, session_constant: u16) {
+        let base = if self.last_client_sequence == 0 {
+            (session_constant as u32) << 16
+        } else {
+            self.last_client_sequence
+        };
+        for msg_id in self.fragments.take_stale(FRAGMENT_REQUEST_AFTER) {
+            if !self.completed_fragmented.contains(&msg_id) {
+                self.heartbeat_requests.push((base & !0x7FFF) | msg_id as u32);
+            }
+        }
+    }
+
+    /// Record a client heartbeat's sequence and ask for any skipped since the
+    /// highest one. Returns false for a sequence already seen.
+    fn note_client_sequence(&mut self, sequence: u32) -> bool {
+        if self.recent_client_sequences.contains(&sequence) {
+            return false;
+        }
+        if self.recent_client_sequences.len() >= CLIENT_SEQUENCE_HISTORY {
+            self.recent_client_sequences.pop_front();
+        }
+        self.recent_client_sequences.push_back(sequence);
+        let last = self.last_client_sequence;
+        if last != 0 && sequence > last {
+            for skipped in (last + 1..sequence).take(MAX_SKIPPED_REQUESTS) {
+                self.heartbeat_requests.push(skipped);
+            }
+        }
+        self.last_client_sequence = last.max(sequence);
+        true
+    }
+
+    /// Server heartbeat datagrams, fragmented if needed
+    fn heartbeat_datagrams(&mut self, tick: u32, map_size: usize, actions: &[RelayedAction], segments: &[Vec<u8>]) -> Vec<Vec<u8>> {
+        let packet = self.heartbeat(tick, map_size, actions, segments);
+        let msg_id = self.next_msg_id;
+        self.next_msg_id = self.next_msg_id.wrapping_add(1) & 0x7FFF;
+        split_message(packet[0], msg_id, &packet[1..], MAX_FRAGMENT_PAYLOAD)
+    }
+
+    /// Server heartbeat for one tick. In-game peers get a single tick closure
+    /// carrying `actions`/`segments`; queued sync actions ride along.
+    fn heartbeat(&mut self, tick: u32, map_size: usize, actions: &[RelayedAction], segments: &[Vec<u8>]) -> Vec<u8> {
+        if self.stage == PeerStage::MapOffered {
+            self.sync_actions.push(map_ready_for_download(self.peer_id, map_size as u64, self.map_tick));
+        }
+        let in_game = self.stage == PeerStage::InGame;
+        let empty = actions.is_empty() && segments.is_empty();
+
+        let mut flags = DeserializationMask::empty();
+        if in_game {
+            flags |= DeserializationMask::HAS_TICK_CLOSURES | DeserializationMask::SINGLE_TICK_CLOSURE;
+            if empty {
+                flags |= DeserializationMask::LOAD_TICK_ONLY;
+            }
+        }
+        if !self.sync_actions.is_empty() {
+            flags |= DeserializationMask::HAS_SYNC_ACTIONS;
+        }
+        if !self.heartbeat_requests.is_empty() {
+            flags |= DeserializationMask::HAS_REQUESTS;
+        }
+
+        let mut writer = BinaryWriter::with_capacity(64);
+        writer.write_u8(MessageType::ServerToClientHeartbeat as u8);
+        writer.write_u8(flags.bits());
+        writer.write_u32_le(self.heartbeat_sequence);
+        self.heartbeat_sequence = self.heartbeat_sequence.wrapping_add(1);
+
+        if in_game {
+            writer.write_u64_le(tick as u64);
+            if !empty {
+                let has_segments = !segments.is_empty() as u32;
+                writer.write_opt_u32(((actions.len() as u32) * 2) | has_segments);
+                // S2C order: player delta, then type (C2S is the other way round)
+                for action in actions {
+                    writer.write_opt_u16(action.player_index.wrapping_sub(self.last_action_player));
+                    self.last_action_player = action.player_index;
+                    writer.write_opt_u16(action.action_type);
+                    writer.write_bytes(&action.data);
+                }
+                if !segments.is_empty() {
+                    writer.write_opt_u32(segments.len() as u32);
+                    for segment in segments {
+                        writer.write_bytes(segment);
+                    }
+                }
+            }
+        }
+
+        if !self.sync_actions.is_empty() {
+            writer.write_opt_u32(self.sync_actions.len() as u32);
+            for action in self.sync_actions.drain(..) {
+                writer.write_bytes(&action);
+            }
+        }
+        if !self.heartbeat_requests.is_empty() {
+            writer.write_opt_u32(self.heartbeat_requests.len() as u32);
+            for sequence in self.heartbeat_requests.drain(..) {
+                writer.write_u32_le(sequence);
+            }
+        }
+        writer.into_vec()
+    }
+}
+
+/// Protocol state of the stand-in server, independent of any socket.
+///
+/// `handle_datagram` answers client datagrams and `advance` runs one game tick;
+/// `serve_udp` and `serve` drive both from a real or in-memory socket.
+#[derive(Debug)]
+pub struct MockServer {
+    config: MockServerConfig,
+    tick: u32,
+    peers: HashMap<SocketAddr, Peer>,
+    /// Player index per username, kept across reconnects
+    players: HashMap<String, u16>,
+    next_peer_id: u16,
+    session_constant: u16,
+    pending_actions: Vec<RelayedAction>,
+    pending_segments: Vec<Vec<u8>>,
+}
+
+impl MockServer {
+    pub fn new(config: MockServerConfig) -> Self {
+        Self {
+            tick: config.start_tick,
+            config,
+            peers: HashMap::new(),
+            players: HashMap::new(),
+            next_peer_id: 1,
+            session_constant: (super::rand_u32() & 0xFFFF) as u16,
+            pending_actions: Vec::new(),
+            pending_segments: Vec::new(),
+        }
+    }
+
+    pub fn game_tick(&self) -> u32 {
+        self.tick
+    }
+
+    /// Usernames of clients that finished joining
+    pub fn players(&self) -> Vec<String> {
+        let mut players: Vec<_> = self
+            .peers
+            .values()
+            .filter(|p| p.stage == PeerStage::InGame)
+            .map(|p| p.username.clone())
+            .collect();
+        players.sort();
+        players
+    }
+
+    /// Handle one datagram from `from`; returns the datagrams to send back to it.
+    pub fn handle_datagram(&mut self, from: SocketAddr, data: &[u8]) -> Vec<Vec<u8>> {
+        match self.dispatch(from, data) {
+            Ok(out) => out,
+            Err(e) => {
+                if std::env::var("FACTORIO_DEBUG").is_ok() {
+                    eprintln!("[DEBUG] mock-server: dropping {} bytes from {}: {}", data.len(), from, e);
+                }
+                Vec::new()
+            }
+        }
+    }
+
+    /// Run one game tick: every accepted peer gets a heartbeat.
+    pub fn advance(&mut self) -> Vec<(SocketAddr, Vec<u8>)> {
+        self.tick = self.tick.wrapping_add(1);
+        let idle: Vec<_> = self
+            .peers
+            .iter()
+            .filter(|(_, p)| p.last_seen.elapsed() > PEER_TIMEOUT)
+            .map(|(addr, _)| *addr)
+            .collect();
+        for addr in idle {
+            self.remove_peer(addr);
+        }
+
+        let actions = std::mem::take(&mut self.pending_actions);
+        let segments = std::mem::take(&mut self.pending_segments);
+        let mut out = Vec::with_capacity(self.peers.len());
+        let mut leaving = Vec::new();
+        for (addr, peer) in self.peers.iter_mut() {
+            if peer.stage == PeerStage::Connecting {
+                continue;
+            }
+            peer.request_stale_heartbeats(self.session_constant);
+            if peer.desync_tick == Some(self.tick) {
+                peer.sync_actions.push(player_desynced(peer.peer_id));
+            }
+            for datagram in peer.heartbeat_datagrams(self.tick, self.config.map.len(), &actions, &segments) {
+                out.push((*addr, datagram));
+            }
+            if peer.leaving {
+                leaving.push(*addr);
+            }
+        }
+        for addr in leaving {
+            self.remove_peer(addr);
+        }
+        out
+    }
+
+    /// Serve any number of clients on a UDP socket. Only returns on a receive error.
+    pub async fn serve_udp(mut self, socket: UdpSocket) -> Result<()> {
+        let debug = std::env::var("FACTORIO_DEBUG").is_ok();
+        let mut buf = vec![0u8; 65536];
+        let mut next_tick = tokio::time::Instant::now() + TICK_INTERVAL;
+        loop {
+            if tokio::time::Instant::now() >= next_tick {
+                next_tick += TICK_INTERVAL;
+                for (to, packet) in self.advance() {
+                    if let Err(e) = socket.send_to(&packet, to).await {
+                        if debug {
+                            eprintln!("[DEBUG] mock-server: send to {} failed: {}", to, e);
+                        }
+                    }
+                }
+            }
+            let Ok(recv) = tokio::time::timeout_at(next_tick, socket.recv_from(&mut buf)).await else {
+                continue;
+            };
+            let (len, from) = recv.map_err(|e| Error::Io(e.to_string()))?;
+            for packet in self.handle_datagram(from, &buf[..len]) {
+                if let Err(e) = socket.send_to(&packet, from).await {
+                    if debug {
+                        eprintln!("[DEBUG] mock-server: send to {} failed: {}", from, e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Serve a single client over any `DatagramSocket`, e.g. one end of a
+    /// `ChannelSocket::pair`. `peer_addr` is the address the client is known by.
+    /// Returns once the socket fails (for a channel: the client end was dropped).
+    pub async fn serve(mut self, mut socket: impl DatagramSocket, peer_addr: SocketAddr) -> Result<()> {
+        let mut next_tick = tokio::time::Instant::now() + TICK_INTERVAL;
+        loop {
+            if tokio::time::Instant::now() >= next_tick {
+                next_tick += TICK_INTERVAL;
+                for (_, packet) in self.advance() {
+                    socket.send(&packet).await?;
+                }
+            }
+            let Ok(data) = tokio::time::timeout_at(next_tick, socket.recv()).await else {
+                continue;
+            };
+            for packet in self.handle_datagram(peer_addr, &data?) {
+                socket.send(&packet).await?;
+            }
+        }
+    }
+
+    fn dispatch(&mut self, from: SocketAddr, data: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let &type_byte = data.first().ok_or(Error::UnexpectedEof)?;
+        let msg_type = MessageType::from_u8(type_byte).ok_or(Error::InvalidMessageType(type_byte))?;
+        if let Some(peer) = self.peers.get_mut(&from) {
+            peer.last_seen = Instant::now();
+        }
+        if msg_type == MessageType::ClientToServerHeartbeat && type_byte & 0x40 != 0 {
+            return self.on_heartbeat_fragment(from, data);
+        }
+        // Neither of these carries a message id, even when marked reliable.
+        let payload = match msg_type {
+            MessageType::GameInformationRequest | MessageType::TransferBlockRequest => &data[1..],
+            _ => &data[PacketHeader::parse(data)?.1..],
+        };
+
+        match msg_type {
+            MessageType::GameInformationRequest => Ok(vec![self.server_info_reply()]),
+            MessageType::ConnectionRequest => self.on_connection_request(from, payload),
+            MessageType::ConnectionRequestReplyConfirm => self.on_confirm(from, payload),
+            MessageType::ClientToServerHeartbeat => {
+                self.on_heartbeat(from, payload)?;
+                Ok(Vec::new())
+            }
+            MessageType::TransferBlockRequest => self.on_block_request(from, payload),
+            MessageType::Ping => {
+                let mut reply = vec![MessageType::PingReply as u8];
+                reply.extend_from_slice(payload);
+                Ok(vec![reply])
+            }
+            MessageType::RequestForHeartbeatWhenDisconnecting => {
+                self.on_disconnect_request(from);
+                Ok(Vec::new())
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn server_info_reply(&self) -> Vec<u8> {
+        let info = ServerInfo {
+            name: self.config.name.clone(),
+            description: String::new(),
+            version: self.config.version,
+            max_players: 0,
+            has_password: self.config.password.is_some(),
+            mods: self.config.mods.clone(),
+            players: self.players(),
+            tick: self.tick,
+            partial: false,
+        };
+        let mut writer = BinaryWriter::new();
+        writer.write_u8(MessageType::GameInformationRequestReply as u8);
+        info.write(&mut writer);
+        writer.into_vec()
+    }
+
+    fn on_connection_request(&mut self, from: SocketAddr, payload: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let request = ConnectionRequest::read(&mut BinaryReader::new(payload))?;
+        let server_request_id = match self.peers.get(&from) {
+            // Retransmitted or duplicated request: answer with the same ids.
+            Some(peer) if peer.stage == PeerStage::Connecting && peer.client_request_id == request.client_request_id => {
+                peer.server_request_id
+            }
+            // Anything else from a known address starts a fresh session.
+            _ => {
+                self.remove_peer(from);
+                let server_request_id = super::rand_u32();
+                self.peers.insert(from, Peer::new(request.client_request_id, server_request_id));
+                server_request_id
+            }
+        };
+
+        let reply = ConnectionRequestReply {
+            version: self.config.version,
+            client_request_id: request.client_request_id,
+            server_request_id,
+            max_packet_size: MAX_PACKET_SIZE as u16,
+        };
+        let mut writer = BinaryWriter::new();
+        writer.write_u8(MessageType::ConnectionRequestReply as u8);
+        reply.write(&mut writer);
+        Ok(vec![writer.into_vec()])
+    }
+
+    fn on_confirm(&mut self, from: SocketAddr, payload: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let confirm = ConnectionRequestReplyConfirm::read(&mut BinaryReader::new(payload))?;
+        let Some(peer) = self.peers.get(&from) else {
+            return Ok(Vec::new());
+        };
+        if peer.client_request_id != confirm.client_request_id || peer.server_request_id != confirm.server_request_id {
+            return Ok(Vec::new());
+        }
+        if peer.stage == PeerStage::Connected {
+            // The accept was lost or the confirm duplicated; accept again.
+            return Ok(vec![self.accept_packet(from)]);
+        }
+        if peer.stage != PeerStage::Connecting {
+            return Ok(Vec::new());
+        }
+
+        let status = self.confirm_status(from, &confirm);
+        if status != 0 {
+            self.peers.remove(&from);
+            let mut writer = BinaryWriter::with_capacity(6);
+            writer.write_u8(MessageType::ConnectionAcceptOrDeny as u8);
+            writer.write_u32_le(confirm.client_request_id);
+            writer.write_u8(status);
+            return Ok(vec![writer.into_vec()]);
+        }
+
+        let next_index = self.players.len() as u16;
+        let player_index = *self.players.entry(confirm.username.clone()).or_insert(next_index);
+        let peer_id = self.next_peer_id;
+        self.next_peer_id = self.next_peer_id.wrapping_add(1).max(1);
+        let tick = self.tick;
+        if let Some(peer) = self.peers.get_mut(&from) {
+            peer.stage = PeerStage::Connected;
+            peer.peer_id = peer_id;
+            peer.player_index = player_index;
+            peer.username = confirm.username;
+            peer.heartbeat_sequence = tick;
+        }
+        Ok(vec![self.accept_packet(from)])
+    }
+
+    /// ConnectionAcceptOrDeny status for a confirm (0 = accept)
+    fn confirm_status(&self, from: SocketAddr, confirm: &ConnectionRequestReplyConfirm) -> u8 {
+        if let Some(password) = &self.config.password {
+            if confirm.password_hash.is_empty() {
+                return STATUS_PASSWORD_REQUIRED;
+            }
+            if confirm.password_hash != hash_password(password, confirm.server_request_id) {
+                return STATUS_WRONG_PASSWORD;
+            }
+        }
+        let taken = self.peers.iter().any(|(addr, p)| {
+            *addr != from && p.stage != PeerStage::Connecting && p.username == confirm.username
+        });
+        if taken {
+            return STATUS_USERNAME_TAKEN;
+        }
+        0
+    }
+
+    fn accept_packet(&self, to: SocketAddr) -> Vec<u8> {
+        let peer = &self.peers[&to];
+        let mut writer = BinaryWriter::with_capacity(128);
+        writer.write_u8(MessageType::ConnectionAcceptOrDeny as u8);
+        writer.write_u32_le(peer.client_request_id);
+        writer.write_u8(0); // accepted
+        writer.write_string(&self.config.name);
+        writer.write_string(""); // server key
+        writer.write_string(""); // unused auth field
+        writer.write_u8(self.config.latency);
+        writer.write_opt_u32(60); // max updates per second
+        writer.write_u32_le(0); // game id
+        writer.write_u64_le(0); // steam id
+
+        // ClientsPeerInfo: server username/state, then every accepted peer
+        writer.write_string("server");
+        writer.write_u8(0);
+        writer.write_opt_u16(0);
+        let mut peers: Vec<_> = self.peers.values().filter(|p| p.stage != PeerStage::Connecting).collect();
+        peers.sort_by_key(|p| p.peer_id);
+        writer.write_opt_u32(peers.len() as u32);
+        for p in peers {
+            writer.write_opt_u16(p.peer_id);
+            writer.write_string(&p.username);
+            writer.write_u8(0); // no optional fields
+        }
+
+        writer.write_u32_le(peer.heartbeat_sequence);
+        writer.write_u32_le(((self.session_constant as u32) << 16) | INITIAL_MSG_ID as u32);
+        writer.write_u16_le(peer.peer_id);
+        writer.write_opt_u32(self.config.mods.len() as u32);
+        for m in &self.config.mods {
+            m.write(&mut writer);
+        }
+        writer.into_vec()
+    }
+
+    fn on_heartbeat_fragment(&mut self, from: SocketAddr, data: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let (header, payload_start) = PacketHeader::parse(data)?;
+        let Some(peer) = self.peers.get_mut(&from) else {
+            return Ok(Vec::new());
+        };
+        if peer.completed_fragmented.contains(&header.message_id) {
+            return Ok(Vec::new());
+        }
+        let Some(message) = peer.fragments.insert(&header, data, payload_start) else {
+            return Ok(Vec::new());
+        };
+        if peer.completed_fragmented.len() >= COMPLETED_FRAGMENTED_HISTORY {
+            peer.completed_fragmented.pop_front();
+        }
+        peer.completed_fragmented.push_back(header.message_id);
+        self.on_heartbeat(from, &message[1..])?;
+        Ok(Vec::new())
+    }
+
+    fn on_heartbeat(&mut self, from: SocketAddr, payload: &[u8]) -> Result<()> {
+        let Some(peer) = self.peers.get(&from) else {
+            return Ok(());
+        };
+        if peer.stage == PeerStage::Connecting {
+            return Ok(());
+        }
+        let player_index = peer.player_index;
+
+        let mut reader = BinaryReader::new(payload);
+        let flags = DeserializationMask::from_bits_truncate(reader.read_u8()?);
+        let sequence = reader.read_u32_le()?;
+        if let Some(peer) = self.peers.get_mut(&from) {
+            if !peer.note_client_sequence(sequence) {
+                return Ok(());
+            }
+        }
+        if flags.contains(DeserializationMask::HAS_TICK_CLOSURES) {
+            let (actions, segments) = read_client_tick_closures(&mut reader, flags, player_index)?;
+            self.pending_actions.extend(actions);
+            self.pending_segments.extend(segments);
+        }
+        let _tick = reader.read_u64_le()?;
+        if flags.contains(DeserializationMask::HAS_SYNC_ACTIONS) {
+            for state in read_client_states(reader.remaining_slice()) {
+                self.on_client_state(from, state);
+            }
+        }
+        Ok(())
+    }
+
+    fn on_client_state(&mut self, from: SocketAddr, state: u8) {
+        let tick = self.tick;
+        let start_tick = tick.wrapping_add(self.config.latency as u32);
+        let Some(peer) = self.peers.get_mut(&from) else {
+            return;
+        };
+        match (state, peer.stage) {
+            (CLIENT_STATE_READY_FOR_MAP, PeerStage::Connected) => {
+                peer.stage = PeerStage::MapOffered;
+                peer.map_tick = tick;
+            }
+            (CLIENT_STATE_READY_FOR_GAMEPLAY, PeerStage::MapOffered | PeerStage::Downloading) => {
+                peer.stage = PeerStage::InGame;
+                peer.desync_tick = self.config.desync_after.map(|after| tick.wrapping_add(after));
+                peer.sync_actions.push(start_sending_tick_closures(peer.peer_id, start_tick));
+                let join = CodecInputAction::PlayerJoinGame {
+                    peer_id: peer.peer_id,
+                    player_index_plus_one: peer.player_index.wrapping_add(1),
+                    mode: 0,
+                    username: peer.username.clone(),
+                    flag_a: false,
+                    flag_b: false,
+                };
+                self.pending_actions.push(RelayedAction::new(peer.player_index, &join));
+            }
+            _ => {}
+        }
+    }
+
+    fn on_block_request(&mut self, from: SocketAddr, payload: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let block = BinaryReader::new(payload).read_u32_le()?;
+        let Some(peer) = self.peers.get_mut(&from) else {
+            return Ok(Vec::new());
+        };
+        if !matches!(peer.stage, PeerStage::MapOffered | PeerStage::Downloading) {
+            return Ok(Vec::new());
+        }
+        peer.stage = PeerStage::Downloading;
+
+        let start = block as usize * TRANSFER_BLOCK_SIZE;
+        if start >= self.config.map.len() {
+            return Ok(Vec::new());
+        }
+        let end = (start + TRANSFER_BLOCK_SIZE).min(self.config.map.len());
+        let mut writer = BinaryWriter::with_capacity(5 + end - start);
+        writer.write_u8(MessageType::TransferBlock as u8);
+        writer.write_u32_le(block);
+        writer.write_bytes(&self.config.map[start..end]);
+        Ok(vec![writer.into_vec()])
+    }
+
+    fn on_disconnect_request(&mut self, from: SocketAddr) {
+        let Some(peer) = self.peers.get_mut(&from) else {
+            return;
+        };
+        if peer.stage == PeerStage::Connecting || peer.leaving {
+            return;
+        }
+        // Acknowledged in the next heartbeat, after which the peer is dropped.
+        peer.leaving = true;
+        let mut writer = BinaryWriter::with_capacity(4);
+        writer.write_u8(SynchronizerActionType::PeerDisconnect as u8);
+        writer.write_opt_u16(peer.peer_id);
+        writer.write_u8(0); // reason: quit
+        peer.sync_actions.push(writer.into_vec());
+    }
+
+    fn remove_peer(&mut self, addr: SocketAddr) {
+        let Some(peer) = self.peers.remove(&addr) else {
+            return;
+        };
+        if peer.stage == PeerStage::InGame {
+            let leave = CodecInputAction::PlayerLeaveGame { peer_id: peer.peer_id, reason: 0 };
+            self.pending_actions.push(RelayedAction::new(peer.player_index, &leave));
+        }
+    }
+}
+
+/// Actions and raw segments from the tick closures of a client heartbeat.
+/// Everything is attributed to the sending player; the client's deltas are ignored.
+fn read_client_tick_closures(
+    reader: &mut BinaryReader,
+    flags: DeserializationMask,
+    player_index: u16,
+) -> Result<(Vec<RelayedAction>, Vec<Vec<u8>>)> {
+    let count = if flags.contains(DeserializationMask::SINGLE_TICK_CLOSURE) {
+        1
+    } else {
+        reader.read_opt_u32()?
+    };
+    let mut actions = Vec::new();
+    let mut segments = Vec::new();
+    for _ in 0..count {
+        let _tick = reader.read_u64_le()?;
+        if flags.contains(DeserializationMask::LOAD_TICK_ONLY) {
+            continue;
+        }
+        let count_and_segments = reader.read_opt_u32()?;
+        for _ in 0..count_and_segments / 2 {
+            let action_type = reader.read_opt_u16()?;
+            let _player_delta = reader.read_opt_u16()?;
+            let data = read_action_data(reader, action_type)?;
+            actions.push(RelayedAction { player_index, action_type, data });
+        }
+        if count_and_segments & 1 != 0 {
+            for _ in 0..reader.read_opt_u32()? {
+                let mut segment = reader.remaining_slice().to_vec();
+                let before = reader.position();
+                let _action_type = reader.read_opt_u16()?;
+                let _id = reader.read_u32_le()?;
+                let _player_index = reader.read_opt_u16()?;
+                let _total = reader.read_opt_u32()?;
+                let _part = reader.read_opt_u32()?;
+                let len = reader.read_opt_u32()? as usize;
+                reader.skip(len)?;
+                segment.truncate(reader.position() - before);
+                segments.push(segment);
+            }
+        }
+    }
+    Ok((actions, segments))
+}
+
+/// Payload of one action, sized by decoding it with the codec.
+fn read_action_data(reader: &mut BinaryReader, action_type: u16) -> Result<Vec<u8>> {
+    let start = reader.position();
+    CodecInputAction::read_data_numbered(action_type, reader, &ActionNumbering::CANONICAL)?;
+    let len = reader.position() - start;
+    reader.set_position(start);
+    Ok(reader.read_bytes(len)?.to_vec())
+}
+
+/// ClientChangedState values from a pre-game state trailer
+/// ([opt_u32 count] then [u8 type][u8 value] per action). Stops at anything else.
+fn read_client_states(data: &[u8]) -> Vec<u8> {
+    let mut reader = BinaryReader::new(data);
+    let mut states = Vec::new();
+    let Ok(count) = reader.read_opt_u32() else {
+        return states;
+    };
+    for _ in 0..count {
+        let (Ok(action_type), Ok(value)) = (reader.read_u8(), reader.read_u8()) else {
+            break;
+        };
+        match SynchronizerActionType::from_u8(action_type) {
+            Some(SynchronizerActionType::ClientChangedState) => states.push(value),
+            Some(
+                SynchronizerActionType::MapLoadingProgressUpdate
+                | SynchronizerActionType::MapDownloadingProgressUpdate,
+            ) => {}
+            _ => break,
+        }
+    }
+    states
+}
+
+fn start_sending_tick_closures(peer_id: u16, tick: u32) -> Vec<u8> {
+    let mut writer = BinaryWriter::with_capacity(8);
+    writer.write_u8(SynchronizerActionType::ClientShouldStartSendingTickClosures as u8);
+    writer.write_opt_u16(peer_id);
+    writer.write_u32_le(tick);
+    writer.into_vec()
+}
+
+fn player_desynced(peer_id: u16) -> Vec<u8> {
+    let mut writer = BinaryWriter::with_capacity(4);
+    writer.write_u8(SynchronizerActionType::PlayerDesynced as u8);
+    writer.write_opt_u16(peer_id);
+    writer.into_vec()
+}
+
+fn map_ready_for_download(peer_id: u16, size: u64, map_tick: u32) -> Vec<u8> {
+    let mut writer = BinaryWriter::with_capacity(48);
+    writer.write_u8(SynchronizerActionType::MapReadyForDownload as u8);
+    writer.write_opt_u16(peer_id);
+    writer.write_u64_le(size);
+    writer.write_u64_le(0); // auxiliary
+    writer.write_u32_le(0); // crc
+    writer.write_u64_le(map_tick as u64);
+    writer.write_u32_le(0);
+    writer.write_u32_le(0);
+    writer.write_bool(false);
+    writer.write_bool(false);
+    // Empty script/mod registration lists
+    for _ in 0..3 {
+        writer.write_opt_u32(0);
+    }
+    writer.into_vec()
+}
+
+/// Address tests reach a `mock_session` server at
+#[cfg(test)]
+pub(crate) const MOCK_SERVER_ADDR: SocketAddr =
+    SocketAddr::V4(std::net::SocketAddrV4::new(std::net::Ipv4Addr::LOCALHOST, 34197));
+
+/// Spawn a mock server for `config` on one end of a `ChannelSocket::pair`.
+/// Returns the client end, to hand to `Transport::with_socket` (wrapped if the
+/// test needs to), and the server task, which ends once the client end is dropped.
+#[cfg(test)]
+pub(crate) fn mock_session(
+    config: MockServerConfig,
+) -> (super::transport::ChannelSocket, tokio::task::JoinHandle<Result<()>>) {
+    let client_addr: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+    let (client, server) = super::transport::ChannelSocket::pair(client_addr, MOCK_SERVER_ADDR);
+    (client, tokio::spawn(MockServer::new(config).serve(server, client_addr)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::message::Credentials;
+    use crate::protocol::profile::HandshakeChecksums;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    /// Run the handshake for `username` from `from`; returns the AcceptOrDeny status.
+    fn handshake(server: &mut MockServer, from: SocketAddr, username: &str, credentials: &Credentials) -> u8 {
+        let mut request = vec![MessageType::ConnectionRequest as u8, 0, 0];
+        let mut writer = BinaryWriter::new();
+        ConnectionRequest::new(7).write(&mut writer);
+        request.extend_from_slice(writer.as_slice());
+        let reply = server.handle_datagram(from, &request).remove(0);
+        let reply = ConnectionRequestReply::read(&mut BinaryReader::new(&reply[1..])).unwrap();
+        assert_eq!(reply.client_request_id, 7);
+
+        let confirm = ConnectionRequestReplyConfirm::new(
+            7,
+            reply.server_request_id,
+            username.into(),
+            Vec::new(),
+            HandshakeChecksums::FACTORIO_2_0_72_SPACE_AGE,
+        )
+            .with_credentials(credentials);
+        let mut packet = vec![MessageType::ConnectionRequestReplyConfirm as u8, 1, 0];
+        let mut writer = BinaryWriter::new();
+        confirm.write(&mut writer);
+        packet.extend_from_slice(writer.as_slice());
+        let response = server.handle_datagram(from, &packet).remove(0);
+        assert_eq!(response[0], MessageType::ConnectionAcceptOrDeny as u8);
+        response[5]
+    }
+
+    #[test]
+    fn test_handshake_password() {
+        let mut server = MockServer::new(MockServerConfig {
+            password: Some("hunter2".into()),
+            ..MockServerConfig::default()
+        });
+        assert_eq!(handshake(&mut server, addr(1), "a", &Credentials::default()), STATUS_PASSWORD_REQUIRED);
+        assert_eq!(handshake(&mut server, addr(1), "a", &Credentials::with_password("nope")), STATUS_WRONG_PASSWORD);
+        assert_eq!(handshake(&mut server, addr(1), "a", &Credentials::with_password("hunter2")), 0);
+        assert_eq!(handshake(&mut server, addr(2), "a", &Credentials::with_password("hunter2")), STATUS_USERNAME_TAKEN);
+
+        let info = ServerInfo::parse(&server.handle_datagram(addr(3), &[MessageType::GameInformationRequest as u8])[0]).unwrap();
+        assert!(info.has_password);
+        assert_eq!(info.name, "mock server");
+    }
+
+    #[test]
+    fn test_actions_echoed_in_tick_closure() {
+        let mut server = MockServer::new(MockServerConfig {
+            map: vec![0xAB; 1200],
+            ..MockServerConfig::default()
+        });
+        let client = addr(1);
+        assert_eq!(handshake(&mut server, client, "echo", &Credentials::default()), 0);
+
+        // Sequences have to differ, repeats are ignored
+        let state_heartbeat = |sequence: u8, trailer: &[u8]| {
+            let mut packet = vec![MessageType::ClientToServerHeartbeat as u8, 0x10, sequence, 0, 0, 0];
+            packet.extend_from_slice(&u64::MAX.to_le_bytes());
+            packet.extend_from_slice(trailer);
+            packet
+        };
+        server.handle_datagram(client, &state_heartbeat(1, &[0x02, 0x03, 0x03, 0x09, 0x00]));
+        let offered = server.advance().remove(0).1;
+        assert_eq!(offered[1], DeserializationMask::HAS_SYNC_ACTIONS.bits());
+        assert_eq!(offered[7], SynchronizerActionType::MapReadyForDownload as u8);
+
+        let last_block = server.handle_datagram(client, &[0x0C, 2, 0, 0, 0]).remove(0);
+        assert_eq!(last_block.len(), 5 + 1200 - 2 * TRANSFER_BLOCK_SIZE);
+        assert!(server.handle_datagram(client, &[0x0C, 3, 0, 0, 0]).is_empty());
+
+        server.handle_datagram(client, &state_heartbeat(2, &[0x03, 0x06, 0xff, 0x03, 0x05, 0x03, 0x06]));
+        let joined = server.advance().remove(0).1;
+        assert_eq!(joined[1], 0x16); // closure + start tick
+        assert_eq!(server.players(), vec!["echo".to_string()]);
+
+        // One StopWalking closure from the client, C2S order: type, delta, data
+        let mut packet = vec![MessageType::ClientToServerHeartbeat as u8, 0x06, 3, 0, 0, 0];
+        let mut writer = BinaryWriter::new();
+        writer.write_u64_le(server.game_tick() as u64 + 10);
+        writer.write_opt_u32(2);
+        CodecInputAction::StopWalking.write_protocol_order(&mut writer, 1);
+        writer.write_u64_le(server.game_tick() as u64);
+        packet.extend_from_slice(writer.as_slice());
+        server.handle_datagram(client, &packet);
+
+        let echoed = server.advance().remove(0).1;
+        let mut reader = BinaryReader::new(&echoed[6..]);
+        assert_eq!(echoed[1], 0x06);
+        assert_eq!(reader.read_u64_le().unwrap(), server.game_tick() as u64);
+        assert_eq!(reader.read_opt_u32().unwrap(), 2);
+        assert_eq!(reader.read_opt_u16().unwrap(), 0); // same player as the join
+        assert_eq!(reader.read_opt_u16().unwrap(), CodecInputAction::StopWalking.action_type() as u16);
+        assert!(reader.is_empty());
+
+        let empty = server.advance().remove(0).1;
+        assert_eq!(empty[1], 0x0e);
+    }
+}
@@ -1,7 +1,17 @@
 pub mod packet;
 pub mod message;
 pub mod transport;
+pub mod capture;
 pub mod connection;
+pub mod profile;
+pub mod discovery;
+pub mod probe;
+pub mod mock_server;
+pub mod netsim;
+pub mod heartbeat;
+pub mod fragment;
+pub mod map_download;
+pub mod shared_map;
 
 pub(crate) fn rand_u32() -> u32 {
     use std::time::{SystemTime, UNIX_EPOCH};
@@ -14,13 +24,26 @@ pub use packet::{
     encode_type_byte, parse_type_byte, MAX_PACKET_SIZE,
 };
 pub use message::{
-    BuildVersion, ModInfo, ModVersion,
+    ApplicationVersion, BuildVersion, ModInfo, ModVersion,
     ConnectionRequest, ConnectionRequestReply, ConnectionRequestReplyConfirm,
-    ConnectionAcceptOrDeny, DenialReason, ServerInfo,
-    TransferBlockRequest, TransferBlock,
+    ConnectionAcceptOrDeny, Credentials, DenialReason, ServerInfo, hash_password,
+    TransferBlockRequest, TransferBlock, TRANSFER_BLOCK_SIZE, RequestForHeartbeatWhenDisconnecting,
     ClientToServerHeartbeat,
     InputAction,
 };
-pub use transport::Transport;
-pub use connection::{Connection, ConnectionState, PlayerState, ReceivedPacket};
+pub use profile::{HandshakeChecksums, HandshakeConfig, KnownChecksums, VersionProfile, VERSION_PROFILES};
+pub use discovery::{discover, DiscoveredServer, LanBroadcast, LAN_BROADCAST_PORT};
+pub use probe::{probe, probe_with_timeout};
+pub use mock_server::{MockServer, MockServerConfig};
+pub use netsim::{LinkStats, NetworkConditions, SimulatedSocket};
+pub use heartbeat::{ActionSegment, ConfirmRecord, SegmentAssembler, ServerHeartbeat, ServerSyncAction, ServerTickClosure};
+pub use map_download::{BlockDownloader, MapDownloadProgress};
+pub use shared_map::{SharedMaps, SharedSave};
+pub use transport::{ChannelSocket, DatagramSocket, Transport, TransportFactory, UdpDatagramSocket, UdpTransportFactory};
+pub use capture::{Capture, CaptureRecord, Direction, Recorder};
+pub use connection::{
+    ActionOutcome, ActionReceipt, Connection, ConnectionState, DesyncCause, DesyncReport, HeartbeatParsing,
+    NetworkStats, ObserverConnection, PlayerState, ReceivedPacket, ReconnectPolicy, RosterEntry, ServerRunState,
+    DEFAULT_ACTION_TIMEOUT,
+};
 pub use connection::ConnectionActions;
@@ -0,0 +1,330 @@
+//! Network-condition simulator
+//!
+//! `SimulatedSocket` wraps any `DatagramSocket` and degrades the link the way a bad
+//! VPN does: loss, duplication, reordering, latency with jitter, and a bandwidth
+//! cap. Every decision comes from a seeded RNG, so a given seed and datagram
+//! sequence always produce the same impairments.
+//!
+//! Delayed outbound datagrams are flushed from `send` and `recv`, so they only go
+//! out while the owner keeps calling those (as `Connection` does).
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::time::Instant;
+
+use crate::error::Result;
+use super::transport::DatagramSocket;
+
+/// Extra hold applied to a reordered datagram so later ones overtake it
+const REORDER_HOLD: Duration = Duration::from_millis(10);
+
+/// Impairments for one direction of a link. The default is a perfect link.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkConditions {
+    /// Probability (0.0-1.0) that a datagram is dropped
+    pub loss: f64,
+    /// Probability that a datagram is delivered twice
+    pub duplicate: f64,
+    /// Probability that a datagram is held back and overtaken by later ones
+    pub reorder: f64,
+    /// Fixed one-way delay
+    pub latency: Duration,
+    /// Uniform random extra delay in `0..jitter`
+    pub jitter: Duration,
+    /// Link capacity in bytes per second; `None` is unlimited
+    pub bandwidth: Option<u32>,
+    pub seed: u64,
+}
+
+impl NetworkConditions {
+    pub fn lossy(loss: f64, seed: u64) -> Self {
+        Self { loss, seed, ..Self::default() }
+    }
+}
+
+/// What a direction of the simulator did so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    pub delivered: u64,
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+}
+
+/// SplitMix64; small, seedable and good enough for coin flips.
+#[derive(Debug)]
+struct SimRng(u64);
+
+impl SimRng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.next_f64() < probability
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Scheduled {
+    at: Instant,
+    /// Tie-breaker keeping equal deadlines in arrival order
+    seq: u64,
+    data: Vec<u8>,
+}
+
+/// One direction: decides each datagram's fate and holds it until it is due.
+#[derive(Debug)]
+struct Link {
+    conditions: NetworkConditions,
+    rng: SimRng,
+    queue: BinaryHeap<Reverse<Scheduled>>,
+    next_seq: u64,
+    /// When the bandwidth-limited link finishes sending what is already queued
+    busy_until: Instant,
+    stats: LinkStats,
+}
+
+impl Link {
+    fn new(conditions: NetworkConditions, stream: u64) -> Self {
+        let rng = SimRng(conditions.seed ^ stream);
+        Self {
+            conditions,
+            rng,
+            queue: BinaryHeap::new(),
+            next_seq: 0,
+            busy_until: Instant::now(),
+            stats: LinkStats::default(),
+        }
+    }
+
+    fn schedule(&mut self, data: Vec<u8>, now: Instant) {
+        if self.rng.chance(self.conditions.loss) {
+            self.stats.dropped += 1;
+            return;
+        }
+        let copies = if self.rng.chance(self.conditions.duplicate) {
+            self.stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let mut at = now;
+            if let Some(bandwidth) = self.conditions.bandwidth {
+                let wire_time = Duration::from_secs_f64(data.len() as f64 / bandwidth.max(1) as f64);
+                self.busy_until = self.busy_until.max(now) + wire_time;
+                at = self.busy_until;
+            }
+            at += self.conditions.latency;
+            if !self.conditions.jitter.is_zero() {
+                at += self.conditions.jitter.mul_f64(self.rng.next_f64());
+            }
+            if self.rng.chance(self.conditions.reorder) {
+                self.stats.reordered += 1;
+                at += REORDER_HOLD + self.conditions.jitter;
+            }
+            self.queue.push(Reverse(Scheduled { at, seq: self.next_seq, data: data.clone() }));
+            self.next_seq += 1;
+        }
+    }
+
+    fn pop_due(&mut self, now: Instant) -> Option<Vec<u8>> {
+        match self.queue.peek() {
+            Some(Reverse(next)) if next.at <= now => {
+                self.stats.delivered += 1;
+                self.queue.pop().map(|Reverse(s)| s.data)
+            }
+            _ => None,
+        }
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.queue.peek().map(|Reverse(s)| s.at)
+    }
+}
+
+/// A `DatagramSocket` seen through a degraded network.
+///
+/// Never reports itself as UDP, so `Connection` keeps using it instead of binding
+/// a fresh socket for the handshake.
+pub struct SimulatedSocket<S> {
+    inner: S,
+    outbound: Link,
+    inbound: Link,
+}
+
+impl<S: DatagramSocket> SimulatedSocket<S> {
+    /// Apply the same conditions in both directions (each with its own RNG stream).
+    pub fn new(inner: S, conditions: NetworkConditions) -> Self {
+        Self::asymmetric(inner, conditions.clone(), conditions)
+    }
+
+    pub fn asymmetric(inner: S, outbound: NetworkConditions, inbound: NetworkConditions) -> Self {
+        Self {
+            inner,
+            outbound: Link::new(outbound, 0),
+            inbound: Link::new(inbound, 0x5851f42d4c957f2d),
+        }
+    }
+
+    pub fn outbound_stats(&self) -> LinkStats {
+        self.outbound.stats
+    }
+
+    pub fn inbound_stats(&self) -> LinkStats {
+        self.inbound.stats
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    async fn flush_outbound(&mut self) -> Result<()> {
+        let now = Instant::now();
+        while let Some(data) = self.outbound.pop_due(now) {
+            self.inner.send(&data).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<S: DatagramSocket> DatagramSocket for SimulatedSocket<S> {
+    async fn send(&mut self, data: &[u8]) -> Result<()> {
+        self.outbound.schedule(data.to_vec(), Instant::now());
+        self.flush_outbound().await
+    }
+
+    async fn recv(&mut self) -> Result<Vec<u8>> {
+        loop {
+            self.flush_outbound().await?;
+            if let Some(data) = self.inbound.pop_due(Instant::now()) {
+                return Ok(data);
+            }
+            let wake = match (self.inbound.next_due(), self.outbound.next_due()) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            tokio::select! {
+                received = self.inner.recv() => {
+                    self.inbound.schedule(received?, Instant::now());
+                }
+                _ = tokio::time::sleep_until(wake.unwrap_or_else(Instant::now)), if wake.is_some() => {}
+            }
+        }
+    }
+
+    fn try_recv(&mut self) -> Result<Option<Vec<u8>>> {
+        let now = Instant::now();
+        while let Some(data) = self.inner.try_recv()? {
+            self.inbound.schedule(data, now);
+        }
+        Ok(self.inbound.pop_due(now))
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::transport::ChannelSocket;
+
+    fn pair() -> (ChannelSocket, ChannelSocket) {
+        ChannelSocket::pair("127.0.0.1:1".parse().unwrap(), "127.0.0.1:2".parse().unwrap())
+    }
+
+    async fn send_numbered(socket: &mut impl DatagramSocket, count: u8) {
+        for i in 0..count {
+            socket.send(&[i]).await.unwrap();
+        }
+    }
+
+    fn drain(socket: &mut impl DatagramSocket) -> Vec<u8> {
+        std::iter::from_fn(|| socket.try_recv().unwrap()).map(|d| d[0]).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_loss_is_seeded() {
+        let run = |seed| async move {
+            let (a, mut b) = pair();
+            let mut a = SimulatedSocket::new(a, NetworkConditions::lossy(0.3, seed));
+            send_numbered(&mut a, 100).await;
+            (drain(&mut b), a.outbound_stats())
+        };
+        let (first, stats) = run(7).await;
+        assert_eq!(run(7).await.0, first);
+        assert_ne!(run(8).await.0, first);
+        assert_eq!(stats.dropped + stats.delivered, 100);
+        assert_eq!(first.len() as u64, stats.delivered);
+        assert!((15..=45).contains(&stats.dropped), "{:?}", stats);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delay_reorder_duplicate() {
+        let (mut a, b) = pair();
+        let conditions = NetworkConditions {
+            latency: Duration::from_millis(50),
+            duplicate: 0.2,
+            reorder: 0.2,
+            seed: 3,
+            ..NetworkConditions::default()
+        };
+        let mut b = SimulatedSocket::asymmetric(b, NetworkConditions::default(), conditions);
+        send_numbered(&mut a, 50).await;
+
+        // Nothing arrives before the link latency has passed.
+        assert_eq!(b.try_recv().unwrap(), None);
+        tokio::time::advance(Duration::from_millis(49)).await;
+        assert_eq!(b.try_recv().unwrap(), None);
+
+        let mut received = Vec::new();
+        while let Ok(Ok(data)) = tokio::time::timeout(Duration::from_millis(100), b.recv()).await {
+            received.push(data[0]);
+        }
+        let stats = b.inbound_stats();
+        assert!(stats.duplicated > 0 && stats.reordered > 0, "{:?}", stats);
+        assert_eq!(received.len() as u64, 50 + stats.duplicated);
+        assert!(received.windows(2).any(|w| w[0] > w[1]));
+        let mut unique = received.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique, (0..50).collect::<Vec<_>>());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_bandwidth_cap() {
+        let (a, mut b) = pair();
+        let conditions = NetworkConditions { bandwidth: Some(10_000), ..NetworkConditions::default() };
+        let mut a = SimulatedSocket::asymmetric(a, conditions, NetworkConditions::default());
+        // 10 x 1000 bytes at 10 kB/s: one datagram every 100ms
+        for _ in 0..10 {
+            a.send(&[0u8; 1000]).await.unwrap();
+        }
+        let start = Instant::now();
+        let mut arrivals = 0;
+        while arrivals < 10 {
+            // Delayed outbound datagrams go out from recv, so poll it briefly.
+            let _ = tokio::time::timeout(Duration::from_millis(10), a.recv()).await;
+            arrivals += drain(&mut b).len();
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(990) && elapsed < Duration::from_millis(1100), "{:?}", elapsed);
+    }
+}
@@ -55,9 +55,11 @@ impl MessageType {
 /// Type byte flags (combined with message type)
 /// Bit 5 = reliable/sequenced flag (real client sets this)
 /// Bit 6 = is fragmented
-/// Bit 7 = unused
+/// Bit 7 = last fragment of a fragmented message (a one-fragment
+/// ConnectionRequestReply arrives as 0xC3)
 const TYPE_RELIABLE_BIT: u8 = 0x20;
 const TYPE_FRAGMENTED_BIT: u8 = 0x40;
+pub(crate) const TYPE_LAST_FRAGMENT_BIT: u8 = 0x80;
 
 /// Message ID flags
 /// Bit 15 = has confirmations to ACK
@@ -89,7 +91,8 @@ pub struct PacketHeader {
     pub message_type: MessageType,
     pub reliable: bool,
     pub fragmented: bool,
-    pub server_flag: bool,
+    /// Bit 7: this fragment ends its message
+    pub last_fragment: bool,
     pub message_id: u16,
     pub has_confirmations: bool,
     pub fragment_id: Option<u16>,
@@ -109,7 +112,7 @@ impl PacketHeader {
         let type_byte = data[pos];
         pos += 1;
         let (message_type, reliable, fragmented_flag) = parse_type_byte(type_byte);
-        let server_flag = (type_byte & 0x80) != 0;
+        let last_fragment = fragmented_flag && (type_byte & TYPE_LAST_FRAGMENT_BIT) != 0;
         // Fragment ID exists for ANY message type when bit6 is set
         let fragmented = fragmented_flag;
 
@@ -132,7 +135,7 @@ impl PacketHeader {
                     message_type,
                     reliable,
                     fragmented,
-                    server_flag,
+                    last_fragment,
                     message_id: 0,
                     has_confirmations: false,
                     fragment_id: None,
@@ -183,7 +186,7 @@ impl PacketHeader {
                 message_type,
                 reliable,
                 fragmented,
-                server_flag,
+                last_fragment,
                 message_id,
                 has_confirmations,
                 fragment_id,
@@ -287,7 +290,7 @@ mod tests {
         assert!(reliable);
         assert!(fragmented);
 
-        // 0xC3 = 0b11000011 = type 3 + fragmented (bit 6) + bit 7 (unused)
+        // 0xC3 = 0b11000011 = type 3 + fragmented (bit 6) + last fragment (bit 7)
         let (msg_type, _reliable, fragmented) = parse_type_byte(0xC3);
         assert_eq!(msg_type, MessageType::ConnectionRequestReply);
         assert!(fragmented);
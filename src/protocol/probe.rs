@@ -0,0 +1,97 @@
+//! Server probe: GameInformationRequest without joining
+//!
+//! Sends GameInformationRequest (0x10, plus the reliable-flag variant 0x30 like the
+//! real client) and waits for GameInformationRequestReply. No ConnectionRequest is
+//! ever sent, so probing never takes a player slot.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::error::{Error, Result, Stage};
+use super::message::ServerInfo;
+use super::packet::MessageType;
+use super::transport::Transport;
+use super::discovery::LAN_BROADCAST_PORT;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+const PROBE_RESEND_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Query a server's name, version, mods, players etc. without joining.
+pub async fn probe(addr: SocketAddr) -> Result<ServerInfo> {
+    probe_with_timeout(addr, PROBE_TIMEOUT).await
+}
+
+pub async fn probe_with_timeout(addr: SocketAddr, timeout: Duration) -> Result<ServerInfo> {
+    let mut transport = probe_transport(addr).await?;
+    probe_with_transport(&mut transport, timeout).await
+}
+
+/// Transport for a probe: bound to the LAN discovery port if available, matching real
+/// client behavior, otherwise any port.
+pub(crate) async fn probe_transport(addr: SocketAddr) -> Result<Transport> {
+    let bind_addr = match addr {
+        SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], LAN_BROADCAST_PORT)),
+        SocketAddr::V6(_) => SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 0], LAN_BROADCAST_PORT)),
+    };
+    match Transport::new_with_bind(addr, bind_addr).await {
+        Ok(t) => Ok(t),
+        Err(_) => Transport::new(addr).await,
+    }
+}
+
+pub(crate) async fn probe_with_transport(transport: &mut Transport, timeout: Duration) -> Result<ServerInfo> {
+    let start = std::time::Instant::now();
+    let mut last_sent: Option<std::time::Instant> = None;
+    while start.elapsed() < timeout {
+        if last_sent.is_none_or(|t| t.elapsed() >= PROBE_RESEND_INTERVAL) {
+            transport.send_raw(&[MessageType::GameInformationRequest as u8]).await?;
+            transport.send_raw(&[MessageType::GameInformationRequest as u8 | 0x20]).await?;
+            last_sent = Some(std::time::Instant::now());
+        }
+        if let Some(data) = transport.recv_raw_timeout(Duration::from_millis(100)).await? {
+            if !data.is_empty() && (data[0] & 0x1F) == MessageType::GameInformationRequestReply as u8 {
+                return ServerInfo::parse(&data);
+            }
+        }
+    }
+    Err(Error::Timeout { stage: Stage::ServerInfo })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::BinaryWriter;
+    use crate::protocol::message::ApplicationVersion;
+
+    #[tokio::test]
+    async fn test_probe_local() {
+        let server = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            let (_, from) = server.recv_from(&mut buf).await.unwrap();
+            assert_eq!(buf[0] & 0x1F, MessageType::GameInformationRequest as u8);
+            let info = ServerInfo {
+                name: "probe me".into(),
+                description: String::new(),
+                version: ApplicationVersion::FACTORIO_2_0_72,
+                max_players: 0,
+                has_password: false,
+                mods: Vec::new(),
+                players: vec!["alice".into()],
+                tick: 42,
+                partial: false,
+            };
+            let mut writer = BinaryWriter::new();
+            writer.write_u8(MessageType::GameInformationRequestReply as u8);
+            info.write(&mut writer);
+            server.send_to(writer.as_slice(), from).await.unwrap();
+        });
+
+        let mut transport = Transport::new(server_addr).await.unwrap();
+        let info = probe_with_transport(&mut transport, Duration::from_secs(2)).await.unwrap();
+        assert_eq!(info.name, "probe me");
+        assert_eq!(info.players, vec!["alice".to_string()]);
+        assert_eq!(info.tick, 42);
+    }
+}
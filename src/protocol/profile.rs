@@ -0,0 +1,524 @@
+//! Handshake profiles: which application version, mod list and checksums we
+//! present to the server in ConnectionRequest / ConnectionRequestReplyConfirm.
+//!
+//! Sources, in priority order:
+//! 1. Explicit `HandshakeConfig` values (or FACTORIO_CORE_CHECKSUM / FACTORIO_PROTOTYPE_CHECKSUM)
+//! 2. A local factorio-current.log for the same version
+//! 3. The checksums known to the game build's `VersionProfile`
+//!
+//! A `VersionProfile` describes everything that changes between game builds:
+//! action numbering, the map format the parser can read, and the handshake
+//! constants above. The profile is picked from the version the server reports:
+//! 2.0.72 has its checksums and mod CRCs on file, the rest of 2.0 shares its
+//! numbering and map format but has to negotiate those. A version with no
+//! profile is refused up front instead of connecting with the wrong numbering
+//! and desyncing later.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+
+use crate::codec::{ActionNumbering, MapFormat, MapVersion};
+use crate::error::{Error, Result};
+use super::message::{ApplicationVersion, ModInfo, ModVersion};
+
+/// Mods shipped with the game; their version always equals the game version.
+pub const BUILTIN_MODS: &[&str] = &["base", "elevated-rails", "quality", "space-age"];
+
+/// Core and prototype-list checksums sent in ConnectionRequestReplyConfirm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandshakeChecksums {
+    pub core: u32,
+    pub prototype_list: u32,
+}
+
+/// Checksums observed from real clients for one enabled mod set (sorted)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KnownChecksums {
+    pub mods: &'static [&'static str],
+    pub checksums: HandshakeChecksums,
+}
+
+impl HandshakeChecksums {
+    pub const FACTORIO_2_0_72_SPACE_AGE: Self = Self {
+        core: 3316885848,
+        prototype_list: 748475845,
+    };
+
+    /// Look up checksums for a version and mod list in the built-in profiles.
+    pub fn known(version: ApplicationVersion, mods: &[ModInfo]) -> Option<Self> {
+        VersionProfile::for_version(version).ok()?.known_checksums(mods)
+    }
+}
+
+/// Protocol details of a range of game builds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionProfile {
+    pub name: &'static str,
+    /// First and last (major, minor, patch) the profile applies to
+    pub versions: ((u16, u16, u16), (u16, u16, u16)),
+    /// Version advertised when the server's is not known yet
+    pub default_version: ApplicationVersion,
+    pub numbering: ActionNumbering,
+    /// Saves the map parser reads for these builds
+    pub map_format: MapFormat,
+    /// Built-in mods and their CRCs, used when the server's mod list can't be queried
+    pub builtin_mods: &'static [(&'static str, u32)],
+    pub checksums: &'static [KnownChecksums],
+}
+
+/// Built-in profiles, most specific first
+pub const VERSION_PROFILES: &[VersionProfile] = &[VersionProfile::FACTORIO_2_0_72, VersionProfile::FACTORIO_2_0];
+
+impl VersionProfile {
+    /// Space Age 2.0.72, the build the codec was reverse engineered from
+    pub const FACTORIO_2_0_72: Self = Self {
+        name: "2.0.72",
+        versions: ((2, 0, 72), (2, 0, 72)),
+        default_version: ApplicationVersion::FACTORIO_2_0_72,
+        numbering: ActionNumbering::CANONICAL,
+        map_format: MapFormat::FACTORIO_2_0,
+        builtin_mods: &[
+            ("base", 0x70059c86),
+            ("elevated-rails", 0x31790248),
+            ("quality", 0x441a8746),
+            ("space-age", 0x5a0ae76b),
+        ],
+        checksums: &[KnownChecksums {
+            mods: &["base", "elevated-rails", "quality", "space-age"],
+            checksums: HandshakeChecksums::FACTORIO_2_0_72_SPACE_AGE,
+        }],
+    };
+
+    /// Other 2.0 builds. Action numbering and the map format have not changed
+    /// within 2.0; mod CRCs and checksums do, so they come from the server's
+    /// mod list, a local install or `HandshakeConfig`.
+    pub const FACTORIO_2_0: Self = Self {
+        name: "2.0",
+        versions: ((2, 0, 0), (2, 0, u16::MAX)),
+        default_version: ApplicationVersion::FACTORIO_2_0_72,
+        numbering: ActionNumbering::CANONICAL,
+        map_format: MapFormat::FACTORIO_2_0,
+        builtin_mods: &[],
+        checksums: &[],
+    };
+
+    /// Profile for a game version, or `Error::UnsupportedVersion`
+    pub fn for_version(version: ApplicationVersion) -> Result<&'static Self> {
+        VERSION_PROFILES
+            .iter()
+            .find(|profile| profile.covers(version.major, version.minor, version.patch))
+            .ok_or_else(|| Error::UnsupportedVersion(version.to_string()))
+    }
+
+    pub fn covers(&self, major: u16, minor: u16, patch: u16) -> bool {
+        let (first, last) = self.versions;
+        (first..=last).contains(&(major, minor, patch))
+    }
+
+    /// The server serializes the map with its own version, so a map outside
+    /// the profile's map format was written by a build we don't know.
+    pub fn check_map_version(&self, version: &MapVersion) -> Result<()> {
+        self.map_format.check(version)
+    }
+
+    pub fn known_checksums(&self, mods: &[ModInfo]) -> Option<HandshakeChecksums> {
+        let mut names: Vec<&str> = mods.iter().map(|m| m.name.as_str()).collect();
+        names.sort_unstable();
+        self.checksums
+            .iter()
+            .find(|k| k.mods.len() == names.len() && k.mods.iter().zip(&names).all(|(a, b)| a == b))
+            .map(|k| k.checksums)
+    }
+
+    /// Built-in mods at the given game version. The CRCs change with every
+    /// patch, so there are only any for the profile's own build.
+    pub fn default_mods(&self, version: ApplicationVersion) -> Option<Vec<ModInfo>> {
+        let default = self.default_version;
+        if self.builtin_mods.is_empty()
+            || (default.major, default.minor, default.patch) != (version.major, version.minor, version.patch)
+        {
+            return None;
+        }
+        let mod_version = builtin_mod_version(version)?;
+        Some(
+            self.builtin_mods
+                .iter()
+                .map(|&(name, crc)| ModInfo { name: name.to_string(), version: mod_version, crc })
+                .collect(),
+        )
+    }
+}
+
+/// Version of the built-in mods at a game version; `None` if it doesn't fit
+/// the one-byte mod version fields.
+pub fn builtin_mod_version(version: ApplicationVersion) -> Option<ModVersion> {
+    Some(ModVersion::new(
+        u8::try_from(version.major).ok()?,
+        u8::try_from(version.minor).ok()?,
+        u8::try_from(version.patch).ok()?,
+    ))
+}
+
+/// User-supplied handshake overrides. Anything left as `None` is negotiated.
+#[derive(Debug, Clone, Default)]
+pub struct HandshakeConfig {
+    /// Version sent in ConnectionRequest. `None` uses the version the server
+    /// advertises in GameInformationRequestReply (2.0.72 if that query fails).
+    /// Either way it is replaced by the server's if ConnectionRequestReply differs.
+    pub version: Option<ApplicationVersion>,
+    /// Mod list to send instead of the server's.
+    pub mods: Option<Vec<ModInfo>>,
+    /// Factorio mods directory (containing mod-list.json), used when the server query fails.
+    pub mods_dir: Option<PathBuf>,
+    pub checksums: Option<HandshakeChecksums>,
+    /// factorio-current.log to read checksums from, instead of the platform defaults.
+    pub log_path: Option<PathBuf>,
+    /// Profile to use whatever version the server reports, for builds not in
+    /// `VERSION_PROFILES`.
+    pub profile: Option<VersionProfile>,
+}
+
+impl HandshakeConfig {
+    /// Defaults plus FACTORIO_CORE_CHECKSUM, FACTORIO_PROTOTYPE_CHECKSUM,
+    /// FACTORIO_MODS_DIR and FACTORIO_LOG_PATH from the environment.
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        let core = std::env::var("FACTORIO_CORE_CHECKSUM").ok().and_then(|v| v.trim().parse().ok());
+        let proto = std::env::var("FACTORIO_PROTOTYPE_CHECKSUM").ok().and_then(|v| v.trim().parse().ok());
+        if let (Some(core), Some(prototype_list)) = (core, proto) {
+            config.checksums = Some(HandshakeChecksums { core, prototype_list });
+        }
+        config.mods_dir = std::env::var("FACTORIO_MODS_DIR").ok().map(PathBuf::from);
+        config.log_path = std::env::var("FACTORIO_LOG_PATH").ok().map(PathBuf::from);
+        config
+    }
+
+    /// Profile for the negotiated version, preferring an explicit override.
+    pub fn resolve_profile(&self, version: ApplicationVersion) -> Result<VersionProfile> {
+        match self.profile {
+            Some(profile) => Ok(profile),
+            None => VersionProfile::for_version(version).copied(),
+        }
+    }
+
+    /// Resolve checksums for the negotiated version and mod list. With none
+    /// known this is `Error::UnsupportedVersion`: guessed checksums would only
+    /// get the join denied without saying why.
+    pub fn resolve_checksums(&self, version: ApplicationVersion, mods: &[ModInfo]) -> Result<HandshakeChecksums> {
+        if let Some(checksums) = self.checksums {
+            return Ok(checksums);
+        }
+        self.log_candidates()
+            .iter()
+            .filter_map(|path| LocalLogInfo::read(path))
+            .find(|info| info.matches_version(version))
+            .and_then(|info| info.checksums())
+            .or_else(|| self.resolve_profile(version).ok()?.known_checksums(mods))
+            .ok_or_else(|| {
+                Error::UnsupportedVersion(format!(
+                    "{} (checksums unknown for this mod set, set HandshakeConfig.checksums or \
+                     FACTORIO_CORE_CHECKSUM/FACTORIO_PROTOTYPE_CHECKSUM)",
+                    version
+                ))
+            })
+    }
+
+    /// Build a mod list from the local mods directory (mod-list.json), taking
+    /// CRCs from the local log when available.
+    pub fn local_mods(&self, version: ApplicationVersion) -> Option<Vec<ModInfo>> {
+        let dir = self.mods_dir.clone().or_else(default_mods_dir)?;
+        let crcs = self
+            .log_candidates()
+            .iter()
+            .filter_map(|path| LocalLogInfo::read(path))
+            .find(|info| info.matches_version(version))
+            .map(|info| info.mod_crcs)
+            .unwrap_or_default();
+        read_mods_dir(&dir, version, &crcs)
+    }
+
+    fn log_candidates(&self) -> Vec<PathBuf> {
+        match &self.log_path {
+            Some(path) => vec![path.clone()],
+            None => default_log_paths(),
+        }
+    }
+}
+
+/// Values scraped from a factorio-current.log.
+#[derive(Debug, Clone, Default)]
+pub struct LocalLogInfo {
+    pub version: Option<(u16, u16, u16)>,
+    pub core_checksum: Option<u32>,
+    pub prototype_list_checksum: Option<u32>,
+    pub mod_crcs: HashMap<String, u32>,
+}
+
+impl LocalLogInfo {
+    pub fn read(path: &Path) -> Option<Self> {
+        let file = File::open(path).ok()?;
+        let info = Self::parse(io::BufReader::new(file).lines().map_while(|l| l.ok()));
+        if info.core_checksum.is_some() || !info.mod_crcs.is_empty() {
+            Some(info)
+        } else {
+            None
+        }
+    }
+
+    pub fn parse<I: IntoIterator<Item = String>>(lines: I) -> Self {
+        let mut info = Self::default();
+        for line in lines {
+            if info.version.is_none() {
+                info.version = parse_log_version(&line);
+            }
+            if info.core_checksum.is_none() {
+                info.core_checksum = parse_checksum(&line, "Checksum for core:");
+            }
+            if info.prototype_list_checksum.is_none() {
+                info.prototype_list_checksum = parse_checksum(&line, "Prototype list checksum:");
+            }
+            if let Some(idx) = line.find("Checksum of ") {
+                let rest = &line[idx + "Checksum of ".len()..];
+                if let Some((name, value)) = rest.split_once(':') {
+                    if let Ok(crc) = value.trim().parse() {
+                        info.mod_crcs.insert(name.trim().to_string(), crc);
+                    }
+                }
+            }
+        }
+        info
+    }
+
+    /// Logs without a version banner match no version: their checksums could
+    /// be from any build.
+    fn matches_version(&self, version: ApplicationVersion) -> bool {
+        self.version == Some((version.major, version.minor, version.patch))
+    }
+
+    pub fn checksums(&self) -> Option<HandshakeChecksums> {
+        Some(HandshakeChecksums {
+            core: self.core_checksum?,
+            prototype_list: self.prototype_list_checksum?,
+        })
+    }
+}
+
+fn parse_checksum(line: &str, label: &str) -> Option<u32> {
+    let idx = line.find(label)?;
+    let rest = &line[idx + label.len()..];
+    rest.split_whitespace().next()?.parse().ok()
+}
+
+/// Parse the "Factorio 2.0.72 (build 84292, ...)" banner line.
+fn parse_log_version(line: &str) -> Option<(u16, u16, u16)> {
+    let idx = line.find("Factorio ")?;
+    let rest = &line[idx + "Factorio ".len()..];
+    if !rest.contains("(build") {
+        return None;
+    }
+    let mut parts = rest.split_whitespace().next()?.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    let patch = parts.next()?.parse().ok()?;
+    Some((major, minor, patch))
+}
+
+fn default_log_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Some(home) = dirs::home_dir() {
+        paths.push(home.join("Library/Application Support/factorio-server/factorio-current.log"));
+        paths.push(home.join("Library/Application Support/factorio/factorio-current.log"));
+        paths.push(home.join(".factorio/factorio-current.log"));
+        paths.push(home.join(".local/share/Steam/steamapps/common/Factorio/factorio-current.log"));
+    }
+    if let Ok(appdata) = std::env::var("APPDATA") {
+        paths.push(PathBuf::from(appdata).join("Factorio").join("factorio-current.log"));
+    }
+    paths.push(PathBuf::from("/opt/factorio/factorio-current.log"));
+    paths.push(PathBuf::from("/factorio/factorio-current.log"));
+    paths.push(PathBuf::from("/tmp/factorio-console.log"));
+    paths
+}
+
+fn default_mods_dir() -> Option<PathBuf> {
+    let home = dirs::home_dir()?;
+    [
+        home.join("Library/Application Support/factorio/mods"),
+        home.join(".factorio/mods"),
+    ]
+    .into_iter()
+    .find(|p| p.join("mod-list.json").exists())
+}
+
+/// Read enabled mods from `mod-list.json`. Built-in mods take the game version;
+/// others take the version from their `name_x.y.z[.zip]` entry in the directory.
+/// `None` if any enabled mod has no CRC in `crcs`, since a wrong CRC is a
+/// certain mod mismatch.
+fn read_mods_dir(dir: &Path, version: ApplicationVersion, crcs: &HashMap<String, u32>) -> Option<Vec<ModInfo>> {
+    let list = std::fs::read_to_string(dir.join("mod-list.json")).ok()?;
+    let list: serde_json::Value = serde_json::from_str(&list).ok()?;
+    let entries: Vec<String> = std::fs::read_dir(dir)
+        .map(|rd| {
+            rd.filter_map(|e| e.ok())
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut mods = Vec::new();
+    for entry in list.get("mods")?.as_array()? {
+        let enabled = entry.get("enabled").and_then(|v| v.as_bool()).unwrap_or(true);
+        let Some(name) = entry.get("name").and_then(|v| v.as_str()) else {
+            continue;
+        };
+        if !enabled {
+            continue;
+        }
+        let mod_version = if BUILTIN_MODS.contains(&name) {
+            builtin_mod_version(version)
+        } else {
+            entries
+                .iter()
+                .filter_map(|file| parse_mod_file_version(file, name))
+                .max_by_key(|v| (v.major, v.minor, v.patch))
+        };
+        let Some(mod_version) = mod_version else {
+            continue;
+        };
+        mods.push(ModInfo {
+            name: name.to_string(),
+            version: mod_version,
+            crc: *crcs.get(name)?,
+        });
+    }
+    Some(mods)
+}
+
+fn parse_mod_file_version(file: &str, name: &str) -> Option<ModVersion> {
+    let rest = file.strip_prefix(name)?.strip_prefix('_')?;
+    let rest = rest.strip_suffix(".zip").unwrap_or(rest);
+    let mut parts = rest.split('.');
+    let version = ModVersion::new(
+        parts.next()?.parse().ok()?,
+        parts.next()?.parse().ok()?,
+        parts.next()?.parse().ok()?,
+    );
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mods(names: &[&str]) -> Vec<ModInfo> {
+        names
+            .iter()
+            .map(|n| ModInfo {
+                name: n.to_string(),
+                version: ModVersion::new(2, 0, 72),
+                crc: 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_known_checksums() {
+        let v = ApplicationVersion::FACTORIO_2_0_72;
+        let space_age = mods(&["space-age", "base", "quality", "elevated-rails"]);
+        assert_eq!(
+            HandshakeChecksums::known(v, &space_age),
+            Some(HandshakeChecksums::FACTORIO_2_0_72_SPACE_AGE)
+        );
+        assert_eq!(HandshakeChecksums::known(v, &mods(&["base"])), None);
+    }
+
+    #[test]
+    fn test_version_profiles() {
+        let profile = VersionProfile::for_version(ApplicationVersion::FACTORIO_2_0_72).unwrap();
+        assert_eq!(profile.name, "2.0.72");
+        assert_eq!(profile.default_mods(ApplicationVersion::FACTORIO_2_0_72).map(|m| m.len()), Some(4));
+
+        // Other 2.0 patches fall back to the generic profile
+        let patch = ApplicationVersion { patch: 99, ..ApplicationVersion::FACTORIO_2_0_72 };
+        let generic = VersionProfile::for_version(patch).unwrap();
+        assert_eq!(generic.name, "2.0");
+        assert_eq!(generic.numbering, profile.numbering);
+        assert!(generic.default_mods(patch).is_none());
+        // 2.0.72's CRCs are not reused for another patch
+        assert!(profile.default_mods(patch).is_none());
+        assert_eq!(builtin_mod_version(ApplicationVersion { patch: 300, ..patch }), None);
+        assert_eq!(generic.known_checksums(&mods(&["base"])), None);
+        let map = MapVersion { major: 2, minor: 0, patch: 99, build: 0, quality_version: 0 };
+        assert!(profile.check_map_version(&map).is_ok());
+
+        let unknown = ApplicationVersion { major: 2, minor: 1, patch: 0, ..ApplicationVersion::FACTORIO_2_0_72 };
+        match VersionProfile::for_version(unknown) {
+            Err(Error::UnsupportedVersion(v)) => assert_eq!(v, "2.1.0 (build 84292)"),
+            other => panic!("unexpected {:?}", other),
+        }
+        let map = MapVersion { major: 2, minor: 1, patch: 0, build: 0, quality_version: 0 };
+        assert!(profile.check_map_version(&map).is_err());
+
+        // An explicit profile is used for any version
+        let config = HandshakeConfig { profile: Some(VersionProfile::FACTORIO_2_0_72), ..HandshakeConfig::default() };
+        assert_eq!(config.resolve_profile(unknown).unwrap().name, "2.0.72");
+        assert!(HandshakeConfig::default().resolve_profile(unknown).is_err());
+    }
+
+    #[test]
+    fn test_parse_log() {
+        let lines = [
+            "   0.000 2025-01-01 12:00:00; Factorio 2.0.73 (build 84300, linux64, headless, space-age)",
+            "   0.100 Checksum for core: 123456789",
+            "   0.200 Checksum of base: 42",
+            "   0.201 Checksum of quality: 7",
+            "   0.300 Prototype list checksum: 987654321",
+        ];
+        let info = LocalLogInfo::parse(lines.iter().map(|s| s.to_string()));
+        assert_eq!(info.version, Some((2, 0, 73)));
+        assert_eq!(
+            info.checksums(),
+            Some(HandshakeChecksums { core: 123456789, prototype_list: 987654321 })
+        );
+        assert_eq!(info.mod_crcs.get("base"), Some(&42));
+        assert_eq!(info.mod_crcs.get("quality"), Some(&7));
+        assert!(!info.matches_version(ApplicationVersion::FACTORIO_2_0_72));
+
+        let unversioned = LocalLogInfo::parse(lines[1..].iter().map(|s| s.to_string()));
+        assert!(unversioned.checksums().is_some());
+        assert!(!unversioned.matches_version(ApplicationVersion::FACTORIO_2_0_72));
+    }
+
+    #[test]
+    fn test_read_mods_dir_needs_crcs() {
+        let dir = std::env::temp_dir().join(format!("factorio-mods-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("mod-list.json"),
+            r#"{"mods": [{"name": "base", "enabled": true}, {"name": "foo", "enabled": true}]}"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("foo_1.2.3.zip"), b"").unwrap();
+
+        let v = ApplicationVersion::FACTORIO_2_0_72;
+        let mut crcs = HashMap::from([("base".to_string(), 42)]);
+        assert!(read_mods_dir(&dir, v, &crcs).is_none());
+        crcs.insert("foo".into(), 7);
+        let mods = read_mods_dir(&dir, v, &crcs).unwrap();
+        assert_eq!(mods.iter().map(|m| m.crc).collect::<Vec<_>>(), vec![42, 7]);
+        assert_eq!(mods[1].version, ModVersion::new(1, 2, 3));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_mod_file_version() {
+        assert_eq!(parse_mod_file_version("foo_1.2.3.zip", "foo"), Some(ModVersion::new(1, 2, 3)));
+        assert_eq!(parse_mod_file_version("foo_1.2.3", "foo"), Some(ModVersion::new(1, 2, 3)));
+        assert_eq!(parse_mod_file_version("foobar_1.2.3.zip", "foo"), None);
+    }
+}
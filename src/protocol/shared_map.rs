@@ -0,0 +1,131 @@
+//! Saves shared between the connections of one process
+//!
+//! Clients that ask for the map around the same time are offered the same save,
+//! identified by the CRC and size in MapReadyForDownload. With a `SharedMaps`
+//! attached (see `ClientPool`), the first connection to be offered a save
+//! downloads and parses it; the others keep sending download-progress
+//! heartbeats until it is published, then take the bytes, the parsed `MapData`
+//! and the simulation's starting world without requesting a block. All of it
+//! is shared through `Arc`s; a connection's world is copied only once its
+//! simulation changes it. If the first connection fails, the next one waiting
+//! takes over.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::codec::MapData;
+use crate::state::GameWorld;
+
+/// (CRC, transfer size) from MapReadyForDownload
+pub(crate) type SaveKey = (u32, u32);
+
+/// One downloaded save
+#[derive(Debug, Clone)]
+pub struct SharedSave {
+    pub data: Arc<Vec<u8>>,
+    /// `None` if the downloading connection did not parse it
+    pub parsed: Option<Arc<MapData>>,
+    /// The simulation world built from `parsed`, before any tick ran
+    pub world: Option<Arc<GameWorld>>,
+}
+
+#[derive(Debug)]
+enum Slot {
+    Downloading,
+    Ready(SharedSave),
+}
+
+/// What a connection offered a save should do
+pub(crate) enum Claim {
+    /// Nobody has it: download it and `publish` (or drop the guard to give up)
+    Download(DownloadGuard),
+    /// Another connection is downloading it
+    Wait,
+    Ready(SharedSave),
+}
+
+/// Downloaded and parsed saves, cheap to clone and share between connections
+#[derive(Debug, Clone, Default)]
+pub struct SharedMaps {
+    slots: Arc<Mutex<HashMap<SaveKey, Slot>>>,
+}
+
+impl SharedMaps {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, crc: u32, transfer_size: u32) -> Option<SharedSave> {
+        match self.slots.lock().unwrap().get(&(crc, transfer_size)) {
+            Some(Slot::Ready(save)) => Some(save.clone()),
+            _ => None,
+        }
+    }
+
+    /// Number of saves published so far
+    pub fn len(&self) -> usize {
+        self.slots.lock().unwrap().values().filter(|slot| matches!(slot, Slot::Ready(_))).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn claim(&self, key: SaveKey) -> Claim {
+        let mut slots = self.slots.lock().unwrap();
+        match slots.get(&key) {
+            Some(Slot::Ready(save)) => Claim::Ready(save.clone()),
+            Some(Slot::Downloading) => Claim::Wait,
+            None => {
+                slots.insert(key, Slot::Downloading);
+                Claim::Download(DownloadGuard { maps: self.clone(), key, published: false })
+            }
+        }
+    }
+}
+
+/// Held by the connection downloading a save; dropping it unpublished lets a
+/// waiting connection take over.
+pub(crate) struct DownloadGuard {
+    maps: SharedMaps,
+    key: SaveKey,
+    published: bool,
+}
+
+impl DownloadGuard {
+    pub fn publish(mut self, save: SharedSave) {
+        self.maps.slots.lock().unwrap().insert(self.key, Slot::Ready(save));
+        self.published = true;
+    }
+}
+
+impl Drop for DownloadGuard {
+    fn drop(&mut self) {
+        if !self.published {
+            self.maps.slots.lock().unwrap().remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_claim_publish_and_abandon() {
+        let maps = SharedMaps::new();
+        let Claim::Download(guard) = maps.claim((1, 100)) else { panic!("first claim downloads") };
+        assert!(matches!(maps.claim((1, 100)), Claim::Wait));
+
+        // Giving up hands the save to the next claimant
+        drop(guard);
+        let Claim::Download(guard) = maps.claim((1, 100)) else { panic!("abandoned save is free") };
+        guard.publish(SharedSave { data: Arc::new(vec![7; 100]), parsed: None, world: None });
+        match maps.claim((1, 100)) {
+            Claim::Ready(save) => assert_eq!(save.data.len(), 100),
+            _ => panic!("published save is ready"),
+        }
+        assert_eq!(maps.len(), 1);
+        assert!(maps.get(2, 100).is_none());
+    }
+}
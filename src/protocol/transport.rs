@@ -1,32 +1,67 @@
 use std::net::SocketAddr;
 use std::time::Duration;
+use async_trait::async_trait;
 use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
 #[cfg(unix)]
 use std::os::unix::io::AsRawFd;
 
 use crate::error::{Error, Result};
+use crate::protocol::capture::{Capture, Direction, Recorder, Replay};
 use crate::protocol::packet::{PacketHeader, PacketBuilder, MessageType, MAX_PACKET_SIZE};
 
-/// Simple UDP transport layer
+/// Upper bound on how long a replay transport sleeps when nothing is ready yet
+const REPLAY_IDLE_SLEEP: Duration = Duration::from_millis(1);
+
+/// A datagram link to one peer.
 ///
-/// Handles raw UDP send/receive with basic packet framing.
-/// Message sequencing and ACKs are handled at a higher level.
-pub struct Transport {
-    socket: UdpSocket,
-    remote_addr: SocketAddr,
-    next_msg_id: u16,
+/// `Transport` does framing, message ids and capture on top of this; implementations
+/// only move whole datagrams. `recv` must be cancel-safe since it is raced against
+/// timeouts.
+#[async_trait]
+pub trait DatagramSocket: Send {
+    async fn send(&mut self, data: &[u8]) -> Result<()>;
+
+    /// Wait for the next datagram.
+    async fn recv(&mut self) -> Result<Vec<u8>>;
+
+    /// Next datagram if one is already queued.
+    fn try_recv(&mut self) -> Result<Option<Vec<u8>>>;
+
+    fn local_addr(&self) -> Result<SocketAddr>;
+
+    /// Whether `Connection` may replace this with a freshly bound UDP socket
+    /// (as the real client does between the server info query and the handshake).
+    fn is_udp(&self) -> bool {
+        false
+    }
 }
 
-impl Transport {
-    pub async fn new(remote_addr: SocketAddr) -> Result<Self> {
-        let bind_addr = match remote_addr {
-            SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
-            SocketAddr::V6(_) => SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 0], 0)),
-        };
-        Self::new_with_bind(remote_addr, bind_addr).await
+/// Opens the transport for each connect of a `Connection`, including the
+/// fresh connection a reconnect builds.
+#[async_trait]
+pub trait TransportFactory: Send + Sync {
+    async fn open(&self, remote_addr: SocketAddr) -> Result<Transport>;
+}
+
+/// The default factory: a newly bound UDP socket every time
+pub struct UdpTransportFactory;
+
+#[async_trait]
+impl TransportFactory for UdpTransportFactory {
+    async fn open(&self, remote_addr: SocketAddr) -> Result<Transport> {
+        Transport::new(remote_addr).await
     }
+}
 
-    pub async fn new_with_bind(remote_addr: SocketAddr, bind_addr: SocketAddr) -> Result<Self> {
+/// UDP socket connected (logically) to one remote address
+pub struct UdpDatagramSocket {
+    socket: UdpSocket,
+    remote_addr: SocketAddr,
+}
+
+impl UdpDatagramSocket {
+    pub async fn bind(remote_addr: SocketAddr, bind_addr: SocketAddr) -> Result<Self> {
         let socket = UdpSocket::bind(bind_addr).await
             .map_err(|e| Error::Io(e.to_string()))?;
 
@@ -43,16 +78,189 @@ impl Transport {
             );
         }
 
-        Ok(Self {
-            socket,
-            remote_addr,
+        Ok(Self { socket, remote_addr })
+    }
+}
+
+#[async_trait]
+impl DatagramSocket for UdpDatagramSocket {
+    async fn send(&mut self, data: &[u8]) -> Result<()> {
+        self.socket.send_to(data, self.remote_addr).await
+            .map_err(|e| Error::Io(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Datagrams from any address but the server's are dropped, so other hosts
+    /// can't inject heartbeats, blocks or denials.
+    async fn recv(&mut self) -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; MAX_PACKET_SIZE];
+        loop {
+            let (len, from) = self.socket.recv_from(&mut buf).await
+                .map_err(|e| Error::Io(e.to_string()))?;
+            if from == self.remote_addr {
+                buf.truncate(len);
+                return Ok(buf);
+            }
+        }
+    }
+
+    fn try_recv(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut buf = vec![0u8; MAX_PACKET_SIZE];
+        loop {
+            match self.socket.try_recv_from(&mut buf) {
+                Ok((len, from)) if from == self.remote_addr => {
+                    buf.truncate(len);
+                    return Ok(Some(buf));
+                }
+                Ok(_) => continue,
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(Error::Io(e.to_string())),
+            }
+        }
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        self.socket.local_addr()
+            .map_err(|e| Error::Io(e.to_string()))
+    }
+
+    fn is_udp(&self) -> bool {
+        true
+    }
+}
+
+/// In-memory datagram link, for running `Connection` against a scripted peer.
+pub struct ChannelSocket {
+    tx: mpsc::UnboundedSender<Vec<u8>>,
+    rx: mpsc::UnboundedReceiver<Vec<u8>>,
+    local_addr: SocketAddr,
+}
+
+impl ChannelSocket {
+    /// Two connected ends; whatever one sends the other receives, in order.
+    pub fn pair(a_addr: SocketAddr, b_addr: SocketAddr) -> (Self, Self) {
+        let (a_tx, b_rx) = mpsc::unbounded_channel();
+        let (b_tx, a_rx) = mpsc::unbounded_channel();
+        (
+            Self { tx: a_tx, rx: a_rx, local_addr: a_addr },
+            Self { tx: b_tx, rx: b_rx, local_addr: b_addr },
+        )
+    }
+}
+
+#[async_trait]
+impl DatagramSocket for ChannelSocket {
+    async fn send(&mut self, data: &[u8]) -> Result<()> {
+        self.tx.send(data.to_vec())
+            .map_err(|_| Error::Disconnected { reason: "channel peer closed".into() })
+    }
+
+    async fn recv(&mut self) -> Result<Vec<u8>> {
+        self.rx.recv().await
+            .ok_or_else(|| Error::Disconnected { reason: "channel peer closed".into() })
+    }
+
+    fn try_recv(&mut self) -> Result<Option<Vec<u8>>> {
+        match self.rx.try_recv() {
+            Ok(data) => Ok(Some(data)),
+            Err(mpsc::error::TryRecvError::Empty) => Ok(None),
+            Err(mpsc::error::TryRecvError::Disconnected) => Err(Error::Disconnected { reason: "channel peer closed".into() }),
+        }
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+}
+
+#[async_trait]
+impl DatagramSocket for Replay {
+    async fn send(&mut self, _data: &[u8]) -> Result<()> {
+        self.on_send();
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> Result<Vec<u8>> {
+        loop {
+            if let Some(data) = self.next_ready() {
+                return Ok(data);
+            }
+            if self.is_exhausted() {
+                return Err(Error::ReplayExhausted);
+            }
+            // Nothing is released until the client sends more; don't spin.
+            tokio::time::sleep(REPLAY_IDLE_SLEEP).await;
+        }
+    }
+
+    fn try_recv(&mut self) -> Result<Option<Vec<u8>>> {
+        Ok(self.next_ready())
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(SocketAddr::from(([127, 0, 0, 1], 0)))
+    }
+}
+
+/// Packet transport layer
+///
+/// Handles raw datagram send/receive with basic packet framing over any
+/// `DatagramSocket` (UDP by default). Message sequencing and ACKs are handled at a
+/// higher level. Can optionally record all traffic to a capture file, or replay one
+/// instead of talking to a socket (see `protocol::capture`).
+pub struct Transport {
+    socket: Box<dyn DatagramSocket>,
+    next_msg_id: u16,
+    recorder: Option<Recorder>,
+}
+
+impl Transport {
+    pub async fn new(remote_addr: SocketAddr) -> Result<Self> {
+        let bind_addr = match remote_addr {
+            SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+            SocketAddr::V6(_) => SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 0], 0)),
+        };
+        Self::new_with_bind(remote_addr, bind_addr).await
+    }
+
+    pub async fn new_with_bind(remote_addr: SocketAddr, bind_addr: SocketAddr) -> Result<Self> {
+        let socket = UdpDatagramSocket::bind(remote_addr, bind_addr).await?;
+        Ok(Self::with_socket(socket))
+    }
+
+    /// Transport over any datagram implementation (e.g. `ChannelSocket`).
+    pub fn with_socket(socket: impl DatagramSocket + 'static) -> Self {
+        Self {
+            socket: Box::new(socket),
             next_msg_id: 1,
-        })
+            recorder: None,
+        }
+    }
+
+    /// Transport that plays back the inbound side of `capture` instead of using a socket.
+    /// Sends are accepted and discarded; they only pace the replay.
+    pub fn replay(capture: &Capture) -> Self {
+        Self::with_socket(Replay::new(capture))
+    }
+
+    /// See `DatagramSocket::is_udp`.
+    pub fn is_udp(&self) -> bool {
+        self.socket.is_udp()
+    }
+
+    /// Record every datagram sent or received from now on.
+    pub fn set_recorder(&mut self, recorder: Option<Recorder>) {
+        self.recorder = recorder;
     }
 
     pub fn local_addr(&self) -> Result<SocketAddr> {
         self.socket.local_addr()
-            .map_err(|e| Error::Io(e.to_string()))
+    }
+
+    fn record(&self, direction: Direction, data: &[u8]) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(direction, data);
+        }
     }
 
     /// Get the next message ID and increment
@@ -67,8 +275,8 @@ impl Transport {
 
     /// Send a raw packet
     pub async fn send_raw(&mut self, data: &[u8]) -> Result<()> {
-        self.socket.send_to(data, self.remote_addr).await
-            .map_err(|e| Error::Io(e.to_string()))?;
+        self.socket.send(data).await?;
+        self.record(Direction::Outbound, data);
         Ok(())
     }
 
@@ -84,16 +292,9 @@ impl Transport {
 
     /// Receive a packet, returns (message_type, message_id, payload)
     pub async fn recv(&mut self) -> Result<(MessageType, u16, Vec<u8>)> {
-        let mut buf = vec![0u8; MAX_PACKET_SIZE];
-
-        let (len, from) = self.socket.recv_from(&mut buf).await
-            .map_err(|e| Error::Io(e.to_string()))?;
-
-        if from != self.remote_addr {
-            return Err(Error::InvalidPacket("packet from wrong address".into()));
-        }
+        let buf = self.socket.recv().await?;
+        self.record(Direction::Inbound, &buf);
 
-        buf.truncate(len);
         let (header, payload_start) = PacketHeader::parse(&buf)?;
         let payload = buf[payload_start..].to_vec();
 
@@ -110,26 +311,21 @@ impl Transport {
 
     /// Try to receive raw bytes without blocking. Returns None if no data available.
     pub fn try_recv_raw(&mut self) -> Result<Option<Vec<u8>>> {
-        let mut buf = vec![0u8; MAX_PACKET_SIZE];
-        match self.socket.try_recv_from(&mut buf) {
-            Ok((len, _)) => {
-                buf.truncate(len);
-                Ok(Some(buf))
-            }
-            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(None),
-            Err(e) => Err(Error::Io(e.to_string())),
+        let data = self.socket.try_recv()?;
+        if let Some(data) = &data {
+            self.record(Direction::Inbound, data);
         }
+        Ok(data)
     }
 
     /// Receive raw bytes with timeout
     pub async fn recv_raw_timeout(&mut self, timeout: Duration) -> Result<Option<Vec<u8>>> {
-        let mut buf = vec![0u8; MAX_PACKET_SIZE];
-        match tokio::time::timeout(timeout, self.socket.recv_from(&mut buf)).await {
-            Ok(Ok((len, _))) => {
-                buf.truncate(len);
-                Ok(Some(buf))
+        match tokio::time::timeout(timeout, self.socket.recv()).await {
+            Ok(Ok(data)) => {
+                self.record(Direction::Inbound, &data);
+                Ok(Some(data))
             }
-            Ok(Err(e)) => Err(Error::Io(e.to_string())),
+            Ok(Err(e)) => Err(e),
             Err(_) => Ok(None),
         }
     }
@@ -144,5 +340,61 @@ mod tests {
         let addr: SocketAddr = "127.0.0.1:34197".parse().unwrap();
         let transport = Transport::new(addr).await.unwrap();
         assert!(transport.local_addr().is_ok());
+        assert!(transport.is_udp());
+    }
+
+    #[tokio::test]
+    async fn test_replay_transport() {
+        use crate::protocol::capture::CaptureRecord;
+
+        let capture = Capture {
+            records: vec![
+                CaptureRecord { at: Duration::ZERO, direction: Direction::Outbound, data: vec![0x10] },
+                CaptureRecord { at: Duration::ZERO, direction: Direction::Inbound, data: vec![0x11, 0xaa] },
+            ],
+        };
+        let mut transport = Transport::replay(&capture);
+        assert!(!transport.is_udp());
+        assert_eq!(transport.recv_raw_timeout(Duration::from_millis(5)).await.unwrap(), None);
+        transport.send_raw(&[0x10]).await.unwrap();
+        assert_eq!(transport.try_recv_raw().unwrap(), Some(vec![0x11, 0xaa]));
+        // A truncated capture ends the connection instead of hanging it
+        assert!(transport.recv_raw_timeout(Duration::from_millis(5)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_udp_ignores_other_senders() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let stranger = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut transport = Transport::new_with_bind(server.local_addr().unwrap(), "127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let client_addr = transport.local_addr().unwrap();
+
+        stranger.send_to(&[0x99], client_addr).await.unwrap();
+        server.send_to(&[0x11], client_addr).await.unwrap();
+        assert_eq!(transport.recv_raw_timeout(Duration::from_secs(1)).await.unwrap(), Some(vec![0x11]));
+        stranger.send_to(&[0x99], client_addr).await.unwrap();
+        assert_eq!(transport.recv_raw_timeout(Duration::from_millis(50)).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_channel_transport() {
+        let (a, b) = ChannelSocket::pair(
+            "127.0.0.1:1".parse().unwrap(),
+            "127.0.0.1:2".parse().unwrap(),
+        );
+        let mut client = Transport::with_socket(a);
+        let mut server = Transport::with_socket(b);
+        assert_eq!(client.try_recv_raw().unwrap(), None);
+
+        let msg_id = client.send(MessageType::TransferBlockRequest, &[1, 2, 3], true).await.unwrap();
+        let (msg_type, id, payload) = server.recv().await.unwrap();
+        assert_eq!(msg_type, MessageType::TransferBlockRequest);
+        assert_eq!(id, msg_id);
+        assert_eq!(payload, vec![1, 2, 3]);
+
+        drop(server);
+        assert!(client.send_raw(&[0]).await.is_err());
     }
 }